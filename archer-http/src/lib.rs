@@ -18,11 +18,25 @@ use axum::{
     Json,
 };
 use ordered_float::OrderedFloat;
+use time::OffsetDateTime;
 pub use tower;
 pub use tower_http;
 
 pub enum ApiResponse<T> {
     Data(Vec<T>),
+    /// Single page of a larger result set, where `total` is the size of the full set.
+    Page {
+        data: Vec<T>,
+        total: usize,
+        limit: usize,
+        offset: usize,
+    },
+    /// Result that is partially successful, for example, when some of the requested items weren't
+    /// found.
+    Partial {
+        data: Vec<T>,
+        errors: Vec<ApiError>,
+    },
     Error(ApiError),
 }
 
@@ -40,8 +54,8 @@ where
             total: usize,
             limit: usize,
             offset: usize,
-            #[serde(skip_serializing_if = "Option::is_none")]
-            errors: Option<[ResponseError<'a>; 1]>,
+            #[serde(skip_serializing_if = "Vec::is_empty")]
+            errors: Vec<ResponseError<'a>>,
         }
 
         #[derive(Serialize)]
@@ -52,24 +66,49 @@ where
             trace_id: Option<&'a TraceId>,
         }
 
+        impl<'a> From<&'a ApiError> for ResponseError<'a> {
+            fn from(error: &'a ApiError) -> Self {
+                Self {
+                    code: error.code.as_u16(),
+                    msg: &error.msg,
+                    trace_id: error.trace_id.as_ref(),
+                }
+            }
+        }
+
         let resp = match self {
             Self::Data(data) => Response {
                 data,
                 total: data.len(),
                 limit: 0,
                 offset: 0,
-                errors: None,
+                errors: Vec::new(),
+            },
+            Self::Page {
+                data,
+                total,
+                limit,
+                offset,
+            } => Response {
+                data,
+                total: *total,
+                limit: *limit,
+                offset: *offset,
+                errors: Vec::new(),
+            },
+            Self::Partial { data, errors } => Response {
+                data,
+                total: data.len(),
+                limit: 0,
+                offset: 0,
+                errors: errors.iter().map(Into::into).collect(),
             },
             Self::Error(error) => Response {
                 data: &[],
                 total: 0,
                 limit: 0,
                 offset: 0,
-                errors: Some([ResponseError {
-                    code: error.code.as_u16(),
-                    msg: &error.msg,
-                    trace_id: error.trace_id.as_ref(),
-                }]),
+                errors: vec![error.into()],
             },
         };
 
@@ -127,7 +166,7 @@ pub enum ParseIdError {
     Zero,
 }
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
 #[serde(transparent)]
 pub struct TraceId(#[serde(with = "serde::hex::nonzero")] pub NonZeroU128);
 
@@ -169,7 +208,7 @@ impl FromStr for SpanId {
     }
 }
 
-#[derive(Serialize)]
+#[derive(Deserialize, Serialize)]
 #[serde(transparent)]
 pub struct ProcessId(pub String);
 
@@ -179,17 +218,19 @@ impl From<String> for ProcessId {
     }
 }
 
-#[derive(Serialize)]
+#[derive(Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Trace {
     #[serde(rename = "traceID")]
     pub trace_id: TraceId,
     pub spans: Vec<Span>,
+    #[serde(default)]
     pub processes: HashMap<String, Process>,
+    #[serde(default)]
     pub warnings: Vec<String>,
 }
 
-#[derive(Serialize)]
+#[derive(Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Span {
     #[serde(rename = "traceID")]
@@ -197,22 +238,32 @@ pub struct Span {
     #[serde(rename = "spanID")]
     pub span_id: SpanId,
     // deprecated
-    #[serde(rename = "parentSpanID", skip_serializing_if = "Option::is_none")]
+    #[serde(
+        rename = "parentSpanID",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
     pub parent_span_id: Option<SpanId>,
+    #[serde(default)]
     pub flags: u32,
     pub operation_name: String,
+    #[serde(default)]
     pub references: Vec<Reference>,
     pub start_time: i128,
     pub duration: i128,
+    #[serde(default)]
     pub tags: Vec<KeyValue>,
+    #[serde(default)]
     pub logs: Vec<Log>,
     #[serde(rename = "processID")]
     pub process_id: ProcessId,
+    #[serde(default)]
     pub process: Option<Process>,
+    #[serde(default)]
     pub warnings: Vec<String>,
 }
 
-#[derive(Serialize)]
+#[derive(Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Reference {
     pub ref_type: ReferenceType,
@@ -220,30 +271,34 @@ pub struct Reference {
     pub trace_id: TraceId,
     #[serde(rename = "spanID")]
     pub span_id: SpanId,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<KeyValue>,
 }
 
-#[derive(Serialize)]
+#[derive(Deserialize, Serialize)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum ReferenceType {
     ChildOf,
     FollowsFrom,
 }
 
-#[derive(Eq, Hash, PartialEq, Serialize)]
+#[derive(Eq, Hash, PartialEq, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Process {
     pub service_name: String,
+    #[serde(default)]
     pub tags: Vec<KeyValue>,
 }
 
-#[derive(Serialize)]
+#[derive(Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Log {
     pub timestamp: i128,
+    #[serde(default)]
     pub fields: Vec<KeyValue>,
 }
 
-#[derive(Eq, Hash, PartialEq, Serialize)]
+#[derive(Eq, Hash, PartialEq, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct KeyValue {
     pub key: String,
@@ -251,7 +306,7 @@ pub struct KeyValue {
     pub value: Value,
 }
 
-#[derive(Serialize)]
+#[derive(Deserialize, Serialize)]
 #[serde(rename_all = "lowercase", tag = "type", content = "value")]
 pub enum Value {
     String(String),
@@ -297,9 +352,279 @@ pub struct DependencyLink {
     pub call_count: u64,
 }
 
+/// Distribution of the traces that match a search, by their start time and duration.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TraceHistogram {
+    /// Equally sized time ranges, in microseconds since the Unix epoch.
+    pub time_buckets: Vec<HistogramBucket>,
+    /// Duration ranges in microseconds, each twice as large as the previous one.
+    pub duration_buckets: Vec<HistogramBucket>,
+    /// Trace counts of each combination of time and duration bucket, omitting empty ones.
+    pub cells: Vec<HistogramCell>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HistogramBucket {
+    /// Inclusive lower bound.
+    pub start: i64,
+    /// Exclusive upper bound.
+    pub end: i64,
+    pub count: usize,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HistogramCell {
+    /// Index into the time buckets.
+    pub time: usize,
+    /// Index into the duration buckets.
+    pub duration: usize,
+    pub count: usize,
+}
+
+/// Structural difference between two traces, where spans are merged into a single tree by their
+/// path of service and operation names.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TraceDiff {
+    #[serde(rename = "traceIDA")]
+    pub trace_id_a: TraceId,
+    #[serde(rename = "traceIDB")]
+    pub trace_id_b: TraceId,
+    pub roots: Vec<DiffNode>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiffNode {
+    pub service: String,
+    pub operation: String,
+    /// Amount of spans at this position in trace A.
+    pub count_a: usize,
+    /// Amount of spans at this position in trace B.
+    pub count_b: usize,
+    pub children: Vec<DiffNode>,
+}
+
+/// Configuration of the UI, in the structure that it expects. It's merged with the UI's defaults,
+/// so only the settings that Archer controls are included.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UiConfig {
+    pub archive_enabled: bool,
+    pub dependencies: UiMenu,
+    pub monitor: UiMenu,
+    pub search: UiSearch,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UiMenu {
+    pub menu_enabled: bool,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UiSearch {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_lookback: Option<String>,
+}
+
+/// Failed spans of a service, that share the same error message and status code.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ErrorGroup {
+    /// Error message, if the spans recorded any.
+    pub message: Option<String>,
+    /// HTTP or gRPC status code, if the spans recorded any.
+    pub status_code: Option<i64>,
+    /// Amount of failed spans in this group.
+    pub count: usize,
+    /// All operations, in which the error occurred.
+    pub operations: Vec<String>,
+    /// Start time of the earliest span in microseconds.
+    pub first_seen: i128,
+    /// Start time of the latest span in microseconds.
+    pub last_seen: i128,
+    /// Examples of traces with the error, newest first.
+    #[serde(rename = "traceIDs")]
+    pub trace_ids: Vec<TraceId>,
+}
+
+/// Log of a span, together with the span it belongs to, to show the logs of a whole trace in a
+/// single timeline.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TraceLog {
+    #[serde(rename = "spanID")]
+    pub span_id: SpanId,
+    pub service_name: String,
+    pub operation_name: String,
+    pub timestamp: i128,
+    pub fields: Vec<KeyValue>,
+}
+
+/// Chain of spans, that determines the total duration of a trace. Speeding up any other span
+/// doesn't make the trace faster.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CriticalPath {
+    #[serde(rename = "traceID")]
+    pub trace_id: TraceId,
+    /// Sections of the path in chronological order, where each is spent in a single span,
+    /// without waiting for any of its children.
+    pub segments: Vec<CriticalPathSegment>,
+    /// Time on the path summed up per span, largest first.
+    pub spans: Vec<CriticalPathSpan>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CriticalPathSegment {
+    #[serde(rename = "spanID")]
+    pub span_id: SpanId,
+    /// Start of the segment in microseconds.
+    pub start_time: i128,
+    /// Duration of the segment in microseconds.
+    pub duration: i128,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CriticalPathSpan {
+    #[serde(rename = "spanID")]
+    pub span_id: SpanId,
+    pub service_name: String,
+    pub operation_name: String,
+    /// Time of the span on the critical path in microseconds.
+    pub duration: i128,
+}
+
+/// Call graph of a trace, where all spans with the same service and operation are aggregated into
+/// a single node.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TraceGraph {
+    #[serde(rename = "traceID")]
+    pub trace_id: TraceId,
+    pub nodes: Vec<GraphNode>,
+    pub edges: Vec<GraphEdge>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GraphNode {
+    pub service: String,
+    pub operation: String,
+    /// Amount of spans, that make up this node.
+    pub count: usize,
+    /// Amount of spans, that are marked as error.
+    pub errors: usize,
+    /// Summed up duration of all spans in microseconds.
+    pub duration: i64,
+    /// Summed up duration of all spans in microseconds, excluding the time covered by their
+    /// children.
+    pub self_duration: i64,
+}
+
+/// Calls from one node to another, aggregated from all spans and their direct children.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GraphEdge {
+    /// Index of the calling node.
+    pub from: usize,
+    /// Index of the called node.
+    pub to: usize,
+    pub count: usize,
+    /// Summed up duration of the called spans in microseconds.
+    pub duration: i64,
+}
+
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Operation {
     pub name: String,
     pub span_kind: String,
 }
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MetricFamily {
+    pub name: &'static str,
+    #[serde(rename = "type")]
+    pub ty: MetricType,
+    pub help: String,
+    pub metrics: Vec<Metric>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum MetricType {
+    Gauge,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Metric {
+    pub labels: Vec<Label>,
+    pub metric_points: Vec<MetricPoint>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Label {
+    pub name: &'static str,
+    pub value: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MetricPoint {
+    pub gauge_value: GaugeValue,
+    #[serde(with = "time::serde::rfc3339")]
+    pub timestamp: OffsetDateTime,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GaugeValue {
+    pub double_value: f64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MinStep {
+    pub min_step: u64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StorageStats {
+    pub span_count: u64,
+    pub trace_count: u64,
+    pub services: Vec<ServiceStats>,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub oldest_span: Option<OffsetDateTime>,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub newest_span: Option<OffsetDateTime>,
+    /// Size of the database on disk, in bytes.
+    pub file_size: u64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServiceStats {
+    pub name: String,
+    pub span_count: u64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MaintenanceResult {
+    /// Amount of bytes that the database shrunk.
+    pub freed_bytes: u64,
+    /// Time it took to run the maintenance, in milliseconds.
+    pub duration: u64,
+}
@@ -3,6 +3,7 @@ use std::{env, io::Result, path::PathBuf};
 fn main() -> Result<()> {
     let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
     std::fs::create_dir_all(out_dir.join("jaeger"))?;
+    std::fs::create_dir_all(out_dir.join("jaeger_v3"))?;
     std::fs::create_dir_all(out_dir.join("opentelemetry"))?;
 
     tonic_build::configure()
@@ -18,9 +19,20 @@ fn main() -> Result<()> {
 
     tonic_build::configure()
         .build_client(false)
+        .extern_path(".opentelemetry", "crate::opentelemetry")
+        .out_dir(out_dir.join("jaeger_v3"))
+        .compile(
+            &["external/jaeger/api_v3/query_service.proto"],
+            &["external", "../opentelemetry-proto"],
+        )?;
+
+    tonic_build::configure()
         .out_dir(out_dir.join("opentelemetry"))
         .compile(
-            &["../opentelemetry-proto/opentelemetry/proto/collector/trace/v1/trace_service.proto"],
+            &[
+                "../opentelemetry-proto/opentelemetry/proto/collector/logs/v1/logs_service.proto",
+                "../opentelemetry-proto/opentelemetry/proto/collector/trace/v1/trace_service.proto",
+            ],
             &["../opentelemetry-proto"],
         )
 }
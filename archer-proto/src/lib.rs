@@ -8,11 +8,24 @@ pub mod jaeger {
     pub mod api_v2 {
         include!(concat!(env!("OUT_DIR"), "/jaeger/jaeger.api_v2.rs"));
     }
+
+    pub mod api_v3 {
+        include!(concat!(env!("OUT_DIR"), "/jaeger_v3/jaeger.api_v3.rs"));
+    }
 }
 
 pub mod opentelemetry {
     pub mod proto {
         pub mod collector {
+            pub mod logs {
+                pub mod v1 {
+                    include!(concat!(
+                        env!("OUT_DIR"),
+                        "/opentelemetry/opentelemetry.proto.collector.logs.v1.rs"
+                    ));
+                }
+            }
+
             pub mod trace {
                 pub mod v1 {
                     include!(concat!(
@@ -32,6 +45,15 @@ pub mod opentelemetry {
             }
         }
 
+        pub mod logs {
+            pub mod v1 {
+                include!(concat!(
+                    env!("OUT_DIR"),
+                    "/opentelemetry/opentelemetry.proto.logs.v1.rs"
+                ));
+            }
+        }
+
         pub mod resource {
             pub mod v1 {
                 include!(concat!(
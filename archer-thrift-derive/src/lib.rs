@@ -2,53 +2,107 @@
 
 #![deny(rust_2018_idioms, clippy::all, clippy::pedantic)]
 #![warn(missing_docs, clippy::missing_docs_in_private_items)]
-#![allow(
-    clippy::cast_possible_truncation,
-    clippy::cast_possible_wrap,
-    clippy::missing_panics_doc
-)]
+#![allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+
+use std::{fmt::Display, str::FromStr};
 
 use proc_macro2::{Ident, TokenStream};
 use quote::{format_ident, quote};
 use syn::{
-    parse_macro_input, Data, DataEnum, DataStruct, DeriveInput, Field, Fields, GenericArgument,
-    PathArguments, Type,
+    parse_macro_input, Attribute, Data, DataEnum, DataStruct, DeriveInput, Error, Field, Fields,
+    GenericArgument, Lifetime, Lit, Meta, NestedMeta, Path, PathArguments, PathSegment, Result,
+    Type,
 };
 
 /// Derive the implementation of `ThriftDeserialize`.
-#[proc_macro_derive(ThriftDeserialize)]
+///
+/// Struct fields are assigned Thrift field IDs based on their position (starting at `1`), unless
+/// the ID is explicitly defined with the `#[thrift(id = N)]` attribute.
+///
+/// Structs with a lifetime can contain `Cow<'a, str>` and `Cow<'a, [u8]>` fields. These are always
+/// read as owned values into the `'static` version of the struct. With the `#[thrift(borrowed)]`
+/// attribute on the struct, `ThriftDeserializeBorrowed` is implemented as well, which borrows them
+/// from the input instead.
+///
+/// Unsupported input results in compile errors, that point at the offending item.
+#[proc_macro_derive(ThriftDeserialize, attributes(thrift))]
 pub fn thrift_deserialize(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
-    let name = input.ident;
 
-    let expanded = match input.data {
-        Data::Struct(ref data) => derive_struct(&name, data),
-        Data::Enum(ref data) => derive_enum(&name, data),
-        Data::Union(_) => panic!("unions not supported"),
-    };
+    derive(&input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
 
-    expanded.into()
+/// Generate the implementation for any of the supported data types.
+fn derive(input: &DeriveInput) -> Result<TokenStream> {
+    let name = &input.ident;
+    let lifetime = input.generics.lifetimes().next().map(|def| &def.lifetime);
+
+    match input.data {
+        Data::Struct(ref data) => {
+            let borrowed = is_borrowed(&input.attrs)?;
+            if borrowed && lifetime.is_none() {
+                return Err(Error::new_spanned(
+                    name,
+                    "borrowed structs must have a lifetime",
+                ));
+            }
+
+            derive_struct(name, lifetime, borrowed, data)
+        }
+        Data::Enum(ref data) => derive_enum(name, data),
+        Data::Union(ref data) => Err(Error::new_spanned(
+            data.union_token,
+            "unions are not supported",
+        )),
+    }
 }
 
 /// Generate an implementation for enums.
-fn derive_enum(name: &Ident, data: &DataEnum) -> TokenStream {
-    let variants = data
+///
+/// Like in the Thrift IDL, variants without an explicit `#[thrift(value = N)]` attribute take the
+/// value of the previous variant plus one, starting at `0`.
+fn derive_enum(name: &Ident, data: &DataEnum) -> Result<TokenStream> {
+    let mut next = 0;
+    let values = data
         .variants
         .iter()
-        .enumerate()
-        .map(|(i, v)| {
-            assert!(v.fields.is_empty(), "only simple enums supported");
+        .map(|v| {
+            if !v.fields.is_empty() {
+                return Err(Error::new_spanned(
+                    &v.fields,
+                    "only variants without fields are supported",
+                ));
+            }
 
-            let i = i as i32;
-            let name = &v.ident;
+            let value = int_attr(&v.attrs, "value")?.unwrap_or(next);
+            next = value + 1;
 
-            quote! { #i => Self::#name }
+            Ok((&v.ident, value))
         })
+        .collect::<Result<Vec<_>>>()?;
+
+    if let Some((dup, value)) = values
+        .iter()
+        .enumerate()
+        .find(|(i, (_, value))| values[..*i].iter().any(|(_, other)| other == value))
+        .map(|(_, v)| v)
+    {
+        return Err(Error::new_spanned(
+            dup,
+            format!("duplicate value {value} on {name}::{dup}"),
+        ));
+    }
+
+    let variants = values
+        .iter()
+        .map(|(name, value)| quote! { #value => Self::#name })
         .collect::<Vec<_>>();
 
     let error_message = format!("unknown {name} value `{{}}`");
 
-    quote! {
+    Ok(quote! {
         impl crate::ThriftDeserialize for #name {
             fn read(prot: &mut impl TInputProtocol) -> ::thrift::Result<Self> {
                 Ok(match prot.read_i32()? {
@@ -62,74 +116,128 @@ fn derive_enum(name: &Ident, data: &DataEnum) -> TokenStream {
                 })
             }
         }
-    }
+    })
 }
 
 /// Generate an implementation for structs.
-fn derive_struct(name: &Ident, data: &DataStruct) -> TokenStream {
+fn derive_struct(
+    name: &Ident,
+    lifetime: Option<&Lifetime>,
+    borrowed: bool,
+    data: &DataStruct,
+) -> Result<TokenStream> {
     let fields = match data.fields {
         Fields::Named(ref fields) => fields
             .named
             .iter()
             .enumerate()
             .map(|(i, f)| FieldInfo::from_field(name, f, i))
-            .collect(),
-        Fields::Unnamed(_) => panic!("unnamed structs not supported"),
+            .collect::<Result<Vec<_>>>()?,
+        Fields::Unnamed(ref fields) => {
+            return Err(Error::new_spanned(
+                fields,
+                "structs with unnamed fields are not supported",
+            ))
+        }
         Fields::Unit => Vec::new(),
     };
 
+    if let Some(dup) = fields
+        .iter()
+        .enumerate()
+        .find(|(i, f)| fields[..*i].iter().any(|other| other.index == f.index))
+        .map(|(_, f)| f)
+    {
+        return Err(Error::new_spanned(
+            dup.name,
+            format!("duplicate field ID {} on {}", dup.index, dup.error_name),
+        ));
+    }
+
     let fields_map = fields
         .iter()
-        .filter_map(|f| f.required.then_some(&f.lookup_name));
-    let errors_map = fields.iter().filter_map(|f| {
-        f.required.then(|| {
-            let error_name = &f.error_name;
-            let lookup_name = &f.lookup_name;
-            quote! { crate::jaeger::verify_read(#error_name, #lookup_name) }
-        })
+        .filter_map(|f| f.required.then_some(&f.lookup_name))
+        .collect::<Vec<_>>();
+    let errors_map = fields.iter().filter(|f| f.required).map(|f| {
+        let error_name = &f.error_name;
+        let lookup_name = &f.lookup_name;
+        quote! { crate::jaeger::verify_read(#error_name, #lookup_name) }
     });
 
-    let matches = fields.iter().map(FieldInfo::to_match);
+    let errors_map = quote! { #(#errors_map?);*; };
+    // The same parsing loop for both the owned and borrowed implementation, only the way each
+    // field is read differs.
+    let read = |borrowed| {
+        let matches = fields.iter().map(|f| f.to_match(borrowed));
+
+        quote! {
+            prot.read_struct_begin()?;
+            let mut value = Self::default();
+            #(let mut #fields_map = false);*;
+
+            loop {
+                let ident = prot.read_field_begin()?;
+                if ident.field_type == ::thrift::protocol::TType::Stop {
+                    break;
+                }
 
-    quote! {
-        impl crate::ThriftDeserialize for #name {
-            fn read(prot: &mut impl TInputProtocol) -> ::thrift::Result<Self> {
-                prot.read_struct_begin()?;
-                let mut value = Self::default();
-                #(let mut #fields_map = false);*;
-
-                loop {
-                    let ident = prot.read_field_begin()?;
-                    if ident.field_type == ::thrift::protocol::TType::Stop {
-                        break;
-                    }
+                match ::thrift::protocol::field_id(&ident)? {
+                    #(#matches)*
+                    _ => prot.skip(ident.field_type)?,
+                }
 
-                    match ::thrift::protocol::field_id(&ident)? {
-                        #(#matches)*
-                        _ => prot.skip(ident.field_type)?,
-                    }
+                prot.read_field_end()?;
+            }
 
-                    prot.read_field_end()?;
-                }
+            prot.read_struct_end()?;
 
-                prot.read_struct_end()?;
+            #errors_map
 
-                #(#errors_map?);*;
+            Ok(value)
+        }
+    };
+
+    let owned_read = read(false);
+    let owned = if lifetime.is_some() {
+        quote! { #name<'static> }
+    } else {
+        quote! { #name }
+    };
 
-                Ok(value)
+    let mut expanded = quote! {
+        impl crate::ThriftDeserialize for #owned {
+            fn read(prot: &mut impl TInputProtocol) -> ::thrift::Result<Self> {
+                #owned_read
             }
         }
+    };
+
+    if let Some(lifetime) = lifetime.filter(|_| borrowed) {
+        let borrowed_read = read(true);
+
+        expanded.extend(quote! {
+            impl<#lifetime> crate::ThriftDeserializeBorrowed<#lifetime> for #name<#lifetime> {
+                fn read(
+                    prot: &mut impl crate::TBorrowedInputProtocol<#lifetime>,
+                ) -> ::thrift::Result<Self> {
+                    #borrowed_read
+                }
+            }
+        });
     }
+
+    Ok(expanded)
 }
 
-/// Check whether the type is likely to be an [`Option`].
+/// Check whether the type is likely to be an [`Option`]. Only the last segment is checked, so
+/// qualified paths like `std::option::Option` are detected as well.
 fn is_option(ty: &Type) -> bool {
     match ty {
         Type::Path(path) => path
             .path
             .segments
-            .first()
-            .map_or(false, |seg| seg.ident == "Option"),
+            .last()
+            .is_some_and(|seg| seg.ident == "Option"),
         _ => false,
     }
 }
@@ -138,22 +246,21 @@ fn is_option(ty: &Type) -> bool {
 /// (like `Option<u32>`, or `Vec<String>`).
 fn inner_type(ty: &Type) -> Option<&Type> {
     match ty {
-        Type::Path(path) => {
-            if path.path.segments.len() != 1 {
+        Type::Path(path) => type_argument(path.path.segments.last()?),
+        _ => None,
+    }
+}
+
+/// Extract the single generic type argument of a path segment, like the `u32` in `Option<u32>`.
+fn type_argument(segment: &PathSegment) -> Option<&Type> {
+    match segment.arguments {
+        PathArguments::AngleBracketed(ref args) => {
+            if args.args.len() != 1 {
                 return None;
             }
 
-            match path.path.segments.first()?.arguments {
-                PathArguments::AngleBracketed(ref args) => {
-                    if args.args.len() != 1 {
-                        return None;
-                    }
-
-                    match args.args.first()? {
-                        GenericArgument::Type(ty) => Some(ty),
-                        _ => None,
-                    }
-                }
+            match args.args.first()? {
+                GenericArgument::Type(ty) => Some(ty),
                 _ => None,
             }
         }
@@ -172,7 +279,8 @@ struct FieldInfo<'a> {
     /// String version in the form `{struct}.{field}`, that is used for error reporting, when a
     /// required field was missing in the payload.
     error_name: String,
-    /// Field index in the Thrift data.
+    /// Field index in the Thrift data, either derived from the position in the struct or
+    /// explicitly defined through the `#[thrift(id = N)]` attribute.
     index: i16,
     /// The field's known type, which can be turned into a parsing statement of the right type.
     ty: KnownType<'a>,
@@ -183,27 +291,34 @@ struct FieldInfo<'a> {
 impl<'a> FieldInfo<'a> {
     /// Create the field info from given basic information. All other information is derived from
     /// these input parameters.
-    fn from_field(struct_name: &Ident, field: &'a Field, index: usize) -> Self {
-        let name = field.ident.as_ref().expect("missing field ident");
+    fn from_field(struct_name: &Ident, field: &'a Field, index: usize) -> Result<Self> {
+        let name = field
+            .ident
+            .as_ref()
+            .ok_or_else(|| Error::new_spanned(field, "field must have a name"))?;
         let required = !is_option(&field.ty);
 
-        Self {
+        let ty = if required {
+            &field.ty
+        } else {
+            inner_type(&field.ty).ok_or_else(|| {
+                Error::new_spanned(&field.ty, "Option must have exactly one type argument")
+            })?
+        };
+
+        Ok(Self {
             name,
             lookup_name: format_ident!("read_{name}"),
             error_name: format!("{struct_name}.{name}"),
-            index: index as i16 + 1,
-            ty: KnownType::from_type(if required {
-                &field.ty
-            } else {
-                inner_type(&field.ty).expect("failed getting Option inner type")
-            }),
+            index: int_attr(&field.attrs, "id")?.unwrap_or(index as i16 + 1),
+            ty: KnownType::from_type(ty)?,
             required,
-        }
+        })
     }
 
     /// Create a match statement for the parsing loop of the struct. Fields can occur in random
     /// order and this parses and assigns a value when it is discovered in the payload.
-    fn to_match(&self) -> TokenStream {
+    fn to_match(&self, borrowed: bool) -> TokenStream {
         let Self {
             name,
             lookup_name,
@@ -212,7 +327,7 @@ impl<'a> FieldInfo<'a> {
             ty,
             required,
         } = self;
-        let read_impl = ty.read_impl();
+        let read_impl = ty.read_impl(borrowed);
 
         if *required {
             quote! {
@@ -229,95 +344,229 @@ impl<'a> FieldInfo<'a> {
     }
 }
 
+/// Collect the arguments of all `#[thrift(...)]` attributes.
+fn thrift_attrs(attrs: &[Attribute]) -> Result<Vec<NestedMeta>> {
+    let mut nested = Vec::new();
+
+    for attr in attrs.iter().filter(|attr| attr.path.is_ident("thrift")) {
+        match attr.parse_meta()? {
+            Meta::List(list) => nested.extend(list.nested),
+            meta => {
+                return Err(Error::new_spanned(
+                    meta,
+                    "expected attribute in the form `#[thrift(...)]`",
+                ))
+            }
+        }
+    }
+
+    Ok(nested)
+}
+
+/// Check whether the struct has the `#[thrift(borrowed)]` attribute.
+fn is_borrowed(attrs: &[Attribute]) -> Result<bool> {
+    let mut borrowed = false;
+
+    for nested in thrift_attrs(attrs)? {
+        match nested {
+            NestedMeta::Meta(Meta::Path(path)) if path.is_ident("borrowed") => borrowed = true,
+            nested => {
+                return Err(Error::new_spanned(
+                    nested,
+                    "unknown thrift attribute, expected `borrowed`",
+                ))
+            }
+        }
+    }
+
+    Ok(borrowed)
+}
+
+/// Check whether the type is [`u8`].
+fn is_u8(ty: &Type) -> bool {
+    matches!(ty, Type::Path(path) if path.path.is_ident("u8"))
+}
+
+/// Check whether the type has a lifetime argument, like `Tag<'a>`, which means it can be read in
+/// borrowed form as well.
+fn has_lifetime(segment: &PathSegment) -> bool {
+    match segment.arguments {
+        PathArguments::AngleBracketed(ref args) => args
+            .args
+            .iter()
+            .any(|arg| matches!(arg, GenericArgument::Lifetime(_))),
+        _ => false,
+    }
+}
+
+/// Extract an integer from the `#[thrift(name = N)]` attribute, if present. This is the field ID
+/// for struct fields and the value for enum variants.
+fn int_attr<T>(attrs: &[Attribute], name: &str) -> Result<Option<T>>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    let mut value = None;
+
+    for nested in thrift_attrs(attrs)? {
+        match nested {
+            NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident(name) => match nv.lit {
+                Lit::Int(lit) => value = Some(lit.base10_parse()?),
+                lit => {
+                    return Err(Error::new_spanned(
+                        lit,
+                        format!("{name} must be an integer"),
+                    ))
+                }
+            },
+            nested => {
+                return Err(Error::new_spanned(
+                    nested,
+                    format!("unknown thrift attribute, expected `{name} = N`"),
+                ))
+            }
+        }
+    }
+
+    Ok(value)
+}
+
 /// One of the known and supported types. These are types, that can be translated to source code for
 /// parsing from Thrift's raw payload into the Rust type.
-#[derive(Clone, Copy)]
+#[derive(Clone)]
 enum KnownType<'a> {
     /// [`String`] value.
     String,
+    /// [`Cow`](std::borrow::Cow) of [`str`], that is borrowed from the input if possible.
+    CowStr,
+    /// [`Cow`](std::borrow::Cow) of [`u8`] slices, that is borrowed from the input if possible.
+    CowBytes,
     /// [`bool`] value.
     Bool,
     /// 64-bit [`f64`] value.
     F64,
+    /// 16-bit signed [`i16`] value.
+    I16,
     /// 32-bit signed [`i32`] value.
     I32,
     /// 64-bit signed [`i64`] value.
     I64,
     /// [`Vec`] of [`u8`].
     VecU8,
-    /// [`Vec`] of some type `T`, that is expected to implement the required Thrift deserialization
-    /// trait.
-    VecT(&'a Ident),
+    /// [`Vec`] of any other known type, including further lists like `Vec<Vec<u8>>`.
+    Vec(Box<KnownType<'a>>),
     /// Some external type `T`, that is expected to implement the required Thrift deserialization
-    /// trait. Same as with the vector variant, this is a best effort, and just expected to
-    /// implement the requried trait. If it turns out to not implement the trait, it'll result in
-    /// a compile error.
-    External(&'a Ident),
+    /// trait. This is a best effort, and just expected to implement the requried trait. If it
+    /// turns out to not implement the trait, it'll result in a compile error. If it has a
+    /// lifetime, it's expected to implement the borrowed version as well.
+    External(&'a Path, bool),
 }
 
 impl<'a> KnownType<'a> {
-    /// Try to parse the given type into one of the known types, panicking in case it's none of
-    /// them.
-    fn from_type(ty: &'a Type) -> Self {
-        match ty {
-            Type::Path(path) => {
-                assert!(
-                    path.path.segments.len() == 1,
-                    "path must have exactly 1 segment"
-                );
-
-                let segment = path.path.segments.first().unwrap();
-                let name = &segment.ident;
-
-                match name {
-                    _ if name == "String" => Self::String,
-                    _ if name == "bool" => Self::Bool,
-                    _ if name == "f64" => Self::F64,
-                    _ if name == "i32" => Self::I32,
-                    _ if name == "i64" => Self::I64,
-                    _ if name == "Vec" => match segment.arguments {
-                        PathArguments::None => panic!("invalid Vec, without generic args"),
-                        PathArguments::AngleBracketed(ref args) => {
-                            assert!(
-                                args.args.len() == 1,
-                                "Vec must have exactly one type argument"
-                            );
-
-                            match args.args.first().unwrap() {
-                                GenericArgument::Type(ty) => match ty {
-                                    Type::Path(path) => {
-                                        match path.path.get_ident().expect("path is not an ident") {
-                                            name if name == "u8" => Self::VecU8,
-                                            name => Self::VecT(name),
-                                        }
-                                    }
-                                    _ => panic!("invalid type"),
-                                },
-                                _ => panic!("invalid generic argument"),
-                            }
-                        }
-                        PathArguments::Parenthesized(_) => panic!("invalid Vec, with parenthesis"),
-                    },
-                    _ => Self::External(name),
+    /// Try to parse the given type into one of the known types, failing in case it's none of them.
+    ///
+    /// The types are identified by the last segment of their path, so qualified paths like
+    /// `std::string::String` or `super::jaeger::Tag` are supported.
+    fn from_type(ty: &'a Type) -> Result<Self> {
+        let (path, segment) = match ty {
+            Type::Path(path) if path.qself.is_none() => path
+                .path
+                .segments
+                .last()
+                .map(|segment| (&path.path, segment)),
+            _ => None,
+        }
+        .ok_or_else(|| Error::new_spanned(ty, "only plain type paths are supported"))?;
+        let name = &segment.ident;
+
+        Ok(match name {
+            _ if name == "String" => Self::String,
+            _ if name == "bool" => Self::Bool,
+            _ if name == "f64" => Self::F64,
+            _ if name == "i16" => Self::I16,
+            _ if name == "i32" => Self::I32,
+            _ if name == "i64" => Self::I64,
+            _ if name == "Cow" => match segment.arguments {
+                PathArguments::AngleBracketed(ref args) => match args.args.last() {
+                    Some(GenericArgument::Type(Type::Path(path))) if path.path.is_ident("str") => {
+                        Self::CowStr
+                    }
+                    Some(GenericArgument::Type(Type::Slice(slice))) if is_u8(&slice.elem) => {
+                        Self::CowBytes
+                    }
+                    _ => {
+                        return Err(Error::new_spanned(
+                            ty,
+                            "only Cow<str> and Cow<[u8]> are supported",
+                        ))
+                    }
+                },
+                _ => return Err(Error::new_spanned(ty, "Cow must have type arguments")),
+            },
+            _ if name == "Vec" => match type_argument(segment) {
+                Some(inner) if is_u8(inner) => Self::VecU8,
+                Some(inner) => Self::Vec(Box::new(Self::from_type(inner)?)),
+                None => {
+                    return Err(Error::new_spanned(
+                        ty,
+                        "Vec must have exactly one type argument",
+                    ))
                 }
+            },
+            _ if name == "Option" => {
+                return Err(Error::new_spanned(
+                    ty,
+                    "Option is only supported as the outer type of a field",
+                ))
             }
-            _ => panic!("type is not a path"),
-        }
+            _ => Self::External(path, has_lifetime(segment)),
+        })
     }
 
     /// Generate the read implementation, that pulls data from the input stream and turns it into
-    /// the right type.
-    fn read_impl(self) -> TokenStream {
+    /// the right type. The borrowed implementation takes strings and binary data from the input
+    /// directly.
+    fn read_impl(&self, borrowed: bool) -> TokenStream {
         match self {
             Self::String => quote! { prot.read_string() },
+            Self::CowStr if borrowed => quote! {
+                prot.read_str().map(::std::borrow::Cow::Borrowed)
+            },
+            Self::CowStr => quote! { prot.read_string().map(::std::borrow::Cow::Owned) },
+            Self::CowBytes if borrowed => quote! {
+                prot.read_slice().map(::std::borrow::Cow::Borrowed)
+            },
+            Self::CowBytes => quote! { prot.read_bytes().map(::std::borrow::Cow::Owned) },
             Self::Bool => quote! { prot.read_bool() },
             Self::F64 => quote! { prot.read_double() },
+            Self::I16 => quote! { prot.read_i16() },
             Self::I32 => quote! { prot.read_i32() },
             Self::I64 => quote! { prot.read_i64() },
             Self::VecU8 => quote! { prot.read_bytes() },
-            Self::VecT(ident) => quote! { read_list::<#ident>(prot) },
-            Self::External(ident) => quote! {
-               <#ident as crate::ThriftDeserialize>::read(prot)
+            Self::Vec(elem) => match **elem {
+                Self::External(_, true) if borrowed => quote! { read_list_borrowed(prot) },
+                Self::External(_, true) => quote! { read_list(prot) },
+                Self::External(path, false) => quote! { read_list::<#path>(prot) },
+                ref elem => {
+                    let read_elem = elem.read_impl(borrowed);
+                    quote! {
+                        {
+                            let list_ident = prot.read_list_begin()?;
+                            let list = (0..list_ident.size)
+                                .map(|_| #read_elem)
+                                .collect::<::thrift::Result<Vec<_>>>()?;
+                            prot.read_list_end()?;
+                            ::thrift::Result::Ok(list)
+                        }
+                    }
+                }
+            },
+            Self::External(_, true) if borrowed => quote! {
+                crate::ThriftDeserializeBorrowed::read(prot)
+            },
+            Self::External(_, true) => quote! { crate::ThriftDeserialize::read(prot) },
+            Self::External(path, false) => quote! {
+               <#path as crate::ThriftDeserialize>::read(prot)
             },
         }
     }
@@ -0,0 +1,9 @@
+use archer_thrift_derive::ThriftDeserialize;
+
+#[derive(ThriftDeserialize)]
+#[thrift(borrowed)]
+struct Tag {
+    key: String,
+}
+
+fn main() {}
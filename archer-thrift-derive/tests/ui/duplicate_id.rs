@@ -0,0 +1,10 @@
+use archer_thrift_derive::ThriftDeserialize;
+
+#[derive(ThriftDeserialize)]
+struct Tag {
+    key: String,
+    #[thrift(id = 1)]
+    value: String,
+}
+
+fn main() {}
@@ -0,0 +1,11 @@
+use archer_thrift_derive::ThriftDeserialize;
+
+#[derive(ThriftDeserialize)]
+enum SpanRefType {
+    #[thrift(value = 1)]
+    ChildOf,
+    #[thrift(value = 1)]
+    FollowsFrom,
+}
+
+fn main() {}
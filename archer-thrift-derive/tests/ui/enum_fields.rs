@@ -0,0 +1,9 @@
+use archer_thrift_derive::ThriftDeserialize;
+
+#[derive(ThriftDeserialize)]
+enum TagType {
+    String,
+    Long(i64),
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use archer_thrift_derive::ThriftDeserialize;
+
+#[derive(ThriftDeserialize)]
+struct Tag {
+    #[thrift(id = "one")]
+    key: String,
+}
+
+fn main() {}
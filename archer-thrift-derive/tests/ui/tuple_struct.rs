@@ -0,0 +1,6 @@
+use archer_thrift_derive::ThriftDeserialize;
+
+#[derive(ThriftDeserialize)]
+struct Tag(String, i64);
+
+fn main() {}
@@ -0,0 +1,9 @@
+use archer_thrift_derive::ThriftDeserialize;
+
+#[derive(ThriftDeserialize)]
+union Value {
+    int: i64,
+    float: f64,
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use archer_thrift_derive::ThriftDeserialize;
+
+#[derive(ThriftDeserialize)]
+struct Tag {
+    #[thrift(index = 1)]
+    key: String,
+}
+
+fn main() {}
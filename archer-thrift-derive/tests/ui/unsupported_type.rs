@@ -0,0 +1,8 @@
+use archer_thrift_derive::ThriftDeserialize;
+
+#[derive(ThriftDeserialize)]
+struct Tag<'a> {
+    key: &'a str,
+}
+
+fn main() {}
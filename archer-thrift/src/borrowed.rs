@@ -0,0 +1,190 @@
+//! Input protocols that read from a byte slice, and can borrow strings and binary data from it
+//! instead of allocating them.
+
+use std::{
+    cell::Cell,
+    io::{self, Read},
+    rc::Rc,
+};
+
+use thrift::{
+    protocol::{
+        TBinaryInputProtocol, TCompactInputProtocol, TFieldIdentifier, TInputProtocol,
+        TListIdentifier, TMapIdentifier, TMessageIdentifier, TSetIdentifier, TStructIdentifier,
+    },
+    ProtocolError, ProtocolErrorKind,
+};
+
+/// Input protocol, that can return strings and binary data as slices of the input.
+pub trait TBorrowedInputProtocol<'a>: TInputProtocol {
+    /// Read a string, without copying it.
+    fn read_str(&mut self) -> thrift::Result<&'a str>;
+    /// Read binary data, without copying it.
+    fn read_slice(&mut self) -> thrift::Result<&'a [u8]>;
+}
+
+/// Transport over a byte slice, that shares its position between all clones. That allows the
+/// regular protocol to read most values, while strings are taken from the same position directly.
+#[derive(Clone)]
+pub struct SliceTransport<'a>(Rc<Cell<&'a [u8]>>);
+
+impl<'a> SliceTransport<'a> {
+    fn new(input: &'a [u8]) -> Self {
+        Self(Rc::new(Cell::new(input)))
+    }
+
+    fn read_byte(&self) -> thrift::Result<u8> {
+        self.take_slice(1).map(|buf| buf[0])
+    }
+
+    fn take_slice(&self, len: usize) -> thrift::Result<&'a [u8]> {
+        let buf = self.0.get();
+        if buf.len() < len {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+
+        let (taken, rest) = buf.split_at(len);
+        self.0.set(rest);
+        Ok(taken)
+    }
+}
+
+impl Read for SliceTransport<'_> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        let mut buf = self.0.get();
+        let read = buf.read(out)?;
+        self.0.set(buf);
+        Ok(read)
+    }
+}
+
+/// Protocol, whose encoding of string and binary lengths is known, so their content can be taken
+/// from the input directly.
+pub trait SliceProtocol<'a>: TInputProtocol {
+    /// Create the protocol on top of the transport.
+    fn new(transport: SliceTransport<'a>) -> Self;
+    /// Read the length prefix of a string or binary value.
+    fn read_len(transport: &SliceTransport<'a>) -> thrift::Result<usize>;
+}
+
+impl<'a> SliceProtocol<'a> for TCompactInputProtocol<SliceTransport<'a>> {
+    fn new(transport: SliceTransport<'a>) -> Self {
+        Self::new(transport)
+    }
+
+    fn read_len(transport: &SliceTransport<'a>) -> thrift::Result<usize> {
+        let mut len = 0_u32;
+
+        for shift in (0..32).step_by(7) {
+            let byte = transport.read_byte()?;
+            len |= u32::from(byte & 0x7f) << shift;
+
+            if byte & 0x80 == 0 {
+                return Ok(len as usize);
+            }
+        }
+
+        Err(thrift::Error::Protocol(ProtocolError::new(
+            ProtocolErrorKind::InvalidData,
+            "length varint is too long",
+        )))
+    }
+}
+
+impl<'a> SliceProtocol<'a> for TBinaryInputProtocol<SliceTransport<'a>> {
+    fn new(transport: SliceTransport<'a>) -> Self {
+        Self::new(transport, true)
+    }
+
+    fn read_len(transport: &SliceTransport<'a>) -> thrift::Result<usize> {
+        let mut len = [0; 4];
+        len.copy_from_slice(transport.take_slice(4)?);
+
+        usize::try_from(i32::from_be_bytes(len)).map_err(|_| {
+            thrift::Error::Protocol(ProtocolError::new(
+                ProtocolErrorKind::NegativeSize,
+                "negative length",
+            ))
+        })
+    }
+}
+
+/// Wrapper around one of Thrift's regular protocols, that reads from a byte slice.
+pub struct TSliceInputProtocol<'a, P> {
+    inner: P,
+    transport: SliceTransport<'a>,
+}
+
+/// Compact protocol, reading from a byte slice.
+pub type TCompactSliceInputProtocol<'a> =
+    TSliceInputProtocol<'a, TCompactInputProtocol<SliceTransport<'a>>>;
+
+/// Binary protocol in strict mode, reading from a byte slice.
+pub type TBinarySliceInputProtocol<'a> =
+    TSliceInputProtocol<'a, TBinaryInputProtocol<SliceTransport<'a>>>;
+
+impl<'a, P: SliceProtocol<'a>> TSliceInputProtocol<'a, P> {
+    /// Create a new protocol, reading from the start of the input.
+    #[must_use]
+    pub fn new(input: &'a [u8]) -> Self {
+        let transport = SliceTransport::new(input);
+
+        Self {
+            inner: P::new(transport.clone()),
+            transport,
+        }
+    }
+}
+
+impl<'a, P: SliceProtocol<'a>> TBorrowedInputProtocol<'a> for TSliceInputProtocol<'a, P> {
+    fn read_str(&mut self) -> thrift::Result<&'a str> {
+        std::str::from_utf8(self.read_slice()?).map_err(|e| {
+            thrift::Error::Protocol(ProtocolError::new(
+                ProtocolErrorKind::InvalidData,
+                e.to_string(),
+            ))
+        })
+    }
+
+    fn read_slice(&mut self) -> thrift::Result<&'a [u8]> {
+        let len = P::read_len(&self.transport)?;
+        self.transport.take_slice(len)
+    }
+}
+
+/// Forward the methods of the input protocol to the inner protocol.
+macro_rules! delegate {
+    ($($name:ident -> $ty:ty),* $(,)?) => {
+        $(
+            fn $name(&mut self) -> thrift::Result<$ty> {
+                self.inner.$name()
+            }
+        )*
+    };
+}
+
+impl<'a, P: SliceProtocol<'a>> TInputProtocol for TSliceInputProtocol<'a, P> {
+    delegate! {
+        read_message_begin -> TMessageIdentifier,
+        read_message_end -> (),
+        read_struct_begin -> Option<TStructIdentifier>,
+        read_struct_end -> (),
+        read_field_begin -> TFieldIdentifier,
+        read_field_end -> (),
+        read_bool -> bool,
+        read_bytes -> Vec<u8>,
+        read_i8 -> i8,
+        read_i16 -> i16,
+        read_i32 -> i32,
+        read_i64 -> i64,
+        read_double -> f64,
+        read_string -> String,
+        read_list_begin -> TListIdentifier,
+        read_list_end -> (),
+        read_set_begin -> TSetIdentifier,
+        read_set_end -> (),
+        read_map_begin -> TMapIdentifier,
+        read_map_end -> (),
+        read_byte -> u8,
+    }
+}
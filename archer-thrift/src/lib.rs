@@ -3,12 +3,21 @@
 #![allow(unused_extern_crates)]
 #![allow(clippy::too_many_arguments, clippy::type_complexity, clippy::vec_box)]
 
+mod borrowed;
 mod models;
 
-pub use models::{agent, jaeger};
+pub use borrowed::{
+    SliceProtocol, SliceTransport, TBinarySliceInputProtocol, TBorrowedInputProtocol,
+    TCompactSliceInputProtocol, TSliceInputProtocol,
+};
+pub use models::{agent, jaeger, zipkincore};
 pub use thrift;
 use thrift::protocol::TInputProtocol;
 
 trait ThriftDeserialize: Sized {
     fn read(prot: &mut impl TInputProtocol) -> thrift::Result<Self>;
 }
+
+trait ThriftDeserializeBorrowed<'a>: Sized {
+    fn read(prot: &mut impl TBorrowedInputProtocol<'a>) -> thrift::Result<Self>;
+}
@@ -1,15 +1,16 @@
 pub mod agent {
     use archer_thrift_derive::ThriftDeserialize;
-    use thrift::{
-        protocol::{TInputProtocol, TOutputProtocol},
-        ApplicationError, ApplicationErrorKind,
-    };
+    use thrift::{protocol::TInputProtocol, ApplicationError, ApplicationErrorKind};
 
-    use super::jaeger::Batch;
-    use crate::ThriftDeserialize;
+    use super::{
+        jaeger::{read_list, Batch},
+        zipkincore::Span as ZipkinSpan,
+    };
+    use crate::{TBorrowedInputProtocol, ThriftDeserialize, ThriftDeserializeBorrowed};
 
     pub trait AgentSyncHandler {
-        fn handle_emit_batch(&self, batch: Batch) -> thrift::Result<()>;
+        fn handle_emit_zipkin_batch(&self, spans: Vec<ZipkinSpan>) -> thrift::Result<()>;
+        fn handle_emit_batch(&self, batch: Batch<'_>) -> thrift::Result<()>;
     }
 
     pub struct AgentSyncProcessor<T>(T);
@@ -19,43 +20,73 @@ pub mod agent {
             Self(handler)
         }
 
-        pub fn process(
+        /// Process a single message. All methods of the agent are one-way calls, so no response
+        /// is ever written, and errors are returned to the caller instead.
+        pub fn process<'a>(
             &self,
-            input: &mut impl TInputProtocol,
-            output: &mut impl TOutputProtocol,
+            input: &mut impl TBorrowedInputProtocol<'a>,
         ) -> thrift::Result<()> {
             let ident = input.read_message_begin()?;
-            let result = match ident.name.as_str() {
+            match ident.name.as_str() {
+                "emitZipkinBatch" => self.process_emit_zipkin_batch(input),
                 "emitBatch" => self.process_emit_batch(input),
                 method => Err(thrift::Error::Application(ApplicationError::new(
                     ApplicationErrorKind::UnknownMethod,
                     format!("unknown method {method}"),
                 ))),
-            };
+            }
+        }
+
+        fn process_emit_zipkin_batch(&self, input: &mut impl TInputProtocol) -> thrift::Result<()> {
+            let args = AgentEmitZipkinBatchArgs::read(input)?;
 
-            thrift::server::handle_process_result(&ident, result, output)
+            self.0
+                .handle_emit_zipkin_batch(args.spans)
+                .map_err(into_application_error)
         }
 
-        fn process_emit_batch(&self, input: &mut impl TInputProtocol) -> thrift::Result<()> {
-            let args = AgentEmitBatchArgs::read(input)?;
+        fn process_emit_batch<'a>(
+            &self,
+            input: &mut impl TBorrowedInputProtocol<'a>,
+        ) -> thrift::Result<()> {
+            let args = <AgentEmitBatchArgs<'a> as ThriftDeserializeBorrowed<'a>>::read(input)?;
 
-            self.0.handle_emit_batch(args.batch).map_err(|e| match e {
-                thrift::Error::Application(err) => thrift::Error::Application(err),
-                _ => thrift::Error::Application(ApplicationError::new(
-                    ApplicationErrorKind::Unknown,
-                    e.to_string(),
-                )),
-            })
+            self.0
+                .handle_emit_batch(args.batch)
+                .map_err(into_application_error)
+        }
+    }
+
+    /// Wrap failures of the handler, but keep its own errors as they are, so the caller can tell
+    /// them apart.
+    fn into_application_error(e: thrift::Error) -> thrift::Error {
+        match e {
+            thrift::Error::Application(_) | thrift::Error::User(_) => e,
+            _ => thrift::Error::Application(ApplicationError::new(
+                ApplicationErrorKind::Unknown,
+                e.to_string(),
+            )),
         }
     }
 
     #[derive(Default, ThriftDeserialize)]
-    struct AgentEmitBatchArgs {
-        batch: Batch,
+    struct AgentEmitZipkinBatchArgs {
+        spans: Vec<ZipkinSpan>,
+    }
+
+    #[derive(Default, ThriftDeserialize)]
+    #[thrift(borrowed)]
+    struct AgentEmitBatchArgs<'a> {
+        batch: Batch<'a>,
     }
 }
 
+/// Types of the Jaeger protocol. The ones that make up the spans contain strings and binary data
+/// as [`Cow`], so they can borrow them from the input when it's available as a whole, like in
+/// the agent.
 pub mod jaeger {
+    use std::borrow::Cow;
+
     use archer_thrift_derive::ThriftDeserialize;
     use thrift::{
         protocol::{self, TInputProtocol, TType},
@@ -63,7 +94,7 @@ pub mod jaeger {
         ProtocolError, ProtocolErrorKind,
     };
 
-    use crate::ThriftDeserialize;
+    use crate::{TBorrowedInputProtocol, ThriftDeserialize, ThriftDeserializeBorrowed};
 
     #[derive(Clone, Copy, Debug, Default, ThriftDeserialize)]
     pub enum TagType {
@@ -76,20 +107,22 @@ pub mod jaeger {
     }
 
     #[derive(Clone, Debug, Default, ThriftDeserialize)]
-    pub struct Tag {
-        pub key: String,
+    #[thrift(borrowed)]
+    pub struct Tag<'a> {
+        pub key: Cow<'a, str>,
         pub v_type: TagType,
-        pub v_str: Option<String>,
+        pub v_str: Option<Cow<'a, str>>,
         pub v_double: Option<f64>,
         pub v_bool: Option<bool>,
         pub v_long: Option<i64>,
-        pub v_binary: Option<Vec<u8>>,
+        pub v_binary: Option<Cow<'a, [u8]>>,
     }
 
     #[derive(Clone, Debug, Default, ThriftDeserialize)]
-    pub struct Log {
+    #[thrift(borrowed)]
+    pub struct Log<'a> {
         pub timestamp: i64,
-        pub fields: Vec<Tag>,
+        pub fields: Vec<Tag<'a>>,
     }
 
     #[derive(Clone, Copy, Debug, Default, ThriftDeserialize)]
@@ -108,24 +141,26 @@ pub mod jaeger {
     }
 
     #[derive(Clone, Debug, Default, ThriftDeserialize)]
-    pub struct Span {
+    #[thrift(borrowed)]
+    pub struct Span<'a> {
         pub trace_id_low: i64,
         pub trace_id_high: i64,
         pub span_id: i64,
         pub parent_span_id: i64,
-        pub operation_name: String,
+        pub operation_name: Cow<'a, str>,
         pub references: Option<Vec<SpanRef>>,
         pub flags: i32,
         pub start_time: i64,
         pub duration: i64,
-        pub tags: Option<Vec<Tag>>,
-        pub logs: Option<Vec<Log>>,
+        pub tags: Option<Vec<Tag<'a>>>,
+        pub logs: Option<Vec<Log<'a>>>,
     }
 
     #[derive(Clone, Debug, Default, ThriftDeserialize)]
-    pub struct Process {
-        pub service_name: String,
-        pub tags: Option<Vec<Tag>>,
+    #[thrift(borrowed)]
+    pub struct Process<'a> {
+        pub service_name: Cow<'a, str>,
+        pub tags: Option<Vec<Tag<'a>>>,
     }
 
     #[derive(Clone, Debug, Default, ThriftDeserialize)]
@@ -136,9 +171,10 @@ pub mod jaeger {
     }
 
     #[derive(Clone, Debug, Default, ThriftDeserialize)]
-    pub struct Batch {
-        pub process: Process,
-        pub spans: Vec<Span>,
+    #[thrift(borrowed)]
+    pub struct Batch<'a> {
+        pub process: Process<'a>,
+        pub spans: Vec<Span<'a>>,
         pub seq_no: Option<i64>,
         pub stats: Option<ClientStats>,
     }
@@ -148,7 +184,7 @@ pub mod jaeger {
     }
 
     pub trait Collector {
-        fn submit_batches(batches: Vec<Batch>) -> Vec<BatchSubmitResponse>;
+        fn submit_batches(batches: Vec<Batch<'_>>) -> Vec<BatchSubmitResponse>;
     }
 
     pub(crate) fn verify_read(field: &str, read: bool) -> thrift::Result<()> {
@@ -173,7 +209,110 @@ pub mod jaeger {
         Ok(fields)
     }
 
-    pub fn read_batch(prot: &mut impl TInputProtocol) -> thrift::Result<Batch> {
-        Batch::read(prot)
+    pub(crate) fn read_list_borrowed<'a, T: ThriftDeserializeBorrowed<'a>>(
+        prot: &mut impl TBorrowedInputProtocol<'a>,
+    ) -> thrift::Result<Vec<T>> {
+        let ident = prot.read_list_begin()?;
+        let fields = (0..ident.size)
+            .map(|_| T::read(prot))
+            .collect::<thrift::Result<_>>()?;
+
+        prot.read_list_end()?;
+        Ok(fields)
+    }
+
+    /// Read a batch, copying all strings and binary data.
+    pub fn read_batch(prot: &mut impl TInputProtocol) -> thrift::Result<Batch<'static>> {
+        <Batch<'static> as ThriftDeserialize>::read(prot)
+    }
+
+    /// Read a batch, borrowing all strings and binary data from the input. Together with the slice
+    /// protocols, a buffered message is decoded without any blocking reads or copies.
+    pub fn read_batch_borrowed<'a>(
+        prot: &mut impl TBorrowedInputProtocol<'a>,
+    ) -> thrift::Result<Batch<'a>> {
+        <Batch<'a> as ThriftDeserializeBorrowed<'a>>::read(prot)
+    }
+}
+
+pub mod zipkincore {
+    use archer_thrift_derive::ThriftDeserialize;
+    use thrift::protocol::TInputProtocol;
+
+    use super::jaeger::read_list;
+    use crate::ThriftDeserialize;
+
+    /// The client sent ("cs") a request to a server. There is only one send per span.
+    pub const CLIENT_SEND: &str = "cs";
+    /// The client received ("cr") a response from a server. There is only one receive per span.
+    pub const CLIENT_RECV: &str = "cr";
+    /// The server sent ("ss") a response to a client. There is only one response per span.
+    pub const SERVER_SEND: &str = "ss";
+    /// The server received ("sr") a request from a client. There is only one request per span.
+    pub const SERVER_RECV: &str = "sr";
+    /// Indicates a client address ("ca") in a span, usually a boolean binary annotation.
+    pub const CLIENT_ADDR: &str = "ca";
+    /// Indicates a server address ("sa") in a span, usually a boolean binary annotation.
+    pub const SERVER_ADDR: &str = "sa";
+    /// The local component ("lc") that recorded a span without any remote communication.
+    pub const LOCAL_COMPONENT: &str = "lc";
+
+    #[derive(Clone, Debug, Default, ThriftDeserialize)]
+    pub struct Endpoint {
+        pub ipv4: i32,
+        pub port: i16,
+        pub service_name: String,
+        pub ipv6: Option<Vec<u8>>,
+    }
+
+    #[derive(Clone, Debug, Default, ThriftDeserialize)]
+    pub struct Annotation {
+        pub timestamp: i64,
+        pub value: String,
+        pub host: Option<Endpoint>,
+    }
+
+    #[derive(Clone, Copy, Debug, Default, ThriftDeserialize)]
+    pub enum AnnotationType {
+        #[default]
+        Bool,
+        Bytes,
+        I16,
+        I32,
+        I64,
+        Double,
+        String,
+    }
+
+    #[derive(Clone, Debug, Default, ThriftDeserialize)]
+    pub struct BinaryAnnotation {
+        pub key: String,
+        pub value: Vec<u8>,
+        pub annotation_type: AnnotationType,
+        pub host: Option<Endpoint>,
+    }
+
+    #[derive(Clone, Debug, Default, ThriftDeserialize)]
+    pub struct Span {
+        #[thrift(id = 1)]
+        pub trace_id: i64,
+        #[thrift(id = 3)]
+        pub name: String,
+        #[thrift(id = 4)]
+        pub id: i64,
+        #[thrift(id = 5)]
+        pub parent_id: Option<i64>,
+        #[thrift(id = 6)]
+        pub annotations: Option<Vec<Annotation>>,
+        #[thrift(id = 8)]
+        pub binary_annotations: Option<Vec<BinaryAnnotation>>,
+        #[thrift(id = 9)]
+        pub debug: Option<bool>,
+        #[thrift(id = 10)]
+        pub timestamp: Option<i64>,
+        #[thrift(id = 11)]
+        pub duration: Option<i64>,
+        #[thrift(id = 12)]
+        pub trace_id_high: Option<i64>,
     }
 }
@@ -363,7 +363,11 @@ export class SearchFormImpl extends React.PureComponent {
         </FormItem>
 
         <FormItem label="Lookback">
-          <Field name="lookback" component={AdaptedSelect} props={{ disabled, defaultValue: '1h' }}>
+          <Field
+            name="lookback"
+            component={AdaptedSelect}
+            props={{ disabled, defaultValue: getConfigValue('search.defaultLookback') || DEFAULT_LOOKBACK }}
+          >
             {optionsWithinMaxLookback(searchMaxLookback)}
             <Option value="custom">Custom Time Range</Option>
           </Field>
@@ -620,7 +624,7 @@ export function mapStateToProps(state) {
     initialValues: {
       service: service || lastSearchService || '-',
       resultsLimit: limit || DEFAULT_LIMIT,
-      lookback: lookback || DEFAULT_LOOKBACK,
+      lookback: lookback || getConfigValue('search.defaultLookback') || DEFAULT_LOOKBACK,
       startDate: queryStartDate || today,
       startDateTime: queryStartDateTime || '00:00',
       endDate: queryEndDate || today,
@@ -96,7 +96,7 @@ export default deepFreeze(
     },
     // fields that should be individually merged vs wholesale replaced
     '__mergeFields',
-    { value: ['dependencies', 'search', 'tracking'] }
+    { value: ['dependencies', 'monitor', 'search', 'tracking'] }
   )
 );
 
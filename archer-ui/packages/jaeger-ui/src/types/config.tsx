@@ -111,6 +111,9 @@ export type Config = {
     // maxLimit configures the "search depth" parameter.
     // The interpretation of search depth varies between different backends.
     maxLimit: number;
+    // defaultLookback is the lookback that is selected when the search form is opened,
+    // e.g. "6h", instead of the last hour.
+    defaultLookback?: string;
   };
 
   // scripts is an array of URLs of additional JavaScript files to be loaded.
@@ -1,14 +1,21 @@
 use std::{
     hash::Hash,
+    io::Write,
     path::{Path, PathBuf},
+    time::UNIX_EPOCH,
 };
 
-use quote::quote;
+use flate2::{write::GzEncoder, Compression};
+use quote::{quote, ToTokens};
 use regex::{Captures, Regex};
 use siphasher::sip128::{Hasher128, SipHasher13};
 use walkdir::{DirEntry, WalkDir};
 
 fn main() {
+    if std::env::var_os("CARGO_FEATURE_EMBED_UI").is_none() {
+        return;
+    }
+
     let git = Regex::new(r"https://github.com/jaegertracing/jaeger-ui").unwrap();
     let jaeger = Regex::new(r"(?i)jaeger").unwrap();
     let sourcemap = Regex::new(r"\n/(\*|/)# sourceMappingURL=.+\.map( \*/)?").unwrap();
@@ -31,7 +38,10 @@ fn main() {
 
             println!("cargo:rerun-if-changed={}", entry.path().display());
 
-            let (content, etag) = if is_textish(&entry) {
+            let path = out_assets.join(entry.path().strip_prefix(&root).unwrap());
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+
+            let (content, buf) = if is_textish(&entry) {
                 let buf = std::fs::read_to_string(entry.path()).unwrap();
                 let buf = git.replace_all(&buf, "https://github.com/dnaka91/archer");
                 let buf = jaeger.replace_all(&buf, |caps: &Captures| {
@@ -45,18 +55,34 @@ fn main() {
                 });
                 let buf = sourcemap.replace_all(&buf, "");
 
-                let path = out_assets.join(entry.path().strip_prefix(&root).unwrap());
-
-                std::fs::create_dir_all(path.parent().unwrap()).unwrap();
                 std::fs::write(&path, buf.as_bytes()).unwrap();
 
                 (
                     path.to_str().unwrap().to_owned(),
-                    create_etag(buf.as_bytes()),
+                    buf.into_owned().into_bytes(),
                 )
             } else {
                 let buf = std::fs::read(entry.path()).unwrap();
-                (entry.path().to_str().unwrap().to_owned(), create_etag(&buf))
+                (entry.path().to_str().unwrap().to_owned(), buf)
+            };
+
+            let etag = create_etag(&buf);
+            let modified = entry
+                .metadata()
+                .unwrap()
+                .modified()
+                .unwrap()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs();
+
+            let (gzip, brotli) = if is_compressible(&entry) {
+                (
+                    encoded(&path, "gz", &buf, gzip(&buf)).into_token_stream(),
+                    encoded(&path, "br", &buf, brotli(&buf)).into_token_stream(),
+                )
+            } else {
+                (quote! { None }, quote! { None })
             };
 
             let route = format!(
@@ -72,6 +98,9 @@ fn main() {
                     content: include_bytes!(#content),
                     etag: #etag,
                     mime: #mime,
+                    modified: #modified,
+                    gzip: #gzip,
+                    brotli: #brotli,
                 }
             };
 
@@ -84,8 +113,18 @@ fn main() {
             pub content: &'static [u8],
             pub etag: &'static str,
             pub mime: &'static str,
+            /// Modification time of the source file, in seconds since the Unix epoch.
+            pub modified: u64,
+            pub gzip: Option<Encoded>,
+            pub brotli: Option<Encoded>,
         }
 
+        pub struct Encoded {
+            pub content: &'static [u8],
+            pub etag: &'static str,
+        }
+
+        #[allow(clippy::unreadable_literal)]
         static ASSETS: phf::Map<&'static str, Asset> = ::phf::phf_map! { #(#entries),* };
     };
 
@@ -110,6 +149,16 @@ fn is_textish(entry: &DirEntry) -> bool {
     entry.file_type().is_file() && ["js", "css", "html"].contains(&ext)
 }
 
+fn is_compressible(entry: &DirEntry) -> bool {
+    let ext = entry
+        .path()
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or_default();
+
+    is_textish(entry) || ["eot", "ico", "json", "svg", "ttf", "txt"].contains(&ext)
+}
+
 fn is_ignored(entry: &DirEntry, root: &Path) -> bool {
     let ext = entry
         .path()
@@ -127,3 +176,39 @@ fn create_etag(data: &[u8]) -> String {
 
     format!("\"{:032x}\"", hasher.finish128().as_u128())
 }
+
+fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+fn brotli(data: &[u8]) -> Vec<u8> {
+    let mut encoder = brotli::CompressorWriter::new(Vec::new(), 4096, 11, 22);
+    encoder.write_all(data).unwrap();
+    encoder.into_inner()
+}
+
+/// Write the compressed variant of an asset next to it, but only if it's actually smaller than the
+/// original.
+fn encoded(path: &Path, ext: &str, original: &[u8], compressed: Vec<u8>) -> impl ToTokens {
+    if compressed.len() >= original.len() {
+        return quote! { None };
+    }
+
+    let mut path = path.as_os_str().to_owned();
+    path.push(".");
+    path.push(ext);
+
+    std::fs::write(&path, &compressed).unwrap();
+
+    let path = path.to_str().unwrap();
+    let etag = create_etag(&compressed);
+
+    quote! {
+        Some(Encoded {
+            content: include_bytes!(#path),
+            etag: #etag,
+        })
+    }
+}
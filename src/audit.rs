@@ -0,0 +1,150 @@
+//! Audit log of all received span batches, that records where each batch came from and what
+//! happened to it. It helps to find clients that flood the collectors, and is written as JSON lines
+//! to be easily processed with common tools.
+
+use std::{collections::BTreeSet, net::SocketAddr};
+
+use anyhow::{anyhow, Context, Result};
+use once_cell::sync::OnceCell;
+use serde::Serialize;
+use time::OffsetDateTime;
+use tokio::{
+    fs::OpenOptions,
+    io::{AsyncWriteExt, BufWriter},
+    sync::mpsc::{self, error::TrySendError},
+};
+use tokio_shutdown::Shutdown;
+use tracing::{error, info, instrument, warn};
+
+use crate::{config, metrics::Receiver, models::Span, ratelimit};
+
+static SENDER: OnceCell<mpsc::Sender<Entry>> = OnceCell::new();
+
+/// Where a batch came from.
+#[derive(Clone, Copy)]
+pub struct Origin {
+    pub receiver: Receiver,
+    /// Address of the client, if the receiver knows it.
+    pub peer: Option<SocketAddr>,
+    /// Size of the uncompressed payload, if the receiver knows it.
+    pub bytes: Option<usize>,
+}
+
+/// What happened to a received batch.
+#[derive(Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    /// Spans were accepted and queued for storage.
+    Accepted,
+    /// Spans were dropped, because a service exceeded its rate limit.
+    RateLimited,
+    /// Spans of some services were dropped, because they exceeded their rate limit. The spans of
+    /// the remaining services were accepted, and only those are listed.
+    PartiallyRateLimited,
+    /// Spans couldn't be converted and were dropped.
+    Invalid,
+}
+
+impl Outcome {
+    /// Outcome of a batch, that passed or failed the rate limit, with the amount of dropped spans.
+    pub fn rate_limit(result: &Result<usize, ratelimit::Exhausted>) -> Self {
+        match result {
+            Ok(0) => Self::Accepted,
+            Ok(_) => Self::PartiallyRateLimited,
+            Err(_) => Self::RateLimited,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Entry {
+    #[serde(with = "time::serde::rfc3339")]
+    timestamp: OffsetDateTime,
+    receiver: &'static str,
+    peer: Option<SocketAddr>,
+    services: BTreeSet<String>,
+    spans: usize,
+    bytes: Option<usize>,
+    outcome: Outcome,
+}
+
+/// Record a received batch of `count` spans. The `spans` are only used to list the services,
+/// and may be empty if they couldn't be converted. This is a no-op if the audit log isn't enabled.
+pub fn record(origin: Origin, count: usize, spans: &[Span], outcome: Outcome) {
+    let Some(sender) = SENDER.get() else {
+        return;
+    };
+
+    let entry = Entry {
+        timestamp: OffsetDateTime::now_utc(),
+        receiver: origin.receiver.as_str(),
+        peer: origin.peer,
+        services: spans
+            .iter()
+            .map(|span| span.process.service.clone())
+            .collect(),
+        spans: count,
+        bytes: origin.bytes,
+        outcome,
+    };
+
+    if let Err(TrySendError::Full(_)) = sender.try_send(entry) {
+        warn!("audit queue is full, dropping entry");
+    }
+}
+
+#[instrument(name = "audit", skip_all)]
+pub async fn run(shutdown: Shutdown, config: Option<config::Audit>) -> Result<()> {
+    let Some(config) = config else {
+        return Ok(());
+    };
+
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&config.path)
+        .await
+        .with_context(|| format!("failed opening audit log {}", config.path.display()))?;
+    let mut file = BufWriter::new(file);
+
+    let (tx, mut rx) = mpsc::channel(config.queue_size);
+    SENDER
+        .set(tx)
+        .map_err(|_| anyhow!("audit log can only be started once"))?;
+
+    info!(path = %config.path.display(), "writing audit log");
+
+    loop {
+        let entry = tokio::select! {
+            () = shutdown.handle() => break,
+            entry = rx.recv() => match entry {
+                Some(entry) => entry,
+                None => break,
+            },
+        };
+
+        if let Err(e) = write(&mut file, &entry).await {
+            error!(error = ?e, "failed writing audit log");
+        }
+
+        // Flush once the queue is drained, to combine writes of bursts.
+        if rx.is_empty() {
+            if let Err(e) = file.flush().await {
+                error!(error = ?e, "failed writing audit log");
+            }
+        }
+    }
+
+    file.flush().await?;
+
+    info!("audit log stopped");
+
+    Ok(())
+}
+
+async fn write(file: &mut BufWriter<tokio::fs::File>, entry: &Entry) -> Result<()> {
+    let mut line = serde_json::to_vec(entry)?;
+    line.push(b'\n');
+
+    file.write_all(&line).await.map_err(Into::into)
+}
@@ -0,0 +1,96 @@
+//! Command line arguments. Everything else is configured through the configuration file, so these
+//! only select what Archer does when started.
+
+use anyhow::{bail, Context, Result};
+
+pub enum Command {
+    /// Run the server in the given role.
+    Run(Role),
+    /// Check the installation, see [`crate::doctor`].
+    Doctor,
+}
+
+/// Parts of Archer that are run, so ingestion and querying can be scaled and secured separately,
+/// while using the same storage.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Role {
+    /// Collectors and the query API together, the default.
+    All,
+    /// Only the collectors, that store received spans.
+    Collector,
+    /// Only the query API, that reads an existing database without ever modifying it.
+    Query,
+}
+
+impl Role {
+    fn parse(value: &str) -> Result<Self> {
+        Ok(match value {
+            "all" => Self::All,
+            "collector" => Self::Collector,
+            "query" => Self::Query,
+            _ => bail!("unknown role `{value}`, expected `all`, `collector` or `query`"),
+        })
+    }
+}
+
+/// Parse the arguments, without the leading program name.
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Command> {
+    let mut args = args.into_iter();
+    let mut role = Role::All;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "doctor" => return Ok(Command::Doctor),
+            // Shorthand for `--role query`.
+            "--read-only" => role = Role::Query,
+            "--role" => role = Role::parse(&args.next().context("`--role` requires a value")?)?,
+            _ => match arg.strip_prefix("--role=") {
+                Some(value) => role = Role::parse(value)?,
+                None => bail!(
+                    "unknown argument `{arg}`, expected `doctor`, `--role <ROLE>` or `--read-only`"
+                ),
+            },
+        }
+    }
+
+    Ok(Command::Run(role))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_args(args: &[&str]) -> Result<Command> {
+        parse(args.iter().map(ToString::to_string))
+    }
+
+    #[test]
+    fn parse_role() {
+        assert!(matches!(parse_args(&[]), Ok(Command::Run(Role::All))));
+        assert!(matches!(
+            parse_args(&["--role=collector"]),
+            Ok(Command::Run(Role::Collector))
+        ));
+        assert!(matches!(
+            parse_args(&["--role", "query"]),
+            Ok(Command::Run(Role::Query))
+        ));
+        assert!(matches!(
+            parse_args(&["--read-only"]),
+            Ok(Command::Run(Role::Query))
+        ));
+    }
+
+    #[test]
+    fn parse_doctor() {
+        assert!(matches!(parse_args(&["doctor"]), Ok(Command::Doctor)));
+    }
+
+    #[test]
+    fn reject_invalid() {
+        assert!(parse_args(&["--role"]).is_err());
+        assert!(parse_args(&["--role=reader"]).is_err());
+        assert!(parse_args(&["--verbose"]).is_err());
+        assert!(parse_args(&["serve"]).is_err());
+    }
+}
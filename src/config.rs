@@ -0,0 +1,782 @@
+//! Configuration of Archer, loaded from an optional TOML file. The file is searched for in the
+//! user's config directory, but a different location can be given through the `ARCHER_CONFIG`
+//! environment variable. Likewise, `ARCHER_DATA_DIR` overrides the data directory.
+
+use std::{
+    collections::{HashMap, HashSet},
+    env, fmt,
+    io::ErrorKind,
+    net::SocketAddr,
+    num::NonZeroUsize,
+    path::PathBuf,
+    thread,
+};
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use time::Time;
+use unidirs::{Directories, UnifiedDirs};
+
+/// Placeholder for secrets, so they don't show up when printing the configuration.
+pub const REDACTED: &str = "<redacted>";
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Bind addresses of all receivers and servers, which also allow to disable them.
+    pub listen: Listen,
+    /// Settings for forwarding all received spans to another collector. Forwarding is disabled if
+    /// this section is missing.
+    pub forwarder: Option<Forwarder>,
+    /// Settings for the Quiver collector.
+    pub quiver: Quiver,
+    /// Settings for the Jaeger query server, that serves the API and web UI.
+    pub query: Query,
+    /// Certificate and key to serve the Jaeger collector, OTLP and query HTTP endpoints over TLS.
+    /// These endpoints use plain HTTP if this section is missing.
+    pub tls: Option<Tls>,
+    /// Limits for the amount of spans, that each service may send. Spans are accepted without
+    /// limit if this section is missing.
+    pub rate_limit: Option<RateLimit>,
+    /// Settings for the Jaeger agent.
+    pub agent: Agent,
+    /// Settings for the Jaeger collector.
+    pub collector: Collector,
+    /// Settings for the span storage.
+    pub storage: Storage,
+    /// Separation of data by tenant.
+    pub tenancy: Tenancy,
+    /// Limits for the size of received spans.
+    pub limits: Limits,
+    /// Rules to normalize received spans, before they're stored.
+    pub processor: Processor,
+    /// Log of every received batch, to find out which clients send how much. The log is disabled
+    /// if this section is missing.
+    pub audit: Option<Audit>,
+    /// Tracing of Archer's own query API, which stores the spans in its own database.
+    pub self_tracing: SelfTracing,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Forwarder {
+    /// Address of the upstream OTLP gRPC endpoint, for example `http://collector:4317`.
+    pub endpoint: String,
+    /// Maximum amount of span batches that are buffered while waiting to be sent. Any further
+    /// batches are dropped until the buffer has space again.
+    #[serde(default = "default_queue_size")]
+    pub queue_size: usize,
+    /// Amount of retries for a failed export, before the batch is dropped.
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Audit {
+    /// File that entries are appended to, as one JSON object per line.
+    pub path: PathBuf,
+    /// Maximum amount of entries that are buffered while waiting to be written. Any further
+    /// entries are dropped until the buffer has space again.
+    #[serde(default = "default_queue_size")]
+    pub queue_size: usize,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Quiver {
+    /// Pre-shared token, that clients must send in their handshake. Any client is accepted if
+    /// this is not set.
+    pub auth_token: Option<String>,
+    /// Path to a PEM file with CA certificates. If set, clients must present a certificate that
+    /// is signed by one of them.
+    pub client_ca: Option<PathBuf>,
+    /// Certificate and key of the server, for example provisioned by an ACME client like
+    /// `certbot`. If missing, a self-signed certificate is generated and stored in the data
+    /// directory.
+    pub tls: Option<Tls>,
+    /// DNS names and IP addresses, that the generated certificate is valid for. Defaults to
+    /// `localhost` and `archer`. Only applies when a new certificate is generated, so an already
+    /// stored one must be deleted for changes to take effect.
+    pub subject_alt_names: Vec<String>,
+    /// Maximum size of a single batch of spans as it's sent by the client, in bytes.
+    pub max_request_size: usize,
+    /// Maximum size of a single batch of spans after decompressing it, in bytes. This protects
+    /// against small payloads that expand to huge amounts of data.
+    pub max_decompressed_size: usize,
+    /// Directory, where a generated certificate is stored. Not part of the file, see
+    /// [`data_dir`].
+    #[serde(skip)]
+    pub data_dir: PathBuf,
+}
+
+impl Default for Quiver {
+    fn default() -> Self {
+        Self {
+            auth_token: None,
+            client_ca: None,
+            tls: None,
+            subject_alt_names: Vec::new(),
+            max_request_size: 4 * 1024 * 1024,
+            max_decompressed_size: 16 * 1024 * 1024,
+            data_dir: PathBuf::new(),
+        }
+    }
+}
+
+impl fmt::Debug for Quiver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Quiver")
+            .field("auth_token", &self.auth_token.as_ref().map(|_| REDACTED))
+            .field("client_ca", &self.client_ca)
+            .field("tls", &self.tls)
+            .field("subject_alt_names", &self.subject_alt_names)
+            .field("max_request_size", &self.max_request_size)
+            .field("max_decompressed_size", &self.max_decompressed_size)
+            .field("data_dir", &self.data_dir)
+            .finish()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SelfTracing {
+    /// Whether to trace the query API at all.
+    pub enabled: bool,
+    /// Services, for which queries aren't traced. Otherwise, looking at Archer's own traces
+    /// creates new traces on every refresh, feeding back into itself.
+    pub skip_services: HashSet<String>,
+}
+
+impl Default for SelfTracing {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            skip_services: [env!("CARGO_PKG_NAME").to_owned()].into(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Query {
+    /// Authentication, that is required for all requests to the query server. The server is open
+    /// to anyone if this section is missing.
+    pub auth: Option<QueryAuth>,
+    /// Directory with a build of the UI, that is served instead of the embedded one. Required to
+    /// have a UI at all, if archer is built without the `embed-ui` feature.
+    pub ui: Option<PathBuf>,
+    /// Adjustment of spans from services with skewed clocks, so child spans fit into their
+    /// parents. Spans are returned as stored if this section is missing.
+    pub clock_skew: Option<ClockSkew>,
+    /// Cross-origin requests from browsers, for frontends like Grafana that run on another
+    /// origin. These are blocked by browsers if this section is missing.
+    pub cors: Option<Cors>,
+    /// Limits of concurrent requests and their duration, so expensive queries can't overload the
+    /// storage.
+    pub limits: QueryLimits,
+    /// Cache of recently viewed traces, so repeated views of the same trace don't hit the storage
+    /// again. Disabled if this section is missing.
+    pub cache: Option<QueryCache>,
+    /// Features of the UI, that are passed to it as its configuration.
+    pub ui_config: UiConfig,
+    /// URL prefix that the UI and API are served under, like `/tracing`, to run behind a reverse
+    /// proxy that routes by path. Served at the root if missing.
+    pub base_path: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UiConfig {
+    /// Show the button to archive traces.
+    pub archive: bool,
+    /// Show the tab with the dependency graph of all services.
+    pub dependencies: bool,
+    /// Show the tab with the service performance monitoring.
+    pub monitor: bool,
+    /// Lookback that the search form starts with, like `1h` or `2d`. The UI's own default is used
+    /// if missing.
+    pub default_lookback: Option<String>,
+}
+
+impl Default for UiConfig {
+    fn default() -> Self {
+        Self {
+            archive: true,
+            dependencies: true,
+            monitor: false,
+            default_lookback: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QueryCache {
+    /// Maximum amount of cached traces. The least recently used ones are evicted first.
+    pub capacity: usize,
+    /// Time in seconds, that a trace is cached. Spans that are received for the trace in the
+    /// meantime, only show up after it expired.
+    pub ttl_seconds: u64,
+}
+
+impl Default for QueryCache {
+    fn default() -> Self {
+        Self {
+            capacity: 100,
+            ttl_seconds: 10,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QueryLimits {
+    /// Maximum amount of requests, that are processed at the same time over all routes. Further
+    /// requests are rejected with `503 Service Unavailable`. Unlimited if missing.
+    pub max_concurrency: Option<usize>,
+    /// Time in seconds, after which a request is aborted with `503 Service Unavailable`.
+    /// Unlimited if missing.
+    pub timeout_seconds: Option<u64>,
+    /// Limits of single routes, like `/api/traces`, that apply in addition to the global
+    /// concurrency limit. The timeout of a route replaces the global one. Routes are given without
+    /// the base path.
+    pub routes: HashMap<String, RouteLimits>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RouteLimits {
+    pub max_concurrency: Option<usize>,
+    pub timeout_seconds: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Cors {
+    /// Origins that may access the API, like `https://grafana.example.com`. A single `*` allows
+    /// any origin.
+    pub allowed_origins: Vec<String>,
+    /// HTTP methods, that may be used for requests.
+    pub allowed_methods: Vec<String>,
+    /// Request headers, that may be sent in addition to the ones that are always allowed.
+    pub allowed_headers: Vec<String>,
+    /// Time in seconds, that browsers may cache the result of a preflight request.
+    pub max_age_seconds: u64,
+}
+
+impl Default for Cors {
+    fn default() -> Self {
+        Self {
+            allowed_origins: vec!["*".to_owned()],
+            allowed_methods: vec!["GET".to_owned(), "POST".to_owned()],
+            allowed_headers: vec!["authorization".to_owned(), "content-type".to_owned()],
+            max_age_seconds: 3600,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClockSkew {
+    /// Maximum shift of a span in milliseconds. Larger skews are only reported as warning on the
+    /// span, as they're more likely a broken trace than a skewed clock.
+    pub max_adjustment_ms: u64,
+}
+
+impl Default for ClockSkew {
+    fn default() -> Self {
+        Self {
+            max_adjustment_ms: 1000,
+        }
+    }
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum QueryAuth {
+    /// Require an `Authorization: Bearer <token>` header.
+    Bearer { token: String },
+    /// Require HTTP basic auth with the given credentials.
+    Basic { username: String, password: String },
+}
+
+impl fmt::Debug for QueryAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Bearer { .. } => f.debug_struct("Bearer").field("token", &REDACTED).finish(),
+            Self::Basic { username, .. } => f
+                .debug_struct("Basic")
+                .field("username", username)
+                .field("password", &REDACTED)
+                .finish(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Tls {
+    /// Path to the PEM encoded certificate chain.
+    pub cert: PathBuf,
+    /// Path to the PEM encoded private key.
+    pub key: PathBuf,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Listen {
+    pub jaeger_agent_compact: Listener,
+    pub jaeger_agent_binary: Listener,
+    /// Batches as Thrift messages, that are prefixed with their length as 4-byte big-endian
+    /// integer. Disabled by default.
+    pub jaeger_agent_tcp: Listener,
+    /// Batches as Thrift messages in the body of `POST /emitBatch`. Disabled by default.
+    pub jaeger_agent_http: Listener,
+    pub jaeger_collector_http: Listener,
+    pub jaeger_collector_grpc: Listener,
+    pub otlp_http: Listener,
+    pub otlp_grpc: Listener,
+    pub quiver: Listener,
+    pub query: Listener,
+    /// Jaeger's gRPC query API in version 3, that returns traces in the OTLP format.
+    pub query_grpc: Listener,
+    /// UNIX domain sockets to listen on, in addition to the TCP addresses.
+    pub unix: UnixSockets,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Listener {
+    /// Whether to start the receiver. Unless noted otherwise, all receivers are enabled by
+    /// default.
+    pub enabled: Option<bool>,
+    /// Address to listen on. Defaults to the standard port of the receiver, on the loopback
+    /// interface in debug builds and all interfaces in release builds.
+    pub address: Option<SocketAddr>,
+}
+
+/// Paths of UNIX domain sockets, for example to receive spans from a sidecar when TCP ports are
+/// restricted. Each receiver still listens on its TCP address as well, unless disabled. Only
+/// available on UNIX platforms.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UnixSockets {
+    pub otlp_http: Option<PathBuf>,
+    pub otlp_grpc: Option<PathBuf>,
+    pub query: Option<PathBuf>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Agent {
+    /// Maximum size of a single UDP packet, in bytes. Larger packets are rejected, as they're
+    /// likely truncated.
+    pub max_packet_size: usize,
+    /// Maximum size of a single batch sent over TCP or HTTP, in bytes.
+    pub max_batch_size: usize,
+    /// Size of the receive buffer of the UDP sockets, in bytes. Packets that arrive while the
+    /// buffer is full are dropped by the OS, so bursts of spans need a larger buffer than the
+    /// default. The OS may cap the size, for example by `net.core.rmem_max` on Linux.
+    pub udp_receive_buffer: Option<usize>,
+    /// Amount of tasks that concurrently receive and decode packets from each UDP socket.
+    /// Defaults to the amount of available CPU cores.
+    pub udp_workers: usize,
+}
+
+impl Default for Agent {
+    fn default() -> Self {
+        Self {
+            max_packet_size: 65000,
+            max_batch_size: 4 * 1024 * 1024,
+            udp_receive_buffer: None,
+            udp_workers: thread::available_parallelism().map_or(1, NonZeroUsize::get),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Collector {
+    /// Maximum size of a single Thrift batch sent over HTTP, in bytes. Larger requests are
+    /// rejected with `413 Payload Too Large`.
+    pub max_body_size: usize,
+}
+
+impl Default for Collector {
+    fn default() -> Self {
+        Self {
+            max_body_size: 4 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Storage {
+    /// Where spans are stored. Defaults to a `SQLite` database in the data directory.
+    pub backend: Backend,
+    /// Compression algorithm for newly stored spans and logs. Existing data keeps the algorithm
+    /// it was stored with, so this can be changed at any time.
+    pub compression: Compression,
+    /// Compression level, only used for `zstd`. Higher levels result in smaller databases, at the
+    /// cost of more CPU time when storing spans.
+    pub zstd_level: i32,
+    /// Background maintenance, that gives unused space back to the file system.
+    pub maintenance: Maintenance,
+    /// Tuning of the `SQLite` database, trading durability for throughput or memory usage.
+    pub sqlite: Sqlite,
+    /// Archive of old spans in Parquet files, which keeps the database small while the spans can
+    /// still be queried. All spans stay in the database if this section is missing.
+    pub cold_tier: Option<ColdTier>,
+    /// Directory of the database and other data. Not part of the file, see [`data_dir`].
+    #[serde(skip)]
+    pub data_dir: PathBuf,
+}
+
+impl Default for Storage {
+    fn default() -> Self {
+        Self {
+            backend: Backend::default(),
+            compression: Compression::default(),
+            zstd_level: 3,
+            maintenance: Maintenance::default(),
+            sqlite: Sqlite::default(),
+            cold_tier: None,
+            data_dir: PathBuf::new(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Sqlite {
+    /// Journal mode of the database. `wal` allows queries to run while spans are stored.
+    pub journal_mode: JournalMode,
+    /// How strictly writes are flushed to disk. `off` greatly improves ingest throughput, but
+    /// data may be lost or the database corrupted when the system crashes.
+    pub synchronous: Synchronous,
+    /// Maximum amount of bytes of the database file, that are accessed through memory mapped
+    /// I/O. Disabled with `0`.
+    pub mmap_size: u64,
+    /// Size of the page cache per connection. Positive values are a number of pages, negative
+    /// values an amount of KiB.
+    pub cache_size: i64,
+    /// Time in milliseconds to wait for a locked database, before failing.
+    pub busy_timeout: u64,
+}
+
+impl Default for Sqlite {
+    fn default() -> Self {
+        Self {
+            journal_mode: JournalMode::default(),
+            synchronous: Synchronous::default(),
+            mmap_size: 0,
+            cache_size: -2000,
+            busy_timeout: 5000,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JournalMode {
+    Delete,
+    Truncate,
+    Persist,
+    Memory,
+    #[default]
+    Wal,
+    Off,
+}
+
+impl JournalMode {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Delete => "delete",
+            Self::Truncate => "truncate",
+            Self::Persist => "persist",
+            Self::Memory => "memory",
+            Self::Wal => "wal",
+            Self::Off => "off",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Synchronous {
+    Off,
+    #[default]
+    Normal,
+    Full,
+    Extra,
+}
+
+impl Synchronous {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Off => "off",
+            Self::Normal => "normal",
+            Self::Full => "full",
+            Self::Extra => "extra",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Backend {
+    /// Persistent database file in the data directory.
+    #[default]
+    Sqlite,
+    /// Database that only lives in memory and is lost on shutdown, for demos and tests. Once it
+    /// holds more than `max_spans` spans, the oldest traces are removed.
+    Memory {
+        #[serde(default = "default_max_spans")]
+        max_spans: u64,
+    },
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ColdTier {
+    /// Directory for the Parquet files. Defaults to `cold` in the data directory. Only local
+    /// directories are supported, so object storage like S3 must be mounted into the file system
+    /// to be used, for example with `s3fs` or `mountpoint-s3`.
+    pub path: Option<PathBuf>,
+    /// Age of traces in hours, after which they're moved out of the database.
+    pub after_hours: u64,
+    /// Time between two checks for traces to move, in minutes. Values below `1` are raised to it.
+    pub interval_minutes: u64,
+}
+
+impl Default for ColdTier {
+    fn default() -> Self {
+        Self {
+            path: None,
+            after_hours: 24,
+            interval_minutes: 60,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Maintenance {
+    /// Whether to run maintenance periodically. It can still be triggered manually through the
+    /// query API when disabled.
+    pub enabled: bool,
+    /// Minimum time between two maintenance runs, in minutes.
+    pub interval_minutes: u64,
+    /// Time without any writes, in seconds, before the database is considered idle. Maintenance
+    /// only starts once the database is idle.
+    pub idle_seconds: u64,
+    /// Time windows in UTC, in the form `HH:MM-HH:MM`, during which maintenance may run. A window
+    /// can wrap around midnight, like `22:00-04:00`. Maintenance may run at any time if empty.
+    pub windows: Vec<Window>,
+}
+
+impl Default for Maintenance {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            interval_minutes: 60,
+            idle_seconds: 30,
+            windows: Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Window {
+    pub start: Time,
+    pub end: Time,
+}
+
+impl Window {
+    pub fn contains(&self, time: Time) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            self.start <= time || time < self.end
+        }
+    }
+}
+
+impl TryFrom<String> for Window {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        fn parse(value: &str) -> Result<Time> {
+            let (hour, minute) = value.split_once(':').context("missing `:` separator")?;
+            Time::from_hms(hour.trim().parse()?, minute.trim().parse()?, 0).map_err(Into::into)
+        }
+
+        let (start, end) = value
+            .split_once('-')
+            .context("missing `-` between start and end")?;
+
+        Ok(Self {
+            start: parse(start).with_context(|| format!("invalid start time `{start}`"))?,
+            end: parse(end).with_context(|| format!("invalid end time `{end}`"))?,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Compression {
+    /// Fast compression with a moderate ratio.
+    #[default]
+    Snappy,
+    /// Slower compression, but considerably smaller than `snappy`.
+    Zstd,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Tenancy {
+    /// Whether to separate data by tenant. If disabled, all data is stored for and read from the
+    /// same default tenant, regardless of the request headers.
+    pub enabled: bool,
+    /// HTTP header or gRPC metadata key, that carries the tenant of a request. Applies to all
+    /// collectors and the query API.
+    pub header: String,
+}
+
+impl Default for Tenancy {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            header: "x-scope-orgid".to_owned(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimit {
+    /// Amount of spans per second, that each service may send.
+    pub spans_per_second: u32,
+    /// Individual budgets for specific services, overriding the default.
+    #[serde(default)]
+    pub services: HashMap<String, u32>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    /// Maximum length of string and binary tag values, in bytes. Longer values are cut off, and
+    /// the span or log they belong to gets a `truncated` tag. Values are kept as is if not set.
+    pub max_tag_length: Option<usize>,
+    /// Maximum amount of tags per span. Any further tags are dropped, and the span gets a warning.
+    pub max_tags_per_span: Option<usize>,
+    /// Maximum amount of logs per span. Any further logs are dropped, and the span gets a warning.
+    pub max_logs_per_span: Option<usize>,
+    /// Maximum amount of spans per trace. Any further spans of a trace are dropped. Traces are
+    /// tracked until no new spans arrived for 10 minutes, after which they're counted anew.
+    pub max_spans_per_trace: Option<usize>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Processor {
+    /// Renames of services, where the first rule with a matching pattern applies.
+    pub rename_services: Vec<ServiceRename>,
+    /// Tags that are removed from every span, by their key.
+    pub remove_tags: HashSet<String>,
+    /// Tag keys that are renamed, from the old to the new key.
+    pub rename_tags: HashMap<String, String>,
+    /// Tags with string values, that are added to every span, unless it already has a tag with
+    /// the same key.
+    pub add_tags: HashMap<String, String>,
+    /// Redaction of sensitive values, like personal data or credentials.
+    pub scrub: Scrub,
+}
+
+/// Redaction of tag values, in the span itself, its logs and its process. It runs after tags were
+/// renamed, so keys refer to the new names.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Scrub {
+    /// Tags that have their value replaced entirely, by their key.
+    pub keys: HashSet<String>,
+    /// Well-known kinds of sensitive data, that are replaced wherever they appear in string
+    /// values.
+    pub builtin: Vec<ScrubPattern>,
+    /// Regular expressions, that are replaced wherever they match in string values.
+    pub patterns: Vec<String>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScrubPattern {
+    /// E-mail addresses.
+    Email,
+    /// Bearer tokens, as used in the `Authorization` HTTP header.
+    BearerToken,
+    /// Credit card numbers, that pass the Luhn checksum.
+    CreditCard,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServiceRename {
+    /// Regular expression, that must match the service name.
+    pub pattern: String,
+    /// New name of the service, which can refer to capture groups of the pattern like `$1` or
+    /// `${name}`.
+    pub replacement: String,
+}
+
+const fn default_queue_size() -> usize {
+    1024
+}
+
+const fn default_max_retries() -> u32 {
+    5
+}
+
+const fn default_max_spans() -> u64 {
+    100_000
+}
+
+pub fn load() -> Result<Config> {
+    let path = match env::var_os("ARCHER_CONFIG") {
+        Some(path) => PathBuf::from(path),
+        None => project_dirs()?.config_dir().join("config.toml").into(),
+    };
+
+    let mut config = match std::fs::read(&path) {
+        Ok(buf) => toml::from_slice(&buf)
+            .with_context(|| format!("failed parsing config at {}", path.display()))?,
+        Err(e) if e.kind() == ErrorKind::NotFound => Config::default(),
+        Err(e) => {
+            return Err(e).with_context(|| format!("failed reading config at {}", path.display()))
+        }
+    };
+
+    let data_dir = data_dir()?;
+    config.storage.data_dir.clone_from(&data_dir);
+    config.quiver.data_dir = data_dir;
+
+    Ok(config)
+}
+
+/// Directory for the database, certificates and other data. It can be set through the
+/// `ARCHER_DATA_DIR` environment variable, for example to a mounted volume in a container, and
+/// defaults to the user's data directory.
+fn data_dir() -> Result<PathBuf> {
+    match env::var_os("ARCHER_DATA_DIR") {
+        Some(dir) => Ok(dir.into()),
+        None => Ok(project_dirs()?.data_dir().into()),
+    }
+}
+
+fn project_dirs() -> Result<UnifiedDirs> {
+    UnifiedDirs::simple("rocks", "dnaka91", env!("CARGO_PKG_NAME"))
+        .default()
+        .context("failed finding project directories")
+}
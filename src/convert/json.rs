@@ -1,10 +1,25 @@
+use std::collections::HashMap;
+
+use anyhow::{Context, Result};
 use archer_http as json;
 use bimap::BiHashMap;
 use time::{Duration, OffsetDateTime};
 
-use crate::models::{Log, Process, RefType, Reference, Span, Tag, TagValue, TraceId};
+use crate::models::{
+    Log, Process, RefType, Reference, Span, SpanId, SpanKind, Tag, TagValue, Timing, TraceId,
+};
 
+/// Convert a complete trace, and flag common issues of its spans as warnings.
 pub fn trace(trace_id: TraceId, spans: impl IntoIterator<Item = Span>) -> json::Trace {
+    let mut spans = spans.into_iter().collect::<Vec<_>>();
+    warnings(&mut spans);
+
+    partial(trace_id, spans)
+}
+
+/// Convert spans that may only be a part of a trace, like the ones of a live update. Warnings
+/// aren't added, as checks of the trace structure would be misleading.
+pub fn partial(trace_id: TraceId, spans: impl IntoIterator<Item = Span>) -> json::Trace {
     let mut processes = BiHashMap::new();
     let mut counter = 0;
 
@@ -22,21 +37,131 @@ pub fn trace(trace_id: TraceId, spans: impl IntoIterator<Item = Span>) -> json::
     }
 }
 
+/// Flatten the logs of all spans into a single list, ordered by their timestamp. Logs with the same
+/// timestamp keep the order of their spans.
+pub fn logs(spans: impl IntoIterator<Item = Span>) -> Vec<json::TraceLog> {
+    let mut logs = spans
+        .into_iter()
+        .flat_map(|span| {
+            let Span {
+                span_id,
+                operation_name,
+                logs,
+                process,
+                ..
+            } = span;
+
+            logs.into_iter().map(move |log| json::TraceLog {
+                span_id: span_id.get().into(),
+                service_name: process.service.clone(),
+                operation_name: operation_name.clone(),
+                timestamp: timestamp(log.timestamp),
+                fields: log.fields.into_iter().map(key_value).collect(),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    logs.sort_by_key(|log| log.timestamp);
+    logs
+}
+
+/// Add warnings to each span about issues, that usually hint at broken instrumentation or skewed
+/// clocks. These are unique span IDs, parents and other referenced spans that are missing from
+/// the trace, spans outside of the time frame of their parent and spans without a duration.
+fn warnings(spans: &mut [Span]) {
+    let mut by_id = HashMap::<SpanId, (&Span, usize)>::new();
+    for span in &*spans {
+        by_id.entry(span.span_id).or_insert((span, 0)).1 += 1;
+    }
+
+    let warnings = spans
+        .iter()
+        .map(|span| {
+            let mut warnings = Vec::new();
+
+            if by_id
+                .get(&span.span_id)
+                .is_some_and(|(_, count)| *count > 1)
+            {
+                warnings.push(format!(
+                    "span ID {:016x} is used by multiple spans of the trace",
+                    span.span_id.get()
+                ));
+            }
+
+            if span.duration <= Duration::ZERO {
+                warnings.push("span has no duration".to_owned());
+            }
+
+            // The first reference within the trace is the parent, the same as for the UI.
+            for (i, reference) in span
+                .references
+                .iter()
+                .filter(|reference| reference.trace_id == span.trace_id)
+                .enumerate()
+            {
+                let kind = if i == 0 { "parent" } else { "referenced" };
+
+                match by_id.get(&reference.span_id) {
+                    None => warnings.push(format!(
+                        "{kind} span {:016x} is missing from the trace",
+                        reference.span_id.get()
+                    )),
+                    Some((parent, _)) if i == 0 => {
+                        if span.start < parent.start
+                            || span.start + span.duration > parent.start + parent.duration
+                        {
+                            warnings.push(
+                                "span is outside of its parent's time frame, which may be caused \
+                                 by clock skew"
+                                    .to_owned(),
+                            );
+                        }
+                    }
+                    Some(_) => {}
+                }
+            }
+
+            warnings
+        })
+        .collect::<Vec<_>>();
+
+    // Imported traces may already carry the same warnings from a previous export.
+    for (span, warnings) in spans.iter_mut().zip(warnings) {
+        for warning in warnings {
+            if !span.warnings.contains(&warning) {
+                span.warnings.push(warning);
+            }
+        }
+    }
+}
+
 fn span(span: Span, process_id: json::ProcessId) -> json::Span {
     json::Span {
         trace_id: span.trace_id.get().into(),
         span_id: span.span_id.get().into(),
-        parent_span_id: None,
+        parent_span_id: span
+            .references
+            .iter()
+            .find(|r| matches!(r.ty, RefType::ChildOf) && r.trace_id == span.trace_id)
+            .map(|r| r.span_id.get().into()),
         flags: span.flags,
         operation_name: span.operation_name,
         references: span.references.into_iter().map(reference).collect(),
         start_time: timestamp(span.start),
         duration: duration(span.duration),
-        tags: span.tags.into_iter().map(key_value).collect(),
+        tags: span
+            .kind
+            .map(SpanKind::tag)
+            .into_iter()
+            .chain(span.timing.map(Timing::tags).into_iter().flatten())
+            .chain(span.tags)
+            .map(key_value)
+            .collect(),
         logs: span.logs.into_iter().map(log).collect(),
         process_id,
         process: None,
-        warnings: Vec::new(),
+        warnings: span.warnings,
     }
 }
 
@@ -48,6 +173,7 @@ fn reference(span_ref: Reference) -> json::Reference {
         },
         trace_id: span_ref.trace_id.get().into(),
         span_id: span_ref.span_id.get().into(),
+        tags: span_ref.tags.into_iter().map(key_value).collect(),
     }
 }
 
@@ -106,3 +232,118 @@ fn process(
         })
         .into()
 }
+
+/// Convert a trace, as rendered by [`trace`], back into its spans.
+pub fn trace_from(trace: json::Trace) -> Result<Vec<Span>> {
+    let processes = trace
+        .processes
+        .into_iter()
+        .map(|(id, process)| (id, from_process(process)))
+        .collect::<HashMap<_, _>>();
+
+    trace
+        .spans
+        .into_iter()
+        .map(|mut span| {
+            let process = match span.process.take() {
+                Some(process) => from_process(process),
+                None => processes
+                    .get(&span.process_id.0)
+                    .cloned()
+                    .with_context(|| format!("process `{}` not found", span.process_id.0))?,
+            };
+
+            from_span(span, process)
+        })
+        .collect()
+}
+
+fn from_span(span: json::Span, process: Process) -> Result<Span> {
+    let trace_id = TraceId::from(span.trace_id.0);
+    let mut references = span
+        .references
+        .into_iter()
+        .map(from_reference)
+        .collect::<Vec<_>>();
+
+    if let Some(parent) = span.parent_span_id {
+        if !references.iter().any(|r| matches!(r.ty, RefType::ChildOf)) {
+            references.insert(
+                0,
+                Reference {
+                    ty: RefType::ChildOf,
+                    trace_id,
+                    span_id: parent.0.into(),
+                    tags: Vec::new(),
+                },
+            );
+        }
+    }
+
+    Ok(Span {
+        trace_id,
+        span_id: span.span_id.0.into(),
+        operation_name: span.operation_name,
+        flags: span.flags,
+        references,
+        start: from_timestamp(span.start_time)?,
+        duration: from_duration(span.duration)?,
+        tags: span.tags.into_iter().map(from_key_value).collect(),
+        logs: span.logs.into_iter().map(from_log).collect::<Result<_>>()?,
+        process,
+        warnings: span.warnings,
+        timing: None,
+        kind: None,
+    }
+    .with_timing_from_tags()
+    .with_kind_from_tags())
+}
+
+fn from_reference(span_ref: json::Reference) -> Reference {
+    Reference {
+        ty: match span_ref.ref_type {
+            json::ReferenceType::ChildOf => RefType::ChildOf,
+            json::ReferenceType::FollowsFrom => RefType::FollowsFrom,
+        },
+        trace_id: span_ref.trace_id.0.into(),
+        span_id: span_ref.span_id.0.into(),
+        tags: span_ref.tags.into_iter().map(from_key_value).collect(),
+    }
+}
+
+fn from_timestamp(timestamp: i128) -> Result<OffsetDateTime> {
+    OffsetDateTime::from_unix_timestamp_nanos(timestamp * 1000).map_err(Into::into)
+}
+
+fn from_duration(duration: i128) -> Result<Duration> {
+    Ok(Duration::microseconds(
+        i64::try_from(duration).context("duration out of range")?,
+    ))
+}
+
+fn from_key_value(kv: json::KeyValue) -> Tag {
+    Tag {
+        key: kv.key,
+        value: match kv.value {
+            json::Value::String(s) => TagValue::String(s),
+            json::Value::Bool(b) => TagValue::Bool(b),
+            json::Value::Int64(i) => TagValue::I64(i),
+            json::Value::Float64(f) => TagValue::F64(f),
+            json::Value::Binary(b) => TagValue::Binary(b),
+        },
+    }
+}
+
+fn from_log(log: json::Log) -> Result<Log> {
+    Ok(Log {
+        timestamp: from_timestamp(log.timestamp)?,
+        fields: log.fields.into_iter().map(from_key_value).collect(),
+    })
+}
+
+fn from_process(process: json::Process) -> Process {
+    Process {
+        service: process.service_name,
+        tags: process.tags.into_iter().map(from_key_value).collect(),
+    }
+}
@@ -1,11 +1,19 @@
-pub use json::trace as trace_to_json;
-pub use otlp::{span as span_from_otlp, span_len as span_from_otlp_len};
+pub use json::{
+    logs as trace_logs_to_json, partial as partial_trace_to_json, trace as trace_to_json,
+    trace_from as trace_from_json,
+};
+pub use otlp::{
+    logs as logs_from_otlp, logs_len as logs_from_otlp_len, span as span_from_otlp,
+    span_len as span_from_otlp_len, span_to as span_to_otlp,
+};
 pub use proto::span as span_from_proto;
 pub use quiver::span as span_from_quiver;
 pub use thrift::span as span_from_thrift;
+pub use zipkin::span as span_from_zipkin;
 
 mod json;
 mod otlp;
 mod proto;
 mod quiver;
 mod thrift;
+mod zipkin;
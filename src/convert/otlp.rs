@@ -2,12 +2,18 @@ use std::num::{NonZeroU128, NonZeroU64};
 
 use anyhow::Result;
 use archer_proto::opentelemetry::proto::{
-    common::v1 as otlp_common, resource::v1 as otlp_res, trace::v1 as otlp,
+    common::v1 as otlp_common, logs::v1 as otlp_logs, resource::v1 as otlp_res, trace::v1 as otlp,
 };
 use opentelemetry_semantic_conventions::resource;
 use time::OffsetDateTime;
 
-use crate::models::{Log, Process, RefType, Reference, Span, SpanId, Tag, TagValue, TraceId};
+use crate::models::{
+    Log, LogRecord, Process, RefType, Reference, Span, SpanId, SpanKind, Tag, TagValue, Timing,
+    TraceId,
+};
+
+/// Service name of resources without any attributes, the same that Jaeger uses.
+const NO_SERVICE_NAME: &str = "OTLPResourceNoServiceName";
 
 pub fn span_len(res_spans: &[otlp::ResourceSpans]) -> usize {
     res_spans
@@ -17,12 +23,15 @@ pub fn span_len(res_spans: &[otlp::ResourceSpans]) -> usize {
         .sum()
 }
 
-pub fn span(res_spans: otlp::ResourceSpans) -> Result<Vec<Span>> {
+/// Convert all spans of the resource. Each span is converted on its own, so a single invalid span
+/// doesn't cause the others to be discarded as well.
+pub fn span(res_spans: otlp::ResourceSpans) -> Vec<Result<Span>> {
     let resource = res_spans.resource.unwrap_or_default();
     let spans = res_spans.scope_spans;
+    let schema_url = res_spans.schema_url;
 
     if resource.attributes.is_empty() && spans.is_empty() {
-        return Ok(Vec::new());
+        return Vec::new();
     }
 
     let process = self::resource(resource);
@@ -30,18 +39,53 @@ pub fn span(res_spans: otlp::ResourceSpans) -> Result<Vec<Span>> {
     spans
         .into_iter()
         .flat_map(|ss| {
+            let scope = ss.scope.unwrap_or_default();
+            let schema_url = scope_schema_url(ss.schema_url, &schema_url);
+
             ss.spans
                 .into_iter()
-                .map(move |s| (s, ss.scope.clone().unwrap_or_default()))
+                .map(move |s| (s, scope.clone(), schema_url.clone()))
         })
-        .map(|(span, lib_tags)| span2(span, lib_tags, process.clone()))
+        .map(|(span, scope, schema_url)| span2(span, scope, schema_url, process.clone()))
+        .collect()
+}
+
+pub fn logs_len(res_logs: &[otlp_logs::ResourceLogs]) -> usize {
+    res_logs
+        .iter()
+        .flat_map(|rl| &rl.scope_logs)
+        .map(|sl| sl.log_records.len())
+        .sum()
+}
+
+pub fn logs(res_logs: otlp_logs::ResourceLogs) -> Result<Vec<LogRecord>> {
+    let resource = res_logs.resource.unwrap_or_default();
+    let logs = res_logs.scope_logs;
+    let schema_url = res_logs.schema_url;
+
+    if resource.attributes.is_empty() && logs.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let process = self::resource(resource);
+
+    logs.into_iter()
+        .flat_map(|sl| {
+            let scope = sl.scope.unwrap_or_default();
+            let schema_url = scope_schema_url(sl.schema_url, &schema_url);
+
+            sl.log_records
+                .into_iter()
+                .map(move |l| (l, scope.clone(), schema_url.clone()))
+        })
+        .map(|(record, scope, schema_url)| log_record(record, scope, schema_url, process.clone()))
         .collect()
 }
 
 fn resource(mut resource: otlp_res::Resource) -> Process {
     if resource.attributes.is_empty() {
         return Process {
-            service: "OTLPResourceNoServiceName".to_owned(),
+            service: NO_SERVICE_NAME.to_owned(),
             tags: Vec::new(),
         };
     }
@@ -98,9 +142,8 @@ fn tag(attribute: otlp_common::KeyValue) -> Option<Tag> {
 fn json_value(any: otlp_common::AnyValue) -> serde_json::Value {
     use otlp_common::any_value::Value;
 
-    let value = match any.value {
-        Some(v) => v,
-        None => return serde_json::Value::Null,
+    let Some(value) = any.value else {
+        return serde_json::Value::Null;
     };
 
     match value {
@@ -126,7 +169,8 @@ fn json_value(any: otlp_common::AnyValue) -> serde_json::Value {
 
 fn span2(
     span: otlp::Span,
-    lib_tags: otlp_common::InstrumentationScope,
+    scope: otlp_common::InstrumentationScope,
+    schema_url: String,
     process: Process,
 ) -> Result<Span> {
     let trace_id = trace_id(&span.trace_id);
@@ -145,6 +189,7 @@ fn span2(
                 ty: RefType::ChildOf,
                 trace_id,
                 span_id: span_id(&span.parent_span_id),
+                tags: Vec::new(),
             })
             .into_iter()
             .chain(span.links.into_iter().map(link))
@@ -152,19 +197,82 @@ fn span2(
         start,
         duration: end - start,
         tags: [
-            tag_from_span_kind(kind),
             tag_from_status_code(status.code()),
             tag_from_error_status_code(status.code()),
             tag_from_status_message(status.message),
             tag_from_trace_state(span.trace_state),
         ]
         .into_iter()
-        .chain(tags_from_inst_library(lib_tags))
         .chain(span.attributes.into_iter().map(tag))
         .flatten()
+        .chain(tags_from_scope(scope, schema_url))
         .collect(),
         logs: span.events.into_iter().map(log).collect::<Result<_>>()?,
         process,
+        warnings: dropped_warnings(
+            span.dropped_attributes_count,
+            span.dropped_events_count,
+            span.dropped_links_count,
+        ),
+        timing: None,
+        kind: span_kind(kind),
+    }
+    .with_timing_from_tags())
+}
+
+fn log_record(
+    record: otlp_logs::LogRecord,
+    scope: otlp_common::InstrumentationScope,
+    schema_url: String,
+    process: Process,
+) -> Result<LogRecord> {
+    let time = if record.time_unix_nano == 0 {
+        record.observed_time_unix_nano
+    } else {
+        record.time_unix_nano
+    };
+    let level = if record.severity_text.is_empty() {
+        severity(record.severity_number()).map(ToOwned::to_owned)
+    } else {
+        Some(record.severity_text)
+    };
+
+    Ok(LogRecord {
+        trace_id: TraceId::try_from(record.trace_id.as_slice()).ok(),
+        span_id: SpanId::try_from(record.span_id.as_slice()).ok(),
+        log: Log {
+            timestamp: timestamp(time)?,
+            fields: [
+                level.map(|level| Tag {
+                    key: "level".to_owned(),
+                    value: TagValue::String(level),
+                }),
+                record.body.and_then(|body| {
+                    tag(otlp_common::KeyValue {
+                        key: "message".to_owned(),
+                        value: Some(body),
+                    })
+                }),
+            ]
+            .into_iter()
+            .flatten()
+            .chain(tags_from_scope(scope, schema_url))
+            .chain(record.attributes.into_iter().filter_map(tag))
+            .collect(),
+        },
+        process,
+    })
+}
+
+fn severity(number: otlp_logs::SeverityNumber) -> Option<&'static str> {
+    Some(match number as i32 {
+        1..=4 => "TRACE",
+        5..=8 => "DEBUG",
+        9..=12 => "INFO",
+        13..=16 => "WARN",
+        17..=20 => "ERROR",
+        21..=24 => "FATAL",
+        _ => return None,
     })
 }
 
@@ -201,22 +309,34 @@ fn link(link: otlp::span::Link) -> Reference {
         ty: RefType::FollowsFrom,
         trace_id: trace_id(&link.trace_id),
         span_id: span_id(&link.span_id),
+        tags: link.attributes.into_iter().filter_map(tag).collect(),
     }
 }
 
-fn tag_from_span_kind(span_kind: otlp::span::SpanKind) -> Option<Tag> {
-    use otlp::span::SpanKind;
+/// Describe the parts of a span, that the sender discarded before sending it, for example due to
+/// its attribute limits.
+fn dropped_warnings(attributes: u32, events: u32, links: u32) -> Vec<String> {
+    [
+        (attributes, "attributes"),
+        (events, "events"),
+        (links, "links"),
+    ]
+    .into_iter()
+    .filter(|(count, _)| *count > 0)
+    .map(|(count, kind)| format!("{count} {kind} were dropped by the sender"))
+    .collect()
+}
 
-    Some(Tag {
-        key: "span.kind".to_owned(),
-        value: TagValue::String(match span_kind {
-            SpanKind::Unspecified => return None,
-            SpanKind::Internal => "internal".to_owned(),
-            SpanKind::Server => "server".to_owned(),
-            SpanKind::Client => "client".to_owned(),
-            SpanKind::Producer => "producer".to_owned(),
-            SpanKind::Consumer => "consumer".to_owned(),
-        }),
+fn span_kind(span_kind: otlp::span::SpanKind) -> Option<SpanKind> {
+    use otlp::span::SpanKind as OtlpSpanKind;
+
+    Some(match span_kind {
+        OtlpSpanKind::Unspecified => return None,
+        OtlpSpanKind::Internal => SpanKind::Internal,
+        OtlpSpanKind::Server => SpanKind::Server,
+        OtlpSpanKind::Client => SpanKind::Client,
+        OtlpSpanKind::Producer => SpanKind::Producer,
+        OtlpSpanKind::Consumer => SpanKind::Consumer,
     })
 }
 
@@ -259,17 +379,43 @@ fn tag_from_trace_state(trace_state: String) -> Option<Tag> {
     })
 }
 
-fn tags_from_inst_library(inst_lib: otlp_common::InstrumentationScope) -> [Option<Tag>; 2] {
+/// Convert the instrumentation scope into tags, following the OpenTelemetry conventions for
+/// non-OTLP formats. The scope attributes are prefixed with `otel.scope.attributes.`, so they can't
+/// clash with the name and version.
+fn tags_from_scope(scope: otlp_common::InstrumentationScope, schema_url: String) -> Vec<Tag> {
+    let string = |key: &str, value: String| {
+        (!value.is_empty()).then(|| Tag {
+            key: key.to_owned(),
+            value: TagValue::String(value),
+        })
+    };
+
     [
-        (!inst_lib.name.is_empty()).then(|| Tag {
-            key: "otel.library.name".to_owned(),
-            value: TagValue::String(inst_lib.name),
-        }),
-        (!inst_lib.version.is_empty()).then(|| Tag {
-            key: "otel.library.version".to_owned(),
-            value: TagValue::String(inst_lib.version),
-        }),
+        string("otel.scope.name", scope.name.clone()),
+        string("otel.scope.version", scope.version.clone()),
+        // Deprecated names of the above, still used by older consumers.
+        string("otel.library.name", scope.name),
+        string("otel.library.version", scope.version),
+        string("otel.schema_url", schema_url),
     ]
+    .into_iter()
+    .flatten()
+    .chain(scope.attributes.into_iter().filter_map(|attribute| {
+        tag(attribute).map(|tag| Tag {
+            key: format!("otel.scope.attributes.{}", tag.key),
+            ..tag
+        })
+    }))
+    .collect()
+}
+
+/// Schema URL of a scope, which falls back to the one of its resource.
+fn scope_schema_url(schema_url: String, resource: &str) -> String {
+    if schema_url.is_empty() {
+        resource.to_owned()
+    } else {
+        schema_url
+    }
 }
 
 fn log(event: otlp::span::Event) -> Result<Log> {
@@ -285,3 +431,258 @@ fn log(event: otlp::span::Event) -> Result<Log> {
             .collect(),
     })
 }
+
+/// Convert the spans into their OTLP representation, grouping them by their process and
+/// instrumentation scope. This is the inverse of [`span`], and spans that were received as OTLP
+/// convert back into the same form.
+pub fn span_to(spans: &[Span]) -> Vec<otlp::ResourceSpans> {
+    let mut groups = Vec::<(&Process, Vec<otlp::ScopeSpans>)>::new();
+
+    for span in spans {
+        let otlp_span = otlp_span(span);
+        let (scope, schema_url) = otlp_scope(span);
+
+        let index = groups
+            .iter()
+            .position(|(p, _)| *p == &span.process)
+            .unwrap_or_else(|| {
+                groups.push((&span.process, Vec::new()));
+                groups.len() - 1
+            });
+        let scopes = &mut groups[index].1;
+
+        match scopes
+            .iter_mut()
+            .find(|s| s.scope == scope && s.schema_url == schema_url)
+        {
+            Some(scope) => scope.spans.push(otlp_span),
+            None => scopes.push(otlp::ScopeSpans {
+                scope,
+                spans: vec![otlp_span],
+                schema_url,
+            }),
+        }
+    }
+
+    groups
+        .into_iter()
+        .map(|(process, scope_spans)| otlp::ResourceSpans {
+            resource: Some(otlp_resource(process)),
+            scope_spans,
+            schema_url: String::new(),
+        })
+        .collect()
+}
+
+/// Convert the process back into a resource. The placeholder name for resources without any
+/// attributes results in an empty resource again.
+fn otlp_resource(process: &Process) -> otlp_res::Resource {
+    let service = (process.service != NO_SERVICE_NAME).then(|| otlp_common::KeyValue {
+        key: resource::SERVICE_NAME.as_str().to_owned(),
+        value: Some(otlp_common::AnyValue {
+            value: Some(otlp_common::any_value::Value::StringValue(
+                process.service.clone(),
+            )),
+        }),
+    });
+
+    otlp_res::Resource {
+        attributes: service
+            .into_iter()
+            .chain(process.tags.iter().map(key_value))
+            .collect(),
+        dropped_attributes_count: 0,
+    }
+}
+
+/// Convert a single span back into OTLP. Tags that were created from OTLP fields when the span
+/// was received, like the status and trace state, are turned back into these fields.
+fn otlp_span(span: &Span) -> otlp::Span {
+    use otlp::{span::SpanKind as OtlpSpanKind, status::StatusCode};
+
+    let kind = match span.kind {
+        None => OtlpSpanKind::Unspecified,
+        Some(SpanKind::Internal) => OtlpSpanKind::Internal,
+        Some(SpanKind::Server) => OtlpSpanKind::Server,
+        Some(SpanKind::Client) => OtlpSpanKind::Client,
+        Some(SpanKind::Producer) => OtlpSpanKind::Producer,
+        Some(SpanKind::Consumer) => OtlpSpanKind::Consumer,
+    };
+    let mut code = None;
+    let mut error = false;
+    let mut message = String::new();
+    let mut trace_state = String::new();
+
+    let attributes = span
+        .tags
+        .iter()
+        .filter(|tag| match (tag.key.as_str(), &tag.value) {
+            ("otel.status_code", TagValue::String(value)) => {
+                code = match value.as_str() {
+                    "OK" => Some(StatusCode::Ok),
+                    "ERROR" => Some(StatusCode::Error),
+                    _ => None,
+                };
+                false
+            }
+            ("error", TagValue::Bool(value)) => {
+                error = *value;
+                false
+            }
+            ("otel.status_description", TagValue::String(value)) => {
+                message.clone_from(value);
+                false
+            }
+            ("w3c.tracestate", TagValue::String(value)) => {
+                trace_state.clone_from(value);
+                false
+            }
+            (key, _) => !is_scope_tag(key),
+        })
+        .chain(span.timing.map(Timing::tags).iter().flatten())
+        .map(key_value)
+        .collect();
+
+    // The parent must be in the same trace, all other references are kept as links.
+    let parent = span
+        .references
+        .iter()
+        .position(|r| matches!(r.ty, RefType::ChildOf) && r.trace_id == span.trace_id);
+    let code = code.or(error.then_some(StatusCode::Error));
+
+    otlp::Span {
+        trace_id: span.trace_id.to_bytes().to_vec(),
+        span_id: span.span_id.to_bytes().to_vec(),
+        trace_state,
+        parent_span_id: parent
+            .map(|i| span.references[i].span_id.to_bytes().to_vec())
+            .unwrap_or_default(),
+        name: span.operation_name.clone(),
+        kind: kind.into(),
+        start_time_unix_nano: unix_nanos(span.start),
+        end_time_unix_nano: unix_nanos(span.start + span.duration),
+        attributes,
+        dropped_attributes_count: dropped_count(&span.warnings, "attributes"),
+        events: span.logs.iter().map(otlp_event).collect(),
+        dropped_events_count: dropped_count(&span.warnings, "events"),
+        links: span
+            .references
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| Some(*i) != parent)
+            .map(|(_, r)| otlp::span::Link {
+                trace_id: r.trace_id.to_bytes().to_vec(),
+                span_id: r.span_id.to_bytes().to_vec(),
+                trace_state: String::new(),
+                attributes: r.tags.iter().map(key_value).collect(),
+                dropped_attributes_count: 0,
+            })
+            .collect(),
+        dropped_links_count: dropped_count(&span.warnings, "links"),
+        status: (code.is_some() || !message.is_empty()).then(|| otlp::Status {
+            message,
+            code: code.unwrap_or(StatusCode::Unset).into(),
+        }),
+    }
+}
+
+fn otlp_event(log: &Log) -> otlp::span::Event {
+    otlp::span::Event {
+        time_unix_nano: unix_nanos(log.timestamp),
+        name: log
+            .fields
+            .iter()
+            .find_map(|field| match (field.key.as_str(), &field.value) {
+                ("event", TagValue::String(name)) => Some(name.clone()),
+                _ => None,
+            })
+            .unwrap_or_default(),
+        attributes: log
+            .fields
+            .iter()
+            .filter(|field| field.key != "event")
+            .map(key_value)
+            .collect(),
+        dropped_attributes_count: 0,
+    }
+}
+
+fn unix_nanos(timestamp: OffsetDateTime) -> u64 {
+    u64::try_from(timestamp.unix_timestamp_nanos()).unwrap_or_default()
+}
+
+/// Restore the amount of discarded parts of a span, from the warnings that were created by
+/// [`dropped_warnings`].
+fn dropped_count(warnings: &[String], kind: &str) -> u32 {
+    warnings
+        .iter()
+        .find_map(|warning| {
+            warning
+                .strip_suffix(" were dropped by the sender")?
+                .strip_suffix(kind)?
+                .strip_suffix(' ')?
+                .parse()
+                .ok()
+        })
+        .unwrap_or_default()
+}
+
+/// Whether the tag was created from the instrumentation scope, when the span was received.
+fn is_scope_tag(key: &str) -> bool {
+    key.starts_with("otel.scope.") || key.starts_with("otel.library.") || key == "otel.schema_url"
+}
+
+/// Restore the instrumentation scope and schema URL of a span from its tags.
+fn otlp_scope(span: &Span) -> (Option<otlp_common::InstrumentationScope>, String) {
+    let mut scope = otlp_common::InstrumentationScope::default();
+    let mut schema_url = String::new();
+
+    for tag in &span.tags {
+        match (tag.key.as_str(), &tag.value) {
+            ("otel.scope.name", TagValue::String(name)) => scope.name.clone_from(name),
+            ("otel.scope.version", TagValue::String(version)) => {
+                scope.version.clone_from(version);
+            }
+            ("otel.library.name", TagValue::String(name)) if scope.name.is_empty() => {
+                scope.name.clone_from(name);
+            }
+            ("otel.library.version", TagValue::String(version)) if scope.version.is_empty() => {
+                scope.version.clone_from(version);
+            }
+            ("otel.schema_url", TagValue::String(url)) => schema_url.clone_from(url),
+            (key, _) => {
+                if let Some(key) = key.strip_prefix("otel.scope.attributes.") {
+                    scope.attributes.push(key_value(&Tag {
+                        key: key.to_owned(),
+                        value: tag.value.clone(),
+                    }));
+                }
+            }
+        }
+    }
+
+    let scope = (scope != otlp_common::InstrumentationScope::default()).then_some(scope);
+
+    (scope, schema_url)
+}
+
+fn key_value(tag: &Tag) -> otlp_common::KeyValue {
+    use otlp_common::any_value::Value;
+
+    otlp_common::KeyValue {
+        key: tag.key.clone(),
+        value: Some(otlp_common::AnyValue {
+            value: Some(match &tag.value {
+                TagValue::F64(v) => Value::DoubleValue(*v),
+                TagValue::I64(v) => Value::IntValue(*v),
+                TagValue::U64(v) => i64::try_from(*v)
+                    .map_or_else(|_| Value::StringValue(v.to_string()), Value::IntValue),
+                TagValue::I128(v) => Value::StringValue(v.to_string()),
+                TagValue::U128(v) => Value::StringValue(v.to_string()),
+                TagValue::Bool(v) => Value::BoolValue(*v),
+                TagValue::String(v) => Value::StringValue(v.clone()),
+                TagValue::Binary(v) => Value::BytesValue(v.clone()),
+            }),
+        }),
+    }
+}
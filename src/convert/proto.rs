@@ -18,7 +18,12 @@ pub fn span(span: proto::Span) -> Result<Span> {
         tags: span.tags.into_iter().map(key_value).collect(),
         logs: span.logs.into_iter().map(log).collect::<Result<_>>()?,
         process: process(span.process.context("process field missing")?),
-    })
+        warnings: Vec::new(),
+        timing: None,
+        kind: None,
+    }
+    .with_timing_from_tags()
+    .with_kind_from_tags())
 }
 
 fn trace_id(id: Vec<u8>) -> TraceId {
@@ -47,6 +52,7 @@ fn reference(span_ref: proto::SpanRef) -> Reference {
         },
         trace_id: trace_id(span_ref.trace_id),
         span_id: span_id(span_ref.span_id),
+        tags: Vec::new(),
     }
 }
 
@@ -1,9 +1,11 @@
 use crate::{
-    models::{Log, Process, RefType, Reference, Span, Tag, TagValue},
+    models::{Log, Process, RefType, Reference, Span, Tag, TagValue, Timing},
     quiver::models as quiver,
 };
 
 pub fn span(span: quiver::Span) -> Span {
+    let error_tagged = span.tags.iter().any(|tag| tag.key == "error");
+
     Span {
         trace_id: span.trace_id.into(),
         span_id: span.span_id.into(),
@@ -19,19 +21,50 @@ pub fn span(span: quiver::Span) -> Span {
                 },
                 trace_id: reference.trace_id.into(),
                 span_id: reference.span_id.into(),
+                tags: Vec::new(),
             })
             .collect(),
         start: span.start,
         duration: span.duration,
-        tags: timing(span.timing)
+        tags: location(span.location)
             .into_iter()
-            .chain(location(span.location).into_iter().flatten())
+            .flatten()
             .chain(thread(span.thread).into_iter().flatten())
             .chain(span.tags.into_iter().map(tag))
+            .chain(status(span.status, error_tagged).into_iter().flatten())
             .collect(),
         logs: span.logs.into_iter().map(log).collect(),
         process: process(span.process),
+        warnings: Vec::new(),
+        timing: Some(Timing {
+            busy: span.timing.busy,
+            idle: span.timing.idle,
+        }),
+        kind: None,
     }
+    .with_kind_from_tags()
+}
+
+/// Convert the status into the same tags, that spans received through OTLP carry. The `error` tag
+/// is only added if the span doesn't have one already.
+fn status(status: Option<quiver::Status>, error_tagged: bool) -> Option<Vec<Tag>> {
+    let tag = |key: &str, value: String| Tag {
+        key: key.to_owned(),
+        value: TagValue::String(value),
+    };
+
+    Some(match status? {
+        quiver::Status::Ok => vec![tag("otel.status_code", "OK".to_owned())],
+        quiver::Status::Error { message } => (!error_tagged)
+            .then(|| Tag {
+                key: "error".to_owned(),
+                value: TagValue::Bool(true),
+            })
+            .into_iter()
+            .chain([tag("otel.status_code", "ERROR".to_owned())])
+            .chain((!message.is_empty()).then(|| tag("otel.status_description", message)))
+            .collect(),
+    })
 }
 
 fn tag(tag: quiver::Tag) -> Tag {
@@ -91,19 +124,6 @@ fn process(process: quiver::Process) -> Process {
     }
 }
 
-fn timing(timing: quiver::Timing) -> [Tag; 2] {
-    [
-        Tag {
-            key: "busy_ns".to_owned(),
-            value: TagValue::I128(timing.busy.whole_nanoseconds()),
-        },
-        Tag {
-            key: "idle_ns".to_owned(),
-            value: TagValue::I128(timing.idle.whole_nanoseconds()),
-        },
-    ]
-}
-
 fn location(location: Option<quiver::Location>) -> Option<[Tag; 3]> {
     let location = location?;
     Some([
@@ -1,4 +1,7 @@
-use std::num::{NonZeroU128, NonZeroU64};
+use std::{
+    borrow::Cow,
+    num::{NonZeroU128, NonZeroU64},
+};
 
 use anyhow::Result;
 use archer_thrift::jaeger as thrift;
@@ -6,7 +9,7 @@ use time::{Duration, OffsetDateTime};
 
 use crate::models::{Log, Process, RefType, Reference, Span, SpanId, Tag, TagValue, TraceId};
 
-pub fn span(span: thrift::Span, process: thrift::Process) -> Result<Span> {
+pub fn span(span: thrift::Span<'_>, process: thrift::Process<'_>) -> Result<Span> {
     let references = span.references.unwrap_or_default();
 
     let parent = parent_span_id(
@@ -19,7 +22,7 @@ pub fn span(span: thrift::Span, process: thrift::Process) -> Result<Span> {
     Ok(Span {
         trace_id: trace_id(span.trace_id_high, span.trace_id_low),
         span_id: span_id(span.span_id),
-        operation_name: span.operation_name,
+        operation_name: span.operation_name.into_owned(),
         references: parent.into_iter().chain(references).map(span_ref).collect(),
         flags: span.flags as _,
         start: timestamp(span.start_time)?,
@@ -32,7 +35,12 @@ pub fn span(span: thrift::Span, process: thrift::Process) -> Result<Span> {
             .map(log)
             .collect::<Result<_>>()?,
         process: self::process(process),
-    })
+        warnings: Vec::new(),
+        timing: None,
+        kind: None,
+    }
+    .with_timing_from_tags()
+    .with_kind_from_tags())
 }
 
 fn parent_span_id(
@@ -73,6 +81,7 @@ fn span_ref(span_ref: thrift::SpanRef) -> Reference {
         ty: span_ref_type(span_ref.ref_type),
         trace_id: trace_id(span_ref.trace_id_high, span_ref.trace_id_low),
         span_id: span_id(span_ref.span_id),
+        tags: Vec::new(),
     }
 }
 
@@ -83,7 +92,7 @@ fn span_ref_type(ty: thrift::SpanRefType) -> RefType {
     }
 }
 
-fn log(log: thrift::Log) -> Result<Log> {
+fn log(log: thrift::Log<'_>) -> Result<Log> {
     Ok(Log {
         timestamp: timestamp(log.timestamp)?,
         fields: log.fields.into_iter().map(tag).collect(),
@@ -98,9 +107,9 @@ fn duration(microseconds: i64) -> Duration {
     Duration::microseconds(microseconds)
 }
 
-fn process(process: thrift::Process) -> Process {
+fn process(process: thrift::Process<'_>) -> Process {
     Process {
-        service: process.service_name,
+        service: process.service_name.into_owned(),
         tags: process
             .tags
             .unwrap_or_default()
@@ -110,27 +119,29 @@ fn process(process: thrift::Process) -> Process {
     }
 }
 
-fn tag(tag: thrift::Tag) -> Tag {
+fn tag(tag: thrift::Tag<'_>) -> Tag {
+    let key = tag.key.into_owned();
+
     match tag.v_type {
         thrift::TagType::Bool => Tag {
-            key: tag.key,
+            key,
             value: TagValue::Bool(tag.v_bool.unwrap_or_default()),
         },
         thrift::TagType::Binary => Tag {
-            key: tag.key,
-            value: TagValue::Binary(tag.v_binary.unwrap_or_default()),
+            key,
+            value: TagValue::Binary(tag.v_binary.map(Cow::into_owned).unwrap_or_default()),
         },
         thrift::TagType::Double => Tag {
-            key: tag.key,
+            key,
             value: TagValue::F64(tag.v_double.unwrap_or_default()),
         },
         thrift::TagType::Long => Tag {
-            key: tag.key,
+            key,
             value: TagValue::I64(tag.v_long.unwrap_or_default()),
         },
         thrift::TagType::String => Tag {
-            key: tag.key,
-            value: TagValue::String(tag.v_str.unwrap_or_default()),
+            key,
+            value: TagValue::String(tag.v_str.map(Cow::into_owned).unwrap_or_default()),
         },
     }
 }
@@ -0,0 +1,271 @@
+use std::{
+    net::{Ipv4Addr, Ipv6Addr},
+    num::{NonZeroU128, NonZeroU64},
+};
+
+use anyhow::{Context, Result};
+use archer_thrift::zipkincore as zipkin;
+use time::{Duration, OffsetDateTime};
+
+use crate::models::{
+    Log, Process, RefType, Reference, Span, SpanId, SpanKind, Tag, TagValue, TraceId,
+};
+
+/// Default service name, in case none of the annotations carry an endpoint.
+const UNKNOWN_SERVICE: &str = "unknown-service-name";
+
+pub fn span(span: zipkin::Span) -> Result<Span> {
+    let annotations = span.annotations.unwrap_or_default();
+    let binary_annotations = span.binary_annotations.unwrap_or_default();
+
+    let trace_id = trace_id(span.trace_id_high.unwrap_or_default(), span.trace_id);
+    let (start, duration) = timing(span.timestamp, span.duration, &annotations)?;
+
+    Ok(Span {
+        trace_id,
+        span_id: span_id(span.id),
+        operation_name: span.name,
+        flags: if span.debug.unwrap_or_default() { 3 } else { 1 },
+        references: span
+            .parent_id
+            .filter(|&id| id != 0)
+            .map(|id| Reference {
+                ty: RefType::ChildOf,
+                trace_id,
+                span_id: self::span_id(id),
+                tags: Vec::new(),
+            })
+            .into_iter()
+            .collect(),
+        start,
+        duration,
+        tags: binary_annotations
+            .iter()
+            .map(binary_annotation)
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .flatten()
+            .collect(),
+        logs: annotations
+            .iter()
+            .filter(|a| !is_core(&a.value))
+            .map(log)
+            .collect::<Result<_>>()?,
+        process: process(&annotations, &binary_annotations),
+        warnings: Vec::new(),
+        timing: None,
+        kind: span_kind(&annotations),
+    }
+    .with_timing_from_tags())
+}
+
+#[allow(clippy::cast_sign_loss)]
+fn trace_id(high: i64, low: i64) -> TraceId {
+    NonZeroU128::new((u128::from(high as u64)) << 64 | u128::from(low as u64))
+        .unwrap_or_else(rand::random)
+        .into()
+}
+
+#[allow(clippy::cast_sign_loss)]
+fn span_id(id: i64) -> SpanId {
+    NonZeroU64::new(id as _).unwrap_or_else(rand::random).into()
+}
+
+fn timestamp(microseconds: i64) -> Result<OffsetDateTime> {
+    OffsetDateTime::from_unix_timestamp_nanos(i128::from(microseconds) * 1000).map_err(Into::into)
+}
+
+/// Determine the start time and duration of the span. Zipkin v1 spans can either carry this
+/// information explicitly, or it has to be derived from the core annotations (`cs`, `cr`, `sr`
+/// and `ss`).
+fn timing(
+    timestamp: Option<i64>,
+    duration: Option<i64>,
+    annotations: &[zipkin::Annotation],
+) -> Result<(OffsetDateTime, Duration)> {
+    let find = |value: &str| {
+        annotations
+            .iter()
+            .find(|a| a.value == value)
+            .map(|a| a.timestamp)
+    };
+
+    let (send, recv) = match (find(zipkin::CLIENT_SEND), find(zipkin::CLIENT_RECV)) {
+        (Some(cs), cr) => (Some(cs), cr),
+        (None, _) => (find(zipkin::SERVER_RECV), find(zipkin::SERVER_SEND)),
+    };
+
+    let start = timestamp
+        .or(send)
+        .or_else(|| annotations.iter().map(|a| a.timestamp).min())
+        .unwrap_or_default();
+    let duration = duration
+        .or_else(|| send.zip(recv).map(|(send, recv)| recv - send))
+        .unwrap_or_default();
+
+    Ok((self::timestamp(start)?, Duration::microseconds(duration)))
+}
+
+fn is_core(value: &str) -> bool {
+    matches!(
+        value,
+        zipkin::CLIENT_SEND | zipkin::CLIENT_RECV | zipkin::SERVER_SEND | zipkin::SERVER_RECV
+    )
+}
+
+fn span_kind(annotations: &[zipkin::Annotation]) -> Option<SpanKind> {
+    let has = |value: &str| annotations.iter().any(|a| a.value == value);
+
+    if has(zipkin::SERVER_RECV) || has(zipkin::SERVER_SEND) {
+        Some(SpanKind::Server)
+    } else if has(zipkin::CLIENT_SEND) || has(zipkin::CLIENT_RECV) {
+        Some(SpanKind::Client)
+    } else {
+        None
+    }
+}
+
+fn log(annotation: &zipkin::Annotation) -> Result<Log> {
+    Ok(Log {
+        timestamp: timestamp(annotation.timestamp)?,
+        fields: vec![Tag {
+            key: "event".to_owned(),
+            value: TagValue::String(annotation.value.clone()),
+        }],
+    })
+}
+
+fn binary_annotation(annotation: &zipkin::BinaryAnnotation) -> Result<Vec<Tag>> {
+    use zipkin::AnnotationType;
+
+    match annotation.key.as_str() {
+        zipkin::CLIENT_ADDR | zipkin::SERVER_ADDR => {
+            return Ok(annotation.host.as_ref().map(peer_tags).unwrap_or_default());
+        }
+        zipkin::LOCAL_COMPONENT => {
+            return Ok(vec![Tag {
+                key: "component".to_owned(),
+                value: TagValue::String(String::from_utf8_lossy(&annotation.value).into_owned()),
+            }]);
+        }
+        _ => {}
+    }
+
+    let value = &annotation.value;
+    let invalid = || format!("invalid value for binary annotation `{}`", annotation.key);
+
+    let value = match annotation.annotation_type {
+        AnnotationType::Bool => TagValue::Bool(value.first() == Some(&1)),
+        AnnotationType::Bytes => TagValue::Binary(value.clone()),
+        AnnotationType::I16 => {
+            TagValue::I64(i16::from_be_bytes(value[..].try_into().with_context(invalid)?).into())
+        }
+        AnnotationType::I32 => {
+            TagValue::I64(i32::from_be_bytes(value[..].try_into().with_context(invalid)?).into())
+        }
+        AnnotationType::I64 => TagValue::I64(i64::from_be_bytes(
+            value[..].try_into().with_context(invalid)?,
+        )),
+        AnnotationType::Double => TagValue::F64(f64::from_bits(u64::from_be_bytes(
+            value[..].try_into().with_context(invalid)?,
+        ))),
+        AnnotationType::String => TagValue::String(String::from_utf8_lossy(value).into_owned()),
+    };
+
+    Ok(vec![Tag {
+        key: annotation.key.clone(),
+        value,
+    }])
+}
+
+fn peer_tags(endpoint: &zipkin::Endpoint) -> Vec<Tag> {
+    [
+        (!endpoint.service_name.is_empty()).then(|| Tag {
+            key: "peer.service".to_owned(),
+            value: TagValue::String(endpoint.service_name.clone()),
+        }),
+        (endpoint.ipv4 != 0).then(|| Tag {
+            key: "peer.ipv4".to_owned(),
+            value: TagValue::String(ipv4(endpoint.ipv4).to_string()),
+        }),
+        ipv6(endpoint.ipv6.as_deref()).map(|ip| Tag {
+            key: "peer.ipv6".to_owned(),
+            value: TagValue::String(ip.to_string()),
+        }),
+        (endpoint.port != 0).then(|| Tag {
+            key: "peer.port".to_owned(),
+            value: TagValue::I64(port(endpoint.port)),
+        }),
+    ]
+    .into_iter()
+    .flatten()
+    .collect()
+}
+
+/// Find the endpoint that describes the local service. Core annotations are preferred, then the
+/// local component annotation and finally any other annotation with an endpoint.
+fn process(
+    annotations: &[zipkin::Annotation],
+    binary_annotations: &[zipkin::BinaryAnnotation],
+) -> Process {
+    let endpoint = annotations
+        .iter()
+        .filter(|a| is_core(&a.value))
+        .find_map(|a| a.host.as_ref())
+        .or_else(|| {
+            binary_annotations
+                .iter()
+                .filter(|a| a.key == zipkin::LOCAL_COMPONENT)
+                .find_map(|a| a.host.as_ref())
+        })
+        .or_else(|| annotations.iter().find_map(|a| a.host.as_ref()))
+        .or_else(|| {
+            binary_annotations
+                .iter()
+                .filter(|a| a.key != zipkin::CLIENT_ADDR && a.key != zipkin::SERVER_ADDR)
+                .find_map(|a| a.host.as_ref())
+        });
+
+    let Some(endpoint) = endpoint else {
+        return Process {
+            service: UNKNOWN_SERVICE.to_owned(),
+            tags: Vec::new(),
+        };
+    };
+
+    Process {
+        service: if endpoint.service_name.is_empty() {
+            UNKNOWN_SERVICE.to_owned()
+        } else {
+            endpoint.service_name.clone()
+        },
+        tags: [
+            (endpoint.ipv4 != 0).then(|| Tag {
+                key: "ip".to_owned(),
+                value: TagValue::String(ipv4(endpoint.ipv4).to_string()),
+            }),
+            ipv6(endpoint.ipv6.as_deref()).map(|ip| Tag {
+                key: "ipv6".to_owned(),
+                value: TagValue::String(ip.to_string()),
+            }),
+        ]
+        .into_iter()
+        .flatten()
+        .collect(),
+    }
+}
+
+#[allow(clippy::cast_sign_loss)]
+fn ipv4(ip: i32) -> Ipv4Addr {
+    Ipv4Addr::from(ip as u32)
+}
+
+fn ipv6(ip: Option<&[u8]>) -> Option<Ipv6Addr> {
+    <[u8; 16]>::try_from(ip?).ok().map(Ipv6Addr::from)
+}
+
+/// Ports are transmitted as signed 16-bit integer, but must be interpreted as unsigned.
+#[allow(clippy::cast_sign_loss)]
+fn port(port: i16) -> i64 {
+    i64::from(port as u16)
+}
@@ -0,0 +1,197 @@
+//! Self-check of the installation, run with `archer doctor`. It finds common problems before
+//! they surface at runtime, and prints the effective configuration, which is helpful to include
+//! in bug reports.
+
+use std::{
+    fmt::Display,
+    net::{SocketAddr, TcpListener, UdpSocket},
+    path::Path,
+};
+
+use anyhow::{ensure, Context, Result};
+use time::{Duration, OffsetDateTime};
+
+use crate::{config::Config, net, quiver, storage, tls};
+
+/// Certificates that expire within this time frame are reported, so they can be renewed early.
+const EXPIRY_WARNING: Duration = Duration::days(30);
+
+pub async fn run(config: &Config) -> Result<()> {
+    println!("Effective configuration:\n{config:#?}\n");
+
+    let addrs = net::Addresses::new(&config.listen);
+    let mut report = Report::default();
+
+    check_database(&mut report, config).await;
+    if addrs.quiver.is_some() {
+        check_certificate(&mut report, config);
+    }
+    check_ports(&mut report, &addrs);
+
+    println!(
+        "\n{} problem(s), {} warning(s)",
+        report.problems, report.warnings
+    );
+
+    ensure!(report.problems == 0, "doctor found problems, see above");
+
+    Ok(())
+}
+
+#[derive(Default)]
+struct Report {
+    warnings: usize,
+    problems: usize,
+}
+
+impl Report {
+    #[allow(clippy::unused_self)]
+    fn ok(&mut self, msg: impl Display) {
+        println!("[ok]   {msg}");
+    }
+
+    fn warn(&mut self, msg: impl Display) {
+        self.warnings += 1;
+        println!("[warn] {msg}");
+    }
+
+    fn fail(&mut self, msg: impl Display) {
+        self.problems += 1;
+        println!("[fail] {msg}");
+    }
+}
+
+async fn check_database(report: &mut Report, config: &Config) {
+    match storage::check_integrity(&config.storage).await {
+        Ok(problems) if problems.is_empty() => report.ok("database integrity"),
+        Ok(problems) => {
+            for problem in problems {
+                report.fail(format_args!("database integrity: {problem}"));
+            }
+        }
+        Err(e) => report.fail(format_args!("database integrity: {e:#}")),
+    }
+}
+
+fn check_certificate(report: &mut Report, config: &Config) {
+    let settings = &config.quiver;
+    let path = match &settings.tls {
+        Some(tls) => tls.cert.clone(),
+        None => quiver::collector::generated_certificate_dir(settings).join("cert.pem"),
+    };
+
+    if settings.tls.is_none() && !path.exists() {
+        report.ok("quiver certificate: none yet, a self-signed one is generated on startup");
+    } else {
+        match certificate_validity(&path) {
+            Ok((not_before, _)) if not_before > OffsetDateTime::now_utc() => report.fail(
+                format_args!("quiver certificate: not valid before {not_before}"),
+            ),
+            Ok((_, not_after)) if not_after < OffsetDateTime::now_utc() => {
+                report.fail(format_args!("quiver certificate: expired at {not_after}"));
+            }
+            Ok((_, not_after)) if not_after - OffsetDateTime::now_utc() < EXPIRY_WARNING => {
+                report.warn(format_args!(
+                    "quiver certificate: expires soon at {not_after}"
+                ));
+            }
+            Ok((_, not_after)) => {
+                report.ok(format_args!("quiver certificate: valid until {not_after}"));
+            }
+            Err(e) => report.fail(format_args!("quiver certificate: {e:#}")),
+        }
+    }
+
+    if let Some(path) = &settings.client_ca {
+        match std::fs::read(path)
+            .with_context(|| format!("failed reading {}", path.display()))
+            .and_then(|pem| tls::certs(&pem))
+        {
+            Ok(certs) => report.ok(format_args!(
+                "quiver client CA: {} certificate(s)",
+                certs.len()
+            )),
+            Err(e) => report.fail(format_args!("quiver client CA: {e:#}")),
+        }
+    }
+}
+
+fn certificate_validity(path: &Path) -> Result<(OffsetDateTime, OffsetDateTime)> {
+    let pem = std::fs::read(path).with_context(|| format!("failed reading {}", path.display()))?;
+    let certs = tls::certs(&pem)?;
+
+    tls::validity(&certs[0])
+}
+
+/// Try binding each enabled listener, to find ports that are already taken. This naturally fails
+/// as well, if Archer itself is running at the same time.
+fn check_ports(report: &mut Report, addrs: &net::Addresses) {
+    let listeners = [
+        (
+            "jaeger agent compact",
+            addrs.jaeger_agent_compact,
+            Protocol::Udp,
+        ),
+        (
+            "jaeger agent binary",
+            addrs.jaeger_agent_binary,
+            Protocol::Udp,
+        ),
+        ("jaeger agent tcp", addrs.jaeger_agent_tcp, Protocol::Tcp),
+        ("jaeger agent http", addrs.jaeger_agent_http, Protocol::Tcp),
+        (
+            "jaeger collector http",
+            addrs.jaeger_collector_http,
+            Protocol::Tcp,
+        ),
+        (
+            "jaeger collector grpc",
+            addrs.jaeger_collector_grpc,
+            Protocol::Tcp,
+        ),
+        ("otlp http", addrs.otlp_http, Protocol::Tcp),
+        ("otlp grpc", addrs.otlp_grpc, Protocol::Tcp),
+        ("quiver", addrs.quiver, Protocol::Udp),
+        ("query", addrs.query, Protocol::Tcp),
+        ("query grpc", addrs.query_grpc, Protocol::Tcp),
+    ];
+
+    for (name, addr, protocol) in listeners {
+        let Some(addr) = addr else {
+            continue;
+        };
+
+        match protocol.bind(addr) {
+            Ok(()) => report.ok(format_args!(
+                "{name} port: {protocol}://{addr} is available"
+            )),
+            Err(e) => report.fail(format_args!(
+                "{name} port: {protocol}://{addr} is unavailable: {e}"
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Protocol {
+    Tcp,
+    Udp,
+}
+
+impl Protocol {
+    fn bind(self, addr: SocketAddr) -> std::io::Result<()> {
+        match self {
+            Self::Tcp => TcpListener::bind(addr).map(drop),
+            Self::Udp => UdpSocket::bind(addr).map(drop),
+        }
+    }
+}
+
+impl Display for Protocol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Tcp => "tcp",
+            Self::Udp => "udp",
+        })
+    }
+}
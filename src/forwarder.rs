@@ -0,0 +1,191 @@
+//! Forwarding of all received spans to an upstream OTLP collector, which allows to run Archer as
+//! local aggregator in front of a central collector.
+
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use archer_proto::{
+    opentelemetry::proto::{
+        collector::trace::v1::{
+            trace_service_client::TraceServiceClient, ExportTraceServiceRequest,
+        },
+        trace::v1::ResourceSpans,
+    },
+    tonic::{
+        self,
+        codegen::CompressionEncoding,
+        transport::{Channel, Endpoint},
+        Code,
+    },
+};
+use once_cell::sync::OnceCell;
+use rand::Rng;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio_shutdown::Shutdown;
+use tracing::{debug, error, info, instrument, warn};
+
+use crate::{
+    config, convert,
+    metrics::{self, DropReason},
+    models::Span,
+};
+
+static SENDER: OnceCell<mpsc::Sender<Vec<ResourceSpans>>> = OnceCell::new();
+
+/// Time to establish the connection to the upstream collector.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Time for a single export request, before it's considered failed.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Time to send the remaining queued spans on shutdown, after which they're dropped.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Queue the spans for forwarding. This is a no-op if the forwarder isn't enabled.
+pub fn forward(spans: &[Span]) {
+    let Some(sender) = SENDER.get() else {
+        return;
+    };
+
+    match sender.try_send(convert::span_to_otlp(spans)) {
+        Ok(()) => {}
+        Err(TrySendError::Closed(_)) => {
+            debug!(count = spans.len(), "forwarder stopped, dropping spans");
+            metrics::spans_dropped(DropReason::Forwarding, spans.len());
+        }
+        Err(TrySendError::Full(_)) => {
+            warn!(
+                count = spans.len(),
+                "forwarding queue is full, dropping spans"
+            );
+            metrics::spans_dropped(DropReason::Forwarding, spans.len());
+        }
+    }
+}
+
+#[instrument(name = "forwarder", skip_all)]
+pub async fn run(shutdown: Shutdown, config: Option<config::Forwarder>) -> Result<()> {
+    let Some(config) = config else {
+        return Ok(());
+    };
+
+    let channel = Endpoint::from_shared(config.endpoint.clone())?
+        .connect_timeout(CONNECT_TIMEOUT)
+        .timeout(REQUEST_TIMEOUT)
+        .connect_lazy();
+    let mut client = TraceServiceClient::new(channel).send_compressed(CompressionEncoding::Gzip);
+
+    let (tx, mut rx) = mpsc::channel(config.queue_size);
+    SENDER
+        .set(tx)
+        .map_err(|_| anyhow!("forwarder can only be started once"))?;
+
+    info!(endpoint = %config.endpoint, "forwarding spans");
+
+    let mut pending = None;
+
+    loop {
+        let batch = tokio::select! {
+            () = shutdown.handle() => break,
+            batch = rx.recv() => match batch {
+                Some(batch) => batch,
+                None => break,
+            },
+        };
+
+        let interrupted = tokio::select! {
+            () = shutdown.handle() => true,
+            () = export(&mut client, &batch, config.max_retries) => false,
+        };
+
+        if interrupted {
+            pending = Some(batch);
+            break;
+        }
+    }
+
+    drain(&mut client, &mut rx, pending).await;
+
+    info!("forwarder stopped");
+
+    Ok(())
+}
+
+/// Send the spans that are still queued on shutdown, without retries. Whatever isn't sent in time
+/// is dropped.
+async fn drain(
+    client: &mut TraceServiceClient<Channel>,
+    rx: &mut mpsc::Receiver<Vec<ResourceSpans>>,
+    mut pending: Option<Vec<ResourceSpans>>,
+) {
+    rx.close();
+
+    let deadline = tokio::time::Instant::now() + DRAIN_TIMEOUT;
+
+    while let Some(batch) = pending.take().or_else(|| rx.try_recv().ok()) {
+        if tokio::time::timeout_at(deadline, export(client, &batch, 0))
+            .await
+            .is_err()
+        {
+            let mut count = convert::span_from_otlp_len(&batch);
+            while let Ok(batch) = rx.try_recv() {
+                count += convert::span_from_otlp_len(&batch);
+            }
+
+            warn!(
+                count,
+                "failed forwarding queued spans in time, dropping them"
+            );
+            metrics::spans_dropped(DropReason::Forwarding, count);
+            break;
+        }
+    }
+}
+
+/// Send the spans upstream, retrying with exponential backoff for any errors that are considered
+/// temporary by the OTLP specification.
+async fn export(
+    client: &mut TraceServiceClient<Channel>,
+    resource_spans: &[ResourceSpans],
+    max_retries: u32,
+) {
+    let mut backoff = Duration::from_millis(500);
+
+    for attempt in 0..=max_retries {
+        let request = ExportTraceServiceRequest {
+            resource_spans: resource_spans.to_vec(),
+        };
+
+        let Err(status) = client.export(request).await else {
+            return;
+        };
+
+        if !retryable(&status) || attempt == max_retries {
+            error!(error = ?status, "failed forwarding spans");
+            break;
+        }
+
+        // Randomized, so many instances don't retry against the upstream at the same time.
+        let delay = backoff.mul_f64(rand::thread_rng().gen_range(0.5..=1.0));
+        warn!(error = ?status, ?delay, "failed forwarding spans, retrying");
+
+        tokio::time::sleep(delay).await;
+        backoff = (backoff * 2).min(Duration::from_secs(30));
+    }
+
+    metrics::spans_dropped(
+        DropReason::Forwarding,
+        convert::span_from_otlp_len(resource_spans),
+    );
+}
+
+fn retryable(status: &tonic::Status) -> bool {
+    matches!(
+        status.code(),
+        Code::Cancelled
+            | Code::DeadlineExceeded
+            | Code::ResourceExhausted
+            | Code::Aborted
+            | Code::OutOfRange
+            | Code::Unavailable
+            | Code::DataLoss
+    )
+}
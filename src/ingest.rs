@@ -0,0 +1,55 @@
+//! Common path of received spans, shared by all collectors once they converted a batch. It decides
+//! the order in which the processor and the limits see the spans, records the batch in the audit
+//! log, and hands it to the forwarder and the storage.
+
+use tracing::error;
+
+use crate::{
+    audit::{self, Origin, Outcome},
+    forwarder, limits,
+    models::Span,
+    processor,
+    ratelimit::{self, Exhausted},
+    storage::Database,
+    tasks,
+};
+
+/// Get a batch of `count` received spans of the tenant ready for storage, and forward it. Only
+/// batches accepted by the rate limit are counted against the per-trace limit, so a rejected and
+/// later retried batch doesn't use up the trace's budget.
+pub fn prepare(
+    tenant: &str,
+    origin: Origin,
+    count: usize,
+    mut spans: Vec<Span>,
+) -> Result<Vec<Span>, Exhausted> {
+    processor::apply(&mut spans);
+
+    let result = ratelimit::check(&mut spans);
+    audit::record(origin, count, &spans, Outcome::rate_limit(&result));
+    result?;
+
+    limits::apply(tenant, &mut spans);
+    forwarder::forward(&spans);
+
+    Ok(spans)
+}
+
+/// Like [`prepare`], but saves the spans in the background, for collectors that don't confirm
+/// whether a batch was stored.
+pub fn accept(
+    db: Database,
+    origin: Origin,
+    count: usize,
+    spans: Vec<Span>,
+) -> Result<(), Exhausted> {
+    let spans = prepare(db.tenant(), origin, count, spans)?;
+
+    tasks::spawn(async move {
+        if let Err(e) = db.save_spans(spans).await {
+            error!(error = ?e, "failed to save spans to DB");
+        }
+    });
+
+    Ok(())
+}
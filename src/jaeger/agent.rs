@@ -1,45 +1,101 @@
-use std::{net::SocketAddr, time::Instant};
+use std::{
+    cell::Cell,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use anyhow::Result;
+use archer_http::axum::{
+    body::Bytes,
+    extract::{ConnectInfo, DefaultBodyLimit, State},
+    http::StatusCode,
+    routing::post,
+    Router,
+};
 use archer_thrift::{
     agent::{AgentSyncHandler, AgentSyncProcessor},
-    jaeger,
-    thrift::{
-        self,
-        protocol::{
-            TBinaryInputProtocol, TBinaryOutputProtocol, TCompactInputProtocol,
-            TCompactOutputProtocol,
-        },
-    },
+    jaeger, thrift, zipkincore, TBinarySliceInputProtocol, TCompactSliceInputProtocol,
 };
-use bytes::BytesMut;
-use futures_util::{SinkExt, StreamExt};
-use tokio::net::UdpSocket;
+use futures_util::{future, StreamExt};
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
 use tokio_shutdown::Shutdown;
-use tokio_util::{codec::BytesCodec, udp::UdpFramed};
-use tracing::{debug_span, error, info, instrument, warn, Span};
+use tokio_util::{
+    codec::{BytesCodec, FramedRead, LengthDelimitedCodec, LengthDelimitedCodecError},
+    udp::UdpFramed,
+};
+use tracing::{debug, debug_span, error, info, instrument, warn, Span};
 
-use crate::{convert, net, storage::Database};
+use crate::{
+    audit::{self, Origin, Outcome},
+    config, convert, ingest,
+    metrics::{self, DropReason, PacketError, Receiver},
+    models, net,
+    ratelimit::Exhausted,
+    storage::Database,
+};
+
+thread_local! {
+    /// Origin of the message, that is currently processed. The Thrift processor gives handlers no
+    /// access to the request, so it's passed to them on the side.
+    static ORIGIN: Cell<Option<Origin>> = const { Cell::new(None) };
+}
+
+/// Run the processing of a message, with its origin available to the handler.
+fn with_origin<T>(origin: Origin, f: impl FnOnce() -> T) -> T {
+    ORIGIN.set(Some(origin));
+    let result = f();
+    ORIGIN.set(None);
+    result
+}
 
 #[instrument(name = "agent", skip_all)]
-pub async fn run(shutdown: Shutdown, database: Database) -> Result<()> {
-    let (compact, binary) = tokio::try_join!(
+pub async fn run(
+    shutdown: Shutdown,
+    database: Database,
+    settings: config::Agent,
+    addrs: net::Addresses,
+) -> Result<()> {
+    let (compact, binary, tcp, http) = tokio::try_join!(
         tokio::spawn(run_compact(
             Span::current(),
             shutdown.clone(),
             database.clone(),
-            SocketAddr::from(net::JAEGER_AGENT_COMPACT),
+            addrs.jaeger_agent_compact,
+            settings.max_packet_size,
+            settings.udp_receive_buffer,
+            settings.udp_workers,
         )),
         tokio::spawn(run_binary(
+            Span::current(),
+            shutdown.clone(),
+            database.clone(),
+            addrs.jaeger_agent_binary,
+            settings.max_packet_size,
+            settings.udp_receive_buffer,
+            settings.udp_workers,
+        )),
+        tokio::spawn(run_tcp(
+            Span::current(),
+            shutdown.clone(),
+            database.clone(),
+            addrs.jaeger_agent_tcp,
+            settings.max_batch_size,
+        )),
+        tokio::spawn(run_http(
             Span::current(),
             shutdown,
             database,
-            SocketAddr::from(net::JAEGER_AGENT_BINARY),
+            addrs.jaeger_agent_http,
+            settings.max_batch_size,
         )),
     )?;
 
     compact?;
     binary?;
+    tcp?;
+    http?;
 
     Ok(())
 }
@@ -49,17 +105,31 @@ async fn run_compact(
     parent: Span,
     shutdown: Shutdown,
     database: Database,
-    addr: SocketAddr,
+    addr: Option<SocketAddr>,
+    max_packet_size: usize,
+    receive_buffer: Option<usize>,
+    workers: usize,
 ) -> Result<()> {
-    let socket = UdpSocket::bind(addr).await?;
-    info!("listening on http://{addr}");
+    let Some(addr) = addr else {
+        return Ok(());
+    };
 
-    run_udp_server(shutdown, database, socket, |processor, input, output| {
-        processor.process(
-            &mut TCompactInputProtocol::new(input),
-            &mut TCompactOutputProtocol::new(output),
-        )
-    })
+    let socket = bind_udp(addr, receive_buffer)?;
+    info!("listening on udp://{addr}");
+
+    let handler = Handler {
+        db: database,
+        receiver: Receiver::JaegerAgentCompact,
+    };
+
+    run_udp_server(
+        shutdown,
+        handler,
+        socket,
+        max_packet_size,
+        workers,
+        |processor, input| processor.process(&mut TCompactSliceInputProtocol::new(input)),
+    )
     .await;
 
     info!("server stopped");
@@ -72,17 +142,31 @@ async fn run_binary(
     parent: Span,
     shutdown: Shutdown,
     database: Database,
-    addr: SocketAddr,
+    addr: Option<SocketAddr>,
+    max_packet_size: usize,
+    receive_buffer: Option<usize>,
+    workers: usize,
 ) -> Result<()> {
-    let socket = UdpSocket::bind(addr).await?;
-    info!("listening on http://{addr}");
+    let Some(addr) = addr else {
+        return Ok(());
+    };
 
-    run_udp_server(shutdown, database, socket, |processor, input, output| {
-        processor.process(
-            &mut TBinaryInputProtocol::new(input, true),
-            &mut TBinaryOutputProtocol::new(output, true),
-        )
-    })
+    let socket = bind_udp(addr, receive_buffer)?;
+    info!("listening on udp://{addr}");
+
+    let handler = Handler {
+        db: database,
+        receiver: Receiver::JaegerAgentBinary,
+    };
+
+    run_udp_server(
+        shutdown,
+        handler,
+        socket,
+        max_packet_size,
+        workers,
+        |processor, input| processor.process(&mut TBinarySliceInputProtocol::new(input)),
+    )
     .await;
 
     info!("server stopped");
@@ -90,19 +174,289 @@ async fn run_binary(
     Ok(())
 }
 
-async fn run_udp_server(
+#[instrument(name = "tcp", parent = parent, skip_all)]
+async fn run_tcp(
+    parent: Span,
+    shutdown: Shutdown,
+    database: Database,
+    addr: Option<SocketAddr>,
+    max_batch_size: usize,
+) -> Result<()> {
+    let Some(addr) = addr else {
+        return Ok(());
+    };
+
+    let listener = TcpListener::bind(addr).await?;
+    info!("listening on tcp://{addr}");
+
+    let processor = Arc::new(AgentSyncProcessor::new(Handler {
+        db: database,
+        receiver: Receiver::JaegerAgentTcp,
+    }));
+
+    loop {
+        let (stream, peer) = tokio::select! {
+            () = shutdown.handle() => break,
+            res = listener.accept() => match res {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    error!(error = ?err, "failed accepting connection");
+                    continue;
+                }
+            },
+        };
+
+        tokio::spawn(handle_tcp_connection(
+            shutdown.clone(),
+            Arc::clone(&processor),
+            stream,
+            peer,
+            max_batch_size,
+        ));
+    }
+
+    info!("server stopped");
+
+    Ok(())
+}
+
+/// Process batches from a single TCP connection, where each batch is a Thrift message in either
+/// the compact or binary protocol, prefixed with its length as 4-byte big-endian integer (the
+/// same framing as Thrift's `TFramedTransport`).
+async fn handle_tcp_connection(
+    shutdown: Shutdown,
+    processor: Arc<AgentSyncProcessor<Handler>>,
+    stream: TcpStream,
+    peer: SocketAddr,
+    max_batch_size: usize,
+) {
+    let mut framed = FramedRead::new(
+        stream,
+        LengthDelimitedCodec::builder()
+            .max_frame_length(max_batch_size)
+            .new_codec(),
+    );
+
+    loop {
+        let frame = tokio::select! {
+            () = shutdown.handle() => break,
+            res = framed.next() => match res {
+                Some(Ok(frame)) => frame,
+                Some(Err(err)) => {
+                    warn!(error = ?err, "failed receiving data, closing connection");
+                    if matches!(err.get_ref(), Some(e) if e.is::<LengthDelimitedCodecError>()) {
+                        metrics::packet_rejected(Receiver::JaegerAgentTcp, PacketError::TooLarge);
+                    }
+                    break;
+                }
+                None => break,
+            },
+        };
+
+        let origin = Origin {
+            receiver: Receiver::JaegerAgentTcp,
+            peer: Some(peer),
+            bytes: Some(frame.len()),
+        };
+
+        match with_origin(origin, || process_message(&processor, &frame)) {
+            Ok(()) => {}
+            Err(err) if rate_limited(&err) => debug!(error = ?err, "dropping spans"),
+            Err(err) => {
+                error!(error = ?err, "failed to process request");
+                metrics::packet_rejected(Receiver::JaegerAgentTcp, PacketError::from(&err));
+            }
+        }
+    }
+}
+
+#[instrument(name = "http", parent = parent, skip_all)]
+async fn run_http(
+    parent: Span,
     shutdown: Shutdown,
     database: Database,
+    addr: Option<SocketAddr>,
+    max_batch_size: usize,
+) -> Result<()> {
+    let Some(addr) = addr else {
+        return Ok(());
+    };
+
+    let processor = Arc::new(AgentSyncProcessor::new(Handler {
+        db: database,
+        receiver: Receiver::JaegerAgentHttp,
+    }));
+
+    let app = Router::new()
+        .route("/emitBatch", post(emit_batch))
+        .layer(DefaultBodyLimit::max(max_batch_size))
+        .with_state(processor);
+
+    net::serve(addr, app, None, shutdown).await?;
+
+    info!("server stopped");
+
+    Ok(())
+}
+
+/// Accept a single Thrift message in either the compact or binary protocol, the same as it
+/// would be sent over UDP, but without the size limit of a single packet.
+async fn emit_batch(
+    State(processor): State<Arc<AgentSyncProcessor<Handler>>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    body: Bytes,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let origin = Origin {
+        receiver: Receiver::JaegerAgentHttp,
+        peer: Some(peer),
+        bytes: Some(body.len()),
+    };
+
+    match with_origin(origin, || process_message(&processor, &body)) {
+        Ok(()) => Ok(StatusCode::ACCEPTED),
+        Err(err) if rate_limited(&err) => Err((StatusCode::TOO_MANY_REQUESTS, err.to_string())),
+        Err(err) => {
+            metrics::packet_rejected(Receiver::JaegerAgentHttp, PacketError::from(&err));
+            Err((StatusCode::BAD_REQUEST, err.to_string()))
+        }
+    }
+}
+
+/// Whether the spans of a message were rejected by the rate limit, in which case the message
+/// itself is fine, and the spans are already counted as dropped.
+fn rate_limited(err: &thrift::Error) -> bool {
+    matches!(err, thrift::Error::User(e) if e.is::<Exhausted>())
+}
+
+/// Process a Thrift message, detecting the protocol from its first bytes.
+fn process_message(processor: &AgentSyncProcessor<Handler>, input: &[u8]) -> thrift::Result<()> {
+    match input {
+        [0x82, ..] => processor.process(&mut TCompactSliceInputProtocol::new(input)),
+        [0x80, 0x01, ..] => processor.process(&mut TBinarySliceInputProtocol::new(input)),
+        _ => Err(thrift::new_protocol_error(
+            thrift::ProtocolErrorKind::InvalidData,
+            "message is neither in compact nor binary protocol",
+        )),
+    }
+}
+
+/// Bind a UDP socket, with a custom receive buffer size if set.
+fn bind_udp(addr: SocketAddr, receive_buffer: Option<usize>) -> Result<UdpSocket> {
+    let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+
+    if let Some(size) = receive_buffer {
+        socket.set_recv_buffer_size(size)?;
+
+        // Linux doubles the value to make room for bookkeeping, so only a smaller value than
+        // requested means that the OS capped it.
+        let actual = socket.recv_buffer_size()?;
+        if actual < size {
+            warn!(
+                requested = size,
+                actual, "receive buffer size was capped by the OS"
+            );
+        }
+    }
+
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+
+    Ok(UdpSocket::from_std(socket.into())?)
+}
+
+/// Total amount of packets, that the OS dropped for the socket so far. Only Linux exposes this
+/// counter, in the last column of `/proc/net/udp`, where the socket is identified by its inode.
+#[cfg(target_os = "linux")]
+fn os_drops(socket: &UdpSocket) -> Option<u64> {
+    use std::{fs, os::unix::prelude::*};
+
+    let inode = fs::metadata(format!("/proc/self/fd/{}", socket.as_raw_fd()))
+        .ok()?
+        .ino()
+        .to_string();
+
+    ["/proc/net/udp", "/proc/net/udp6"]
+        .into_iter()
+        .filter_map(|path| fs::read_to_string(path).ok())
+        .find_map(|table| {
+            table.lines().skip(1).find_map(|line| {
+                let fields = line.split_whitespace().collect::<Vec<_>>();
+                (fields.get(9) == Some(&inode.as_str()))
+                    .then(|| fields.get(12)?.parse().ok())
+                    .flatten()
+            })
+        })
+}
+
+#[cfg(not(target_os = "linux"))]
+fn os_drops(_socket: &UdpSocket) -> Option<u64> {
+    None
+}
+
+/// Receive packets with multiple workers that share the socket, so decoding isn't limited to a
+/// single core. The OS drop counter of the socket is checked periodically until shutdown.
+async fn run_udp_server(
+    shutdown: Shutdown,
+    handler: Handler,
     socket: UdpSocket,
-    process: impl Fn(&AgentSyncProcessor<Handler>, &[u8], &mut [u8]) -> Result<(), thrift::Error>,
+    max_packet_size: usize,
+    workers: usize,
+    process: fn(&AgentSyncProcessor<Handler>, &[u8]) -> thrift::Result<()>,
+) {
+    let socket = Arc::new(socket);
+    let receiver = handler.receiver;
+
+    let workers = (0..workers.max(1))
+        .map(|_| {
+            tokio::spawn(run_udp_worker(
+                shutdown.clone(),
+                handler.clone(),
+                Arc::clone(&socket),
+                max_packet_size,
+                process,
+            ))
+        })
+        .collect::<Vec<_>>();
+
+    let mut drops = os_drops(&socket).unwrap_or_default();
+    let mut interval = tokio::time::interval(Duration::from_secs(10));
+
+    loop {
+        tokio::select! {
+            () = shutdown.handle() => break,
+            _ = interval.tick() => {
+                if let Some(total) = os_drops(&socket) {
+                    if total > drops {
+                        warn!(count = total - drops, "packets dropped due to full receive buffer");
+                        metrics::packets_dropped(receiver, total - drops);
+                    }
+                    drops = total;
+                }
+            }
+        }
+    }
+
+    for worker in future::join_all(workers).await {
+        if let Err(e) = worker {
+            error!(error = ?e, "UDP worker failed");
+        }
+    }
+}
+
+async fn run_udp_worker(
+    shutdown: Shutdown,
+    handler: Handler,
+    socket: Arc<UdpSocket>,
+    max_packet_size: usize,
+    process: fn(&AgentSyncProcessor<Handler>, &[u8]) -> thrift::Result<()>,
 ) {
     let mut framed = UdpFramed::new(socket, BytesCodec::new());
-    let mut output = BytesMut::new();
-    let processor = AgentSyncProcessor::new(Handler(database));
+    let receiver = handler.receiver;
+    let processor = AgentSyncProcessor::new(handler);
 
     loop {
-        let (frame, addr) = tokio::select! {
-            _ = shutdown.handle() => break,
+        let (frame, peer) = tokio::select! {
+            () = shutdown.handle() => break,
             res = framed.next() => match res {
                 Some(Ok(res)) => res,
                 Some(Err(err)) => {
@@ -113,57 +467,96 @@ async fn run_udp_server(
             },
         };
 
-        let success = debug_span!(parent: None, "request").in_scope(|| {
+        // Packets that exceed the limit are likely cut off somewhere on the way, or by the
+        // sender already, so they're rejected before wasting time on decoding them.
+        if frame.len() > max_packet_size {
+            warn!(
+                size = frame.len(),
+                max_packet_size, "rejecting oversized packet"
+            );
+            metrics::packet_rejected(receiver, PacketError::TooLarge);
+            continue;
+        }
+
+        debug_span!(parent: None, "request").in_scope(|| {
             let now = Instant::now();
             tracing::debug!("started processing request");
 
-            if let Err(err) = (process)(&processor, &frame, &mut output) {
-                error!(error = ?err, "failed to process request");
-                return false;
+            let origin = Origin {
+                receiver,
+                peer: Some(peer),
+                bytes: Some(frame.len()),
+            };
+
+            match with_origin(origin, || (process)(&processor, &frame)) {
+                Ok(()) => {}
+                // UDP has no way of signaling backpressure, so the spans are silently dropped.
+                Err(err) if rate_limited(&err) => {
+                    debug!(error = ?err, "dropping spans");
+                    return;
+                }
+                Err(err) => {
+                    error!(error = ?err, "failed to process request");
+                    metrics::packet_rejected(receiver, PacketError::from(&err));
+                    return;
+                }
             }
 
             let latency = format!("{} ms", now.elapsed().as_millis());
             tracing::debug!(%latency, "finished processing request");
-
-            true
         });
+    }
+}
 
-        if !success {
-            continue;
-        }
+#[derive(Clone)]
+struct Handler {
+    db: Database,
+    receiver: Receiver,
+}
 
-        let output = output.split().freeze();
+impl Handler {
+    fn save(&self, count: usize, spans: Result<Vec<models::Span>>) -> thrift::Result<()> {
+        metrics::spans_received(self.receiver, count);
 
-        if !output.is_empty() {
-            if let Err(err) = framed.send((output, addr)).await {
-                error!(error = ?err, "failed to send back response");
-            }
-        }
+        let origin = ORIGIN.get().unwrap_or(Origin {
+            receiver: self.receiver,
+            peer: None,
+            bytes: None,
+        });
+
+        let spans = spans.map_err(|e| {
+            warn!(error = ?e, "failed converting spans");
+            metrics::spans_dropped(DropReason::Conversion, count);
+            audit::record(origin, count, &[], Outcome::Invalid);
+            thrift::Error::User(e.into())
+        })?;
+
+        ingest::accept(self.db.clone(), origin, count, spans)
+            .map_err(|e| thrift::Error::User(e.into()))
     }
 }
 
-struct Handler(Database);
-
 impl AgentSyncHandler for Handler {
     #[instrument(skip_all)]
-    fn handle_emit_batch(&self, batch: jaeger::Batch) -> thrift::Result<()> {
-        let spans = batch
-            .spans
-            .into_iter()
-            .map(|span| convert::span_from_thrift(span, batch.process.clone()))
-            .collect::<Result<Vec<_>>>()
-            .map_err(|e| {
-                warn!(error = ?e, "failed converting spans");
-                thrift::Error::User(e.into())
-            })?;
-        let db = self.0.clone();
-
-        tokio::spawn(async move {
-            if let Err(e) = db.save_spans(spans).await {
-                error!(error = ?e, "failed to save spans to DB");
-            }
-        });
+    fn handle_emit_zipkin_batch(&self, spans: Vec<zipkincore::Span>) -> thrift::Result<()> {
+        self.save(
+            spans.len(),
+            spans
+                .into_iter()
+                .map(convert::span_from_zipkin)
+                .collect::<Result<Vec<_>>>(),
+        )
+    }
 
-        Ok(())
+    #[instrument(skip_all)]
+    fn handle_emit_batch(&self, batch: jaeger::Batch<'_>) -> thrift::Result<()> {
+        self.save(
+            batch.spans.len(),
+            batch
+                .spans
+                .into_iter()
+                .map(|span| convert::span_from_thrift(span, batch.process.clone()))
+                .collect::<Result<Vec<_>>>(),
+        )
     }
 }
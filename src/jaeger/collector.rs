@@ -1,18 +1,29 @@
-use std::{io::Read, net::SocketAddr};
+use std::{
+    io::{self, BufReader},
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
 
 use anyhow::Result;
 use archer_http::{
     axum::{
         async_trait,
         body::{Bytes, HttpBody},
-        extract::{rejection::BytesRejection, FromRequest, State},
-        http::{Request, StatusCode},
+        error_handling::HandleErrorLayer,
+        extract::{BodyStream, ConnectInfo, DefaultBodyLimit, FromRef, FromRequest, State},
+        http::{
+            header::{CONTENT_LENGTH, CONTENT_TYPE},
+            HeaderMap, Request, StatusCode,
+        },
         response::{IntoResponse, Response},
         routing::post,
-        BoxError, Router, Server,
+        BoxError, Router,
     },
     tower::ServiceBuilder,
-    tower_http::ServiceBuilderExt,
+    tower_http::{decompression::RequestDecompressionLayer, ServiceBuilderExt},
 };
 use archer_proto::{
     jaeger::api_v2::{
@@ -20,28 +31,53 @@ use archer_proto::{
         collector_service_server::{self, CollectorServiceServer},
         PostSpansRequest, PostSpansResponse,
     },
+    prost::Message,
     tonic::{self, codegen::CompressionEncoding},
 };
-use archer_thrift::{jaeger::Batch, thrift::protocol::TBinaryInputProtocol};
+use archer_thrift::{
+    jaeger::{self, Batch},
+    thrift::protocol::{TBinaryInputProtocol, TCompactInputProtocol},
+    TBinarySliceInputProtocol, TCompactSliceInputProtocol,
+};
+use futures_util::StreamExt;
+use mime::Mime;
 use tokio_shutdown::Shutdown;
+use tokio_util::io::{StreamReader, SyncIoBridge};
 use tracing::{error, info, instrument, warn};
 
-use crate::{convert, net, storage::Database};
+use crate::{
+    audit::{self, Origin, Outcome},
+    config, convert, ingest,
+    metrics::{self, DropReason, Receiver},
+    net,
+    storage::Database,
+    tenancy,
+};
 
 #[instrument(name = "collector", skip_all)]
-pub async fn run(shutdown: Shutdown, database: Database) -> Result<()> {
+pub async fn run(
+    shutdown: Shutdown,
+    database: Database,
+    settings: config::Collector,
+    tls: Option<Arc<rustls::ServerConfig>>,
+    addrs: net::Addresses,
+) -> Result<()> {
     let (http, grpc) = tokio::try_join!(
         tokio::spawn(run_http(
             tracing::Span::current(),
             shutdown.clone(),
-            database.clone(),
-            SocketAddr::from(net::JAEGER_COLLECTOR_HTTP),
+            AppState {
+                database: database.clone(),
+                max_body_size: MaxBodySize(settings.max_body_size),
+            },
+            addrs.jaeger_collector_http,
+            tls,
         )),
         tokio::spawn(run_grpc(
             tracing::Span::current(),
             shutdown,
             database,
-            SocketAddr::from(net::JAEGER_COLLECTOR_GRPC),
+            addrs.jaeger_collector_grpc,
         ))
     )?;
 
@@ -51,24 +87,53 @@ pub async fn run(shutdown: Shutdown, database: Database) -> Result<()> {
     Ok(())
 }
 
+#[derive(Clone)]
+struct AppState {
+    database: Database,
+    max_body_size: MaxBodySize,
+}
+
+impl FromRef<AppState> for Database {
+    fn from_ref(input: &AppState) -> Self {
+        input.database.clone()
+    }
+}
+
+impl FromRef<AppState> for MaxBodySize {
+    fn from_ref(input: &AppState) -> Self {
+        input.max_body_size
+    }
+}
+
+/// Upper limit for the size of request bodies, in bytes.
+#[derive(Clone, Copy)]
+struct MaxBodySize(usize);
+
 #[instrument(name = "http", parent = parent, skip_all)]
 async fn run_http(
     parent: tracing::Span,
     shutdown: Shutdown,
-    database: Database,
-    addr: SocketAddr,
+    state: AppState,
+    addr: Option<SocketAddr>,
+    tls: Option<Arc<rustls::ServerConfig>>,
 ) -> Result<()> {
-    info!("listening on http://{addr}");
+    let Some(addr) = addr else {
+        return Ok(());
+    };
 
     let app = Router::new()
         .route("/api/traces", post(traces))
-        .layer(ServiceBuilder::new().compression().trace_for_http())
-        .with_state(database);
+        .layer(DefaultBodyLimit::max(state.max_body_size.0))
+        .layer(
+            ServiceBuilder::new()
+                .layer(HandleErrorLayer::new(net::handle_error))
+                .layer(RequestDecompressionLayer::new())
+                .compression()
+                .trace_for_http(),
+        )
+        .with_state(state);
 
-    Server::bind(&addr)
-        .serve(app.into_make_service())
-        .with_graceful_shutdown(shutdown.handle())
-        .await?;
+    net::serve(addr, app, tls, shutdown).await?;
 
     info!("server stopped");
 
@@ -77,8 +142,33 @@ async fn run_http(
 
 async fn traces(
     State(db): State<Database>,
-    Thrift(batch): Thrift<Batch>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    body: ThriftBody,
 ) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let origin = Origin {
+        receiver: Receiver::JaegerHttp,
+        peer: Some(peer),
+        bytes: body.size,
+    };
+
+    // Strings of buffered bodies are borrowed, and only copied once, when converting the spans.
+    let batch = match body.payload {
+        Payload::Buffered(protocol, ref bytes) => match protocol {
+            Protocol::Binary => {
+                jaeger::read_batch_borrowed(&mut TBinarySliceInputProtocol::new(bytes))
+            }
+            Protocol::Compact => {
+                jaeger::read_batch_borrowed(&mut TCompactSliceInputProtocol::new(bytes))
+            }
+        }
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?,
+        Payload::Streamed(batch) => batch,
+    };
+
+    let count = batch.spans.len();
+    metrics::spans_received(Receiver::JaegerHttp, count);
+
     let spans = batch
         .spans
         .into_iter()
@@ -86,66 +176,167 @@ async fn traces(
         .collect::<Result<Vec<_>>>()
         .map_err(|e| {
             error!(error = ?e, "failed converting spans");
+            metrics::spans_dropped(DropReason::Conversion, count);
+            audit::record(origin, count, &[], Outcome::Invalid);
             (StatusCode::BAD_REQUEST, e.to_string())
         })?;
 
-    tokio::spawn(async move {
-        if let Err(e) = db.save_spans(spans).await {
-            error!(error = ?e, "failed to save spans to DB");
-        }
-    });
+    let db = db.for_tenant(tenancy::from_headers(&headers));
+
+    ingest::accept(db, origin, count, spans)
+        .map_err(|e| (StatusCode::TOO_MANY_REQUESTS, e.to_string()))?;
 
     Ok(StatusCode::ACCEPTED)
 }
 
-struct Thrift<T>(pub T);
+/// Bodies of known size up to this limit are buffered and decoded in place. Larger ones, or ones
+/// without a content length, are decoded while they're received.
+const BUFFERED_BODY_SIZE: usize = 64 * 1024;
+
+/// Thrift encoded request body, in the protocol given by the content type.
+struct ThriftBody {
+    payload: Payload,
+    /// Size of the body in bytes, if known.
+    size: Option<usize>,
+}
+
+enum Payload {
+    /// Small body, that is fully buffered in memory and decoded without copying.
+    Buffered(Protocol, Bytes),
+    /// Large body, that was decoded from the stream, without buffering it as a whole.
+    Streamed(Batch<'static>),
+}
 
 #[async_trait]
-impl<T, S, B> FromRequest<S, B> for Thrift<T>
+impl<S, B> FromRequest<S, B> for ThriftBody
 where
-    T: ThriftDeserialize,
     B: HttpBody + Send + 'static,
-    B::Data: Send,
+    B::Data: Into<Bytes> + Send,
     B::Error: Into<BoxError>,
     S: Send + Sync,
+    MaxBodySize: FromRef<S>,
 {
     type Rejection = ThriftRejection;
 
     async fn from_request(req: Request<B>, state: &S) -> Result<Self, Self::Rejection> {
-        let bytes = Bytes::from_request(req, state).await?;
-        let value = T::deserialize(&bytes[..])?;
+        let MaxBodySize(limit) = MaxBodySize::from_ref(state);
+        let protocol = protocol(req.headers())
+            .map_err(|(status, message)| ThriftRejection::Protocol(status, message))?;
+
+        let content_length = req
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok()?.parse::<usize>().ok());
+
+        match content_length {
+            Some(len) if len > limit => return Err(ThriftRejection::TooLarge),
+            Some(len) if len <= BUFFERED_BODY_SIZE => {
+                let bytes = Bytes::from_request(req, state)
+                    .await
+                    .map_err(|_| ThriftRejection::TooLarge)?;
+                return Ok(Self {
+                    size: Some(bytes.len()),
+                    payload: Payload::Buffered(protocol, bytes),
+                });
+            }
+            _ => {}
+        }
+
+        let Ok(body) = BodyStream::from_request(req, state).await;
+
+        // The content length is optional, so the limit is enforced on the received data as well.
+        let exceeded = Arc::new(AtomicBool::new(false));
+        let mut received = 0;
+        let body = body.map({
+            let exceeded = Arc::clone(&exceeded);
+            move |chunk| {
+                let chunk = chunk.map_err(io::Error::other)?;
+                received += chunk.len();
+
+                if received > limit {
+                    exceeded.store(true, Ordering::Relaxed);
+                    return Err(io::Error::other("request body too large"));
+                }
+
+                Ok(chunk)
+            }
+        });
+
+        let reader = BufReader::new(SyncIoBridge::new(StreamReader::new(body)));
+        let result = tokio::task::spawn_blocking(move || match protocol {
+            Protocol::Binary => jaeger::read_batch(&mut TBinaryInputProtocol::new(reader, true)),
+            Protocol::Compact => jaeger::read_batch(&mut TCompactInputProtocol::new(reader)),
+        })
+        .await?;
 
-        Ok(Self(value))
+        match result {
+            Ok(batch) => Ok(Self {
+                payload: Payload::Streamed(batch),
+                size: content_length,
+            }),
+            Err(_) if exceeded.load(Ordering::Relaxed) => Err(ThriftRejection::TooLarge),
+            Err(e) => Err(e.into()),
+        }
     }
 }
 
 #[derive(Debug, thiserror::Error)]
 enum ThriftRejection {
-    #[error("{0}")]
-    Bytes(#[from] BytesRejection),
+    #[error("Request body exceeds the size limit")]
+    TooLarge,
+    #[error("{1}")]
+    Protocol(StatusCode, String),
+    #[error("Failed to read the request body")]
+    Read(#[from] tokio::task::JoinError),
     #[error("Failed to parse the request body as Thrift message")]
     Decode(#[from] archer_thrift::thrift::Error),
 }
 
 impl IntoResponse for ThriftRejection {
     fn into_response(self) -> Response {
-        StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        match self {
+            Self::TooLarge => StatusCode::PAYLOAD_TOO_LARGE.into_response(),
+            Self::Protocol(status, message) => (status, message).into_response(),
+            Self::Read(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+            Self::Decode(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+        }
     }
 }
 
-trait ThriftDeserialize: Sized {
-    fn deserialize<R>(data: R) -> archer_thrift::thrift::Result<Self>
-    where
-        R: Read;
+/// Thrift protocol, that a batch is encoded with.
+#[derive(Clone, Copy)]
+enum Protocol {
+    Binary,
+    Compact,
 }
 
-impl ThriftDeserialize for archer_thrift::jaeger::Batch {
-    fn deserialize<R>(data: R) -> archer_thrift::thrift::Result<Self>
-    where
-        R: Read,
+/// Determine the protocol from the content type. Without one, the binary protocol is assumed, as
+/// that is what the Jaeger clients send.
+fn protocol(headers: &HeaderMap) -> Result<Protocol, (StatusCode, String)> {
+    let Some(value) = headers.get(CONTENT_TYPE) else {
+        return Ok(Protocol::Binary);
+    };
+
+    let content_type = value.to_str().ok().and_then(|ct| ct.parse::<Mime>().ok());
+
+    match content_type
+        .as_ref()
+        .filter(|ct| ct.type_() == mime::APPLICATION)
+        .map(Mime::subtype)
     {
-        let mut prot = TBinaryInputProtocol::new(data, true);
-        archer_thrift::jaeger::read_batch(&mut prot)
+        Some(subtype) if subtype == "x-thrift" || subtype == "vnd.apache.thrift.binary" => {
+            Ok(Protocol::Binary)
+        }
+        Some(subtype) if subtype == "vnd.apache.thrift.compact" => Ok(Protocol::Compact),
+        _ => Err((
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            format!(
+                "Unsupported content type `{}`, expected `application/x-thrift`, \
+                 `application/vnd.apache.thrift.binary` or \
+                 `application/vnd.apache.thrift.compact`",
+                String::from_utf8_lossy(value.as_bytes()),
+            ),
+        )),
     }
 }
 
@@ -154,8 +345,12 @@ async fn run_grpc(
     parent: tracing::Span,
     shutdown: Shutdown,
     database: Database,
-    addr: SocketAddr,
+    addr: Option<SocketAddr>,
 ) -> Result<()> {
+    let Some(addr) = addr else {
+        return Ok(());
+    };
+
     info!("listening on http://{addr}");
 
     tonic::transport::Server::builder()
@@ -181,11 +376,23 @@ impl collector_service_server::CollectorService for CollectorService {
         &self,
         request: tonic::Request<PostSpansRequest>,
     ) -> Result<tonic::Response<PostSpansResponse>, tonic::Status> {
+        let db = self
+            .0
+            .for_tenant(tenancy::from_metadata(request.metadata()));
+        let origin = Origin {
+            receiver: Receiver::JaegerGrpc,
+            peer: request.remote_addr(),
+            bytes: Some(request.get_ref().encoded_len()),
+        };
         let PostSpansRequest { batch } = request.into_inner();
         let api_v2::Batch { spans, process } =
             batch.ok_or_else(|| tonic::Status::invalid_argument("batch field missing"))?;
         let process = process
             .ok_or_else(|| tonic::Status::invalid_argument("process information missing"))?;
+
+        let count = spans.len();
+        metrics::spans_received(Receiver::JaegerGrpc, count);
+
         let spans = spans
             .into_iter()
             .map(|mut span| {
@@ -195,15 +402,13 @@ impl collector_service_server::CollectorService for CollectorService {
             .collect::<Result<Vec<_>>>()
             .map_err(|e| {
                 warn!(error = ?e, "failed to convert spans");
+                metrics::spans_dropped(DropReason::Conversion, count);
+                audit::record(origin, count, &[], Outcome::Invalid);
                 tonic::Status::invalid_argument(e.to_string())
             })?;
-        let db = self.0.clone();
 
-        tokio::spawn(async move {
-            if let Err(e) = db.save_spans(spans).await {
-                error!(error = ?e, "failed to save spans to DB");
-            }
-        });
+        ingest::accept(db, origin, count, spans)
+            .map_err(|e| tonic::Status::resource_exhausted(e.to_string()))?;
 
         Ok(tonic::Response::new(PostSpansResponse::default()))
     }
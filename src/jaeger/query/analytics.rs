@@ -0,0 +1,209 @@
+//! Analysis of the stored spans of a single service, that goes beyond the search of individual
+//! traces. Like SPM, everything is computed on demand from the spans.
+
+use std::collections::{BTreeSet, HashMap};
+
+use archer_http::{
+    axum::{
+        extract::{rejection::QueryRejection, Query},
+        response::IntoResponse,
+        Json,
+    },
+    ApiError, ApiResponse, ErrorGroup,
+};
+use serde::Deserialize;
+use time::{Duration, OffsetDateTime};
+use tracing::instrument;
+
+use super::{bad_query, de, histogram, Tenanted};
+use crate::{
+    models::{Span, Tag, TagValue},
+    storage::ReadOnlyDatabase,
+};
+
+/// Amount of example traces, that are included in each error group.
+const EXAMPLE_TRACES: usize = 5;
+
+/// Tags that contain the error message, from the most to the least specific one.
+const MESSAGE_TAGS: &[&str] = &[
+    "error.message",
+    "exception.message",
+    "otel.status_description",
+    "error.object",
+];
+
+/// Tags that contain a status code, for HTTP and gRPC respectively.
+const STATUS_CODE_TAGS: &[&str] = &[
+    "http.status_code",
+    "http.response.status_code",
+    "rpc.grpc.status_code",
+];
+
+#[derive(Deserialize)]
+pub struct ErrorsQuery {
+    service: String,
+    #[serde(default, deserialize_with = "de::duration_human")]
+    lookback: Option<Duration>,
+    #[serde(default, deserialize_with = "de::limit")]
+    limit: Option<u32>,
+}
+
+/// The most frequent errors of a service, grouped by message and status code.
+#[instrument(skip_all)]
+pub async fn errors(
+    query: Result<Query<ErrorsQuery>, QueryRejection>,
+    Tenanted(db): Tenanted<ReadOnlyDatabase>,
+) -> Result<impl IntoResponse, ApiError> {
+    let Query(query) = query.map_err(bad_query)?;
+
+    let end = OffsetDateTime::now_utc();
+    let start = end - query.lookback.unwrap_or(Duration::HOUR);
+    let limit = query.limit.map_or(20, |limit| limit as usize);
+
+    let spans = db
+        .list_service_spans(vec![query.service.clone()], start, end)
+        .await?;
+
+    let mut groups = group_errors(
+        spans
+            .iter()
+            .filter(|span| span.process.service == query.service && span.is_error()),
+    );
+    groups.truncate(limit);
+
+    Ok(ApiResponse::Data(groups))
+}
+
+#[derive(Deserialize)]
+pub struct LatencyQuery {
+    service: String,
+    #[serde(default)]
+    operation: String,
+    #[serde(default, deserialize_with = "de::duration_human")]
+    lookback: Option<Duration>,
+}
+
+/// Heatmap of the latency of a service or one of its operations, with the number of calls by time
+/// window and duration bucket. Without an operation, the duration of whole traces is used.
+#[instrument(skip_all)]
+pub async fn latency(
+    query: Result<Query<LatencyQuery>, QueryRejection>,
+    Tenanted(db): Tenanted<ReadOnlyDatabase>,
+) -> Result<impl IntoResponse, ApiError> {
+    let Query(query) = query.map_err(bad_query)?;
+
+    let end = OffsetDateTime::now_utc();
+    let start = end - query.lookback.unwrap_or(Duration::HOUR);
+
+    let durations = db
+        .list_operation_durations(
+            query.service,
+            (!query.operation.is_empty()).then_some(query.operation),
+            start,
+            end,
+        )
+        .await?;
+
+    Ok(Json(histogram::compute(
+        histogram::micros(start),
+        histogram::micros(end),
+        &durations,
+    )))
+}
+
+/// Group the failed spans and sort the groups by their size, largest first.
+fn group_errors<'a>(spans: impl Iterator<Item = &'a Span>) -> Vec<ErrorGroup> {
+    let mut groups = HashMap::<(Option<&str>, Option<i64>), Vec<&Span>>::new();
+
+    for span in spans {
+        groups
+            .entry((message(span), status_code(span)))
+            .or_default()
+            .push(span);
+    }
+
+    let mut groups = groups
+        .into_iter()
+        .map(|((message, status_code), mut spans)| {
+            spans.sort_unstable_by_key(|span| std::cmp::Reverse(span.start));
+
+            let mut trace_ids = Vec::with_capacity(EXAMPLE_TRACES);
+            for span in &spans {
+                let trace_id = span.trace_id.get().into();
+                if !trace_ids.contains(&trace_id) {
+                    trace_ids.push(trace_id);
+                    if trace_ids.len() == EXAMPLE_TRACES {
+                        break;
+                    }
+                }
+            }
+
+            ErrorGroup {
+                message: message.map(ToOwned::to_owned),
+                status_code,
+                count: spans.len(),
+                operations: spans
+                    .iter()
+                    .map(|span| span.operation_name.clone())
+                    .collect::<BTreeSet<_>>()
+                    .into_iter()
+                    .collect(),
+                first_seen: micros(spans.last().map(|span| span.start)),
+                last_seen: micros(spans.first().map(|span| span.start)),
+                trace_ids,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    groups.sort_unstable_by(|a, b| {
+        b.count
+            .cmp(&a.count)
+            .then_with(|| b.last_seen.cmp(&a.last_seen))
+    });
+    groups
+}
+
+/// Error message of the span, either from its tags or from the logs that recorded the error.
+fn message(span: &Span) -> Option<&str> {
+    let logs = span.logs.iter().map(|log| log.fields.as_slice());
+
+    std::iter::once(span.tags.as_slice())
+        .chain(logs)
+        .find_map(|tags| {
+            MESSAGE_TAGS
+                .iter()
+                .find_map(|key| string_tag(tags, key))
+                .or_else(|| {
+                    (string_tag(tags, "event") == Some("error"))
+                        .then(|| string_tag(tags, "message"))
+                        .flatten()
+                })
+        })
+}
+
+fn status_code(span: &Span) -> Option<i64> {
+    STATUS_CODE_TAGS.iter().find_map(|key| {
+        span.tags
+            .iter()
+            .find(|tag| tag.key == *key)
+            .and_then(|tag| match &tag.value {
+                TagValue::I64(v) => Some(*v),
+                TagValue::U64(v) => i64::try_from(*v).ok(),
+                TagValue::String(v) => v.parse().ok(),
+                _ => None,
+            })
+    })
+}
+
+fn string_tag<'a>(tags: &'a [Tag], key: &str) -> Option<&'a str> {
+    tags.iter()
+        .find(|tag| tag.key == key)
+        .and_then(|tag| match &tag.value {
+            TagValue::String(v) if !v.is_empty() => Some(v.as_str()),
+            _ => None,
+        })
+}
+
+fn micros(time: Option<OffsetDateTime>) -> i128 {
+    time.map_or(0, |time| time.unix_timestamp_nanos() / 1000)
+}
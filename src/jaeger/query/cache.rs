@@ -0,0 +1,113 @@
+//! Short-lived cache of trace lookups, which cuts the latency when multiple users view the same
+//! trace, or the UI loads it repeatedly. Entries are evicted once they're expired, or when the
+//! cache is full.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use hashlink::LruCache;
+use once_cell::sync::OnceCell;
+use tokio_shutdown::Shutdown;
+use tracing::{info, instrument};
+
+use crate::{
+    config,
+    models::{Span, TraceId},
+    storage::{ReadOnlyDatabase, StorageError},
+};
+
+static CACHE: OnceCell<TraceCache> = OnceCell::new();
+
+/// Cached traces, keyed by their tenant and ID, together with the time they were cached.
+type Traces = LruCache<(Arc<str>, TraceId), (Instant, Vec<Span>)>;
+
+struct TraceCache {
+    ttl: Duration,
+    traces: Mutex<Traces>,
+}
+
+/// Get the spans of a single trace, either from the cache or the database. This is the same as
+/// [`ReadOnlyDatabase::find_trace`] if the cache isn't enabled.
+pub async fn find_trace(
+    db: &ReadOnlyDatabase,
+    trace_id: TraceId,
+) -> Result<Vec<Span>, StorageError> {
+    let Some(cache) = CACHE.get() else {
+        return db.find_trace(trace_id).await;
+    };
+
+    let key = (Arc::<str>::from(db.tenant()), trace_id);
+
+    if let Ok(mut traces) = cache.traces.lock() {
+        if let Some((inserted, spans)) = traces.get(&key) {
+            if inserted.elapsed() < cache.ttl {
+                return Ok(spans.clone());
+            }
+        }
+    }
+
+    let spans = db.find_trace(trace_id).await?;
+
+    if let Ok(mut traces) = cache.traces.lock() {
+        traces.insert(key, (Instant::now(), spans.clone()));
+    }
+
+    Ok(spans)
+}
+
+/// Enable the cache, and periodically remove expired entries until shutdown.
+#[instrument(name = "cache", parent = parent, skip_all)]
+pub async fn run(
+    parent: tracing::Span,
+    shutdown: Shutdown,
+    config: Option<config::QueryCache>,
+) -> Result<()> {
+    let Some(config) = config else {
+        return Ok(());
+    };
+
+    let ttl = Duration::from_secs(config.ttl_seconds);
+    CACHE
+        .set(TraceCache {
+            ttl,
+            traces: Mutex::new(LruCache::new(config.capacity)),
+        })
+        .map_err(|_| anyhow!("query cache can only be started once"))?;
+
+    info!(capacity = config.capacity, ?ttl, "caching traces");
+
+    let mut interval = tokio::time::interval(ttl.max(Duration::from_secs(1)));
+
+    loop {
+        tokio::select! {
+            () = shutdown.handle() => break,
+            _ = interval.tick() => purge(),
+        }
+    }
+
+    Ok(())
+}
+
+fn purge() {
+    let Some(cache) = CACHE.get() else {
+        return;
+    };
+    let Ok(mut traces) = cache.traces.lock() else {
+        return;
+    };
+
+    // Entries are ordered from least to most recently used, not by their age, so all of them
+    // have to be checked.
+    let expired = traces
+        .iter()
+        .filter(|(_, (inserted, _))| inserted.elapsed() >= cache.ttl)
+        .map(|(key, _)| key.clone())
+        .collect::<Vec<_>>();
+
+    for key in expired {
+        traces.remove(&key);
+    }
+}
@@ -0,0 +1,122 @@
+//! Server-side structural comparison of two traces, to support the trace diff view of the UI
+//! without shipping both full traces to the browser.
+
+use std::collections::{HashMap, HashSet};
+
+use archer_http::{
+    axum::{
+        extract::{rejection::QueryRejection, Query},
+        http::StatusCode,
+        response::IntoResponse,
+        Json,
+    },
+    ApiError, DiffNode, TraceDiff,
+};
+use tracing::instrument;
+
+use super::{Tenanted, TraceIdsQuery};
+use crate::{
+    models::{Span, SpanId},
+    storage::ReadOnlyDatabase,
+};
+
+#[derive(Clone, Copy)]
+enum Side {
+    A,
+    B,
+}
+
+#[instrument(skip_all)]
+pub async fn compare(
+    trace_ids: Result<Query<TraceIdsQuery>, QueryRejection>,
+    Tenanted(db): Tenanted<ReadOnlyDatabase>,
+) -> Result<impl IntoResponse, ApiError> {
+    let [a, b] = trace_ids
+        .ok()
+        .and_then(|Query(ids)| <[_; 2]>::try_from(ids.0).ok())
+        .ok_or_else(|| ApiError {
+            code: StatusCode::BAD_REQUEST,
+            msg: "exactly two trace IDs must be given".into(),
+            trace_id: None,
+        })?;
+
+    let traces = db
+        .find_traces([a, b].into_iter().map(|id| id.0.into()))
+        .await
+        .map_err(ApiError::from)?;
+
+    let mut roots = Vec::new();
+
+    for (side, id) in [(Side::A, a), (Side::B, b)] {
+        let spans = traces.get(&id.0.into()).ok_or(ApiError {
+            code: StatusCode::NOT_FOUND,
+            msg: "trace id not found".into(),
+            trace_id: Some(id),
+        })?;
+        merge(&mut roots, side, spans);
+    }
+
+    Ok(Json(TraceDiff {
+        trace_id_a: a,
+        trace_id_b: b,
+        roots,
+    }))
+}
+
+/// Merge the spans of a trace into the tree. Spans without a known parent are treated as roots.
+fn merge(roots: &mut Vec<DiffNode>, side: Side, spans: &[Span]) {
+    let ids = spans
+        .iter()
+        .map(|span| span.span_id)
+        .collect::<HashSet<_>>();
+    let mut children = HashMap::<_, Vec<_>>::new();
+
+    for span in spans {
+        let parent = span
+            .references
+            .iter()
+            .find(|reference| reference.trace_id == span.trace_id)
+            .map(|reference| reference.span_id)
+            .filter(|id| ids.contains(id));
+        children.entry(parent).or_default().push(span);
+    }
+
+    for spans in children.values_mut() {
+        spans.sort_by_key(|span| span.start);
+    }
+
+    insert(roots, side, &children, None);
+}
+
+fn insert(
+    nodes: &mut Vec<DiffNode>,
+    side: Side,
+    children: &HashMap<Option<SpanId>, Vec<&Span>>,
+    parent: Option<SpanId>,
+) {
+    for span in children.get(&parent).into_iter().flatten() {
+        let index = nodes
+            .iter()
+            .position(|node| {
+                node.service == span.process.service && node.operation == span.operation_name
+            })
+            .unwrap_or_else(|| {
+                nodes.push(DiffNode {
+                    service: span.process.service.clone(),
+                    operation: span.operation_name.clone(),
+                    count_a: 0,
+                    count_b: 0,
+                    children: Vec::new(),
+                });
+                nodes.len() - 1
+            });
+
+        let node = &mut nodes[index];
+        match side {
+            Side::A => node.count_a += 1,
+            Side::B => node.count_b += 1,
+        }
+
+        insert(&mut node.children, side, children, Some(span.span_id));
+    }
+}
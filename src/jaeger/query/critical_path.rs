@@ -0,0 +1,240 @@
+//! Critical path of a single trace, which is the chain of spans that dominates its wall-clock
+//! latency, to show where optimizations actually pay off.
+//!
+//! The path is found by walking backwards from the end of the root span. At each point, the time
+//! belongs to the child that finished last before it, or to the span itself, if none of its
+//! children was running.
+
+use std::collections::{HashMap, HashSet};
+
+use archer_http::{
+    axum::{extract::Path, response::IntoResponse, Json},
+    ApiError, CriticalPath, CriticalPathSegment, CriticalPathSpan, TraceId,
+};
+use time::{Duration, OffsetDateTime};
+use tracing::instrument;
+
+use super::{cache, graph, skew, Tenanted};
+use crate::{
+    models::{Span, SpanId},
+    storage::ReadOnlyDatabase,
+};
+
+#[instrument(skip_all)]
+pub async fn critical_path(
+    Path(trace_id): Path<TraceId>,
+    Tenanted(db): Tenanted<ReadOnlyDatabase>,
+) -> Result<impl IntoResponse, ApiError> {
+    let mut spans = cache::find_trace(&db, trace_id.0.into())
+        .await
+        .map_err(|e| ApiError {
+            trace_id: Some(trace_id),
+            ..e.into()
+        })?;
+    skew::adjust(&mut spans);
+
+    let segments = compute(&spans);
+
+    let mut totals = HashMap::<SpanId, (&Span, Duration)>::new();
+    for segment in &segments {
+        totals
+            .entry(segment.span.span_id)
+            .or_insert((segment.span, Duration::ZERO))
+            .1 += segment.end - segment.start;
+    }
+
+    let mut totals = totals.into_values().collect::<Vec<_>>();
+    totals.sort_unstable_by_key(|(span, duration)| (std::cmp::Reverse(*duration), span.start));
+
+    Ok(Json(CriticalPath {
+        trace_id,
+        segments: segments
+            .into_iter()
+            .map(|segment| CriticalPathSegment {
+                span_id: segment.span.span_id.get().into(),
+                start_time: micros(segment.start - OffsetDateTime::UNIX_EPOCH),
+                duration: micros(segment.end - segment.start),
+            })
+            .collect(),
+        spans: totals
+            .into_iter()
+            .map(|(span, duration)| CriticalPathSpan {
+                span_id: span.span_id.get().into(),
+                service_name: span.process.service.clone(),
+                operation_name: span.operation_name.clone(),
+                duration: micros(duration),
+            })
+            .collect(),
+    }))
+}
+
+/// Time range, that is spent in a single span.
+struct Segment<'a> {
+    span: &'a Span,
+    start: OffsetDateTime,
+    end: OffsetDateTime,
+}
+
+/// Compute the critical path, in chronological order.
+fn compute(spans: &[Span]) -> Vec<Segment<'_>> {
+    let ids = spans
+        .iter()
+        .map(|span| span.span_id)
+        .collect::<HashSet<_>>();
+    let mut children = HashMap::<SpanId, Vec<&Span>>::new();
+    let mut roots = Vec::new();
+
+    for span in spans {
+        match graph::parent(span).filter(|id| ids.contains(id)) {
+            Some(parent) => children.entry(parent).or_default().push(span),
+            None => roots.push(span),
+        }
+    }
+
+    // Children that finish last are checked first, as the path is walked backwards.
+    for children in children.values_mut() {
+        children.sort_unstable_by_key(|child| std::cmp::Reverse(end(child)));
+    }
+
+    // With several roots, for example when the actual root span is missing, the one that finishes
+    // last determines the end of the trace.
+    let Some(root) = roots.into_iter().max_by_key(|span| end(span)) else {
+        return Vec::new();
+    };
+
+    let mut segments = Vec::new();
+    walk(&children, root, root.start, end(root), &mut segments);
+    segments.reverse();
+    segments
+}
+
+/// Walk backwards through the span, within the given time range, adding the segments in reverse
+/// chronological order.
+fn walk<'a>(
+    children: &HashMap<SpanId, Vec<&'a Span>>,
+    span: &'a Span,
+    start: OffsetDateTime,
+    end: OffsetDateTime,
+    segments: &mut Vec<Segment<'a>>,
+) {
+    let mut cursor = end;
+
+    for &child in children.get(&span.span_id).map_or(&[][..], Vec::as_slice) {
+        if cursor <= start {
+            break;
+        }
+
+        // Children that ran outside of the parent are cut to fit, so clock skew or asynchronous
+        // work can't extend the path beyond the parent.
+        let child_start = child.start.max(start);
+        let child_end = self::end(child).min(cursor);
+        if child_start >= child_end {
+            continue;
+        }
+
+        if child_end < cursor {
+            segments.push(Segment {
+                span,
+                start: child_end,
+                end: cursor,
+            });
+        }
+
+        walk(children, child, child_start, child_end, segments);
+        cursor = child_start;
+    }
+
+    if cursor > start {
+        segments.push(Segment {
+            span,
+            start,
+            end: cursor,
+        });
+    }
+}
+
+fn end(span: &Span) -> OffsetDateTime {
+    span.start + span.duration.max(Duration::ZERO)
+}
+
+fn micros(duration: Duration) -> i128 {
+    duration.whole_microseconds()
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use std::num::{NonZeroU128, NonZeroU64};
+
+    use super::*;
+    use crate::models::{Process, RefType, Reference, TraceId};
+
+    fn span(id: u64, parent: Option<u64>, start: i64, duration: i64) -> Span {
+        let trace_id = TraceId::from(NonZeroU128::new(1).unwrap());
+
+        Span {
+            trace_id,
+            span_id: NonZeroU64::new(id).unwrap().into(),
+            operation_name: format!("op{id}"),
+            flags: 0,
+            references: parent
+                .map(|parent| Reference {
+                    ty: RefType::ChildOf,
+                    trace_id,
+                    span_id: NonZeroU64::new(parent).unwrap().into(),
+                    tags: Vec::new(),
+                })
+                .into_iter()
+                .collect(),
+            start: OffsetDateTime::UNIX_EPOCH + Duration::microseconds(start),
+            duration: Duration::microseconds(duration),
+            tags: Vec::new(),
+            logs: Vec::new(),
+            process: Process::default(),
+            warnings: Vec::new(),
+            timing: None,
+            kind: None,
+        }
+    }
+
+    #[test]
+    fn compute_path() {
+        // 1 |----------------------------|
+        // 2   |--------|
+        // 3      |-------------|
+        // 4         |-----|
+        // 5                      |---------|
+        let spans = [
+            span(1, None, 0, 100),
+            span(2, Some(1), 10, 30),
+            span(3, Some(1), 20, 50),
+            span(4, Some(3), 30, 20),
+            span(5, Some(1), 80, 40),
+        ];
+
+        let result = compute(&spans)
+            .into_iter()
+            .map(|segment| {
+                (
+                    segment.span.span_id.get().get(),
+                    (segment.start - OffsetDateTime::UNIX_EPOCH).whole_microseconds(),
+                    (segment.end - OffsetDateTime::UNIX_EPOCH).whole_microseconds(),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            vec![
+                (1, 0, 10),
+                (2, 10, 20),
+                (3, 20, 30),
+                (4, 30, 50),
+                (3, 50, 70),
+                (1, 70, 80),
+                (5, 80, 100),
+            ],
+            result
+        );
+    }
+}
@@ -1,14 +1,48 @@
-use std::{borrow::Cow, collections::HashMap, fmt, ops::Neg};
+use std::{borrow::Cow, collections::HashMap, fmt, ops::Neg, str::FromStr};
 
-use anyhow::{bail, ensure, Result};
+use anyhow::{anyhow, bail, ensure, Result};
 use archer_http::TraceId;
 use serde::{
     de::{self, Visitor},
-    Deserializer,
+    Deserialize, Deserializer,
 };
 use time::Duration;
 
-pub fn tags<'de, D>(deserializer: D) -> Result<HashMap<String, String>, D::Error>
+use crate::storage::{Comparison, TagFilters};
+
+/// Tag filters of a trace search, with comparisons on the span duration separated out.
+#[derive(Debug, Default, PartialEq)]
+pub struct TagQuery {
+    pub tags: TagFilters,
+    pub durations: Vec<(Comparison, Duration)>,
+}
+
+impl FromIterator<(String, String)> for TagQuery {
+    fn from_iter<T: IntoIterator<Item = (String, String)>>(iter: T) -> Self {
+        Self {
+            tags: iter.into_iter().collect(),
+            durations: Vec::new(),
+        }
+    }
+}
+
+/// Parse the tag filters of a trace search, which can be given in two forms:
+///
+/// - `tags=<JSON map>` with tag keys and values, that must match exactly.
+/// - `tag=<filter>`, repeated for each filter, with the following grammar:
+///
+/// ```text
+/// filter     = key ":" value          ; exact match of the tag value
+///            | key comparison number  ; numeric comparison of the tag value
+/// comparison = "<" | "<=" | ">" | ">="
+/// ```
+///
+/// The key is everything up to the first `:`, `<` or `>`. Numeric comparisons only match tags,
+/// whose value is a number as well. The special key `duration` compares the span duration instead,
+/// and takes a human readable duration like `100ms` instead of a plain number.
+///
+/// All filters must match on the same span.
+pub fn tags<'de, D>(deserializer: D) -> Result<TagQuery, D::Error>
 where
     D: Deserializer<'de>,
 {
@@ -17,39 +51,70 @@ where
 
 struct TagsVisitor;
 
+impl TagsVisitor {
+    fn parse(query: &mut TagQuery, filter: &str) -> Result<()> {
+        let pos = filter
+            .find([':', '<', '>'])
+            .ok_or_else(|| anyhow!("missing `:`, `<` or `>` separator"))?;
+        let (key, rest) = filter.split_at(pos);
+
+        let (comparison, value) = if let Some(value) = rest.strip_prefix(':') {
+            query.tags.exact.insert(key.to_owned(), value.to_owned());
+            return Ok(());
+        } else if let Some(value) = rest.strip_prefix("<=") {
+            (Comparison::Le, value)
+        } else if let Some(value) = rest.strip_prefix(">=") {
+            (Comparison::Ge, value)
+        } else if let Some(value) = rest.strip_prefix('<') {
+            (Comparison::Lt, value)
+        } else {
+            (Comparison::Gt, &rest[1..])
+        };
+
+        if key == "duration" {
+            query
+                .durations
+                .push((comparison, DurationHumanVisitor::parse(value)?));
+        } else {
+            let value = value
+                .parse::<f64>()
+                .ok()
+                .filter(|value| value.is_finite())
+                .ok_or_else(|| anyhow!("`{value}` is not a number"))?;
+            query.tags.numeric.push((key.to_owned(), comparison, value));
+        }
+
+        Ok(())
+    }
+}
+
 impl<'de> Visitor<'de> for TagsVisitor {
-    type Value = HashMap<String, String>;
+    type Value = TagQuery;
 
     fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
-        formatter.write_str("tags as single <key>:<value> pair or JSON map")
+        formatter.write_str("tags as <key>:<value> pair, numeric comparison or JSON map")
     }
 
     fn visit_map<A>(self, mut access: A) -> Result<Self::Value, A::Error>
     where
         A: serde::de::MapAccess<'de>,
     {
-        let mut map = HashMap::new();
+        let mut query = TagQuery::default();
 
         while let Some((k, v)) = access.next_entry::<Cow<'_, str>, Cow<'_, str>>()? {
             match &*k {
-                "tag" => {
-                    let (k, v) = v
-                        .split_once(':')
-                        .ok_or_else(|| de::Error::custom("missing `:` separator"))?;
-
-                    map.insert(k.to_owned(), v.to_owned());
-                }
+                "tag" => Self::parse(&mut query, &v).map_err(de::Error::custom)?,
                 "tags" => {
                     let kvs = serde_json::from_str::<HashMap<_, _>>(&v)
                         .map_err(|e| de::Error::custom(format!("invalid JSON map: {e}")))?;
 
-                    map.extend(kvs);
+                    query.tags.exact.extend(kvs);
                 }
-                _ => continue,
+                _ => {}
             }
         }
 
-        Ok(map)
+        Ok(query)
     }
 }
 
@@ -143,7 +208,7 @@ impl DurationHumanVisitor {
 
         ensure!(value.is_empty(), "unexpected trailing data: {value}");
 
-        Ok(negative.then(|| total.neg()).unwrap_or(total))
+        Ok(if negative { total.neg() } else { total })
     }
 
     fn find_next_unit(value: &str) -> Option<(usize, usize)> {
@@ -295,3 +360,55 @@ impl<'de> serde::de::Visitor<'de> for TraceIdsVisitor {
         Ok(ids)
     }
 }
+
+pub fn parsed<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    match Option::<Cow<'de, str>>::deserialize(deserializer)? {
+        Some(v) if !v.is_empty() => v.parse().map(Some).map_err(de::Error::custom),
+        _ => Ok(None),
+    }
+}
+
+pub fn services<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserializer.deserialize_map(RepeatedVisitor("service"))
+}
+
+pub fn span_kinds<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserializer.deserialize_map(RepeatedVisitor("spanKind"))
+}
+
+/// Collects all values of a single key, that may be repeated several times in a query string.
+struct RepeatedVisitor(&'static str);
+
+impl<'de> Visitor<'de> for RepeatedVisitor {
+    type Value = Vec<String>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(formatter, "map with one or more `{}` keys", self.0)
+    }
+
+    fn visit_map<A>(self, mut access: A) -> Result<Self::Value, A::Error>
+    where
+        A: serde::de::MapAccess<'de>,
+    {
+        let mut values = Vec::new();
+
+        while let Some((k, v)) = access.next_entry::<Cow<'_, str>, String>()? {
+            if k == self.0 {
+                values.push(v);
+            }
+        }
+
+        Ok(values)
+    }
+}
@@ -0,0 +1,149 @@
+//! Call graph of a single trace, that aggregates all spans by their service and operation, to
+//! support the trace graph view of the UI and programmatic analysis of traces.
+
+use std::collections::HashMap;
+
+use archer_http::{
+    axum::{extract::Path, response::IntoResponse, Json},
+    ApiError, GraphEdge, GraphNode, TraceGraph, TraceId,
+};
+use time::Duration;
+use tracing::instrument;
+
+use super::{cache, skew, Tenanted};
+use crate::{
+    models::{Span, SpanId},
+    storage::ReadOnlyDatabase,
+};
+
+#[instrument(skip_all)]
+pub async fn graph(
+    Path(trace_id): Path<TraceId>,
+    Tenanted(db): Tenanted<ReadOnlyDatabase>,
+) -> Result<impl IntoResponse, ApiError> {
+    let mut spans = cache::find_trace(&db, trace_id.0.into())
+        .await
+        .map_err(|e| ApiError {
+            trace_id: Some(trace_id),
+            ..e.into()
+        })?;
+    skew::adjust(&mut spans);
+
+    let (nodes, edges) = aggregate(&spans);
+
+    Ok(Json(TraceGraph {
+        trace_id,
+        nodes,
+        edges,
+    }))
+}
+
+fn aggregate(spans: &[Span]) -> (Vec<GraphNode>, Vec<GraphEdge>) {
+    let by_id = spans
+        .iter()
+        .map(|span| (span.span_id, span))
+        .collect::<HashMap<_, _>>();
+    let mut children = HashMap::<SpanId, Vec<&Span>>::new();
+
+    for span in spans {
+        if let Some(parent) = parent(span).filter(|id| by_id.contains_key(id)) {
+            children.entry(parent).or_default().push(span);
+        }
+    }
+
+    let mut nodes = Vec::<GraphNode>::new();
+    let mut indices = HashMap::<(&str, &str), usize>::new();
+    let mut edges = HashMap::<(usize, usize), GraphEdge>::new();
+
+    for span in spans {
+        let to = node_index(&mut nodes, &mut indices, span);
+        let children = children.get(&span.span_id).map_or(&[][..], Vec::as_slice);
+
+        let node = &mut nodes[to];
+        node.count += 1;
+        node.errors += usize::from(span.is_error());
+        node.duration += micros(span.duration);
+        node.self_duration += micros(self_duration(span, children));
+
+        if let Some(parent) = parent(span).and_then(|id| by_id.get(&id)) {
+            let from = node_index(&mut nodes, &mut indices, parent);
+            let edge = edges.entry((from, to)).or_insert(GraphEdge {
+                from,
+                to,
+                count: 0,
+                duration: 0,
+            });
+
+            edge.count += 1;
+            edge.duration += micros(span.duration);
+        }
+    }
+
+    let mut edges = edges.into_values().collect::<Vec<_>>();
+    edges.sort_unstable_by_key(|edge| (edge.from, edge.to));
+
+    (nodes, edges)
+}
+
+/// Index of the node for the span's service and operation, which is created if it doesn't exist
+/// yet.
+fn node_index<'a>(
+    nodes: &mut Vec<GraphNode>,
+    indices: &mut HashMap<(&'a str, &'a str), usize>,
+    span: &'a Span,
+) -> usize {
+    *indices
+        .entry((&span.process.service, &span.operation_name))
+        .or_insert_with(|| {
+            nodes.push(GraphNode {
+                service: span.process.service.clone(),
+                operation: span.operation_name.clone(),
+                count: 0,
+                errors: 0,
+                duration: 0,
+                self_duration: 0,
+            });
+            nodes.len() - 1
+        })
+}
+
+/// ID of the parent span, which is the first reference within the same trace.
+pub(super) fn parent(span: &Span) -> Option<SpanId> {
+    span.references
+        .iter()
+        .find(|reference| reference.trace_id == span.trace_id)
+        .map(|reference| reference.span_id)
+}
+
+/// Time of the span, that isn't covered by any of its children. Overlapping children, like
+/// parallel calls, only count once.
+fn self_duration(span: &Span, children: &[&Span]) -> Duration {
+    let end = span.start + span.duration.max(Duration::ZERO);
+    let mut ranges = children
+        .iter()
+        .map(|child| {
+            (
+                child.start.clamp(span.start, end),
+                (child.start + child.duration).clamp(span.start, end),
+            )
+        })
+        .collect::<Vec<_>>();
+    ranges.sort_unstable();
+
+    let mut covered = Duration::ZERO;
+    let mut last = span.start;
+
+    for (start, end) in ranges {
+        let start = start.max(last);
+        if end > start {
+            covered += end - start;
+            last = end;
+        }
+    }
+
+    span.duration - covered
+}
+
+fn micros(duration: Duration) -> i64 {
+    i64::try_from(duration.whole_microseconds()).unwrap_or(i64::MAX)
+}
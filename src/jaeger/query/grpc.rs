@@ -0,0 +1,264 @@
+//! Jaeger's gRPC query API in version 3, which is the same as the HTTP API, but returns all spans
+//! in the OTLP format.
+
+use std::{cmp::Reverse, net::SocketAddr, pin::Pin};
+
+use anyhow::{ensure, Context, Result};
+use archer_http::{tower::ServiceBuilder, tower_http::ServiceBuilderExt, TraceId};
+use archer_proto::{
+    jaeger::api_v3::{
+        query_service_server::{self, QueryServiceServer},
+        FindTracesRequest, GetOperationsRequest, GetOperationsResponse, GetServicesRequest,
+        GetServicesResponse, GetTraceRequest, Operation, SpansResponseChunk, TraceQueryParameters,
+    },
+    prost_types,
+    tonic::{
+        self,
+        codegen::CompressionEncoding,
+        metadata::{Ascii, MetadataValue},
+        service::{interceptor::InterceptedService, Interceptor},
+        Request, Response, Status,
+    },
+};
+use futures_util::{stream, Stream, StreamExt};
+use time::{Duration, OffsetDateTime};
+use tokio_shutdown::Shutdown;
+use tracing::{info, instrument};
+
+use super::{cache, skew};
+use crate::{
+    config::QueryAuth,
+    convert,
+    models::Span,
+    storage::{ListSpansParams, ReadOnlyDatabase, StorageError},
+    tenancy,
+};
+
+#[instrument(name = "grpc", parent = parent, skip_all)]
+pub async fn run(
+    parent: tracing::Span,
+    shutdown: Shutdown,
+    database: ReadOnlyDatabase,
+    auth: Option<QueryAuth>,
+    addr: Option<SocketAddr>,
+) -> Result<()> {
+    let Some(addr) = addr else {
+        return Ok(());
+    };
+
+    let service = QueryServiceServer::new(QueryService(database))
+        .accept_compressed(CompressionEncoding::Gzip)
+        .send_compressed(CompressionEncoding::Gzip);
+
+    info!("listening on http://{addr}");
+
+    tonic::transport::Server::builder()
+        .layer(ServiceBuilder::new().trace_for_grpc())
+        .add_service(InterceptedService::new(service, Authorize::new(auth)?))
+        .serve_with_shutdown(addr, shutdown.handle())
+        .await?;
+
+    info!("server stopped");
+
+    Ok(())
+}
+
+/// Require the same credentials as the HTTP API, in the `authorization` metadata of each call.
+#[derive(Clone)]
+struct Authorize(Option<MetadataValue<Ascii>>);
+
+impl Authorize {
+    fn new(auth: Option<QueryAuth>) -> Result<Self> {
+        let expected = auth
+            .map(|auth| {
+                MetadataValue::try_from(match auth {
+                    QueryAuth::Bearer { token } => format!("Bearer {token}"),
+                    QueryAuth::Basic { username, password } => {
+                        format!("Basic {}", base64::encode(format!("{username}:{password}")))
+                    }
+                })
+            })
+            .transpose()?;
+
+        Ok(Self(expected))
+    }
+}
+
+impl Interceptor for Authorize {
+    fn call(&mut self, request: Request<()>) -> Result<Request<()>, Status> {
+        match &self.0 {
+            Some(expected) if request.metadata().get("authorization") != Some(expected) => {
+                Err(Status::unauthenticated("invalid credentials"))
+            }
+            _ => Ok(request),
+        }
+    }
+}
+
+impl From<StorageError> for Status {
+    fn from(value: StorageError) -> Self {
+        let message = value.to_string();
+
+        match value {
+            StorageError::NotFound => Self::not_found(message),
+            StorageError::Conflict(_) => Self::aborted(message),
+            StorageError::Busy(_) => Self::unavailable(message),
+            StorageError::Corrupt(_) => Self::data_loss(message),
+            StorageError::Io(_) => Self::internal(message),
+        }
+    }
+}
+
+type ChunkStream = Pin<Box<dyn Stream<Item = Result<SpansResponseChunk, Status>> + Send>>;
+
+struct QueryService(ReadOnlyDatabase);
+
+impl QueryService {
+    fn db<T>(&self, request: &Request<T>) -> ReadOnlyDatabase {
+        self.0
+            .for_tenant(tenancy::from_metadata(request.metadata()))
+    }
+}
+
+#[tonic::async_trait]
+impl query_service_server::QueryService for QueryService {
+    type GetTraceStream = ChunkStream;
+    type FindTracesStream = ChunkStream;
+
+    /// Get a single trace. The optional time range is ignored, as traces are always looked up by
+    /// their ID alone.
+    async fn get_trace(
+        &self,
+        request: Request<GetTraceRequest>,
+    ) -> Result<Response<Self::GetTraceStream>, Status> {
+        let db = self.db(&request);
+        let trace_id = request
+            .get_ref()
+            .trace_id
+            .parse::<TraceId>()
+            .map_err(|e| Status::invalid_argument(format!("invalid trace ID: {e}")))?;
+
+        let mut spans = cache::find_trace(&db, trace_id.0.into()).await?;
+
+        skew::adjust(&mut spans);
+
+        Ok(Response::new(chunks(vec![spans])))
+    }
+
+    async fn find_traces(
+        &self,
+        request: Request<FindTracesRequest>,
+    ) -> Result<Response<Self::FindTracesStream>, Status> {
+        let db = self.db(&request);
+        let query = request
+            .into_inner()
+            .query
+            .ok_or_else(|| Status::invalid_argument("query field missing"))?;
+        let params = params(query).map_err(|e| Status::invalid_argument(e.to_string()))?;
+
+        let (_, traces) = db.list_spans(params).await?;
+
+        // Newest traces first, the same order that the HTTP API uses.
+        let mut traces = traces.into_values().collect::<Vec<_>>();
+        for spans in &mut traces {
+            skew::adjust(spans);
+        }
+        traces.sort_by_key(|spans| Reverse(spans.iter().map(|span| span.start).min()));
+
+        Ok(Response::new(chunks(traces)))
+    }
+
+    async fn get_services(
+        &self,
+        request: Request<GetServicesRequest>,
+    ) -> Result<Response<GetServicesResponse>, Status> {
+        Ok(Response::new(GetServicesResponse {
+            services: self.db(&request).list_services(None),
+        }))
+    }
+
+    async fn get_operations(
+        &self,
+        request: Request<GetOperationsRequest>,
+    ) -> Result<Response<GetOperationsResponse>, Status> {
+        let db = self.db(&request);
+        let GetOperationsRequest { service, span_kind } = request.into_inner();
+
+        if service.is_empty() {
+            return Err(Status::invalid_argument("service name must be specified"));
+        }
+
+        let operations = db.find_operations(
+            &service,
+            (!span_kind.is_empty()).then_some(span_kind.as_str()),
+            None,
+        );
+
+        Ok(Response::new(GetOperationsResponse {
+            operations: operations
+                .into_iter()
+                .map(|(name, span_kind)| Operation { name, span_kind })
+                .collect(),
+        }))
+    }
+}
+
+/// Send each trace as separate chunk.
+fn chunks(traces: Vec<Vec<Span>>) -> ChunkStream {
+    stream::iter(traces)
+        .map(|spans| SpansResponseChunk {
+            resource_spans: convert::span_to_otlp(&spans),
+        })
+        .map(Ok)
+        .boxed()
+}
+
+fn params(query: TraceQueryParameters) -> Result<ListSpansParams> {
+    ensure!(
+        !query.service_name.is_empty(),
+        "service name must be specified"
+    );
+
+    let now = OffsetDateTime::now_utc();
+    let start = query
+        .start_time_min
+        .map(timestamp)
+        .transpose()?
+        .unwrap_or(now - Duration::hours(48));
+    let end = query
+        .start_time_max
+        .map(timestamp)
+        .transpose()?
+        .unwrap_or(now);
+
+    ensure!(start < end, "start must be before end");
+
+    Ok(ListSpansParams {
+        service: query.service_name,
+        operation: (!query.operation_name.is_empty()).then_some(query.operation_name),
+        start,
+        end,
+        duration_min: query.duration_min.map(duration),
+        duration_max: query.duration_max.map(duration),
+        limit: usize::try_from(query.num_traces)
+            .ok()
+            .filter(|&limit| limit > 0)
+            .unwrap_or(20),
+        offset: 0,
+        tags: query.attributes.into_iter().collect(),
+        error: false,
+        min_span_count: None,
+        span_kind: None,
+    })
+}
+
+fn timestamp(timestamp: prost_types::Timestamp) -> Result<OffsetDateTime> {
+    OffsetDateTime::from_unix_timestamp_nanos(
+        i128::from(timestamp.seconds) * 1_000_000_000 + i128::from(timestamp.nanos),
+    )
+    .context("invalid timestamp")
+}
+
+fn duration(duration: prost_types::Duration) -> Duration {
+    Duration::new(duration.seconds, duration.nanos)
+}
@@ -0,0 +1,155 @@
+//! Overview of trace search results, that only counts the matching traces by their start time and
+//! duration instead of loading all their spans.
+
+use std::collections::BTreeMap;
+
+use archer_http::{
+    axum::{
+        extract::{rejection::QueryRejection, Query},
+        http::StatusCode,
+        response::IntoResponse,
+        Json,
+    },
+    ApiError, HistogramBucket, HistogramCell, TraceHistogram,
+};
+use time::OffsetDateTime;
+use tracing::instrument;
+
+use super::{Tenanted, TracesQuery};
+use crate::storage::ReadOnlyDatabase;
+
+/// Maximum amount of buckets that the searched time range is split into.
+const TIME_BUCKETS: i64 = 60;
+
+#[instrument(skip_all)]
+pub async fn histogram(
+    query: Result<Query<TracesQuery>, QueryRejection>,
+    Tenanted(db): Tenanted<ReadOnlyDatabase>,
+) -> Result<impl IntoResponse, ApiError> {
+    let params = query
+        .map_err(|e| e.to_string())
+        .and_then(|Query(query)| query.into_db().map_err(|e| e.to_string()))
+        .map_err(|e| ApiError {
+            code: StatusCode::BAD_REQUEST,
+            msg: e.into(),
+            trace_id: None,
+        })?;
+
+    let (start, end) = (micros(params.start), micros(params.end));
+    let traces = db
+        .list_trace_durations(params)
+        .await
+        .map_err(ApiError::from)?;
+
+    Ok(Json(compute(start, end, &traces)))
+}
+
+pub(super) fn micros(time: OffsetDateTime) -> i64 {
+    i64::try_from(time.unix_timestamp_nanos() / 1000).unwrap_or(i64::MAX)
+}
+
+/// Index of the duration bucket, where bucket `n` covers `2^(n-1)..2^n` and bucket `0` only
+/// contains zero.
+fn duration_bucket(duration: u64) -> usize {
+    (u64::BITS - duration.leading_zeros()) as usize
+}
+
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+pub(super) fn compute(start: i64, end: i64, traces: &[(OffsetDateTime, u64)]) -> TraceHistogram {
+    let count = (end - start).clamp(1, TIME_BUCKETS);
+    let width = (end - start + count - 1) / count;
+
+    let mut time_buckets = (0..count)
+        .map(|i| HistogramBucket {
+            start: start + i * width,
+            end: (start + (i + 1) * width).min(end),
+            count: 0,
+        })
+        .collect::<Vec<_>>();
+    let mut durations = BTreeMap::<usize, usize>::new();
+    let mut cells = BTreeMap::<(usize, usize), usize>::new();
+
+    for (timestamp, duration) in traces {
+        let time = ((micros(*timestamp) - start) / width).clamp(0, count - 1) as usize;
+        let duration = duration_bucket(*duration);
+
+        time_buckets[time].count += 1;
+        *durations.entry(duration).or_default() += 1;
+        *cells.entry((time, duration)).or_default() += 1;
+    }
+
+    // Fill in the gaps, so the duration buckets form a continuous range.
+    let first = durations.keys().next().copied().unwrap_or_default();
+    let last = durations.keys().next_back().copied().unwrap_or_default();
+    let duration_buckets = (first..=last)
+        .filter(|_| !durations.is_empty())
+        .map(|n| HistogramBucket {
+            start: if n == 0 { 0 } else { 1 << (n - 1) },
+            end: 1_i64.checked_shl(n as u32).unwrap_or(i64::MAX),
+            count: durations.get(&n).copied().unwrap_or_default(),
+        })
+        .collect();
+
+    TraceHistogram {
+        time_buckets,
+        duration_buckets,
+        cells: cells
+            .into_iter()
+            .map(|((time, duration), count)| HistogramCell {
+                time,
+                duration: duration - first,
+                count,
+            })
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn compute_buckets() {
+        let start = OffsetDateTime::UNIX_EPOCH;
+        let traces = [
+            (start, 0),
+            (start + Duration::seconds(30), 3),
+            (start + Duration::seconds(59), 3),
+            (start + Duration::seconds(60), 20),
+        ];
+
+        let result = compute(0, 60_000_000, &traces);
+
+        assert_eq!(60, result.time_buckets.len());
+        assert_eq!(1, result.time_buckets[0].count);
+        assert_eq!(1, result.time_buckets[30].count);
+        assert_eq!(2, result.time_buckets[59].count);
+        assert_eq!(
+            vec![
+                (0, 1, 1),
+                (1, 2, 0),
+                (2, 4, 2),
+                (4, 8, 0),
+                (8, 16, 0),
+                (16, 32, 1)
+            ],
+            result
+                .duration_buckets
+                .iter()
+                .map(|b| (b.start, b.end, b.count))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![(0, 0, 1), (30, 2, 1), (59, 2, 1), (59, 5, 1)],
+            result
+                .cells
+                .iter()
+                .map(|c| (c.time, c.duration, c.count))
+                .collect::<Vec<_>>()
+        );
+    }
+}
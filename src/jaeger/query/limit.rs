@@ -0,0 +1,116 @@
+//! Backpressure for the query API, that limits the amount of concurrent requests and their
+//! duration. Requests over the limit are rejected right away, instead of queueing up in front of
+//! the storage.
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{bail, Result};
+
+use archer_http::{
+    axum::{
+        extract::{MatchedPath, State},
+        http::{Request, StatusCode},
+        middleware::Next,
+        response::{IntoResponse, Response},
+    },
+    ApiError,
+};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::config;
+
+/// Limits of the whole query API and single routes.
+pub struct Limits {
+    permits: Option<Arc<Semaphore>>,
+    timeout: Option<Duration>,
+    routes: HashMap<String, Route>,
+}
+
+struct Route {
+    permits: Option<Arc<Semaphore>>,
+    timeout: Option<Duration>,
+}
+
+impl Limits {
+    /// Create the limits from the configuration. Routes in the configuration don't include the base
+    /// path, but the matched path of a request does when the API is served under one. Each of them
+    /// must be one of the registered routes, so typos don't silently disable a limit.
+    pub fn new<'a>(
+        config: config::QueryLimits,
+        base_path: &str,
+        registered: impl IntoIterator<Item = &'a str>,
+    ) -> Result<Self> {
+        let registered = registered.into_iter().collect::<HashSet<_>>();
+
+        if let Some(unknown) = config
+            .routes
+            .keys()
+            .find(|path| !registered.contains(path.as_str()))
+        {
+            bail!("query limits for unknown route `{unknown}`");
+        }
+
+        Ok(Self {
+            permits: config.max_concurrency.map(|n| Arc::new(Semaphore::new(n))),
+            timeout: config.timeout_seconds.map(Duration::from_secs),
+            routes: config
+                .routes
+                .into_iter()
+                .map(|(path, route)| {
+                    (
+                        format!("{base_path}{path}"),
+                        Route {
+                            permits: route.max_concurrency.map(|n| Arc::new(Semaphore::new(n))),
+                            timeout: route.timeout_seconds.map(Duration::from_secs),
+                        },
+                    )
+                })
+                .collect(),
+        })
+    }
+}
+
+/// Middleware that applies the limits of the matched route.
+pub async fn limit<B>(
+    State(limits): State<Arc<Limits>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .and_then(|path| limits.routes.get(path.as_str()));
+
+    let permits = [
+        limits.permits.as_ref(),
+        route.and_then(|route| route.permits.as_ref()),
+    ];
+    let Some(_permits) = permits
+        .into_iter()
+        .flatten()
+        .map(|permits| Arc::clone(permits).try_acquire_owned().ok())
+        .collect::<Option<Vec<OwnedSemaphorePermit>>>()
+    else {
+        return unavailable("too many concurrent requests, try again later");
+    };
+
+    match route.and_then(|route| route.timeout).or(limits.timeout) {
+        Some(timeout) => tokio::time::timeout(timeout, next.run(req))
+            .await
+            .unwrap_or_else(|_| unavailable("request timed out")),
+        None => next.run(req).await,
+    }
+}
+
+fn unavailable(msg: &'static str) -> Response {
+    ApiError {
+        code: StatusCode::SERVICE_UNAVAILABLE,
+        msg: msg.into(),
+        trace_id: None,
+    }
+    .into_response()
+}
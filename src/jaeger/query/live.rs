@@ -0,0 +1,89 @@
+//! Live tail of newly stored traces, that are pushed to the client as server-sent events while
+//! they arrive.
+
+use std::{collections::HashMap, convert::Infallible};
+
+use archer_http::{
+    axum::{
+        extract::{rejection::QueryRejection, Query, State},
+        response::{
+            sse::{Event, KeepAlive},
+            IntoResponse, Sse,
+        },
+    },
+    ApiError,
+};
+use futures_util::{stream, StreamExt};
+use serde::Deserialize;
+use tokio_shutdown::Shutdown;
+use tracing::{error, instrument};
+
+use super::{bad_query, Tenanted};
+use crate::{
+    convert,
+    models::Span,
+    storage::{Database, Received},
+};
+
+#[derive(Deserialize)]
+pub struct StreamQuery {
+    service: String,
+    #[serde(default)]
+    operation: String,
+}
+
+/// Stream all newly stored traces that contain spans of the service, and optionally the operation.
+/// Each `trace` event carries the spans of a trace, that were stored together. Spans of the same
+/// trace that arrive later are sent as separate events.
+///
+/// A `lagged` event with the amount of skipped batches is sent, if the client can't keep up.
+#[instrument(skip_all)]
+pub async fn stream(
+    query: Result<Query<StreamQuery>, QueryRejection>,
+    State(shutdown): State<Shutdown>,
+    Tenanted(db): Tenanted<Database>,
+) -> Result<impl IntoResponse, ApiError> {
+    let Query(query) = query.map_err(bad_query)?;
+
+    let events = stream::unfold(db.subscribe(), |mut subscription| async move {
+        let received = subscription.recv().await?;
+        Some((received, subscription))
+    })
+    .flat_map(move |received| stream::iter(events(&query, received)))
+    .map(Ok::<_, Infallible>)
+    .take_until(shutdown.handle());
+
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+fn events(query: &StreamQuery, received: Received) -> Vec<Event> {
+    let stored = match received {
+        Received::Spans(stored) => stored,
+        Received::Lagged(skipped) => {
+            return vec![Event::default().event("lagged").data(skipped.to_string())];
+        }
+    };
+
+    let mut traces = HashMap::<_, Vec<&Span>>::new();
+    for span in &stored.spans {
+        traces.entry(span.trace_id).or_default().push(span);
+    }
+
+    traces
+        .into_iter()
+        .filter(|(_, spans)| {
+            spans.iter().any(|span| {
+                span.process.service == query.service
+                    && (query.operation.is_empty() || span.operation_name == query.operation)
+            })
+        })
+        .filter_map(|(trace_id, spans)| {
+            let trace = convert::partial_trace_to_json(trace_id, spans.into_iter().cloned());
+            Event::default()
+                .event("trace")
+                .json_data(trace)
+                .map_err(|e| error!(error = ?e, "failed serializing trace"))
+                .ok()
+        })
+        .collect()
+}
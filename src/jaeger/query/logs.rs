@@ -0,0 +1,27 @@
+//! Logs of all spans in a trace, as a single chronological timeline of everything that happened
+//! in a request.
+
+use archer_http::{
+    axum::{extract::Path, response::IntoResponse},
+    ApiError, ApiResponse, TraceId,
+};
+use tracing::instrument;
+
+use super::{cache, skew, Tenanted};
+use crate::{convert, storage::ReadOnlyDatabase};
+
+#[instrument(skip_all)]
+pub async fn logs(
+    Path(trace_id): Path<TraceId>,
+    Tenanted(db): Tenanted<ReadOnlyDatabase>,
+) -> Result<impl IntoResponse, ApiError> {
+    let mut spans = cache::find_trace(&db, trace_id.0.into())
+        .await
+        .map_err(|e| ApiError {
+            trace_id: Some(trace_id),
+            ..e.into()
+        })?;
+    skew::adjust(&mut spans);
+
+    Ok(ApiResponse::Data(convert::trace_logs_to_json(spans)))
+}
@@ -1,84 +1,464 @@
 #![allow(clippy::unused_async)]
 
-use std::{collections::HashMap, net::SocketAddr};
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
+    convert::Infallible,
+    sync::Arc,
+};
 
-use anyhow::{ensure, Result};
+use anyhow::{ensure, Context, Result};
 use archer_http::{
     axum::{
-        extract::{rejection::QueryRejection, Path, Query, State},
-        headers::IfNoneMatch,
+        async_trait,
+        extract::{
+            rejection::{JsonRejection, QueryRejection},
+            FromRef, FromRequestParts, Path, Query, State,
+        },
         http::{
-            header::{CACHE_CONTROL, CONTENT_TYPE, ETAG, LAST_MODIFIED},
-            HeaderMap, HeaderValue, StatusCode, Uri,
+            header::HeaderName, request::Parts, Extensions, HeaderMap, HeaderValue, Method,
+            Request, StatusCode, Version,
+        },
+        middleware::{self, Next},
+        response::{IntoResponse, Response},
+        routing::{get, post},
+        Json, Router,
+    },
+    tower_http::{
+        compression::{
+            predicate::{DefaultPredicate, Predicate},
+            CompressionLayer,
         },
-        response::IntoResponse,
-        routing::get,
-        Router, Server, TypedHeader,
+        cors::{AllowOrigin, CorsLayer},
+        validate_request::ValidateRequestHeaderLayer,
     },
-    tower::ServiceBuilder,
-    tower_http::ServiceBuilderExt,
-    ApiError, ApiResponse, TraceId,
+    ApiError, ApiResponse, MaintenanceResult, Operation, ServiceStats, StorageStats, TraceId,
 };
 use serde::Deserialize;
 use time::{Duration, OffsetDateTime};
 use tokio_shutdown::Shutdown;
-use tracing::{error, info, instrument};
+use tracing::{info, instrument};
 
 use crate::{
-    convert, net,
-    storage::{ListSpansParams, ReadOnlyDatabase},
+    config::{self, QueryAuth},
+    convert, metrics,
+    models::SpanKind,
+    net,
+    storage::{Comparison, Database, ListSpansParams, ReadOnlyDatabase, StorageError},
+    tenancy, tracer,
 };
 
+mod analytics;
+mod cache;
+mod compare;
+mod critical_path;
 mod de;
+mod graph;
+mod grpc;
+mod histogram;
+mod limit;
+mod live;
+mod logs;
+mod skew;
+mod spm;
+mod ui;
+
+#[derive(Clone)]
+struct AppState {
+    /// Writable database, which is missing in read-only mode.
+    database: Option<Database>,
+    database_ro: ReadOnlyDatabase,
+    shutdown: Shutdown,
+    ui_config: ui::Config,
+}
+
+impl From<StorageError> for ApiError {
+    fn from(value: StorageError) -> Self {
+        Self {
+            code: match value {
+                StorageError::NotFound => StatusCode::NOT_FOUND,
+                StorageError::Conflict(_) => StatusCode::CONFLICT,
+                StorageError::Busy(_) => StatusCode::SERVICE_UNAVAILABLE,
+                StorageError::Corrupt(_) | StorageError::Io(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            },
+            msg: value.to_string().into(),
+            trace_id: None,
+        }
+    }
+}
+
+impl FromRef<AppState> for Option<Database> {
+    fn from_ref(input: &AppState) -> Self {
+        input.database.clone()
+    }
+}
+
+impl FromRef<AppState> for ReadOnlyDatabase {
+    fn from_ref(input: &AppState) -> Self {
+        input.database_ro.clone()
+    }
+}
+
+impl FromRef<AppState> for Shutdown {
+    fn from_ref(input: &AppState) -> Self {
+        input.shutdown.clone()
+    }
+}
+
+impl FromRef<AppState> for ui::Config {
+    fn from_ref(input: &AppState) -> Self {
+        input.ui_config.clone()
+    }
+}
+
+/// Database handle, that is limited to the tenant of the current request.
+struct Tenanted<T>(T);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Tenanted<Database>
+where
+    Option<Database>: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let database = Option::<Database>::from_ref(state).ok_or_else(read_only)?;
+
+        Ok(Self(
+            database.for_tenant(tenancy::from_headers(&parts.headers)),
+        ))
+    }
+}
+
+/// Error for endpoints that modify the database, while it's only opened for reading.
+fn read_only() -> ApiError {
+    ApiError {
+        code: StatusCode::FORBIDDEN,
+        msg: "archer runs in read-only mode".into(),
+        trace_id: None,
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Tenanted<ReadOnlyDatabase>
+where
+    ReadOnlyDatabase: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Self(
+            ReadOnlyDatabase::from_ref(state).for_tenant(tenancy::from_headers(&parts.headers)),
+        ))
+    }
+}
 
 #[instrument(name = "query", skip_all)]
-pub async fn run(shutdown: Shutdown, database: ReadOnlyDatabase) -> Result<()> {
-    let app = Router::new()
-        .route("/api/services", get(services))
-        .route("/api/services/:service/operations", get(operations))
-        .route("/api/operations", get(todo))
-        .route("/api/traces", get(traces))
-        .route("/api/traces/:id", get(trace))
-        .route("/api/archive/:id", get(todo))
-        .route("/api/dependencies", get(dependencies))
-        .route("/api/metrics/latencies", get(todo))
-        .route("/api/metrics/calls", get(todo))
-        .route("/api/metrics/errors", get(todo))
-        .route("/api/metrics/minstep", get(todo))
-        .fallback(asset)
-        .layer(ServiceBuilder::new().compression())
-        .with_state(database);
-
-    let addr = SocketAddr::from(net::JAEGER_QUERY_HTTP);
-    info!("listening on http://{addr}");
-
-    Server::bind(&addr)
-        .serve(app.into_make_service())
-        .with_graceful_shutdown(shutdown.handle())
-        .await?;
+pub async fn run(
+    shutdown: Shutdown,
+    database: Option<Database>,
+    database_ro: ReadOnlyDatabase,
+    settings: config::Query,
+    tls: Option<Arc<rustls::ServerConfig>>,
+    addrs: net::Addresses,
+) -> Result<()> {
+    skew::init(settings.clock_skew)?;
+
+    // Errors of any task, not only panics, end the query service immediately.
+    tokio::try_join!(
+        crate::flatten(tokio::spawn(cache::run(
+            tracing::Span::current(),
+            shutdown.clone(),
+            settings.cache,
+        ))),
+        crate::flatten(tokio::spawn(grpc::run(
+            tracing::Span::current(),
+            shutdown.clone(),
+            database_ro.clone(),
+            settings.auth.clone(),
+            addrs.query_grpc,
+        ))),
+        crate::flatten(tokio::spawn(run_http(
+            tracing::Span::current(),
+            shutdown,
+            database,
+            database_ro,
+            settings,
+            tls,
+            addrs,
+        ))),
+    )?;
+
+    Ok(())
+}
+
+#[instrument(name = "http", parent = parent, skip_all)]
+async fn run_http(
+    parent: tracing::Span,
+    shutdown: Shutdown,
+    database: Option<Database>,
+    database_ro: ReadOnlyDatabase,
+    settings: config::Query,
+    tls: Option<Arc<rustls::ServerConfig>>,
+    addrs: net::Addresses,
+) -> Result<()> {
+    if addrs.query.is_none() && addrs.query_socket.is_none() {
+        return Ok(());
+    }
+
+    let base_path = settings.base_path.as_deref().unwrap_or_default();
+    let base_path = base_path.trim_end_matches('/');
+    ensure!(
+        base_path.is_empty() || base_path.starts_with('/'),
+        "base path `{base_path}` must start with a slash"
+    );
+
+    let ui_config = ui::Config::new(settings.ui_config, base_path)?;
+
+    let routes = [
+        ("/api/config", get(ui::config)),
+        ("/api/services", get(services)),
+        ("/api/services/:service/operations", get(operations)),
+        ("/api/operations", get(all_operations)),
+        ("/api/traces", get(traces)),
+        ("/api/traces/compare", get(compare::compare)),
+        ("/api/traces/histogram", get(histogram::histogram)),
+        ("/api/traces/import", post(import)),
+        ("/api/traces/stream", get(live::stream)),
+        ("/api/traces/:id", get(trace)),
+        (
+            "/api/traces/:id/critical-path",
+            get(critical_path::critical_path),
+        ),
+        ("/api/traces/:id/graph", get(graph::graph)),
+        ("/api/traces/:id/logs", get(logs::logs)),
+        ("/api/archive/:id", get(todo)),
+        ("/api/dependencies", get(dependencies)),
+        ("/api/analytics/errors", get(analytics::errors)),
+        ("/api/analytics/latency", get(analytics::latency)),
+        ("/api/storage/stats", get(storage_stats)),
+        ("/api/storage/maintenance", post(storage_maintenance)),
+        ("/api/metrics/latencies", get(spm::latencies)),
+        ("/api/metrics/calls", get(spm::calls)),
+        ("/api/metrics/errors", get(spm::errors)),
+        ("/api/metrics/minstep", get(spm::min_step)),
+    ];
+
+    let limits = limit::Limits::new(
+        settings.limits,
+        base_path,
+        routes.iter().map(|(path, _)| *path),
+    )?;
+
+    let app = routes
+        .into_iter()
+        .fold(Router::new(), |app, (path, route)| app.route(path, route))
+        .route_layer(middleware::from_fn_with_state(
+            Arc::new(limits),
+            limit::limit,
+        ))
+        .route_layer(middleware::from_fn(metrics::track_query))
+        .route_layer(middleware::from_fn(skip_tracing))
+        .route("/metrics", get(metrics::handler));
+
+    let app = match settings.ui {
+        Some(dir) => {
+            info!(dir = %dir.display(), "serving UI from directory");
+            app.fallback_service(ui::directory(dir, ui_config.clone()))
+        }
+        None => app.fallback(ui::embedded),
+    };
+
+    let app = if base_path.is_empty() {
+        app
+    } else {
+        info!(base_path, "serving under base path");
+        Router::new().nest(base_path, app)
+    };
+
+    let app = match settings.auth {
+        Some(QueryAuth::Bearer { token }) => {
+            ensure!(
+                HeaderValue::from_str(&token).is_ok(),
+                "bearer token contains invalid characters"
+            );
+            app.layer(ValidateRequestHeaderLayer::bearer(&token))
+        }
+        Some(QueryAuth::Basic { username, password }) => {
+            app.layer(ValidateRequestHeaderLayer::basic(&username, &password))
+        }
+        None => app,
+    };
+
+    // Outside of the authentication, as preflight requests never carry credentials.
+    let app = match settings.cors {
+        Some(config) => app.layer(cors(config)?),
+        None => app,
+    };
+
+    let app = app
+        .layer(
+            CompressionLayer::new().compress_when(DefaultPredicate::new().and(
+                // Byte ranges refer to the uncompressed content, so they must be sent as is.
+                |status: StatusCode, _: Version, _: &HeaderMap, _: &Extensions| {
+                    status != StatusCode::PARTIAL_CONTENT
+                },
+            )),
+        )
+        .with_state(AppState {
+            database,
+            database_ro,
+            shutdown: shutdown.clone(),
+            ui_config,
+        });
+
+    net::serve_with_unix(
+        addrs.query,
+        addrs.query_socket.as_deref(),
+        app,
+        tls,
+        shutdown,
+    )
+    .await?;
 
     info!("server stopped");
 
     Ok(())
 }
 
+/// Middleware that disables self-tracing for requests about any of the skipped services, so
+/// looking at Archer's own traces doesn't create even more of them.
+async fn skip_tracing<B>(
+    path: Option<Path<HashMap<String, String>>>,
+    query: Option<Query<ServiceQuery>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let service = path
+        .and_then(|Path(mut params)| params.remove("service"))
+        .or_else(|| query.map(|Query(query)| query.service));
+
+    if service.is_some_and(|service| tracer::skips(&service)) {
+        tracer::untraced(next.run(req)).await
+    } else {
+        next.run(req).await
+    }
+}
+
+#[derive(Deserialize)]
+struct ServiceQuery {
+    service: String,
+}
+
+fn cors(config: config::Cors) -> Result<CorsLayer> {
+    let origins = if config.allowed_origins.iter().any(|origin| origin == "*") {
+        AllowOrigin::any()
+    } else {
+        AllowOrigin::list(
+            config
+                .allowed_origins
+                .iter()
+                .map(|origin| HeaderValue::from_str(origin))
+                .collect::<Result<Vec<_>, _>>()
+                .context("invalid CORS origin")?,
+        )
+    };
+
+    Ok(CorsLayer::new()
+        .allow_origin(origins)
+        .allow_methods(
+            config
+                .allowed_methods
+                .iter()
+                .map(|method| Method::from_bytes(method.as_bytes()))
+                .collect::<Result<Vec<_>, _>>()
+                .context("invalid CORS method")?,
+        )
+        .allow_headers(
+            config
+                .allowed_headers
+                .iter()
+                .map(|header| HeaderName::from_bytes(header.as_bytes()))
+                .collect::<Result<Vec<_>, _>>()
+                .context("invalid CORS header")?,
+        )
+        .max_age(std::time::Duration::from_secs(config.max_age_seconds)))
+}
+
 #[instrument(skip_all)]
-async fn services(State(db): State<ReadOnlyDatabase>) -> Result<impl IntoResponse, ApiError> {
-    db.list_services()
-        .await
-        .map(ApiResponse::Data)
-        .map_err(ApiError::from)
+async fn services(
+    query: Result<Query<LookbackQuery>, QueryRejection>,
+    Tenanted(db): Tenanted<ReadOnlyDatabase>,
+) -> Result<impl IntoResponse, ApiError> {
+    let Query(query) = query.map_err(bad_query)?;
+
+    Ok(ApiResponse::Data(db.list_services(since(query.lookback))))
 }
 
 #[instrument(skip_all)]
 async fn operations(
     Path(service): Path<String>,
-    State(db): State<ReadOnlyDatabase>,
+    query: Result<Query<LookbackQuery>, QueryRejection>,
+    Tenanted(db): Tenanted<ReadOnlyDatabase>,
 ) -> Result<impl IntoResponse, ApiError> {
-    db.list_operations(service)
-        .await
-        .map(ApiResponse::Data)
-        .map_err(ApiError::from)
+    let Query(query) = query.map_err(bad_query)?;
+
+    Ok(ApiResponse::Data(
+        db.list_operations(&service, since(query.lookback)),
+    ))
+}
+
+/// Optional time frame, in which services or operations must have been seen to be listed.
+#[derive(Deserialize)]
+struct LookbackQuery {
+    #[serde(default, deserialize_with = "de::duration_human")]
+    lookback: Option<Duration>,
+}
+
+/// Convert the lookback into the earliest point in time, at which items must have been seen.
+fn since(lookback: Option<Duration>) -> Option<OffsetDateTime> {
+    lookback.map(|lookback| OffsetDateTime::now_utc() - lookback)
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct OperationsQuery {
+    service: String,
+    #[serde(default)]
+    span_kind: String,
+    #[serde(default, deserialize_with = "de::duration_human")]
+    lookback: Option<Duration>,
+}
+
+#[instrument(skip_all)]
+async fn all_operations(
+    query: Result<Query<OperationsQuery>, QueryRejection>,
+    Tenanted(db): Tenanted<ReadOnlyDatabase>,
+) -> Result<impl IntoResponse, ApiError> {
+    let Query(query) = query.map_err(bad_query)?;
+
+    let operations = db.find_operations(
+        &query.service,
+        (!query.span_kind.is_empty()).then_some(query.span_kind.as_str()),
+        since(query.lookback),
+    );
+
+    Ok(ApiResponse::Data(
+        operations
+            .into_iter()
+            .map(|(name, span_kind)| Operation { name, span_kind })
+            .collect::<Vec<_>>(),
+    ))
+}
+
+fn bad_query(e: QueryRejection) -> ApiError {
+    ApiError {
+        code: StatusCode::BAD_REQUEST,
+        msg: e.to_string().into(),
+        trace_id: None,
+    }
 }
 
 #[cfg_attr(test, derive(Default, PartialEq))]
@@ -98,8 +478,16 @@ struct TracesQuery {
     max_duration: Option<Duration>,
     #[serde(default, deserialize_with = "de::limit")]
     limit: Option<u32>,
+    #[serde(default, deserialize_with = "de::parsed")]
+    offset: Option<u32>,
+    #[serde(default, deserialize_with = "de::parsed")]
+    error: Option<bool>,
+    #[serde(default, deserialize_with = "de::parsed")]
+    min_span_count: Option<u32>,
+    #[serde(default, deserialize_with = "de::parsed")]
+    span_kind: Option<SpanKind>,
     #[serde(default, flatten, deserialize_with = "de::tags")]
-    tags: HashMap<String, String>,
+    tags: de::TagQuery,
 }
 
 impl TracesQuery {
@@ -114,7 +502,25 @@ impl TracesQuery {
 
         ensure!(start < end, "start must be before end");
 
-        if let (Some(min), Some(max)) = (self.min_duration, self.max_duration) {
+        let (mut min_duration, mut max_duration) = (self.min_duration, self.max_duration);
+        let micro = Duration::microseconds(1);
+
+        for (comparison, duration) in self.tags.durations {
+            let (min, max) = match comparison {
+                Comparison::Lt => (None, Some(duration - micro)),
+                Comparison::Le => (None, Some(duration)),
+                Comparison::Gt => (Some(duration + micro), None),
+                Comparison::Ge => (Some(duration), None),
+            };
+
+            min_duration = min_duration.max(min);
+            max_duration = match (max_duration, max) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            };
+        }
+
+        if let (Some(min), Some(max)) = (min_duration, max_duration) {
             ensure!(min < max, " minimum duration must be smaller than maximum");
         }
 
@@ -123,10 +529,14 @@ impl TracesQuery {
             operation: (!self.operation.is_empty()).then_some(self.operation),
             start,
             end,
-            duration_min: self.min_duration,
-            duration_max: self.max_duration,
+            duration_min: min_duration,
+            duration_max: max_duration,
             limit: self.limit.unwrap_or(20) as _,
-            tags: self.tags,
+            offset: self.offset.unwrap_or_default() as _,
+            tags: self.tags.tags,
+            error: self.error.unwrap_or_default(),
+            min_span_count: self.min_span_count,
+            span_kind: self.span_kind,
         })
     }
 }
@@ -140,20 +550,42 @@ struct TraceIdsQuery(#[serde(deserialize_with = "de::trace_ids")] Vec<TraceId>);
 async fn traces(
     query: Result<Query<TracesQuery>, QueryRejection>,
     trace_ids: Option<Query<TraceIdsQuery>>,
-    State(db): State<ReadOnlyDatabase>,
+    Tenanted(db): Tenanted<ReadOnlyDatabase>,
 ) -> Result<impl IntoResponse, ApiError> {
-    let spans = match (query, trace_ids) {
+    match (query, trace_ids) {
         (Ok(Query(query)), None) => {
             let params = query.into_db().map_err(|e| ApiError {
                 code: StatusCode::BAD_REQUEST,
                 msg: e.to_string().into(),
                 trace_id: None,
             })?;
-            db.list_spans(params).await.map_err(ApiError::from)?
+            let (limit, offset) = (params.limit, params.offset);
+            let (total, mut spans) = db.list_spans(params).await.map_err(ApiError::from)?;
+            for spans in spans.values_mut() {
+                skew::adjust(spans);
+            }
+
+            // Newest traces first, the same order that was used to select the page.
+            let mut spans = spans.into_iter().collect::<Vec<_>>();
+            spans.sort_by_key(|(_, spans)| Reverse(spans.iter().map(|span| span.start).min()));
+
+            Ok(ApiResponse::Page {
+                data: spans
+                    .into_iter()
+                    .map(|(trace_id, spans)| convert::trace_to_json(trace_id, spans))
+                    .collect(),
+                total,
+                limit,
+                offset,
+            })
         }
         (Err(_), Some(Query(ids))) => {
-            let spans = db
-                .find_traces(ids.0.iter().map(|id| id.0.into()))
+            let mut ids = ids.0;
+            let mut seen = HashSet::new();
+            ids.retain(|id| seen.insert(*id));
+
+            let mut spans = db
+                .find_traces(ids.iter().map(|id| id.0.into()))
                 .await
                 .map_err(ApiError::from)?;
 
@@ -161,56 +593,106 @@ async fn traces(
                 return Err(ApiError {
                     code: StatusCode::NOT_FOUND,
                     msg: "trace id not found".into(),
-                    trace_id: ids.0.get(0).copied(),
+                    trace_id: ids.first().copied(),
                 });
             }
 
-            spans
-        }
-        (Ok(_), Some(_)) => {
-            return Err(ApiError {
-                code: StatusCode::BAD_REQUEST,
-                msg: "can't search by trace IDs and query at the same time".into(),
-                trace_id: None,
-            });
+            // Keep the order of the requested IDs, and report each missing one individually.
+            let mut traces = Vec::with_capacity(ids.len());
+            let mut errors = Vec::new();
+
+            for id in ids {
+                match spans.remove(&id.0.into()) {
+                    Some(mut spans) => {
+                        skew::adjust(&mut spans);
+                        traces.push(convert::trace_to_json(id.0.into(), spans));
+                    }
+                    None => errors.push(ApiError {
+                        code: StatusCode::NOT_FOUND,
+                        msg: "trace id not found".into(),
+                        trace_id: Some(id),
+                    }),
+                }
+            }
+
+            Ok(ApiResponse::Partial {
+                data: traces,
+                errors,
+            })
         }
-        (Err(e), None) => {
-            return Err(ApiError {
-                code: StatusCode::BAD_REQUEST,
-                msg: e.to_string().into(),
-                trace_id: None,
-            });
+        (Ok(_), Some(_)) => Err(ApiError {
+            code: StatusCode::BAD_REQUEST,
+            msg: "can't search by trace IDs and query at the same time".into(),
+            trace_id: None,
+        }),
+        (Err(e), None) => Err(ApiError {
+            code: StatusCode::BAD_REQUEST,
+            msg: e.to_string().into(),
+            trace_id: None,
+        }),
+    }
+}
+
+/// Parse a document for import, either in the form of the Jaeger UI's JSON download (multiple
+/// traces wrapped in a `data` field) or a single trace.
+fn import_traces(document: serde_json::Value) -> serde_json::Result<Vec<archer_http::Trace>> {
+    match document {
+        serde_json::Value::Object(mut map) if map.contains_key("data") => {
+            serde_json::from_value(map.remove("data").unwrap_or_default())
         }
-    };
+        document => serde_json::from_value(document).map(|trace| vec![trace]),
+    }
+}
 
-    let traces = spans
+#[instrument(skip_all)]
+async fn import(
+    Tenanted(db): Tenanted<Database>,
+    document: Result<Json<serde_json::Value>, JsonRejection>,
+) -> Result<impl IntoResponse, ApiError> {
+    let traces = document
+        .map_err(|e| e.to_string())
+        .and_then(|Json(document)| import_traces(document).map_err(|e| e.to_string()))
+        .map_err(|e| ApiError {
+            code: StatusCode::BAD_REQUEST,
+            msg: e.into(),
+            trace_id: None,
+        })?;
+
+    let trace_ids = traces.iter().map(|trace| trace.trace_id).collect();
+    let spans = traces
         .into_iter()
-        .map(|(trace_id, spans)| convert::trace_to_json(trace_id, spans))
+        .map(convert::trace_from_json)
+        .collect::<Result<Vec<_>>>()
+        .map_err(|e| ApiError {
+            code: StatusCode::BAD_REQUEST,
+            msg: e.to_string().into(),
+            trace_id: None,
+        })?
+        .into_iter()
+        .flatten()
         .collect();
 
-    Ok(ApiResponse::Data(traces))
+    db.save_spans(spans).await?;
+
+    Ok(ApiResponse::Data(trace_ids))
 }
 
 #[instrument(skip_all)]
 async fn trace(
     Path(trace_id): Path<TraceId>,
-    State(db): State<ReadOnlyDatabase>,
+    Tenanted(db): Tenanted<ReadOnlyDatabase>,
 ) -> Result<impl IntoResponse, ApiError> {
-    let spans = db
-        .find_trace(trace_id.0.into())
+    let mut spans = cache::find_trace(&db, trace_id.0.into())
         .await
-        .map_err(ApiError::from)?;
-    let trace_id = spans
-        .first()
-        .map(|span| span.trace_id)
-        .ok_or_else(|| ApiError {
-            code: StatusCode::NOT_FOUND,
-            msg: "trace ID not found".into(),
-            trace_id: None,
+        .map_err(|e| ApiError {
+            trace_id: Some(trace_id),
+            ..e.into()
         })?;
+    skew::adjust(&mut spans);
 
     Ok(ApiResponse::Data(vec![convert::trace_to_json(
-        trace_id, spans,
+        trace_id.0.into(),
+        spans,
     )]))
 }
 
@@ -219,40 +701,50 @@ async fn dependencies() -> impl IntoResponse {
     ApiResponse::Data(Vec::<()>::new())
 }
 
-async fn todo() -> impl IntoResponse {
-    StatusCode::NOT_IMPLEMENTED
+#[instrument(skip_all)]
+async fn storage_stats(
+    Tenanted(db): Tenanted<ReadOnlyDatabase>,
+) -> Result<Json<StorageStats>, ApiError> {
+    let stats = db.stats().await.map_err(ApiError::from)?;
+
+    Ok(Json(StorageStats {
+        span_count: stats.span_count,
+        trace_count: stats.trace_count,
+        services: stats
+            .services
+            .into_iter()
+            .map(|(name, span_count)| ServiceStats { name, span_count })
+            .collect(),
+        oldest_span: stats.oldest_span,
+        newest_span: stats.newest_span,
+        file_size: stats.file_size,
+    }))
 }
 
-include!(concat!(env!("OUT_DIR"), "/assets.rs"));
-
-async fn asset(uri: Uri, if_none_match: Option<TypedHeader<IfNoneMatch>>) -> impl IntoResponse {
-    let asset = ASSETS
-        .get(uri.path())
-        .or_else(|| ASSETS.get("/index.html"))
-        .ok_or_else(|| (HeaderMap::new(), StatusCode::NOT_FOUND))?;
-
-    let headers = [
-        (CONTENT_TYPE, asset.mime),
-        (ETAG, asset.etag),
-        (LAST_MODIFIED, "Thu, 01 Jan 1970 00:00:00 GMT"),
-        (CACHE_CONTROL, "public, max-age=2592000, must-revalidate"),
-    ]
-    .into_iter()
-    .map(|(name, value)| (name, HeaderValue::from_static(value)))
-    .collect::<HeaderMap>();
-
-    let unmatched = if_none_match.map_or(Ok(true), |v| {
-        asset.etag.parse().map(|etag| v.precondition_passes(&etag))
-    });
-
-    match unmatched {
-        Ok(true) => Ok((headers, asset.content)),
-        Ok(false) => Err((headers, StatusCode::NOT_MODIFIED)),
-        Err(e) => {
-            error!(error = ?e, "failed parsing etag");
-            Err((headers, StatusCode::INTERNAL_SERVER_ERROR))
-        }
-    }
+#[derive(Deserialize)]
+struct MaintenanceQuery {
+    #[serde(default)]
+    full: bool,
+}
+
+#[allow(clippy::cast_possible_truncation)]
+#[instrument(skip_all)]
+async fn storage_maintenance(
+    Query(query): Query<MaintenanceQuery>,
+    State(db): State<Option<Database>>,
+) -> Result<Json<MaintenanceResult>, ApiError> {
+    let db = db.ok_or_else(read_only)?;
+    let start = std::time::Instant::now();
+    let freed_bytes = db.maintain(query.full).await.map_err(ApiError::from)?;
+
+    Ok(Json(MaintenanceResult {
+        freed_bytes,
+        duration: start.elapsed().as_millis() as u64,
+    }))
+}
+
+async fn todo() -> impl IntoResponse {
+    StatusCode::NOT_IMPLEMENTED
 }
 
 #[cfg(test)]
@@ -262,6 +754,7 @@ mod tests {
     use std::num::NonZeroU128;
 
     use super::*;
+    use crate::storage::TagFilters;
 
     #[tokio::test]
     async fn deser_trace_ids() {
@@ -309,6 +802,26 @@ mod tests {
         assert_eq!(expect, result.unwrap());
     }
 
+    #[test]
+    fn deser_query_tags_comparison() {
+        let expect = TracesQuery {
+            service: "test".to_owned(),
+            tags: de::TagQuery {
+                tags: TagFilters {
+                    exact: [("a".to_owned(), "1".to_owned())].into_iter().collect(),
+                    numeric: vec![("http.status_code".to_owned(), Comparison::Ge, 500.0)],
+                },
+                durations: vec![(Comparison::Gt, Duration::milliseconds(100))],
+            },
+            ..TracesQuery::default()
+        };
+        let result = serde_urlencoded::from_str(
+            "service=test&tag=a:1&tag=http.status_code%3E%3D500&tag=duration%3E100ms",
+        );
+
+        assert_eq!(expect, result.unwrap());
+    }
+
     #[test]
     fn deser_query_limit() {
         let expect = TracesQuery {
@@ -321,6 +834,33 @@ mod tests {
         assert_eq!(expect, result.unwrap());
     }
 
+    #[test]
+    fn deser_query_offset() {
+        let expect = TracesQuery {
+            service: "test".to_owned(),
+            limit: Some(5),
+            offset: Some(10),
+            ..TracesQuery::default()
+        };
+        let result = serde_urlencoded::from_str("service=test&limit=5&offset=10");
+
+        assert_eq!(expect, result.unwrap());
+    }
+
+    #[test]
+    fn deser_query_error_min_span_count() {
+        let expect = TracesQuery {
+            service: "test".to_owned(),
+            error: Some(true),
+            min_span_count: Some(3),
+            tags: [("a".to_owned(), "1".to_owned())].into_iter().collect(),
+            ..TracesQuery::default()
+        };
+        let result = serde_urlencoded::from_str("service=test&error=true&minSpanCount=3&tag=a:1");
+
+        assert_eq!(expect, result.unwrap());
+    }
+
     #[test]
     fn deser_query_durations() {
         let expect = TracesQuery {
@@ -381,4 +921,35 @@ mod tests {
 
         assert_eq!(expect, result.unwrap());
     }
+
+    #[test]
+    fn deser_import_traces() {
+        let trace = r#"{
+            "traceID": "00000000000000000000000000000005",
+            "spans": [{
+                "traceID": "00000000000000000000000000000005",
+                "spanID": "0000000000000006",
+                "operationName": "op1",
+                "references": [],
+                "startTime": 1661232631416000,
+                "duration": 120,
+                "tags": [{ "key": "a", "type": "int64", "value": 1 }],
+                "logs": [],
+                "processID": "p1"
+            }],
+            "processes": { "p1": { "serviceName": "test", "tags": [] } }
+        }"#;
+
+        let mut traces = import_traces(serde_json::from_str(trace).unwrap()).unwrap();
+        assert_eq!(1, traces.len());
+
+        let spans = convert::trace_from_json(traces.remove(0)).unwrap();
+        assert_eq!(1, spans.len());
+        assert_eq!("test", spans[0].process.service);
+        assert_eq!(Duration::microseconds(120), spans[0].duration);
+
+        let traces =
+            import_traces(serde_json::from_str(&format!(r#"{{"data":[{trace}]}}"#)).unwrap());
+        assert_eq!(1, traces.unwrap().len());
+    }
 }
@@ -0,0 +1,106 @@
+//! Adjustment of clock skew between services, which shifts child spans into the time frame of
+//! their parent, if they were recorded by a process with a different clock. It works the same as
+//! the clock skew adjuster of Jaeger.
+
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+use once_cell::sync::OnceCell;
+use time::Duration;
+
+use super::graph::parent;
+use crate::{config, models::Span};
+
+static MAX_ADJUSTMENT: OnceCell<Duration> = OnceCell::new();
+
+pub fn init(config: Option<config::ClockSkew>) -> Result<()> {
+    let Some(config) = config else {
+        return Ok(());
+    };
+
+    MAX_ADJUSTMENT
+        .set(Duration::milliseconds(
+            config.max_adjustment_ms.try_into().unwrap_or(i64::MAX),
+        ))
+        .map_err(|_| anyhow!("clock skew adjustment can only be initialized once"))
+}
+
+/// Shift the spans of a single trace to compensate for clock skew. Each adjusted span gets a
+/// warning that describes the adjustment. This is a no-op if the adjustment isn't enabled.
+pub fn adjust(spans: &mut [Span]) {
+    let Some(&max) = MAX_ADJUSTMENT.get() else {
+        return;
+    };
+
+    let indices = spans
+        .iter()
+        .enumerate()
+        .map(|(i, span)| (span.span_id, i))
+        .collect::<HashMap<_, _>>();
+    let mut children = HashMap::<usize, Vec<usize>>::new();
+    let mut stack = Vec::new();
+
+    for (i, span) in spans.iter().enumerate() {
+        match parent(span).and_then(|id| indices.get(&id)) {
+            Some(&p) if p != i => children.entry(p).or_default().push(i),
+            _ => stack.push((i, None::<usize>, Duration::ZERO)),
+        }
+    }
+
+    // Spans of the same process share a clock, so they're shifted by the same delta as their
+    // parent. A new delta is only calculated when crossing into another process.
+    while let Some((i, parent, mut delta)) = stack.pop() {
+        if let Some(p) = parent.filter(|&p| spans[p].process != spans[i].process) {
+            delta = skew(&spans[i], &spans[p]);
+
+            if delta.abs() > max {
+                spans[i].warnings.push(format!(
+                    "clock skew of {delta} exceeds the maximum adjustment of {max}, timestamps \
+                     were not adjusted"
+                ));
+                delta = Duration::ZERO;
+            }
+        }
+
+        if !delta.is_zero() {
+            shift(&mut spans[i], delta);
+        }
+
+        stack.extend(
+            children
+                .get(&i)
+                .into_iter()
+                .flatten()
+                .map(|&child| (child, Some(i), delta)),
+        );
+    }
+}
+
+/// Delta to shift the child by, so it fits into the already adjusted parent.
+fn skew(child: &Span, parent: &Span) -> Duration {
+    if child.duration > parent.duration {
+        // Longer than the parent, so the best guess is that both started at the same time.
+        return parent.start - child.start;
+    }
+
+    if child.start >= parent.start && child.start + child.duration <= parent.start + parent.duration
+    {
+        return Duration::ZERO;
+    }
+
+    // Assume that the network latency is split equally between request and response.
+    let latency = (parent.duration - child.duration) / 2;
+    parent.start + latency - child.start
+}
+
+fn shift(span: &mut Span, delta: Duration) {
+    span.start += delta;
+
+    for log in &mut span.logs {
+        log.timestamp += delta;
+    }
+
+    span.warnings.push(format!(
+        "timestamps were adjusted by {delta} to compensate for clock skew"
+    ));
+}
@@ -0,0 +1,401 @@
+//! Service Performance Monitoring (SPM), which powers the _Monitor_ tab of the Jaeger UI. Instead
+//! of relying on an external metrics store, all metrics are computed on demand from the stored
+//! spans.
+
+use std::{collections::BTreeMap, fmt};
+
+use anyhow::{ensure, Result};
+use archer_http::{
+    axum::{
+        extract::{rejection::QueryRejection, Query},
+        http::StatusCode,
+        response::IntoResponse,
+        Json,
+    },
+    ApiError, GaugeValue, Label, Metric, MetricFamily, MetricPoint, MetricType, MinStep,
+};
+use serde::Deserialize;
+use time::{Duration, OffsetDateTime};
+use tracing::instrument;
+
+use super::{de, Tenanted};
+use crate::{
+    models::{Span, SpanKind},
+    storage::ReadOnlyDatabase,
+};
+
+/// Upper limit of data points per metric, to protect against overly expensive requests.
+const MAX_POINTS: i64 = 10_000;
+
+#[cfg_attr(test, derive(Default, PartialEq))]
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MetricsQuery {
+    #[serde(default, deserialize_with = "de::parsed")]
+    group_by_operation: Option<bool>,
+    #[serde(default, deserialize_with = "de::parsed")]
+    end_ts: Option<i64>,
+    #[serde(default, deserialize_with = "de::parsed")]
+    lookback: Option<i64>,
+    #[serde(default, deserialize_with = "de::parsed")]
+    step: Option<i64>,
+    #[serde(default, deserialize_with = "de::parsed")]
+    rate_per: Option<i64>,
+    #[serde(default, deserialize_with = "de::parsed")]
+    quantile: Option<f64>,
+    #[serde(default, flatten, deserialize_with = "de::services")]
+    services: Vec<String>,
+    #[serde(default, flatten, deserialize_with = "de::span_kinds")]
+    span_kinds: Vec<String>,
+}
+
+impl MetricsQuery {
+    fn into_params(self, kind: MetricKind) -> Result<Params> {
+        ensure!(
+            !self.services.is_empty(),
+            "please provide at least one service name"
+        );
+
+        let lookback = Duration::milliseconds(self.lookback.unwrap_or(3_600_000));
+        let step = Duration::milliseconds(self.step.unwrap_or(5_000));
+        let rate_per = Duration::milliseconds(self.rate_per.unwrap_or(600_000));
+
+        ensure!(lookback.is_positive(), "lookback must be positive");
+        ensure!(step.is_positive(), "step must be positive");
+        ensure!(rate_per.is_positive(), "ratePer must be positive");
+        ensure!(
+            lookback.whole_milliseconds() / step.whole_milliseconds() <= i128::from(MAX_POINTS),
+            "too many data points, increase the step or reduce the lookback"
+        );
+
+        let kind = match kind {
+            MetricKind::Latencies(_) => {
+                let quantile = self.quantile.unwrap_or_default();
+                ensure!(
+                    quantile > 0.0 && quantile <= 1.0,
+                    "please provide a quantile between (0, 1]"
+                );
+                MetricKind::Latencies(quantile)
+            }
+            kind => kind,
+        };
+
+        let end = self.end_ts.map_or_else(OffsetDateTime::now_utc, |end| {
+            OffsetDateTime::UNIX_EPOCH + Duration::milliseconds(end)
+        });
+
+        let span_kinds = if self.span_kinds.is_empty() {
+            vec!["server".to_owned()]
+        } else {
+            self.span_kinds
+                .into_iter()
+                .map(|kind| {
+                    kind.strip_prefix("SPAN_KIND_")
+                        .unwrap_or(&kind)
+                        .to_ascii_lowercase()
+                })
+                .collect()
+        };
+
+        Ok(Params {
+            kind,
+            services: self.services,
+            group_by_operation: self.group_by_operation.unwrap_or_default(),
+            span_kinds,
+            end,
+            lookback,
+            step,
+            rate_per,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum MetricKind {
+    Latencies(f64),
+    Calls,
+    Errors,
+}
+
+#[derive(Debug)]
+struct Params {
+    kind: MetricKind,
+    services: Vec<String>,
+    group_by_operation: bool,
+    span_kinds: Vec<String>,
+    end: OffsetDateTime,
+    lookback: Duration,
+    step: Duration,
+    rate_per: Duration,
+}
+
+impl Params {
+    /// Timestamps of all data points, from oldest to newest, with the last one being the end of
+    /// the requested time range.
+    #[allow(clippy::cast_possible_truncation)]
+    fn points(&self) -> Vec<OffsetDateTime> {
+        let count = (self.lookback.whole_milliseconds() / self.step.whole_milliseconds()) as i32;
+
+        (0..=count)
+            .rev()
+            .map(|i| self.end - self.step * i)
+            .collect()
+    }
+
+    fn name(&self) -> &'static str {
+        match (self.kind, self.group_by_operation) {
+            (MetricKind::Latencies(_), false) => "service_latencies",
+            (MetricKind::Latencies(_), true) => "service_operation_latencies",
+            (MetricKind::Calls, false) => "service_call_rate",
+            (MetricKind::Calls, true) => "service_operation_call_rate",
+            (MetricKind::Errors, false) => "service_error_rate",
+            (MetricKind::Errors, true) => "service_operation_error_rate",
+        }
+    }
+
+    fn help(&self) -> String {
+        let group = if self.group_by_operation {
+            "service & operation"
+        } else {
+            "service"
+        };
+
+        match self.kind {
+            MetricKind::Latencies(quantile) => {
+                format!("{quantile:.2}th quantile latency, grouped by {group}")
+            }
+            MetricKind::Calls => format!("calls/sec, grouped by {group}"),
+            MetricKind::Errors => format!(
+                "error rate, computed as a fraction of errors/sec over calls/sec, grouped by {group}"
+            ),
+        }
+    }
+}
+
+/// Minimal information about a single call, extracted from a span.
+struct Call {
+    start: OffsetDateTime,
+    duration: Duration,
+    error: bool,
+}
+
+impl Call {
+    fn from_span(span: &Span) -> Self {
+        Self {
+            start: span.start,
+            duration: span.duration,
+            error: span.is_error(),
+        }
+    }
+}
+
+fn span_kind(span: &Span) -> &str {
+    span.kind.map_or("unspecified", SpanKind::as_str)
+}
+
+fn bad_request(error: impl fmt::Display) -> ApiError {
+    ApiError {
+        code: StatusCode::BAD_REQUEST,
+        msg: error.to_string().into(),
+        trace_id: None,
+    }
+}
+
+async fn compute(
+    db: ReadOnlyDatabase,
+    query: Result<Query<MetricsQuery>, QueryRejection>,
+    kind: MetricKind,
+) -> Result<Json<MetricFamily>, ApiError> {
+    let params = query
+        .map_err(bad_request)?
+        .0
+        .into_params(kind)
+        .map_err(bad_request)?;
+    let points = params.points();
+    let start = points.first().copied().unwrap_or(params.end) - params.rate_per;
+
+    let spans = db
+        .list_service_spans(params.services.clone(), start, params.end)
+        .await?;
+
+    let mut groups = BTreeMap::<(String, Option<String>), Vec<Call>>::new();
+
+    for span in &spans {
+        if !params.span_kinds.iter().any(|kind| kind == span_kind(span)) {
+            continue;
+        }
+
+        let key = (
+            span.process.service.clone(),
+            params
+                .group_by_operation
+                .then(|| span.operation_name.clone()),
+        );
+        groups.entry(key).or_default().push(Call::from_span(span));
+    }
+
+    let metrics = groups
+        .into_iter()
+        .map(|((service, operation), mut calls)| {
+            calls.sort_unstable_by_key(|call| call.start);
+
+            let metric_points = points
+                .iter()
+                .filter_map(|&timestamp| {
+                    let from =
+                        calls.partition_point(|call| call.start <= timestamp - params.rate_per);
+                    let to = calls.partition_point(|call| call.start <= timestamp);
+
+                    let value = compute_value(params.kind, params.rate_per, &calls[from..to])?;
+
+                    Some(MetricPoint {
+                        gauge_value: GaugeValue {
+                            double_value: value,
+                        },
+                        timestamp,
+                    })
+                })
+                .collect();
+
+            let labels = std::iter::once(Label {
+                name: "service_name",
+                value: service,
+            })
+            .chain(operation.map(|operation| Label {
+                name: "operation",
+                value: operation,
+            }))
+            .collect();
+
+            Metric {
+                labels,
+                metric_points,
+            }
+        })
+        .collect();
+
+    Ok(Json(MetricFamily {
+        name: params.name(),
+        ty: MetricType::Gauge,
+        help: params.help(),
+        metrics,
+    }))
+}
+
+/// Calculate the value of a single data point. The calls must be all calls that happened within
+/// the rate window, which ends at the data point's timestamp.
+#[allow(clippy::cast_precision_loss)]
+fn compute_value(kind: MetricKind, rate_per: Duration, calls: &[Call]) -> Option<f64> {
+    if calls.is_empty() {
+        return None;
+    }
+
+    Some(match kind {
+        MetricKind::Latencies(quantile) => {
+            let mut durations = calls
+                .iter()
+                .map(|call| call.duration.as_seconds_f64() * 1000.0)
+                .collect::<Vec<_>>();
+            durations.sort_unstable_by(f64::total_cmp);
+
+            #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+            let rank = (quantile * durations.len() as f64).ceil() as usize;
+            durations[rank.clamp(1, durations.len()) - 1]
+        }
+        MetricKind::Calls => calls.len() as f64 / rate_per.as_seconds_f64(),
+        MetricKind::Errors => {
+            calls.iter().filter(|call| call.error).count() as f64 / calls.len() as f64
+        }
+    })
+}
+
+#[instrument(skip_all)]
+pub async fn latencies(
+    query: Result<Query<MetricsQuery>, QueryRejection>,
+    Tenanted(db): Tenanted<ReadOnlyDatabase>,
+) -> Result<impl IntoResponse, ApiError> {
+    compute(db, query, MetricKind::Latencies(0.0)).await
+}
+
+#[instrument(skip_all)]
+pub async fn calls(
+    query: Result<Query<MetricsQuery>, QueryRejection>,
+    Tenanted(db): Tenanted<ReadOnlyDatabase>,
+) -> Result<impl IntoResponse, ApiError> {
+    compute(db, query, MetricKind::Calls).await
+}
+
+#[instrument(skip_all)]
+pub async fn errors(
+    query: Result<Query<MetricsQuery>, QueryRejection>,
+    Tenanted(db): Tenanted<ReadOnlyDatabase>,
+) -> Result<impl IntoResponse, ApiError> {
+    compute(db, query, MetricKind::Errors).await
+}
+
+#[instrument(skip_all)]
+pub async fn min_step() -> impl IntoResponse {
+    Json(MinStep { min_step: 1000 })
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+
+    #[test]
+    fn deser_query() {
+        let expect = MetricsQuery {
+            group_by_operation: Some(true),
+            end_ts: Some(1_661_236_231_416),
+            lookback: Some(3_600_000),
+            step: Some(60_000),
+            rate_per: Some(600_000),
+            quantile: Some(0.95),
+            services: vec!["a".to_owned(), "b".to_owned()],
+            span_kinds: vec!["server".to_owned()],
+        };
+        let result = serde_urlencoded::from_str(
+            "\
+            service=a&\
+            service=b&\
+            groupByOperation=true&\
+            endTs=1661236231416&\
+            lookback=3600000&\
+            step=60000&\
+            ratePer=600000&\
+            quantile=0.95&\
+            spanKind=server\
+            ",
+        );
+
+        assert_eq!(expect, result.unwrap());
+    }
+
+    #[test]
+    fn compute_latency_quantile() {
+        let calls = (1..=100)
+            .map(|ms| Call {
+                start: OffsetDateTime::UNIX_EPOCH,
+                duration: Duration::milliseconds(ms),
+                error: ms % 10 == 0,
+            })
+            .collect::<Vec<_>>();
+        let rate_per = Duration::seconds(10);
+
+        assert_eq!(
+            Some(95.0),
+            compute_value(MetricKind::Latencies(0.95), rate_per, &calls)
+        );
+        assert_eq!(
+            Some(10.0),
+            compute_value(MetricKind::Calls, rate_per, &calls)
+        );
+        assert_eq!(
+            Some(0.1),
+            compute_value(MetricKind::Errors, rate_per, &calls)
+        );
+        assert_eq!(None, compute_value(MetricKind::Calls, rate_per, &[]));
+    }
+}
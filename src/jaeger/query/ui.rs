@@ -0,0 +1,295 @@
+//! Serving of the web UI, either from the assets that are embedded into the binary, or from a
+//! directory on disk.
+
+use std::{path::PathBuf, sync::Arc};
+
+use anyhow::{ensure, Result};
+#[cfg(feature = "embed-ui")]
+use std::{
+    ops::Bound,
+    time::{Duration, UNIX_EPOCH},
+};
+
+#[cfg(feature = "embed-ui")]
+use archer_http::axum::{
+    headers::{
+        AcceptRanges, ContentRange, ETag, HeaderMapExt, IfModifiedSince, IfNoneMatch, IfRange,
+        LastModified, Range,
+    },
+    http::{
+        header::{ACCEPT_ENCODING, CONTENT_ENCODING, ETAG, VARY},
+        HeaderMap, HeaderValue, Uri,
+    },
+    response::Response,
+};
+use archer_http::{
+    axum::{
+        extract::State,
+        http::{
+            header::{CACHE_CONTROL, CONTENT_TYPE},
+            StatusCode,
+        },
+        response::{Html, IntoResponse},
+        routing::{get, MethodRouter},
+    },
+    tower_http::services::ServeDir,
+    UiMenu, UiSearch,
+};
+use tracing::error;
+
+use crate::config;
+
+#[cfg(feature = "embed-ui")]
+include!(concat!(env!("OUT_DIR"), "/assets.rs"));
+
+/// Configuration of the UI, which is embedded into the `index.html` the same way Jaeger does it.
+#[derive(Clone)]
+pub struct Config {
+    /// Feature toggles as JSON, which are also served at `/api/config`.
+    json: Arc<str>,
+    /// URL prefix that the UI is served under, always ending with a slash.
+    base: Arc<str>,
+}
+
+impl Config {
+    pub fn new(config: config::UiConfig, base_path: &str) -> Result<Self> {
+        if let Some(lookback) = &config.default_lookback {
+            ensure!(
+                lookback.len() > 1
+                    && lookback.ends_with(['m', 'h', 'd'])
+                    && lookback[..lookback.len() - 1]
+                        .bytes()
+                        .all(|b| b.is_ascii_digit()),
+                "invalid default lookback `{lookback}`, expected a number followed by `m`, `h` \
+                 or `d`"
+            );
+        }
+
+        let json = serde_json::to_string(&archer_http::UiConfig {
+            archive_enabled: config.archive,
+            dependencies: UiMenu {
+                menu_enabled: config.dependencies,
+            },
+            monitor: UiMenu {
+                menu_enabled: config.monitor,
+            },
+            search: UiSearch {
+                default_lookback: config.default_lookback,
+            },
+        })?;
+
+        Ok(Self {
+            json: json.into(),
+            base: format!("{base_path}/").into(),
+        })
+    }
+
+    /// Replace the placeholders of the `index.html`, that the UI reads its configuration from.
+    /// All assets and API calls are relative to the `<base>` element.
+    fn inject(&self, html: &str) -> String {
+        html.replacen(
+            "_CONFIG = DEFAULT_CONFIG;",
+            &format!("_CONFIG = {};", self.json),
+            1,
+        )
+        .replacen(
+            r#"<base href="/""#,
+            &format!(r#"<base href="{}""#, self.base),
+            1,
+        )
+    }
+}
+
+pub async fn config(State(config): State<Config>) -> impl IntoResponse {
+    (
+        [(CONTENT_TYPE, "application/json")],
+        config.json.to_string(),
+    )
+}
+
+/// The `index.html` with the configuration, which must not be cached as it changes together with
+/// it.
+fn index(config: &Config, html: &str) -> impl IntoResponse {
+    ([(CACHE_CONTROL, "no-cache")], Html(config.inject(html)))
+}
+
+/// Serve the UI from a directory. Unknown paths fall back to the `index.html`, so the UI can
+/// handle its own routes.
+pub fn directory(dir: PathBuf, config: Config) -> ServeDir<MethodRouter> {
+    ServeDir::new(&dir)
+        .append_index_html_on_directories(false)
+        .fallback(get(|| async move {
+            match tokio::fs::read_to_string(dir.join("index.html")).await {
+                Ok(html) => Ok(index(&config, &html)),
+                Err(e) => {
+                    error!(error = ?e, "failed reading index.html");
+                    Err(StatusCode::NOT_FOUND)
+                }
+            }
+        }))
+}
+
+/// Serve the embedded assets. Compressible assets are pre-compressed at build time, so the
+/// variant that the client accepts is served directly, instead of compressing it on every request.
+///
+/// Responses can be validated by their `ETag` or modification time, and single byte ranges are
+/// supported, so proxies and interrupted downloads don't have to fetch the whole asset again.
+#[cfg(feature = "embed-ui")]
+pub async fn embedded(
+    State(config): State<Config>,
+    uri: Uri,
+    request_headers: HeaderMap,
+) -> Response {
+    let asset = match ASSETS.get(uri.path()) {
+        Some(asset) if uri.path() != "/index.html" => asset,
+        _ => {
+            return match ASSETS.get("/index.html") {
+                Some(asset) => {
+                    index(&config, &String::from_utf8_lossy(asset.content)).into_response()
+                }
+                None => StatusCode::NOT_FOUND.into_response(),
+            };
+        }
+    };
+
+    let (content, etag, encoding) = match (&asset.brotli, &asset.gzip) {
+        (Some(br), _) if accepts(&request_headers, "br") => (br.content, br.etag, Some("br")),
+        (_, Some(gz)) if accepts(&request_headers, "gzip") => (gz.content, gz.etag, Some("gzip")),
+        _ => (asset.content, asset.etag, None),
+    };
+
+    let mut headers = [
+        (CONTENT_TYPE, Some(asset.mime)),
+        (CONTENT_ENCODING, encoding),
+        (
+            VARY,
+            (asset.brotli.is_some() || asset.gzip.is_some()).then_some("accept-encoding"),
+        ),
+        (ETAG, Some(etag)),
+        (
+            CACHE_CONTROL,
+            Some("public, max-age=2592000, must-revalidate"),
+        ),
+    ]
+    .into_iter()
+    .filter_map(|(name, value)| Some((name, HeaderValue::from_static(value?))))
+    .collect::<HeaderMap>();
+
+    let modified = UNIX_EPOCH + Duration::from_secs(asset.modified);
+    headers.typed_insert(LastModified::from(modified));
+    headers.typed_insert(AcceptRanges::bytes());
+
+    let etag = match etag.parse::<ETag>() {
+        Ok(etag) => etag,
+        Err(e) => {
+            error!(error = ?e, "failed parsing etag");
+            return (headers, StatusCode::INTERNAL_SERVER_ERROR).into_response();
+        }
+    };
+
+    // The modification time is only considered without an ETag, as that is the stronger
+    // validator.
+    let unmodified = match request_headers.typed_get::<IfNoneMatch>() {
+        Some(if_none_match) => !if_none_match.precondition_passes(&etag),
+        None => request_headers
+            .typed_get::<IfModifiedSince>()
+            .is_some_and(|since| !since.is_modified(modified)),
+    };
+    if unmodified {
+        return (headers, StatusCode::NOT_MODIFIED).into_response();
+    }
+
+    // A range is only valid for the same version of the asset, otherwise the whole new one is sent.
+    let range = request_headers.typed_get::<Range>().filter(|_| {
+        request_headers
+            .typed_get::<IfRange>()
+            .is_none_or(|if_range| {
+                !if_range.is_modified(Some(&etag), Some(&LastModified::from(modified)))
+            })
+    });
+    let len = content.len() as u64;
+
+    match range.map_or(ByteRange::Full, |range| byte_range(&range, len)) {
+        ByteRange::Full => (headers, content).into_response(),
+        ByteRange::Partial(start, end) => {
+            if let Ok(content_range) = ContentRange::bytes(start..=end, len) {
+                headers.typed_insert(content_range);
+            }
+
+            #[allow(clippy::cast_possible_truncation)]
+            let content = &content[start as usize..=end as usize];
+            (StatusCode::PARTIAL_CONTENT, headers, content).into_response()
+        }
+        ByteRange::Unsatisfiable => {
+            headers.typed_insert(ContentRange::unsatisfied_bytes(len));
+            (StatusCode::RANGE_NOT_SATISFIABLE, headers).into_response()
+        }
+    }
+}
+
+/// Requested part of an asset, resolved against its length.
+#[cfg(feature = "embed-ui")]
+enum ByteRange {
+    Full,
+    /// Inclusive start and end of the range.
+    Partial(u64, u64),
+    Unsatisfiable,
+}
+
+/// Resolve the `Range` header against the content length. Only a single range is supported, for
+/// multiple ranges or invalid ones, the whole content is served instead.
+#[cfg(feature = "embed-ui")]
+fn byte_range(range: &Range, len: u64) -> ByteRange {
+    let mut ranges = range.iter();
+    let (Some(bounds), None) = (ranges.next(), ranges.next()) else {
+        return ByteRange::Full;
+    };
+
+    let (start, end) = match bounds {
+        (Bound::Included(start), Bound::Included(end)) if start <= end => (start, end),
+        (Bound::Included(start), Bound::Unbounded) => (start, u64::MAX),
+        (Bound::Unbounded, Bound::Included(suffix)) if suffix > 0 => {
+            (len.saturating_sub(suffix), u64::MAX)
+        }
+        (Bound::Unbounded, Bound::Included(_)) => return ByteRange::Unsatisfiable,
+        _ => return ByteRange::Full,
+    };
+
+    if start >= len {
+        return ByteRange::Unsatisfiable;
+    }
+
+    ByteRange::Partial(start, end.min(len - 1))
+}
+
+/// Whether the client accepts the content encoding, according to its `Accept-Encoding` header.
+/// Encodings with a quality of zero are explicitly refused.
+#[cfg(feature = "embed-ui")]
+fn accepts(headers: &HeaderMap, encoding: &str) -> bool {
+    headers
+        .get_all(ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|value| {
+            let mut params = value.split(';').map(str::trim);
+            let name = params.next().unwrap_or_default();
+            let refused = params.any(|param| {
+                param
+                    .strip_prefix("q=")
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .is_some_and(|q| q <= 0.0)
+            });
+
+            name.eq_ignore_ascii_case(encoding) && !refused
+        })
+}
+
+/// Without embedded assets, there is no UI unless a directory is configured.
+#[cfg(not(feature = "embed-ui"))]
+pub async fn embedded() -> impl IntoResponse {
+    (
+        StatusCode::NOT_FOUND,
+        "archer was built without the UI, configure a UI directory to serve it from",
+    )
+}
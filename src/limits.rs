@@ -0,0 +1,191 @@
+//! Limits for the size of received spans, shared by all collectors. Unlike the rate limit, spans
+//! that exceed these limits aren't rejected, but cut down to size and marked as such. Only spans
+//! beyond the limit of their trace are dropped entirely.
+
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use once_cell::sync::OnceCell;
+
+use crate::{
+    config,
+    metrics::{self, DropReason, SpanItem},
+    models::{Span, Tag, TagValue, TraceId},
+};
+
+static LIMITS: OnceCell<Limits> = OnceCell::new();
+
+/// Time after the last received span of a trace, after which its span count is forgotten.
+const TRACE_IDLE: Duration = Duration::from_mins(10);
+/// Time between two removals of idle traces, so the scan over all traces doesn't happen on every
+/// received batch.
+const PRUNE_INTERVAL: Duration = Duration::from_mins(1);
+
+struct Limits {
+    config: config::Limits,
+    traces: Mutex<Traces>,
+}
+
+struct Traces {
+    /// Amount of received spans per tenant and trace, and the time of the last one.
+    counts: HashMap<String, HashMap<TraceId, (usize, Instant)>>,
+    last_prune: Instant,
+}
+
+/// Key of the tag, that marks spans and logs with truncated tag values.
+const TRUNCATED: &str = "truncated";
+
+/// Enable the given limits. Without calling this, all spans are kept as is.
+pub fn init(config: config::Limits) -> Result<()> {
+    LIMITS
+        .set(Limits {
+            config,
+            traces: Mutex::new(Traces {
+                counts: HashMap::new(),
+                last_prune: Instant::now(),
+            }),
+        })
+        .map_err(|_| anyhow!("limits can only be initialized once"))
+}
+
+/// Cut down the spans to the configured limits, and remove any spans that exceed the limit of
+/// their trace within the tenant.
+pub fn apply(tenant: &str, spans: &mut Vec<Span>) {
+    let Some(limits) = LIMITS.get() else {
+        return;
+    };
+    let config = &limits.config;
+
+    if let Some(max) = config.max_spans_per_trace {
+        limits.limit_traces(tenant, spans, max);
+    }
+
+    for span in spans {
+        if let Some(max) = config.max_tags_per_span {
+            limit_items(span, SpanItem::Tag, max);
+        }
+        if let Some(max) = config.max_logs_per_span {
+            limit_items(span, SpanItem::Log, max);
+        }
+        if let Some(max) = config.max_tag_length {
+            truncate_span(span, max);
+        }
+    }
+}
+
+impl Limits {
+    fn limit_traces(&self, tenant: &str, spans: &mut Vec<Span>, max: usize) {
+        let Ok(mut traces) = self.traces.lock() else {
+            return;
+        };
+        let now = Instant::now();
+
+        if now.duration_since(traces.last_prune) >= PRUNE_INTERVAL {
+            traces.counts.retain(|_, counts| {
+                counts.retain(|_, (_, last)| now.duration_since(*last) < TRACE_IDLE);
+                !counts.is_empty()
+            });
+            traces.last_prune = now;
+        }
+
+        let traces = match traces.counts.get_mut(tenant) {
+            Some(counts) => counts,
+            None => traces.counts.entry(tenant.to_owned()).or_default(),
+        };
+        let before = spans.len();
+
+        spans.retain(|span| {
+            let (count, last) = traces.entry(span.trace_id).or_insert((0, now));
+            if now.duration_since(*last) >= TRACE_IDLE {
+                *count = 0;
+            }
+
+            *last = now;
+            *count += 1;
+            *count <= max
+        });
+
+        let dropped = before - spans.len();
+        if dropped > 0 {
+            metrics::spans_dropped(DropReason::TraceLimit, dropped);
+        }
+    }
+}
+
+/// Remove all tags or logs of the span beyond `max`, and note it in the span's warnings.
+fn limit_items(span: &mut Span, item: SpanItem, max: usize) {
+    let (len, name) = match item {
+        SpanItem::Tag => (span.tags.len(), "tags"),
+        SpanItem::Log => (span.logs.len(), "logs"),
+    };
+    if len <= max {
+        return;
+    }
+
+    match item {
+        SpanItem::Tag => span.tags.truncate(max),
+        SpanItem::Log => span.logs.truncate(max),
+    }
+
+    let dropped = len - max;
+    metrics::items_dropped(item, dropped);
+    span.warnings.push(format!(
+        "{dropped} {name} were dropped, as the span exceeded the limit of {max}"
+    ));
+}
+
+/// Truncate all tag values of the span. Tags of the span itself, its process and references mark
+/// the span as truncated, while log fields mark only the log they belong to.
+fn truncate_span(span: &mut Span, max: usize) {
+    let mut truncated = truncate_tags(&mut span.tags, max);
+    truncated |= truncate_tags(&mut span.process.tags, max);
+
+    for reference in &mut span.references {
+        truncated |= truncate_tags(&mut reference.tags, max);
+    }
+
+    if truncated {
+        mark(&mut span.tags);
+    }
+
+    for log in &mut span.logs {
+        if truncate_tags(&mut log.fields, max) {
+            mark(&mut log.fields);
+        }
+    }
+}
+
+/// Cut off all string and binary values, that are longer than `max` bytes. Strings are cut at
+/// the last character boundary before the limit. Returns whether any value was truncated.
+fn truncate_tags(tags: &mut [Tag], max: usize) -> bool {
+    let mut truncated = false;
+
+    for tag in tags {
+        match &mut tag.value {
+            TagValue::String(value) if value.len() > max => {
+                value.truncate(value.floor_char_boundary(max));
+                truncated = true;
+            }
+            TagValue::Binary(value) if value.len() > max => {
+                value.truncate(max);
+                truncated = true;
+            }
+            _ => {}
+        }
+    }
+
+    truncated
+}
+
+fn mark(tags: &mut Vec<Tag>) {
+    if !tags.iter().any(|tag| tag.key == TRUNCATED) {
+        tags.push(Tag {
+            key: TRUNCATED.to_owned(),
+            value: TagValue::Bool(true),
+        });
+    }
+}
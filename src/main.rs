@@ -1,6 +1,8 @@
 #![deny(rust_2018_idioms, clippy::all, clippy::pedantic)]
 #![warn(clippy::expect_used, clippy::unwrap_used)]
-#![allow(clippy::needless_pass_by_value)]
+#![allow(clippy::needless_pass_by_value, clippy::struct_field_names)]
+
+use std::{env, time::Duration};
 
 use anyhow::Result;
 use opentelemetry::sdk::{trace, Resource};
@@ -8,71 +10,199 @@ use opentelemetry_semantic_conventions::resource;
 use tokio::task::JoinHandle;
 use tokio_shutdown::Shutdown;
 use tracing::level_filters::LevelFilter;
-use tracing_subscriber::{filter::Targets, prelude::*};
+use tracing_subscriber::{
+    filter::{filter_fn, FilterExt, Targets},
+    prelude::*,
+};
 
+mod audit;
+mod cli;
+mod config;
 mod convert;
+mod doctor;
+mod forwarder;
+mod ingest;
 mod jaeger;
+mod limits;
+mod maintenance;
+mod metrics;
 mod models;
 mod net;
 mod otel;
+mod processor;
 mod quiver;
+mod ratelimit;
 mod storage;
+mod tasks;
+mod tenancy;
+mod tiering;
+mod tls;
 mod tracer;
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let database = storage::init().await?;
-    let database_ro = storage::init_readonly().await?;
-    let shutdown = Shutdown::new()?;
+    let config = config::load()?;
 
-    let tracer = tracer::install_batch(
-        database.clone(),
-        trace::config().with_resource(Resource::new([
-            resource::SERVICE_NAME.string(env!("CARGO_PKG_NAME")),
-            resource::SERVICE_VERSION.string(env!("CARGO_PKG_VERSION")),
-        ])),
-    );
+    let role = match cli::parse(env::args().skip(1))? {
+        cli::Command::Doctor => return doctor::run(&config).await,
+        cli::Command::Run(cli::Role::Query) => return run_query(config).await,
+        cli::Command::Run(role) => role,
+    };
 
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::fmt::layer().with_filter(
-                Targets::new()
-                    .with_default(LevelFilter::WARN)
-                    .with_target(env!("CARGO_CRATE_NAME"), LevelFilter::DEBUG)
-                    .with_target("tower_http", LevelFilter::DEBUG),
-            ),
-        )
-        .with(
-            tracing_opentelemetry::layer()
-                .with_tracer(tracer)
-                .with_filter(
-                    Targets::new()
-                        .with_default(LevelFilter::OFF)
-                        .with_target("archer::jaeger::query", LevelFilter::INFO),
-                ),
-        )
-        .init();
+    ratelimit::init(config.rate_limit)?;
+    limits::init(config.limits)?;
+    processor::init(config.processor)?;
+    tenancy::init(config.tenancy)?;
+    let mut addrs = net::Addresses::new(&config.listen);
+    if role == cli::Role::Collector {
+        addrs.query = None;
+        addrs.query_grpc = None;
+        addrs.query_socket = None;
+    }
+    let tls = config
+        .tls
+        .as_ref()
+        .map(tls::http_server_config)
+        .transpose()?;
+    let database = storage::init(&config.storage).await?;
+    let database_ro = storage::init_readonly(&config.storage, Some(&database)).await?;
+    let shutdown = Shutdown::new()?;
+
+    init_tracing(Some(&database), &config.self_tracing)?;
+    if role == cli::Role::Collector {
+        tracing::info!("running in collector role, the query API is disabled");
+    }
 
     tokio::try_join!(
         flatten(tokio::spawn(jaeger::agent::run(
             shutdown.clone(),
-            database.clone()
+            database.clone(),
+            config.agent,
+            addrs.clone()
         ))),
         flatten(tokio::spawn(jaeger::collector::run(
             shutdown.clone(),
-            database.clone()
+            database.clone(),
+            config.collector,
+            tls.clone(),
+            addrs.clone()
         ))),
         flatten(tokio::spawn(jaeger::query::run(
             shutdown.clone(),
-            database_ro
+            Some(database.clone()),
+            database_ro,
+            config.query,
+            tls.clone(),
+            addrs.clone()
         ))),
         flatten(tokio::spawn(otel::collector::run(
             shutdown.clone(),
             database.clone(),
+            tls,
+            addrs.clone()
+        ))),
+        flatten(tokio::spawn(maintenance::run(
+            shutdown.clone(),
+            database.clone(),
+            config.storage.maintenance,
         ))),
-        flatten(tokio::spawn(quiver::collector::run(shutdown, database))),
+        flatten(tokio::spawn(tiering::run(
+            shutdown.clone(),
+            database.clone(),
+            config.storage.cold_tier,
+            config.storage.data_dir,
+        ))),
+        flatten(tokio::spawn(quiver::collector::run(
+            shutdown.clone(),
+            database,
+            config.quiver,
+            addrs.quiver
+        ))),
+        flatten(tokio::spawn(forwarder::run(
+            shutdown.clone(),
+            config.forwarder
+        ))),
+        flatten(tokio::spawn(audit::run(shutdown, config.audit))),
     )?;
 
+    tasks::drain(Duration::from_secs(10)).await;
+
+    Ok(())
+}
+
+/// Serve the query API from an existing database, without any of the collectors and without ever
+/// modifying the database. That allows to inspect a copy of the database, or to run separate
+/// nodes for ingestion and querying.
+async fn run_query(config: config::Config) -> Result<()> {
+    tenancy::init(config.tenancy)?;
+    let addrs = net::Addresses::new(&config.listen);
+    let tls = config
+        .tls
+        .as_ref()
+        .map(tls::http_server_config)
+        .transpose()?;
+    let database_ro = storage::init_readonly(&config.storage, None).await?;
+    let shutdown = Shutdown::new()?;
+
+    // Self-tracing needs to write its traces into the database, so it's always off.
+    init_tracing(None, &config.self_tracing)?;
+    tracing::info!("running in query role, all collectors are disabled");
+
+    tokio::try_join!(
+        flatten(tokio::spawn(jaeger::query::run(
+            shutdown.clone(),
+            None,
+            database_ro.clone(),
+            config.query,
+            tls,
+            addrs
+        ))),
+        flatten(tokio::spawn(storage::refresh_index(shutdown, database_ro))),
+    )?;
+
+    tasks::drain(Duration::from_secs(10)).await;
+
+    Ok(())
+}
+
+/// Set up logging, and tracing of the query API into the database itself, if there is a writable
+/// one.
+fn init_tracing(
+    database: Option<&storage::Database>,
+    self_tracing: &config::SelfTracing,
+) -> Result<()> {
+    tracer::init(self_tracing)?;
+    let tracer = database.filter(|_| self_tracing.enabled).map(|database| {
+        tracer::install_batch(
+            database.clone(),
+            trace::config().with_resource(Resource::new([
+                resource::SERVICE_NAME.string(env!("CARGO_PKG_NAME")),
+                resource::SERVICE_VERSION.string(env!("CARGO_PKG_VERSION")),
+            ])),
+        )
+    });
+
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::fmt::layer().with_filter(
+                Targets::new()
+                    .with_default(LevelFilter::WARN)
+                    .with_target(env!("CARGO_CRATE_NAME"), LevelFilter::DEBUG)
+                    .with_target("tower_http", LevelFilter::DEBUG),
+            ),
+        )
+        .with(tracer.map(|tracer| {
+            tracing_opentelemetry::layer()
+                .with_tracer(tracer)
+                .with_filter(
+                    Targets::new()
+                        .with_default(LevelFilter::OFF)
+                        .with_target("archer::jaeger::query", LevelFilter::INFO)
+                        .and(filter_fn(|_| tracer::is_traced())),
+                )
+        }))
+        .init();
+
     Ok(())
 }
 
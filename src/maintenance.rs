@@ -0,0 +1,58 @@
+//! Periodic maintenance of the database. `SQLite` doesn't shrink its file when data is deleted, but
+//! keeps the free pages for later use instead. These are given back to the file system here, while
+//! the database is idle.
+
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use time::OffsetDateTime;
+use tokio_shutdown::Shutdown;
+use tracing::{error, info, instrument};
+
+use crate::{config, storage::Database};
+
+/// Interval in which the conditions for a maintenance run are checked.
+const CHECK_INTERVAL: Duration = Duration::from_mins(1);
+
+#[instrument(name = "maintenance", skip_all)]
+pub async fn run(shutdown: Shutdown, db: Database, settings: config::Maintenance) -> Result<()> {
+    if !settings.enabled {
+        return Ok(());
+    }
+
+    let interval = Duration::from_mins(settings.interval_minutes);
+    let idle = Duration::from_secs(settings.idle_seconds);
+    let mut last_run = Instant::now();
+    let mut check =
+        tokio::time::interval_at(tokio::time::Instant::now() + CHECK_INTERVAL, CHECK_INTERVAL);
+
+    loop {
+        tokio::select! {
+            () = shutdown.handle() => break,
+            _ = check.tick() => {}
+        }
+
+        let now = OffsetDateTime::now_utc().time();
+
+        if last_run.elapsed() < interval
+            || db.last_write().elapsed() < idle
+            || !(settings.windows.is_empty()
+                || settings.windows.iter().any(|window| window.contains(now)))
+        {
+            continue;
+        }
+
+        let start = Instant::now();
+
+        match db.maintain(false).await {
+            Ok(freed) => info!(freed, elapsed = ?start.elapsed(), "database maintenance finished"),
+            Err(e) => error!(error = ?e, "database maintenance failed"),
+        }
+
+        last_run = Instant::now();
+    }
+
+    info!("maintenance stopped");
+
+    Ok(())
+}
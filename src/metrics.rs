@@ -0,0 +1,359 @@
+//! Prometheus metrics about the internal state of Archer, like the amount of received spans per
+//! receiver or the latency of storage and query operations.
+
+use std::{fmt::Write, time::Instant};
+
+use archer_http::axum::{
+    extract::MatchedPath,
+    http::{header::CONTENT_TYPE, HeaderValue, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use once_cell::sync::Lazy;
+use prometheus_client::{
+    encoding::{text, EncodeLabelSet, EncodeLabelValue, LabelValueEncoder},
+    metrics::{
+        counter::Counter,
+        family::Family,
+        histogram::{exponential_buckets, Histogram},
+    },
+    registry::Registry,
+};
+use tracing::error;
+
+static METRICS: Lazy<Metrics> = Lazy::new(Metrics::new);
+
+struct Metrics {
+    registry: Registry,
+    spans_received: Family<ReceiverLabels, Counter>,
+    spans_dropped: Family<DropLabels, Counter>,
+    packets_rejected: Family<PacketLabels, Counter>,
+    packets_dropped: Family<ReceiverLabels, Counter>,
+    batches_rejected: Family<RejectLabels, Counter>,
+    items_dropped: Family<ItemLabels, Counter>,
+    storage_writes: Histogram,
+    queries: Family<QueryLabels, Histogram, fn() -> Histogram>,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let mut registry = Registry::with_prefix("archer");
+
+        let spans_received = Family::default();
+        registry.register(
+            "spans_received",
+            "Number of spans received, per receiver",
+            spans_received.clone(),
+        );
+
+        let spans_dropped = Family::default();
+        registry.register(
+            "spans_dropped",
+            "Number of spans that were dropped instead of being stored",
+            spans_dropped.clone(),
+        );
+
+        let packets_rejected = Family::default();
+        registry.register(
+            "agent_packets_rejected",
+            "Number of Jaeger agent packets, that couldn't be processed",
+            packets_rejected.clone(),
+        );
+
+        let packets_dropped = Family::default();
+        registry.register(
+            "agent_packets_dropped",
+            "Number of Jaeger agent packets, that the OS dropped due to a full receive buffer",
+            packets_dropped.clone(),
+        );
+
+        let batches_rejected = Family::default();
+        registry.register(
+            "quiver_batches_rejected",
+            "Number of quiver span batches, that couldn't be processed",
+            batches_rejected.clone(),
+        );
+
+        let items_dropped = Family::default();
+        registry.register(
+            "span_items_dropped",
+            "Number of tags and logs that were removed from spans, due to the span limits",
+            items_dropped.clone(),
+        );
+
+        let storage_writes = Histogram::new(exponential_buckets(0.0005, 2.0, 14));
+        registry.register(
+            "storage_write_duration_seconds",
+            "Latency of writing a batch of spans to the storage",
+            storage_writes.clone(),
+        );
+
+        let queries = Family::new_with_constructor(
+            (|| Histogram::new(exponential_buckets(0.001, 2.0, 14))) as fn() -> Histogram,
+        );
+        registry.register(
+            "query_duration_seconds",
+            "Latency of requests to the query API, per route",
+            queries.clone(),
+        );
+
+        Self {
+            registry,
+            spans_received,
+            spans_dropped,
+            packets_rejected,
+            packets_dropped,
+            batches_rejected,
+            items_dropped,
+            storage_writes,
+            queries,
+        }
+    }
+}
+
+/// Source of spans, used to differentiate the received spans in the metrics.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Receiver {
+    JaegerAgentCompact,
+    JaegerAgentBinary,
+    JaegerAgentTcp,
+    JaegerAgentHttp,
+    JaegerHttp,
+    JaegerGrpc,
+    OtlpHttp,
+    OtlpGrpc,
+    Quiver,
+}
+
+impl Receiver {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::JaegerAgentCompact => "jaeger_agent_compact",
+            Self::JaegerAgentBinary => "jaeger_agent_binary",
+            Self::JaegerAgentTcp => "jaeger_agent_tcp",
+            Self::JaegerAgentHttp => "jaeger_agent_http",
+            Self::JaegerHttp => "jaeger_http",
+            Self::JaegerGrpc => "jaeger_grpc",
+            Self::OtlpHttp => "otlp_http",
+            Self::OtlpGrpc => "otlp_grpc",
+            Self::Quiver => "quiver",
+        }
+    }
+}
+
+impl EncodeLabelValue for Receiver {
+    fn encode(&self, encoder: &mut LabelValueEncoder<'_>) -> Result<(), std::fmt::Error> {
+        encoder.write_str(self.as_str())
+    }
+}
+
+/// Reason for spans not being stored.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum DropReason {
+    /// The spans couldn't be converted into the internal data model.
+    Conversion,
+    /// Writing the spans to the storage failed.
+    Storage,
+    /// The spans couldn't be forwarded to the upstream collector. They might still be stored
+    /// locally.
+    Forwarding,
+    /// The service exceeded its ingest budget.
+    RateLimited,
+    /// The trace already had the maximum amount of spans.
+    TraceLimit,
+}
+
+impl EncodeLabelValue for DropReason {
+    fn encode(&self, encoder: &mut LabelValueEncoder<'_>) -> Result<(), std::fmt::Error> {
+        encoder.write_str(match self {
+            Self::Conversion => "conversion",
+            Self::Storage => "storage",
+            Self::Forwarding => "forwarding",
+            Self::RateLimited => "rate_limited",
+            Self::TraceLimit => "trace_limit",
+        })
+    }
+}
+
+/// Part of a span, that can be removed from it due to the span limits.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum SpanItem {
+    Tag,
+    Log,
+}
+
+impl EncodeLabelValue for SpanItem {
+    fn encode(&self, encoder: &mut LabelValueEncoder<'_>) -> Result<(), std::fmt::Error> {
+        encoder.write_str(match self {
+            Self::Tag => "tag",
+            Self::Log => "log",
+        })
+    }
+}
+
+/// Reason for a Jaeger agent packet or a quiver batch being rejected.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum PacketError {
+    /// The packet exceeded the configured size limit.
+    TooLarge,
+    /// The packet was within the size limit, but exceeded the limit for its decompressed size.
+    DecompressedTooLarge,
+    /// The packet ended before the message was complete, usually because it was cut off.
+    Truncated,
+    /// The packet didn't contain a valid Thrift message.
+    Malformed,
+    /// The packet contained a valid Thrift message, but its spans couldn't be converted.
+    Invalid,
+}
+
+impl From<&archer_thrift::thrift::Error> for PacketError {
+    fn from(value: &archer_thrift::thrift::Error) -> Self {
+        use archer_thrift::thrift::{Error, TransportError, TransportErrorKind};
+
+        match value {
+            Error::Transport(TransportError {
+                kind: TransportErrorKind::EndOfFile,
+                ..
+            }) => Self::Truncated,
+            Error::User(_) => Self::Invalid,
+            _ => Self::Malformed,
+        }
+    }
+}
+
+impl EncodeLabelValue for PacketError {
+    fn encode(&self, encoder: &mut LabelValueEncoder<'_>) -> Result<(), std::fmt::Error> {
+        encoder.write_str(match self {
+            Self::TooLarge => "too_large",
+            Self::DecompressedTooLarge => "decompressed_too_large",
+            Self::Truncated => "truncated",
+            Self::Malformed => "malformed",
+            Self::Invalid => "invalid",
+        })
+    }
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq, EncodeLabelSet)]
+struct ReceiverLabels {
+    receiver: Receiver,
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq, EncodeLabelSet)]
+struct DropLabels {
+    reason: DropReason,
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq, EncodeLabelSet)]
+struct PacketLabels {
+    receiver: Receiver,
+    reason: PacketError,
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq, EncodeLabelSet)]
+struct RejectLabels {
+    reason: PacketError,
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq, EncodeLabelSet)]
+struct ItemLabels {
+    item: SpanItem,
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq, EncodeLabelSet)]
+struct QueryLabels {
+    route: String,
+}
+
+/// Count the given amount of spans as received from the receiver.
+pub fn spans_received(receiver: Receiver, count: usize) {
+    METRICS
+        .spans_received
+        .get_or_create(&ReceiverLabels { receiver })
+        .inc_by(count as u64);
+}
+
+/// Count the given amount of spans as dropped, for the given reason.
+pub fn spans_dropped(reason: DropReason, count: usize) {
+    METRICS
+        .spans_dropped
+        .get_or_create(&DropLabels { reason })
+        .inc_by(count as u64);
+}
+
+/// Count a single Jaeger agent packet as rejected, for the given reason.
+pub fn packet_rejected(receiver: Receiver, reason: PacketError) {
+    METRICS
+        .packets_rejected
+        .get_or_create(&PacketLabels { receiver, reason })
+        .inc();
+}
+
+/// Count the given amount of Jaeger agent packets as dropped by the OS.
+pub fn packets_dropped(receiver: Receiver, count: u64) {
+    METRICS
+        .packets_dropped
+        .get_or_create(&ReceiverLabels { receiver })
+        .inc_by(count);
+}
+
+/// Count a single quiver batch as rejected, for the given reason.
+pub fn batch_rejected(reason: PacketError) {
+    METRICS
+        .batches_rejected
+        .get_or_create(&RejectLabels { reason })
+        .inc();
+}
+
+/// Count the given amount of tags or logs as removed from spans.
+pub fn items_dropped(item: SpanItem, count: usize) {
+    METRICS
+        .items_dropped
+        .get_or_create(&ItemLabels { item })
+        .inc_by(count as u64);
+}
+
+/// Record the latency of a single storage write, which started at the given instant.
+pub fn storage_write(start: Instant) {
+    METRICS
+        .storage_writes
+        .observe(start.elapsed().as_secs_f64());
+}
+
+/// Middleware that records the latency of each request to the query API, grouped by route.
+pub async fn track_query<B>(req: Request<B>, next: Next<B>) -> Response {
+    let route = req.extensions().get::<MatchedPath>().map_or_else(
+        || req.uri().path().to_owned(),
+        |path| path.as_str().to_owned(),
+    );
+    let start = Instant::now();
+
+    let resp = next.run(req).await;
+
+    METRICS
+        .queries
+        .get_or_create(&QueryLabels { route })
+        .observe(start.elapsed().as_secs_f64());
+
+    resp
+}
+
+/// Handler that renders all metrics in the Prometheus text format.
+pub async fn handler() -> impl IntoResponse {
+    let mut buf = String::new();
+
+    match text::encode(&mut buf, &METRICS.registry) {
+        Ok(()) => Ok((
+            [(
+                CONTENT_TYPE,
+                HeaderValue::from_static(
+                    "application/openmetrics-text; version=1.0.0; charset=utf-8",
+                ),
+            )],
+            buf,
+        )),
+        Err(e) => {
+            error!(error = ?e, "failed encoding metrics");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
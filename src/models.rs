@@ -1,13 +1,14 @@
 use std::{
     mem,
     num::{NonZeroU128, NonZeroU64},
+    str::FromStr,
 };
 
 use anyhow::{ensure, Context};
 use serde::{Deserialize, Serialize};
 use time::{Duration, OffsetDateTime};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Span {
     pub trace_id: TraceId,
     pub span_id: SpanId,
@@ -19,28 +20,100 @@ pub struct Span {
     pub tags: Vec<Tag>,
     pub logs: Vec<Log>,
     pub process: Process,
+    /// Problems with the span, that were found while receiving it.
+    #[serde(default)]
+    pub warnings: Vec<String>,
+    /// Split of the duration into active and inactive time, if the instrumentation recorded it.
+    #[serde(default)]
+    pub timing: Option<Timing>,
+    /// Role of the span in the communication between services, if the instrumentation recorded
+    /// it.
+    #[serde(default)]
+    pub kind: Option<SpanKind>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl Span {
+    /// Move the timing out of the `busy_ns` and `idle_ns` tags, that `tracing-opentelemetry`
+    /// records for each span, into [`Self::timing`]. That way, the timing is the same for all
+    /// receivers, regardless of whether their format has a dedicated field for it.
+    #[must_use]
+    pub fn with_timing_from_tags(mut self) -> Self {
+        let nanos = |key: &str| {
+            self.tags
+                .iter()
+                .find(|tag| tag.key == key)
+                .and_then(|tag| match tag.value {
+                    TagValue::I64(v) => Some(i128::from(v)),
+                    TagValue::U64(v) => Some(i128::from(v)),
+                    TagValue::I128(v) => Some(v),
+                    _ => None,
+                })
+                .and_then(|v| i64::try_from(v).ok())
+                .map(Duration::nanoseconds)
+        };
+
+        if let (Some(busy), Some(idle)) = (nanos(Timing::BUSY_KEY), nanos(Timing::IDLE_KEY)) {
+            self.timing = Some(Timing { busy, idle });
+            self.tags
+                .retain(|tag| tag.key != Timing::BUSY_KEY && tag.key != Timing::IDLE_KEY);
+        }
+
+        self
+    }
+
+    /// Move the kind out of the `span.kind` tag into [`Self::kind`], so it's the same for all
+    /// receivers. Tags with unknown kinds are kept as is.
+    #[must_use]
+    pub fn with_kind_from_tags(mut self) -> Self {
+        let kind = self.tags.iter().find_map(|tag| match &tag.value {
+            TagValue::String(value) if tag.key == SpanKind::TAG_KEY => value.parse().ok(),
+            _ => None,
+        });
+
+        if let Some(kind) = kind {
+            self.kind.get_or_insert(kind);
+            self.tags.retain(|tag| tag.key != SpanKind::TAG_KEY);
+        }
+
+        self
+    }
+
+    /// Whether the span is marked as failed, by an `error` tag.
+    pub fn is_error(&self) -> bool {
+        self.tags.iter().any(|tag| {
+            tag.key == "error"
+                && match &tag.value {
+                    TagValue::Bool(b) => *b,
+                    TagValue::String(s) => s == "true",
+                    _ => false,
+                }
+        })
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Reference {
     pub ty: RefType,
     pub trace_id: TraceId,
     pub span_id: SpanId,
+    /// Additional context of the reference, which only span links from OpenTelemetry carry.
+    #[serde(default)]
+    pub tags: Vec<Tag>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum RefType {
     ChildOf,
     FollowsFrom,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Tag {
     pub key: String,
     pub value: TagValue,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum TagValue {
     F64(f64),
     I64(i64),
@@ -52,13 +125,102 @@ pub enum TagValue {
     Binary(Vec<u8>),
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Role of a span in the communication between services, as defined by OpenTelemetry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum SpanKind {
+    Client,
+    Server,
+    Producer,
+    Consumer,
+    Internal,
+}
+
+impl SpanKind {
+    pub const TAG_KEY: &'static str = "span.kind";
+
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Client => "client",
+            Self::Server => "server",
+            Self::Producer => "producer",
+            Self::Consumer => "consumer",
+            Self::Internal => "internal",
+        }
+    }
+
+    /// Represent the kind as tag, for formats that have no dedicated field for it.
+    pub fn tag(self) -> Tag {
+        Tag {
+            key: Self::TAG_KEY.to_owned(),
+            value: TagValue::String(self.as_str().to_owned()),
+        }
+    }
+}
+
+impl FromStr for SpanKind {
+    type Err = UnknownSpanKind;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "client" => Self::Client,
+            "server" => Self::Server,
+            "producer" => Self::Producer,
+            "consumer" => Self::Consumer,
+            "internal" => Self::Internal,
+            _ => return Err(UnknownSpanKind(s.to_owned())),
+        })
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error(
+    "unknown span kind `{0}`, expected `client`, `server`, `producer`, `consumer` or `internal`"
+)]
+pub struct UnknownSpanKind(String);
+
+/// Time that a span spent actively working, and waiting in between, which adds up to its duration.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Timing {
+    pub busy: Duration,
+    pub idle: Duration,
+}
+
+impl Timing {
+    const BUSY_KEY: &'static str = "busy_ns";
+    const IDLE_KEY: &'static str = "idle_ns";
+
+    /// Represent the timing as tags, for formats that have no dedicated field for it.
+    pub fn tags(self) -> [Tag; 2] {
+        [
+            Tag {
+                key: Self::BUSY_KEY.to_owned(),
+                value: TagValue::I128(self.busy.whole_nanoseconds()),
+            },
+            Tag {
+                key: Self::IDLE_KEY.to_owned(),
+                value: TagValue::I128(self.idle.whole_nanoseconds()),
+            },
+        ]
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Log {
     pub timestamp: OffsetDateTime,
     pub fields: Vec<Tag>,
 }
 
-#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+/// Log entry that was received independently of any span, but may still refer to one through its
+/// trace and span ID.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LogRecord {
+    pub trace_id: Option<TraceId>,
+    pub span_id: Option<SpanId>,
+    pub log: Log,
+    pub process: Process,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 pub struct Process {
     pub service: String,
     pub tags: Vec<Tag>,
@@ -124,7 +286,7 @@ impl From<TraceId> for rusqlite::types::Value {
     }
 }
 
-#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
 #[serde(transparent)]
 pub struct SpanId(NonZeroU64);
 
@@ -1,4 +1,16 @@
-use std::net::Ipv4Addr;
+use std::{
+    net::{Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::Result;
+use archer_http::axum::{http::StatusCode, BoxError, Router, Server};
+use axum_server::tls_rustls::RustlsConfig;
+use tokio_shutdown::Shutdown;
+use tracing::info;
+
+use crate::config;
 
 const ADDRESS: Ipv4Addr = if cfg!(debug_assertions) {
     Ipv4Addr::LOCALHOST
@@ -8,11 +20,236 @@ const ADDRESS: Ipv4Addr = if cfg!(debug_assertions) {
 
 pub const JAEGER_AGENT_COMPACT: (Ipv4Addr, u16) = (ADDRESS, 6831);
 pub const JAEGER_AGENT_BINARY: (Ipv4Addr, u16) = (ADDRESS, 6832);
+pub const JAEGER_AGENT_TCP: (Ipv4Addr, u16) = (ADDRESS, 6833);
+pub const JAEGER_AGENT_HTTP: (Ipv4Addr, u16) = (ADDRESS, 5778);
 pub const JAEGER_COLLECTOR_GRPC: (Ipv4Addr, u16) = (ADDRESS, 14250);
 pub const JAEGER_COLLECTOR_HTTP: (Ipv4Addr, u16) = (ADDRESS, 14268);
+pub const JAEGER_QUERY_GRPC: (Ipv4Addr, u16) = (ADDRESS, 16685);
 pub const JAEGER_QUERY_HTTP: (Ipv4Addr, u16) = (ADDRESS, 16686);
 
 pub const OTLP_COLLECTOR_GRPC: (Ipv4Addr, u16) = (ADDRESS, 4317);
 pub const OTLP_COLLECTOR_HTTP: (Ipv4Addr, u16) = (ADDRESS, 4318);
 
 pub const QUIVER_COLLECTOR: (Ipv4Addr, u16) = (ADDRESS, 14000);
+
+/// Bind addresses of all receivers, where `None` means the receiver is disabled, and optional UNIX
+/// domain sockets for some of them.
+#[derive(Clone, Debug)]
+pub struct Addresses {
+    pub jaeger_agent_compact: Option<SocketAddr>,
+    pub jaeger_agent_binary: Option<SocketAddr>,
+    pub jaeger_agent_tcp: Option<SocketAddr>,
+    pub jaeger_agent_http: Option<SocketAddr>,
+    pub jaeger_collector_http: Option<SocketAddr>,
+    pub jaeger_collector_grpc: Option<SocketAddr>,
+    pub otlp_http: Option<SocketAddr>,
+    pub otlp_grpc: Option<SocketAddr>,
+    pub quiver: Option<SocketAddr>,
+    pub query: Option<SocketAddr>,
+    pub query_grpc: Option<SocketAddr>,
+    pub otlp_http_socket: Option<PathBuf>,
+    pub otlp_grpc_socket: Option<PathBuf>,
+    pub query_socket: Option<PathBuf>,
+}
+
+impl Addresses {
+    /// Apply the configured overrides to the default addresses.
+    pub fn new(listen: &config::Listen) -> Self {
+        fn resolve(
+            listener: config::Listener,
+            default: (Ipv4Addr, u16),
+            enabled: bool,
+        ) -> Option<SocketAddr> {
+            listener
+                .enabled
+                .unwrap_or(enabled)
+                .then(|| listener.address.unwrap_or_else(|| default.into()))
+        }
+
+        Self {
+            jaeger_agent_compact: resolve(listen.jaeger_agent_compact, JAEGER_AGENT_COMPACT, true),
+            jaeger_agent_binary: resolve(listen.jaeger_agent_binary, JAEGER_AGENT_BINARY, true),
+            jaeger_agent_tcp: resolve(listen.jaeger_agent_tcp, JAEGER_AGENT_TCP, false),
+            jaeger_agent_http: resolve(listen.jaeger_agent_http, JAEGER_AGENT_HTTP, false),
+            jaeger_collector_http: resolve(
+                listen.jaeger_collector_http,
+                JAEGER_COLLECTOR_HTTP,
+                true,
+            ),
+            jaeger_collector_grpc: resolve(
+                listen.jaeger_collector_grpc,
+                JAEGER_COLLECTOR_GRPC,
+                true,
+            ),
+            otlp_http: resolve(listen.otlp_http, OTLP_COLLECTOR_HTTP, true),
+            otlp_grpc: resolve(listen.otlp_grpc, OTLP_COLLECTOR_GRPC, true),
+            quiver: resolve(listen.quiver, QUIVER_COLLECTOR, true),
+            query: resolve(listen.query, JAEGER_QUERY_HTTP, true),
+            query_grpc: resolve(listen.query_grpc, JAEGER_QUERY_GRPC, true),
+            otlp_http_socket: listen.unix.otlp_http.clone(),
+            otlp_grpc_socket: listen.unix.otlp_grpc.clone(),
+            query_socket: listen.unix.query.clone(),
+        }
+    }
+}
+
+/// Turn errors of fallible middleware into a response.
+pub async fn handle_error(err: BoxError) -> (StatusCode, String) {
+    (StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
+}
+
+/// Serve the router on the given address until shutdown, using TLS if a configuration is given.
+pub async fn serve(
+    addr: SocketAddr,
+    app: Router,
+    tls: Option<Arc<rustls::ServerConfig>>,
+    shutdown: Shutdown,
+) -> Result<()> {
+    let Some(tls) = tls else {
+        info!("listening on http://{addr}");
+
+        Server::bind(&addr)
+            .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+            .with_graceful_shutdown(shutdown.handle())
+            .await?;
+
+        return Ok(());
+    };
+
+    info!("listening on https://{addr}");
+
+    let handle = axum_server::Handle::new();
+    tokio::spawn({
+        let handle = handle.clone();
+        async move {
+            shutdown.handle().await;
+            handle.graceful_shutdown(None);
+        }
+    });
+
+    axum_server::bind_rustls(addr, RustlsConfig::from_config(tls))
+        .handle(handle)
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+        .await?;
+
+    Ok(())
+}
+
+/// Serve the router on the TCP address and the UNIX domain socket, where either of them can be
+/// disabled.
+pub async fn serve_with_unix(
+    addr: Option<SocketAddr>,
+    socket: Option<&Path>,
+    app: Router,
+    tls: Option<Arc<rustls::ServerConfig>>,
+    shutdown: Shutdown,
+) -> Result<()> {
+    let tcp = {
+        let (app, shutdown) = (app.clone(), shutdown.clone());
+        async move {
+            match addr {
+                Some(addr) => serve(addr, app, tls, shutdown).await,
+                None => Ok(()),
+            }
+        }
+    };
+    let unix = async move {
+        match socket {
+            Some(path) => serve_unix(path, app, shutdown).await,
+            None => Ok(()),
+        }
+    };
+
+    tokio::try_join!(tcp, unix)?;
+
+    Ok(())
+}
+
+/// Serve the router on a UNIX domain socket until shutdown. The socket is always unencrypted, as
+/// only local processes can connect to it.
+#[cfg(unix)]
+pub async fn serve_unix(path: &Path, app: Router, shutdown: Shutdown) -> Result<()> {
+    let listener = UnixAccept(bind_unix(path)?);
+
+    info!("listening on unix:{}", path.display());
+
+    let result = Server::builder(listener)
+        .serve(app.into_make_service())
+        .with_graceful_shutdown(shutdown.handle())
+        .await;
+
+    std::fs::remove_file(path).ok();
+
+    Ok(result?)
+}
+
+#[cfg(not(unix))]
+pub async fn serve_unix(path: &Path, _: Router, _: Shutdown) -> Result<()> {
+    Err(unix_unsupported(path))
+}
+
+/// Serve a gRPC server on a UNIX domain socket, by passing the incoming connections to the given
+/// function, that runs the server until shutdown.
+#[cfg(unix)]
+pub async fn serve_grpc_unix<F, Fut>(path: &Path, serve: F) -> Result<()>
+where
+    F: FnOnce(tokio_stream::wrappers::UnixListenerStream) -> Fut,
+    Fut: std::future::Future<Output = Result<(), archer_proto::tonic::transport::Error>>,
+{
+    let incoming = tokio_stream::wrappers::UnixListenerStream::new(bind_unix(path)?);
+
+    info!("listening on unix:{}", path.display());
+
+    let result = serve(incoming).await;
+
+    std::fs::remove_file(path).ok();
+
+    Ok(result?)
+}
+
+#[cfg(not(unix))]
+pub fn unix_unsupported(path: &Path) -> anyhow::Error {
+    anyhow::anyhow!(
+        "can't listen on {}, UNIX domain sockets are not supported on this platform",
+        path.display()
+    )
+}
+
+/// Bind a UNIX domain socket. A socket file, that is left over from a previous run, is replaced,
+/// but any other existing file is not.
+#[cfg(unix)]
+fn bind_unix(path: &Path) -> Result<tokio::net::UnixListener> {
+    use std::os::unix::fs::FileTypeExt;
+
+    use anyhow::Context;
+
+    match std::fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_socket() => std::fs::remove_file(path)
+            .with_context(|| format!("failed removing stale socket {}", path.display()))?,
+        Ok(_) => anyhow::bail!("{} already exists and is not a socket", path.display()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+
+    tokio::net::UnixListener::bind(path)
+        .with_context(|| format!("failed binding socket {}", path.display()))
+}
+
+/// Connections of a UNIX domain socket, for the HTTP server.
+#[cfg(unix)]
+struct UnixAccept(tokio::net::UnixListener);
+
+#[cfg(unix)]
+impl hyper::server::accept::Accept for UnixAccept {
+    type Conn = tokio::net::UnixStream;
+    type Error = std::io::Error;
+
+    fn poll_accept(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Result<Self::Conn, Self::Error>>> {
+        self.0
+            .poll_accept(cx)
+            .map(|result| Some(result.map(|(stream, _)| stream)))
+    }
+}
@@ -1,25 +1,33 @@
-use std::net::SocketAddr;
+use std::{net::SocketAddr, path::PathBuf, sync::Arc};
 
 use anyhow::Result;
 use archer_http::{
     axum::{
         async_trait,
         body::{Bytes, HttpBody},
-        extract::{rejection::BytesRejection, FromRequest, State},
-        http::{header::CONTENT_TYPE, HeaderValue, Request, StatusCode},
+        error_handling::HandleErrorLayer,
+        extract::{rejection::BytesRejection, ConnectInfo, FromRequest, State},
+        http::{header::CONTENT_TYPE, HeaderMap, HeaderValue, Request, StatusCode},
         response::{IntoResponse, Response},
         routing::post,
-        BoxError, Router, Server,
+        BoxError, Router,
     },
     tower::ServiceBuilder,
-    tower_http::ServiceBuilderExt,
+    tower_http::{decompression::RequestDecompressionLayer, ServiceBuilderExt},
 };
 use archer_proto::{
     opentelemetry::proto::{
-        collector::trace::v1::{
-            trace_service_server::{self, TraceServiceServer},
-            ExportTraceServiceRequest, ExportTraceServiceResponse,
+        collector::{
+            logs::v1::{
+                logs_service_server::{self, LogsServiceServer},
+                ExportLogsServiceRequest, ExportLogsServiceResponse,
+            },
+            trace::v1::{
+                trace_service_server::{self, TraceServiceServer},
+                ExportTracePartialSuccess, ExportTraceServiceRequest, ExportTraceServiceResponse,
+            },
         },
+        logs::v1::ResourceLogs,
         trace::v1::ResourceSpans,
     },
     prost::{DecodeError, Message},
@@ -30,28 +38,42 @@ use mime::Mime;
 use tokio_shutdown::Shutdown;
 use tracing::{error, info, instrument, warn};
 
-use crate::{convert, models, net, storage::Database};
+use crate::{
+    audit::Origin,
+    convert, ingest,
+    metrics::{self, DropReason, Receiver},
+    models, net, processor,
+    storage::Database,
+    tasks, tenancy,
+};
 
 #[instrument(name = "otlp", skip_all)]
-pub async fn run(shutdown: Shutdown, database: Database) -> Result<()> {
-    let (grpc, http) = tokio::try_join!(
-        tokio::spawn(run_grpc(
+pub async fn run(
+    shutdown: Shutdown,
+    database: Database,
+    tls: Option<Arc<rustls::ServerConfig>>,
+    addrs: net::Addresses,
+) -> Result<()> {
+    // Flattened, so a listener that fails to start, like a UNIX domain socket that can't be bound,
+    // stops the others right away.
+    tokio::try_join!(
+        crate::flatten(tokio::spawn(run_grpc(
             tracing::Span::current(),
             shutdown.clone(),
             database.clone(),
-            SocketAddr::from(net::OTLP_COLLECTOR_GRPC)
-        )),
-        tokio::spawn(run_http(
+            addrs.otlp_grpc,
+            addrs.otlp_grpc_socket,
+        ))),
+        crate::flatten(tokio::spawn(run_http(
             tracing::Span::current(),
             shutdown,
             database,
-            SocketAddr::from(net::OTLP_COLLECTOR_HTTP)
-        ))
+            addrs.otlp_http,
+            addrs.otlp_http_socket,
+            tls,
+        ))),
     )?;
 
-    grpc?;
-    http?;
-
     Ok(())
 }
 
@@ -60,19 +82,27 @@ async fn run_http(
     parent: tracing::Span,
     shutdown: Shutdown,
     database: Database,
-    addr: SocketAddr,
+    addr: Option<SocketAddr>,
+    socket: Option<PathBuf>,
+    tls: Option<Arc<rustls::ServerConfig>>,
 ) -> Result<()> {
-    info!("listening on http://{addr}");
+    if addr.is_none() && socket.is_none() {
+        return Ok(());
+    }
 
     let app = Router::new()
         .route("/v1/traces", post(traces))
-        .layer(ServiceBuilder::new().compression().trace_for_http())
+        .route("/v1/logs", post(logs))
+        .layer(
+            ServiceBuilder::new()
+                .layer(HandleErrorLayer::new(net::handle_error))
+                .layer(RequestDecompressionLayer::new())
+                .compression()
+                .trace_for_http(),
+        )
         .with_state(database);
 
-    Server::bind(&addr)
-        .serve(app.into_make_service())
-        .with_graceful_shutdown(shutdown.handle())
-        .await?;
+    net::serve_with_unix(addr, socket.as_deref(), app, tls, shutdown).await?;
 
     info!("server stopped");
 
@@ -81,18 +111,45 @@ async fn run_http(
 
 async fn traces(
     State(db): State<Database>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
     Protobuf(request): Protobuf<ExportTraceServiceRequest>,
 ) -> Result<impl IntoResponse, (StatusCode, String)> {
-    let spans = convert_resource_spans(request.resource_spans)
+    // Connections over a UNIX domain socket don't have a peer address.
+    let origin = Origin {
+        receiver: Receiver::OtlpHttp,
+        peer: connect_info.map(|ConnectInfo(peer)| peer),
+        bytes: Some(request.encoded_len()),
+    };
+    let converted = convert_resource_spans(origin.receiver, request.resource_spans);
+    let partial_success = converted.partial_success();
+    let count = converted.spans.len() + converted.rejected;
+    let db = db.for_tenant(tenancy::from_headers(&headers));
+
+    ingest::accept(db, origin, count, converted.spans)
+        .map_err(|e| (StatusCode::TOO_MANY_REQUESTS, e.to_string()))?;
+
+    Ok(Protobuf(ExportTraceServiceResponse { partial_success }))
+}
+
+async fn logs(
+    State(db): State<Database>,
+    headers: HeaderMap,
+    Protobuf(request): Protobuf<ExportLogsServiceRequest>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let mut logs = convert_resource_logs(request.resource_logs)
         .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+    processor::apply_logs(&mut logs);
 
-    tokio::spawn(async move {
-        if let Err(e) = db.save_spans(spans).await {
-            error!(error = ?e, "failed to save spans to DB");
+    let db = db.for_tenant(tenancy::from_headers(&headers));
+
+    tasks::spawn(async move {
+        if let Err(e) = db.save_logs(logs).await {
+            error!(error = ?e, "failed to save logs to DB");
         }
     });
 
-    Ok(Protobuf(ExportTraceServiceResponse {
+    Ok(Protobuf(ExportLogsServiceResponse {
         partial_success: None,
     }))
 }
@@ -155,9 +212,7 @@ fn protobuf_content_type<B>(req: &Request<B>) -> bool {
         .get(CONTENT_TYPE)
         .and_then(|ct| ct.to_str().ok())
         .and_then(|ct| ct.parse::<Mime>().ok())
-        .map_or(false, |ct| {
-            ct.type_() == mime::APPLICATION && ct.subtype() == "x-protobuf"
-        })
+        .is_some_and(|ct| ct.type_() == mime::APPLICATION && ct.subtype() == "x-protobuf")
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -187,19 +242,57 @@ async fn run_grpc(
     parent: tracing::Span,
     shutdown: Shutdown,
     database: Database,
-    addr: SocketAddr,
+    addr: Option<SocketAddr>,
+    socket: Option<PathBuf>,
 ) -> Result<()> {
-    info!("listening on http://{addr}");
-
-    tonic::transport::Server::builder()
-        .layer(ServiceBuilder::new().trace_for_grpc())
-        .add_service(
-            TraceServiceServer::new(TraceService(database))
-                .accept_compressed(CompressionEncoding::Gzip)
-                .send_compressed(CompressionEncoding::Gzip),
-        )
-        .serve_with_shutdown(addr, shutdown.handle())
-        .await?;
+    if addr.is_none() && socket.is_none() {
+        return Ok(());
+    }
+
+    // The router can't be cloned, so each listener gets its own.
+    let router = || {
+        tonic::transport::Server::builder()
+            .layer(ServiceBuilder::new().trace_for_grpc())
+            .add_service(
+                TraceServiceServer::new(TraceService(database.clone()))
+                    .accept_compressed(CompressionEncoding::Gzip)
+                    .send_compressed(CompressionEncoding::Gzip),
+            )
+            .add_service(
+                LogsServiceServer::new(LogsService(database.clone()))
+                    .accept_compressed(CompressionEncoding::Gzip)
+                    .send_compressed(CompressionEncoding::Gzip),
+            )
+    };
+
+    tokio::try_join!(
+        async {
+            let Some(addr) = addr else {
+                return Ok(());
+            };
+
+            info!("listening on http://{addr}");
+
+            router()
+                .serve_with_shutdown(addr, shutdown.handle())
+                .await
+                .map_err(anyhow::Error::from)
+        },
+        async {
+            match socket.as_deref() {
+                #[cfg(unix)]
+                Some(path) => {
+                    net::serve_grpc_unix(path, |incoming| {
+                        router().serve_with_incoming_shutdown(incoming, shutdown.handle())
+                    })
+                    .await
+                }
+                #[cfg(not(unix))]
+                Some(path) => Err(net::unix_unsupported(path)),
+                None => Ok(()),
+            }
+        },
+    )?;
 
     info!("server stopped");
 
@@ -214,34 +307,121 @@ impl trace_service_server::TraceService for TraceService {
         &self,
         request: tonic::Request<ExportTraceServiceRequest>,
     ) -> Result<tonic::Response<ExportTraceServiceResponse>, tonic::Status> {
-        let spans = convert_resource_spans(request.into_inner().resource_spans)
+        let db = self
+            .0
+            .for_tenant(tenancy::from_metadata(request.metadata()));
+        let origin = Origin {
+            receiver: Receiver::OtlpGrpc,
+            peer: request.remote_addr(),
+            bytes: Some(request.get_ref().encoded_len()),
+        };
+        let converted =
+            convert_resource_spans(origin.receiver, request.into_inner().resource_spans);
+        let partial_success = converted.partial_success();
+        let count = converted.spans.len() + converted.rejected;
+
+        ingest::accept(db, origin, count, converted.spans)
+            .map_err(|e| tonic::Status::resource_exhausted(e.to_string()))?;
+
+        Ok(tonic::Response::new(ExportTraceServiceResponse {
+            partial_success,
+        }))
+    }
+}
+
+struct LogsService(Database);
+
+#[tonic::async_trait]
+impl logs_service_server::LogsService for LogsService {
+    async fn export(
+        &self,
+        request: tonic::Request<ExportLogsServiceRequest>,
+    ) -> Result<tonic::Response<ExportLogsServiceResponse>, tonic::Status> {
+        let db = self
+            .0
+            .for_tenant(tenancy::from_metadata(request.metadata()));
+        let mut logs = convert_resource_logs(request.into_inner().resource_logs)
             .map_err(|e| tonic::Status::invalid_argument(e.to_string()))?;
-        let db = self.0.clone();
+        processor::apply_logs(&mut logs);
 
-        tokio::spawn(async move {
-            if let Err(e) = db.save_spans(spans).await {
-                error!(error = ?e, "failed to save spans to DB");
+        tasks::spawn(async move {
+            if let Err(e) = db.save_logs(logs).await {
+                error!(error = ?e, "failed to save logs to DB");
             }
         });
 
-        Ok(tonic::Response::new(ExportTraceServiceResponse::default()))
+        Ok(tonic::Response::new(ExportLogsServiceResponse::default()))
     }
 }
 
-fn convert_resource_spans(resource_spans: Vec<ResourceSpans>) -> Result<Vec<models::Span>> {
+/// Spans of an export request, together with the amount of spans that couldn't be converted.
+struct ConvertedSpans {
+    spans: Vec<models::Span>,
+    rejected: usize,
+    error_message: String,
+}
+
+impl ConvertedSpans {
+    /// Details about rejected spans, to inform the client, if any were rejected.
+    fn partial_success(&self) -> Option<ExportTracePartialSuccess> {
+        (self.rejected > 0).then(|| ExportTracePartialSuccess {
+            rejected_spans: i64::try_from(self.rejected).unwrap_or(i64::MAX),
+            error_message: self.error_message.clone(),
+        })
+    }
+}
+
+fn convert_resource_spans(
+    receiver: Receiver,
+    resource_spans: Vec<ResourceSpans>,
+) -> ConvertedSpans {
     let span_len = convert::span_from_otlp_len(&resource_spans);
+    metrics::spans_received(receiver, span_len);
+
+    let mut converted = ConvertedSpans {
+        spans: Vec::with_capacity(span_len),
+        rejected: 0,
+        error_message: String::new(),
+    };
+
+    for span in resource_spans.into_iter().flat_map(convert::span_from_otlp) {
+        match span {
+            Ok(span) => converted.spans.push(span),
+            Err(e) => {
+                if converted.rejected == 0 {
+                    converted.error_message = e.to_string();
+                }
+                converted.rejected += 1;
+            }
+        }
+    }
+
+    if converted.rejected > 0 {
+        warn!(
+            rejected = converted.rejected,
+            error = %converted.error_message,
+            "failed to convert spans"
+        );
+        metrics::spans_dropped(DropReason::Conversion, converted.rejected);
+    }
+
+    converted
+}
+
+fn convert_resource_logs(resource_logs: Vec<ResourceLogs>) -> Result<Vec<models::LogRecord>> {
+    let log_len = convert::logs_from_otlp_len(&resource_logs);
 
-    resource_spans
+    resource_logs
         .into_iter()
         .try_fold(
-            Vec::with_capacity(span_len),
-            |mut acc, spans| match convert::span_from_otlp(spans) {
-                Ok(spans) => {
-                    acc.extend(spans);
+            Vec::with_capacity(log_len),
+            |mut acc, logs| match convert::logs_from_otlp(logs) {
+                Ok(logs) => {
+                    acc.extend(logs);
                     Ok(acc)
                 }
                 Err(e) => {
-                    warn!(error = ?e, "failed to convert spans");
+                    warn!(error = ?e, "failed to convert logs");
                     Err(e)
                 }
             },
@@ -0,0 +1,329 @@
+//! Normalization of received spans, shared by all collectors. It runs right after the spans are
+//! converted, so everything after it, like rate limits and the storage, already sees the final
+//! service names and tags.
+
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+};
+
+use anyhow::{anyhow, Context, Result};
+use once_cell::sync::OnceCell;
+use regex::{Captures, Regex};
+
+use crate::{
+    config::{self, ScrubPattern, REDACTED},
+    models::{LogRecord, Process, Span, Tag, TagValue},
+};
+
+static PROCESSOR: OnceCell<Processor> = OnceCell::new();
+
+struct Processor {
+    rename_services: Vec<(Regex, String)>,
+    remove_tags: HashSet<String>,
+    rename_tags: HashMap<String, String>,
+    add_tags: HashMap<String, String>,
+    scrub_keys: HashSet<String>,
+    scrub_patterns: Vec<Matcher>,
+}
+
+/// Pattern for sensitive data in tag values.
+struct Matcher {
+    regex: Regex,
+    /// Only redact matches that pass the Luhn checksum, to avoid redacting arbitrary numbers,
+    /// like IDs, as credit cards.
+    luhn: bool,
+}
+
+impl Matcher {
+    fn builtin(pattern: ScrubPattern) -> Result<Self> {
+        let (regex, luhn) = match pattern {
+            ScrubPattern::Email => (r"[\w.%+-]+@[\w-]+(?:\.[\w-]+)*\.[a-zA-Z]{2,}", false),
+            ScrubPattern::BearerToken => (r"(?i)\bbearer\s+[\w\-.~+/]+=*", false),
+            ScrubPattern::CreditCard => (r"\b(?:\d[ -]?){12,18}\d\b", true),
+        };
+
+        Ok(Self {
+            regex: Regex::new(regex)?,
+            luhn,
+        })
+    }
+
+    fn redact<'a>(&self, value: &'a str) -> Cow<'a, str> {
+        self.regex.replace_all(value, |caps: &Captures<'_>| {
+            let matched = &caps[0];
+            if !self.luhn || luhn(matched) {
+                REDACTED.to_owned()
+            } else {
+                matched.to_owned()
+            }
+        })
+    }
+}
+
+/// Validate the checksum of credit card numbers, ignoring any separators.
+fn luhn(number: &str) -> bool {
+    let sum = number
+        .bytes()
+        .filter(u8::is_ascii_digit)
+        .rev()
+        .enumerate()
+        .map(|(i, digit)| {
+            let digit = u32::from(digit - b'0');
+            match (i % 2, digit * 2) {
+                (0, _) => digit,
+                (_, doubled) if doubled > 9 => doubled - 9,
+                (_, doubled) => doubled,
+            }
+        })
+        .sum::<u32>();
+
+    sum % 10 == 0
+}
+
+/// Enable the given rules. Without calling this, or without any rules, spans are kept as is.
+pub fn init(config: config::Processor) -> Result<()> {
+    let config::Processor {
+        rename_services,
+        remove_tags,
+        rename_tags,
+        add_tags,
+        scrub,
+    } = config;
+
+    if rename_services.is_empty()
+        && remove_tags.is_empty()
+        && rename_tags.is_empty()
+        && add_tags.is_empty()
+        && scrub.keys.is_empty()
+        && scrub.builtin.is_empty()
+        && scrub.patterns.is_empty()
+    {
+        return Ok(());
+    }
+
+    let rename_services = rename_services
+        .into_iter()
+        .map(|rule| {
+            Regex::new(&rule.pattern)
+                .with_context(|| format!("invalid service pattern `{}`", rule.pattern))
+                .map(|pattern| (pattern, rule.replacement))
+        })
+        .collect::<Result<_>>()?;
+
+    let scrub_patterns = scrub
+        .builtin
+        .into_iter()
+        .map(Matcher::builtin)
+        .chain(scrub.patterns.into_iter().map(|pattern| {
+            Regex::new(&pattern)
+                .with_context(|| format!("invalid scrub pattern `{pattern}`"))
+                .map(|regex| Matcher { regex, luhn: false })
+        }))
+        .collect::<Result<_>>()?;
+
+    PROCESSOR
+        .set(Processor {
+            rename_services,
+            remove_tags,
+            rename_tags,
+            add_tags,
+            scrub_keys: scrub.keys,
+            scrub_patterns,
+        })
+        .map_err(|_| anyhow!("processor can only be initialized once"))
+}
+
+/// Apply all rules to the spans.
+pub fn apply(spans: &mut [Span]) {
+    let Some(processor) = PROCESSOR.get() else {
+        return;
+    };
+
+    for span in spans {
+        processor.rename_service(&mut span.process);
+        processor.process_tags(&mut span.tags);
+        processor.scrub(&mut span.tags);
+
+        if let Some(redacted) = processor.redact(&span.operation_name) {
+            span.operation_name = redacted;
+        }
+
+        for log in &mut span.logs {
+            processor.scrub(&mut log.fields);
+        }
+
+        for reference in &mut span.references {
+            processor.scrub(&mut reference.tags);
+        }
+
+        processor.scrub(&mut span.process.tags);
+    }
+}
+
+/// Apply the service and scrub rules to separately received logs, so they end up with the same
+/// service names as the spans they belong to, and without sensitive data.
+pub fn apply_logs(records: &mut [LogRecord]) {
+    let Some(processor) = PROCESSOR.get() else {
+        return;
+    };
+
+    for record in records {
+        processor.rename_service(&mut record.process);
+        processor.scrub(&mut record.log.fields);
+        processor.scrub(&mut record.process.tags);
+    }
+}
+
+impl Processor {
+    fn rename_service(&self, process: &mut Process) {
+        let service = &mut process.service;
+
+        if let Some((pattern, replacement)) = self
+            .rename_services
+            .iter()
+            .find(|(pattern, _)| pattern.is_match(service))
+        {
+            *service = pattern.replace(service, replacement).into_owned();
+        }
+    }
+
+    fn process_tags(&self, tags: &mut Vec<Tag>) {
+        tags.retain(|tag| !self.remove_tags.contains(&tag.key));
+
+        for tag in &mut *tags {
+            if let Some(key) = self.rename_tags.get(&tag.key) {
+                tag.key.clone_from(key);
+            }
+        }
+
+        for (key, value) in &self.add_tags {
+            if !tags.iter().any(|tag| &tag.key == key) {
+                tags.push(Tag {
+                    key: key.clone(),
+                    value: TagValue::String(value.clone()),
+                });
+            }
+        }
+    }
+
+    fn scrub(&self, tags: &mut [Tag]) {
+        for tag in tags {
+            if self.scrub_keys.contains(&tag.key) {
+                tag.value = TagValue::String(REDACTED.to_owned());
+                continue;
+            }
+
+            // Numbers are checked in their string form, as card numbers are often sent as such.
+            let value = match &mut tag.value {
+                TagValue::String(value) => {
+                    if let Some(redacted) = self.redact(value) {
+                        *value = redacted;
+                    }
+                    continue;
+                }
+                TagValue::F64(value) => value.to_string(),
+                TagValue::I64(value) => value.to_string(),
+                TagValue::U64(value) => value.to_string(),
+                TagValue::I128(value) => value.to_string(),
+                TagValue::U128(value) => value.to_string(),
+                TagValue::Bool(_) | TagValue::Binary(_) => continue,
+            };
+
+            if let Some(redacted) = self.redact(&value) {
+                tag.value = TagValue::String(redacted);
+            }
+        }
+    }
+
+    /// Apply all scrub patterns to the value, and return the result if anything was redacted.
+    fn redact(&self, value: &str) -> Option<String> {
+        let mut result = Cow::Borrowed(value);
+
+        for matcher in &self.scrub_patterns {
+            if let Cow::Owned(redacted) = matcher.redact(&result) {
+                result = Cow::Owned(redacted);
+            }
+        }
+
+        // Matches that fail the Luhn check are replaced by themselves, so only a changed value
+        // counts as redacted.
+        match result {
+            Cow::Owned(redacted) if redacted != value => Some(redacted),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+
+    fn redact(pattern: ScrubPattern, value: &str) -> String {
+        Matcher::builtin(pattern)
+            .unwrap()
+            .redact(value)
+            .into_owned()
+    }
+
+    #[test]
+    fn luhn_checksum() {
+        assert!(luhn("4111111111111111"));
+        assert!(luhn("4111-1111-1111-1111"));
+        assert!(luhn("5500 0000 0000 0004"));
+        assert!(!luhn("4111111111111112"));
+        assert!(!luhn("1234567890123456"));
+    }
+
+    #[test]
+    fn redact_credit_card() {
+        assert_eq!(
+            "paid with <redacted>",
+            redact(ScrubPattern::CreditCard, "paid with 4111 1111 1111 1111")
+        );
+        assert_eq!(
+            "<redacted>",
+            redact(ScrubPattern::CreditCard, "4111-1111-1111-1111")
+        );
+        assert_eq!(
+            "order 1234567890123456",
+            redact(ScrubPattern::CreditCard, "order 1234567890123456")
+        );
+    }
+
+    #[test]
+    fn redact_bearer_token() {
+        assert_eq!(
+            "<redacted>",
+            redact(
+                ScrubPattern::BearerToken,
+                "Bearer eyJhbGciOi.eyJzdWIi.SflKx-w5c="
+            )
+        );
+        assert_eq!(
+            "auth: <redacted>",
+            redact(ScrubPattern::BearerToken, "auth: bearer abc123")
+        );
+        assert_eq!(
+            "Basic dXNlcjpwYXNz",
+            redact(ScrubPattern::BearerToken, "Basic dXNlcjpwYXNz")
+        );
+    }
+
+    #[test]
+    fn redact_email() {
+        assert_eq!(
+            "sent to <redacted>",
+            redact(
+                ScrubPattern::Email,
+                "sent to jane.doe+test@mail.example.com"
+            )
+        );
+        assert_eq!(
+            "user@localhost",
+            redact(ScrubPattern::Email, "user@localhost")
+        );
+    }
+}
@@ -1,3 +1,2 @@
-PRAGMA journal_mode = wal;
-PRAGMA synchronous = normal;
+PRAGMA auto_vacuum = incremental;
 PRAGMA foreign_keys = on;
@@ -0,0 +1,4 @@
+SELECT processes.data, count(*) FROM spans
+JOIN processes ON processes.hash = spans.process
+WHERE spans.tenant = ?
+GROUP BY spans.process;
@@ -0,0 +1,3 @@
+SELECT data FROM logs
+WHERE tenant = ? AND trace_id IN rarray(?)
+ORDER BY timestamp;
@@ -0,0 +1 @@
+SELECT data FROM spans WHERE tenant = ? AND trace_id = ? AND span_id = ?;
@@ -1 +1,3 @@
-SELECT data FROM spans WHERE trace_id = ?;
+SELECT spans.data, processes.data FROM spans
+LEFT JOIN processes ON processes.hash = spans.process
+WHERE spans.tenant = ? AND trace_id = ?;
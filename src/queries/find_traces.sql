@@ -1,3 +1,4 @@
-SELECT data FROM spans
-WHERE trace_id IN rarray(?)
+SELECT spans.data, processes.data FROM spans
+LEFT JOIN processes ON processes.hash = spans.process
+WHERE spans.tenant = ? AND trace_id IN rarray(?)
 ORDER BY trace_id;
@@ -0,0 +1,3 @@
+SELECT spans.data, processes.data FROM spans
+LEFT JOIN processes ON processes.hash = spans.process
+WHERE spans.tenant = ? AND spans.trace_id IN rarray(?);
@@ -0,0 +1,4 @@
+SELECT tenant, trace_id, service, timestamp FROM traces
+WHERE timestamp < ?
+ORDER BY timestamp
+LIMIT ?;
@@ -0,0 +1,17 @@
+SELECT start, duration FROM spans
+WHERE tenant = :tenant
+    AND service = :service
+    AND operation = :operation
+    AND start >= :t_min
+    AND start <= :t_max
+UNION ALL
+-- Spans stored before their start was recorded fall back to the start of their trace.
+SELECT traces.timestamp, spans.duration FROM traces
+JOIN spans ON spans.trace_id = traces.trace_id AND spans.tenant = traces.tenant
+WHERE traces.tenant = :tenant
+    AND traces.service = :service
+    AND traces.timestamp >= :t_min
+    AND traces.timestamp <= :t_max
+    AND spans.start IS NULL
+    AND spans.operation = :operation
+    AND coalesce(spans.service, :service) = :service;
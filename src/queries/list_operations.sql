@@ -1 +1 @@
-SELECT DISTINCT operation FROM operations WHERE service = ?;
+SELECT tenant, service, operation, span_kind, last_seen FROM operations;
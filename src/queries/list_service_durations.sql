@@ -0,0 +1,5 @@
+SELECT timestamp, max_duration FROM traces
+WHERE tenant = :tenant
+    AND service = :service
+    AND timestamp >= :t_min
+    AND timestamp <= :t_max;
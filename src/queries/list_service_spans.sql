@@ -0,0 +1,19 @@
+SELECT spans.data, processes.data FROM spans
+LEFT JOIN processes ON processes.hash = spans.process
+WHERE spans.tenant = :tenant
+    AND spans.service IN rarray(:services)
+    AND spans.start >= :t_min
+    AND spans.start <= :t_max
+UNION ALL
+-- Spans stored before their service and start were recorded, can only be found by their trace.
+SELECT spans.data, processes.data FROM spans
+LEFT JOIN processes ON processes.hash = spans.process
+WHERE spans.tenant = :tenant
+    AND (spans.service IS NULL OR spans.start IS NULL)
+    AND spans.trace_id IN (
+        SELECT trace_id FROM traces
+        WHERE tenant = :tenant
+            AND service IN rarray(:services)
+            AND timestamp >= :t_min
+            AND timestamp <= :t_max
+    );
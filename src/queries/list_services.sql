@@ -1 +1 @@
-SELECT DISTINCT service FROM services;
+SELECT tenant, service, last_seen FROM services;
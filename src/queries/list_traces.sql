@@ -1,8 +1,65 @@
-SELECT trace_id FROM traces
-WHERE service = :service
-    AND timestamp >= :t_min
-    AND timestamp <= :t_max
-    AND (:d_min IS NULL OR max_duration >= :d_min)
-    AND (:d_max IS NULL OR min_duration <= :d_max)
-ORDER BY timestamp DESC
-LIMIT :limit;
+WITH matches AS (
+    SELECT trace_id, timestamp FROM traces
+    WHERE tenant = :tenant
+        AND service = :service
+        AND timestamp >= :t_min
+        AND timestamp <= :t_max
+        AND (:d_min IS NULL OR max_duration >= :d_min)
+        AND (:d_max IS NULL OR min_duration <= :d_max)
+        AND ((
+            :operation IS NULL AND :d_min IS NULL AND :d_max IS NULL AND :span_kind IS NULL
+        ) OR trace_id IN (
+            SELECT trace_id FROM spans
+            WHERE tenant = :tenant
+                -- Spans stored before the service was recorded have none, and match any service.
+                AND (:operation IS NULL OR (
+                    operation = :operation AND coalesce(service, :service) = :service
+                ))
+                AND (:d_min IS NULL OR duration >= :d_min)
+                AND (:d_max IS NULL OR duration <= :d_max)
+                AND (:span_kind IS NULL OR (
+                    kind = :span_kind AND coalesce(service, :service) = :service
+                ))
+        ))
+        AND (:tag_count = 0 OR trace_id IN (
+            SELECT tags.trace_id FROM (
+                SELECT trace_id, span_id,
+                    substr(tag, 1, instr(tag, '=') - 1) AS key,
+                    substr(tag, instr(tag, '=') + 1) AS value
+                FROM span_tags
+                WHERE span_tags MATCH :tag_query
+                    AND tenant = :tenant
+            ) AS tags
+            JOIN json_each(:tag_filters) AS filter ON filter.value ->> 'key' = tags.key
+            WHERE CASE filter.value ->> 'op'
+                WHEN '=' THEN tags.value = filter.value ->> 'value'
+                -- Numeric comparisons only apply to values that look like a number.
+                ELSE tags.value <> '' AND tags.value NOT GLOB '*[^0-9.eE+-]*' AND CASE filter.value ->> 'op'
+                    WHEN '<' THEN CAST(tags.value AS REAL) < filter.value ->> 'value'
+                    WHEN '<=' THEN CAST(tags.value AS REAL) <= filter.value ->> 'value'
+                    WHEN '>' THEN CAST(tags.value AS REAL) > filter.value ->> 'value'
+                    WHEN '>=' THEN CAST(tags.value AS REAL) >= filter.value ->> 'value'
+                END
+            END
+            GROUP BY tags.trace_id, tags.span_id
+            HAVING count(DISTINCT filter.key) = :tag_count
+        ))
+        AND (NOT :error OR trace_id IN (
+            SELECT trace_id FROM span_tags
+            WHERE span_tags MATCH '"error=true"'
+                AND tenant = :tenant
+                AND tag = 'error=true'
+        ))
+        AND (:min_spans IS NULL OR (
+            SELECT count(*) FROM spans
+            WHERE spans.tenant = traces.tenant AND spans.trace_id = traces.trace_id
+        ) >= :min_spans)
+),
+page AS (
+    SELECT trace_id, timestamp FROM matches
+    ORDER BY timestamp DESC
+    LIMIT :limit OFFSET :offset
+)
+SELECT (SELECT count(*) FROM matches), page.trace_id
+FROM (SELECT 1) LEFT JOIN page
+ORDER BY page.timestamp DESC;
@@ -0,0 +1,53 @@
+CREATE TABLE IF NOT EXISTS traces(
+    trace_id     BLOB    NOT NULL,
+    service      TEXT    NOT NULL,
+    timestamp    TEXT    NOT NULL,
+    min_duration INTEGER NOT NULL,
+    max_duration INTEGER NOT NULL,
+    PRIMARY KEY (trace_id, service)
+) STRICT, WITHOUT ROWID;
+
+CREATE TABLE IF NOT EXISTS spans(
+    trace_id  BLOB    NOT NULL,
+    span_id   BLOB    NOT NULL,
+    operation TEXT    NOT NULL,
+    duration  INTEGER NOT NULL DEFAULT 0,
+    process   BLOB,
+    data      BLOB    NOT NULL,
+    PRIMARY KEY (trace_id, span_id)
+) STRICT, WITHOUT ROWID;
+
+CREATE INDEX IF NOT EXISTS spans_operation_duration ON spans(operation, duration);
+
+CREATE TABLE IF NOT EXISTS processes(
+    hash BLOB NOT NULL,
+    data BLOB NOT NULL,
+    PRIMARY KEY (hash)
+) STRICT, WITHOUT ROWID;
+
+CREATE VIRTUAL TABLE IF NOT EXISTS span_tags USING fts5(
+    trace_id UNINDEXED,
+    span_id  UNINDEXED,
+    tag
+);
+
+CREATE TABLE IF NOT EXISTS logs(
+    timestamp TEXT NOT NULL,
+    trace_id  BLOB,
+    span_id   BLOB,
+    service   TEXT NOT NULL,
+    data      BLOB NOT NULL
+) STRICT;
+
+CREATE INDEX IF NOT EXISTS logs_trace_id ON logs(trace_id) WHERE trace_id IS NOT NULL;
+
+CREATE TABLE IF NOT EXISTS services(
+    service   TEXT NOT NULL,
+    PRIMARY KEY (service)
+) STRICT, WITHOUT ROWID;
+
+CREATE TABLE IF NOT EXISTS operations(
+    service   TEXT NOT NULL,
+    operation TEXT NOT NULL,
+    PRIMARY KEY (service, operation)
+) STRICT, WITHOUT ROWID;
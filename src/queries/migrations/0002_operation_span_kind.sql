@@ -0,0 +1,12 @@
+CREATE TABLE operations_new(
+    service   TEXT NOT NULL,
+    operation TEXT NOT NULL,
+    span_kind TEXT NOT NULL DEFAULT '',
+    PRIMARY KEY (service, operation, span_kind)
+) STRICT, WITHOUT ROWID;
+
+INSERT INTO operations_new (service, operation) SELECT service, operation FROM operations;
+
+DROP TABLE operations;
+
+ALTER TABLE operations_new RENAME TO operations;
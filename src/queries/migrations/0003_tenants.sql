@@ -0,0 +1,32 @@
+ALTER TABLE traces ADD COLUMN tenant TEXT NOT NULL DEFAULT '';
+ALTER TABLE spans ADD COLUMN tenant TEXT NOT NULL DEFAULT '';
+ALTER TABLE logs ADD COLUMN tenant TEXT NOT NULL DEFAULT '';
+
+CREATE INDEX traces_tenant_service_timestamp ON traces(tenant, service, timestamp);
+
+CREATE TABLE services_new(
+    tenant    TEXT NOT NULL DEFAULT '',
+    service   TEXT NOT NULL,
+    PRIMARY KEY (tenant, service)
+) STRICT, WITHOUT ROWID;
+
+INSERT INTO services_new (service) SELECT service FROM services;
+
+DROP TABLE services;
+
+ALTER TABLE services_new RENAME TO services;
+
+CREATE TABLE operations_new(
+    tenant    TEXT NOT NULL DEFAULT '',
+    service   TEXT NOT NULL,
+    operation TEXT NOT NULL,
+    span_kind TEXT NOT NULL DEFAULT '',
+    PRIMARY KEY (tenant, service, operation, span_kind)
+) STRICT, WITHOUT ROWID;
+
+INSERT INTO operations_new (service, operation, span_kind)
+SELECT service, operation, span_kind FROM operations;
+
+DROP TABLE operations;
+
+ALTER TABLE operations_new RENAME TO operations;
@@ -0,0 +1,12 @@
+ALTER TABLE services ADD COLUMN last_seen TEXT;
+ALTER TABLE operations ADD COLUMN last_seen TEXT;
+
+UPDATE services SET last_seen = (
+    SELECT max(timestamp) FROM traces
+    WHERE traces.tenant = services.tenant AND traces.service = services.service
+);
+
+UPDATE operations SET last_seen = (
+    SELECT last_seen FROM services
+    WHERE services.tenant = operations.tenant AND services.service = operations.service
+);
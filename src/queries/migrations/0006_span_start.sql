@@ -0,0 +1,4 @@
+ALTER TABLE spans ADD COLUMN start TEXT;
+
+CREATE INDEX spans_tenant_service_operation_start ON spans(tenant, service, operation, start)
+WHERE start IS NOT NULL;
@@ -0,0 +1,12 @@
+ALTER TABLE spans ADD COLUMN kind TEXT;
+
+-- Until now, the kind was only stored as tag.
+UPDATE spans SET kind = tags.kind
+FROM (
+    SELECT trace_id, span_id, substr(tag, length('span.kind=') + 1) AS kind FROM span_tags
+    WHERE span_tags MATCH '"span.kind"'
+        AND tag LIKE 'span.kind=%'
+) AS tags
+WHERE spans.trace_id = tags.trace_id
+    AND spans.span_id = tags.span_id
+    AND tags.kind IN ('client', 'server', 'producer', 'consumer', 'internal');
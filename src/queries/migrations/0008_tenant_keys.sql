@@ -0,0 +1,64 @@
+-- Tags follow the tenant of their span, so they are rebuilt first while the old spans key still
+-- allows an indexed lookup.
+CREATE VIRTUAL TABLE span_tags_new USING fts5(
+    tenant   UNINDEXED,
+    trace_id UNINDEXED,
+    span_id  UNINDEXED,
+    tag
+);
+
+INSERT INTO span_tags_new (tenant, trace_id, span_id, tag)
+SELECT spans.tenant, span_tags.trace_id, span_tags.span_id, span_tags.tag FROM span_tags
+JOIN spans ON spans.trace_id = span_tags.trace_id AND spans.span_id = span_tags.span_id;
+
+DROP TABLE span_tags;
+
+ALTER TABLE span_tags_new RENAME TO span_tags;
+
+CREATE TABLE traces_new(
+    tenant       TEXT    NOT NULL DEFAULT '',
+    trace_id     BLOB    NOT NULL,
+    service      TEXT    NOT NULL,
+    timestamp    TEXT    NOT NULL,
+    min_duration INTEGER NOT NULL,
+    max_duration INTEGER NOT NULL,
+    PRIMARY KEY (tenant, trace_id, service)
+) STRICT, WITHOUT ROWID;
+
+INSERT INTO traces_new (tenant, trace_id, service, timestamp, min_duration, max_duration)
+SELECT tenant, trace_id, service, timestamp, min_duration, max_duration FROM traces;
+
+DROP TABLE traces;
+
+ALTER TABLE traces_new RENAME TO traces;
+
+CREATE INDEX traces_tenant_service_timestamp ON traces(tenant, service, timestamp);
+
+CREATE TABLE spans_new(
+    tenant    TEXT    NOT NULL DEFAULT '',
+    trace_id  BLOB    NOT NULL,
+    span_id   BLOB    NOT NULL,
+    operation TEXT    NOT NULL,
+    duration  INTEGER NOT NULL DEFAULT 0,
+    process   BLOB,
+    data      BLOB    NOT NULL,
+    service   TEXT,
+    start     TEXT,
+    kind      TEXT,
+    PRIMARY KEY (tenant, trace_id, span_id)
+) STRICT, WITHOUT ROWID;
+
+INSERT INTO spans_new (
+    tenant, trace_id, span_id, operation, duration, process, data, service, start, kind
+)
+SELECT tenant, trace_id, span_id, operation, duration, process, data, service, start, kind
+FROM spans;
+
+DROP TABLE spans;
+
+ALTER TABLE spans_new RENAME TO spans;
+
+CREATE INDEX spans_operation_duration ON spans(operation, duration);
+
+CREATE INDEX spans_tenant_service_operation_start ON spans(tenant, service, operation, start)
+WHERE start IS NOT NULL;
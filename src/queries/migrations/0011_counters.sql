@@ -0,0 +1,30 @@
+-- Counting all spans and traces on every write is a full scan, so they're counted as they change.
+CREATE TABLE counters(
+    name  TEXT    NOT NULL,
+    value INTEGER NOT NULL,
+    PRIMARY KEY (name)
+) STRICT, WITHOUT ROWID;
+
+INSERT INTO counters (name, value)
+VALUES ('spans', (SELECT count(*) FROM spans)), ('traces', (SELECT count(*) FROM traces));
+
+CREATE TRIGGER spans_insert_count AFTER INSERT ON spans BEGIN
+    UPDATE counters SET value = value + 1 WHERE name = 'spans';
+END;
+
+CREATE TRIGGER spans_delete_count AFTER DELETE ON spans BEGIN
+    UPDATE counters SET value = value - 1 WHERE name = 'spans';
+END;
+
+CREATE TRIGGER traces_insert_count AFTER INSERT ON traces BEGIN
+    UPDATE counters SET value = value + 1 WHERE name = 'traces';
+END;
+
+CREATE TRIGGER traces_delete_count AFTER DELETE ON traces BEGIN
+    UPDATE counters SET value = value - 1 WHERE name = 'traces';
+END;
+
+-- Find the oldest traces, and the processes no longer used by any span, without a full scan.
+CREATE INDEX traces_timestamp ON traces(timestamp);
+
+CREATE INDEX spans_process ON spans(process) WHERE process IS NOT NULL;
@@ -0,0 +1 @@
+INSERT INTO logs (timestamp, trace_id, span_id, service, data, tenant) VALUES (?, ?, ?, ?, ?, ?);
@@ -1 +1,3 @@
-INSERT OR IGNORE INTO operations (service, operation) VALUES (?, ?);
+INSERT INTO operations (service, operation, span_kind, tenant, last_seen) VALUES (?, ?, ?, ?, ?)
+ON CONFLICT(tenant, service, operation, span_kind) DO UPDATE SET
+    last_seen = max(coalesce(last_seen, ''), excluded.last_seen);
@@ -0,0 +1 @@
+INSERT OR IGNORE INTO processes (hash, data) VALUES (?, ?);
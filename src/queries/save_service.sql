@@ -1 +1,3 @@
-INSERT OR IGNORE INTO services (service) VALUES (?);
+INSERT INTO services (service, tenant, last_seen) VALUES (?, ?, ?)
+ON CONFLICT(tenant, service) DO UPDATE SET
+    last_seen = max(coalesce(last_seen, ''), excluded.last_seen);
@@ -1 +1,2 @@
-INSERT INTO spans (trace_id, span_id, operation, data) VALUES (?, ?, ?, ?);
+INSERT INTO spans (trace_id, span_id, operation, duration, process, data, tenant, service, start, kind)
+VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?);
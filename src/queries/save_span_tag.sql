@@ -0,0 +1 @@
+INSERT INTO span_tags (tenant, trace_id, span_id, tag) VALUES (?, ?, ?, ?);
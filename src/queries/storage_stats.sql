@@ -0,0 +1,5 @@
+SELECT
+    (SELECT count(*) FROM spans WHERE tenant = :tenant),
+    (SELECT count(DISTINCT trace_id) FROM traces WHERE tenant = :tenant),
+    (SELECT min(timestamp) FROM traces WHERE tenant = :tenant),
+    (SELECT max(timestamp) FROM traces WHERE tenant = :tenant);
@@ -1,33 +1,87 @@
 use std::{
-    io::{Cursor, ErrorKind},
-    net::SocketAddr,
-    path::Path,
+    io::ErrorKind,
+    net::{IpAddr, SocketAddr},
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
     sync::Arc,
     time::Duration,
 };
 
 use anyhow::{bail, Context, Result};
-use quinn::{Connecting, ConnectionError, Endpoint, RecvStream, ServerConfig, VarInt};
-use rustls::{Certificate, PrivateKey};
-use tokio::fs;
+use quinn::{
+    Connecting, ConnectionError, Endpoint, ReadToEndError, RecvStream, SendStream, ServerConfig,
+    VarInt,
+};
+use rcgen::{CertificateParams, SanType};
+use rustls::{server::AllowAnyAuthenticatedClient, RootCertStore};
+use tokio::{fs, time};
 use tokio_shutdown::Shutdown;
-use tracing::{debug, error, info, instrument};
-use unidirs::{Directories, UnifiedDirs};
+use tracing::{debug, error, info, instrument, warn};
+
+use super::models::{BatchResponse, Compression, Handshake, HandshakeResponse};
+use crate::{
+    audit::Origin,
+    config, convert, ingest,
+    metrics::{self, PacketError, Receiver},
+    storage::{Database, StorageError},
+    tasks, tenancy, tls,
+};
 
-use crate::{convert, net, storage::Database};
+/// Maximum size of the handshake message.
+const MAX_HANDSHAKE_SIZE: usize = 16 * 1024;
+/// Time that clients have to send the handshake, after establishing the connection.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Protocol versions, that this server understands.
+const SUPPORTED_VERSIONS: RangeInclusive<u16> = 1..=3;
+/// Protocol of clients from before the handshake, which send each span on its own unidirectional
+/// stream, compressed with snappy.
+const LEGACY_VERSION: u16 = 0;
+/// First protocol version, in which batches are sent over bidirectional streams, and answered with
+/// a [`BatchResponse`] once they're persisted.
+const ACK_VERSION: u16 = 3;
+/// Maximum amount of batches, that a single client can send at the same time.
+const MAX_CONCURRENT_BATCHES: u8 = 100;
+/// Application error code, used when closing a connection due to a failed handshake.
+const HANDSHAKE_FAILED: u32 = 1;
 
 #[instrument(name = "quiver", skip_all)]
-pub async fn run(shutdown: Shutdown, database: Database) -> Result<()> {
-    let addr = SocketAddr::from(net::QUIVER_COLLECTOR);
-    let (config, cert) = load_config().await?;
+pub async fn run(
+    shutdown: Shutdown,
+    database: Database,
+    settings: config::Quiver,
+    addr: Option<SocketAddr>,
+) -> Result<()> {
+    let Some(addr) = addr else {
+        return Ok(());
+    };
+
+    let (config, cert) = load_config(&settings).await?;
     let endpoint = Endpoint::server(config, addr)?;
+    let auth_token = settings.auth_token.as_deref().map(Arc::<str>::from);
+    let limits = PayloadLimits {
+        request: settings.max_request_size,
+        decompressed: settings.max_decompressed_size,
+    };
 
     info!("listening on http://{}", endpoint.local_addr()?);
     info!("server certificate:\n{cert}");
 
+    let mut reload = Reload::new()?;
+
     loop {
         let conn = tokio::select! {
-            _ = shutdown.handle() => break,
+            () = shutdown.handle() => break,
+            () = reload.wait() => {
+                // Only new connections pick up the new configuration, established ones are kept.
+                match load_config(&settings).await {
+                    Ok((config, cert)) => {
+                        endpoint.set_server_config(Some(config));
+                        info!("reloaded server certificate:\n{cert}");
+                    }
+                    Err(e) => error!(error = ?e, "failed reloading server certificate"),
+                }
+                continue;
+            }
             conn = endpoint.accept() => match conn {
                 Some(conn) => conn,
                 None => break,
@@ -40,9 +94,10 @@ pub async fn run(shutdown: Shutdown, database: Database) -> Result<()> {
         );
 
         let database = database.clone();
+        let auth_token = auth_token.clone();
 
         tokio::spawn(async move {
-            if let Err(e) = handle_connection(conn, database).await {
+            if let Err(e) = handle_connection(conn, database, auth_token.as_deref(), limits).await {
                 error!(error = ?e, "failed handling connection");
             }
         });
@@ -51,44 +106,77 @@ pub async fn run(shutdown: Shutdown, database: Database) -> Result<()> {
     Ok(())
 }
 
-async fn load_config() -> Result<(ServerConfig, String)> {
-    let dirs = UnifiedDirs::simple("rocks", "dnaka91", env!("CARGO_PKG_NAME"))
-        .default()
-        .context("failed finding project directories")?;
-    let data_dir = dirs.data_dir().join("quiver");
+/// Trigger for reloading the server certificate, which happens on `SIGHUP`. This allows to rotate
+/// certificates without restarting and interrupting active connections.
+struct Reload {
+    #[cfg(unix)]
+    signal: tokio::signal::unix::Signal,
+}
 
-    let cert = load_file(data_dir.join("cert.pem")).await?;
-    let key = load_file(data_dir.join("key.pem")).await?;
+impl Reload {
+    #[cfg(unix)]
+    fn new() -> Result<Self> {
+        use tokio::signal::unix::{signal, SignalKind};
 
-    let (cert, key, cert_pem) = if let Some((cert_raw, key_raw)) = cert.zip(key) {
-        let cert = rustls_pemfile::read_one(&mut Cursor::new(&cert_raw))?
-            .map(|item| match item {
-                rustls_pemfile::Item::X509Certificate(cert) => Ok(cert),
-                _ => bail!("not a certificate"),
-            })
-            .context("empty data")??;
-        let key = rustls_pemfile::read_one(&mut Cursor::new(key_raw))?
-            .map(|item| match item {
-                rustls_pemfile::Item::PKCS8Key(key) => Ok(key),
-                _ => bail!("not a key"),
-            })
-            .context("empty data")??;
-        let cert_pem = String::from_utf8(cert_raw)?;
+        Ok(Self {
+            signal: signal(SignalKind::hangup())?,
+        })
+    }
 
-        (Certificate(cert), PrivateKey(key), cert_pem)
-    } else {
-        let (cert, cert_pem, key, key_pem) = generate_certificate()?;
-        fs::create_dir_all(&data_dir).await?;
-        fs::write(data_dir.join("cert.pem"), &cert_pem).await?;
-        fs::write(data_dir.join("key.pem"), key_pem).await?;
+    #[cfg(not(unix))]
+    #[allow(clippy::unnecessary_wraps)]
+    fn new() -> Result<Self> {
+        Ok(Self {})
+    }
 
-        (cert, key, cert_pem)
+    #[cfg(unix)]
+    async fn wait(&mut self) {
+        if self.signal.recv().await.is_none() {
+            std::future::pending::<()>().await;
+        }
+    }
+
+    #[cfg(not(unix))]
+    async fn wait(&mut self) {
+        std::future::pending::<()>().await;
+    }
+}
+
+async fn load_config(settings: &config::Quiver) -> Result<(ServerConfig, String)> {
+    let (cert, key) = match &settings.tls {
+        Some(tls) => (
+            fs::read(&tls.cert)
+                .await
+                .with_context(|| format!("failed reading certificate at {}", tls.cert.display()))?,
+            fs::read(&tls.key)
+                .await
+                .with_context(|| format!("failed reading key at {}", tls.key.display()))?,
+        ),
+        None => load_or_generate_certificate(settings).await?,
     };
 
-    let mut config = ServerConfig::with_single_cert(vec![cert], key)?;
+    let certs = tls::certs(&cert)?;
+    let key = tls::private_key(&key)?;
+    let cert_pem = String::from_utf8(cert)?;
+
+    let mut config = match settings.client_ca.as_deref() {
+        Some(path) => {
+            let roots = load_client_ca(path).await?;
+            let mut crypto = rustls::ServerConfig::builder()
+                .with_safe_default_cipher_suites()
+                .with_safe_default_kx_groups()
+                .with_protocol_versions(&[&rustls::version::TLS13])?
+                .with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots))
+                .with_single_cert(certs, key)?;
+            crypto.max_early_data_size = u32::MAX;
+
+            ServerConfig::with_crypto(Arc::new(crypto))
+        }
+        None => ServerConfig::with_single_cert(certs, key)?,
+    };
     Arc::get_mut(&mut config.transport)
         .context("failed getting mutable reference to server transport")?
-        .max_concurrent_bidi_streams(0_u8.into())
+        .max_concurrent_bidi_streams(MAX_CONCURRENT_BATCHES.into())
         .datagram_receive_buffer_size(None)
         .max_idle_timeout(Some(VarInt::from_u32(360_000).into()))
         .keep_alive_interval(Some(Duration::from_secs(30)));
@@ -96,6 +184,20 @@ async fn load_config() -> Result<(ServerConfig, String)> {
     Ok((config, cert_pem))
 }
 
+/// Load the CA certificates, that client certificates are verified against.
+async fn load_client_ca(path: &Path) -> Result<RootCertStore> {
+    let pem = fs::read(path)
+        .await
+        .with_context(|| format!("failed reading client CA at {}", path.display()))?;
+    let mut roots = RootCertStore::empty();
+
+    for cert in tls::certs(&pem)? {
+        roots.add(&cert)?;
+    }
+
+    Ok(roots)
+}
+
 async fn load_file(path: impl AsRef<Path>) -> Result<Option<Vec<u8>>> {
     match fs::read(path.as_ref()).await {
         Ok(buf) => Ok(Some(buf)),
@@ -104,24 +206,99 @@ async fn load_file(path: impl AsRef<Path>) -> Result<Option<Vec<u8>>> {
     }
 }
 
-fn generate_certificate() -> Result<(Certificate, String, PrivateKey, String)> {
-    let cert = rcgen::generate_simple_self_signed(["localhost".to_owned(), "archer".to_owned()])?;
+/// Directory, where the generated self-signed certificate and its key are stored.
+pub fn generated_certificate_dir(settings: &config::Quiver) -> PathBuf {
+    settings.data_dir.join("quiver")
+}
+
+/// Load the PEM encoded certificate and key from the data directory, or generate and store a
+/// new self-signed certificate, if there is none yet.
+async fn load_or_generate_certificate(settings: &config::Quiver) -> Result<(Vec<u8>, Vec<u8>)> {
+    let data_dir = generated_certificate_dir(settings);
+
+    let cert = load_file(data_dir.join("cert.pem")).await?;
+    let key = load_file(data_dir.join("key.pem")).await?;
+
+    if let Some(pair) = cert.zip(key) {
+        return Ok(pair);
+    }
+
+    let (cert_pem, key_pem) = generate_certificate(&settings.subject_alt_names)?;
+    fs::create_dir_all(&data_dir).await?;
+    fs::write(data_dir.join("cert.pem"), &cert_pem).await?;
+    fs::write(data_dir.join("key.pem"), &key_pem).await?;
+
+    Ok((cert_pem.into_bytes(), key_pem.into_bytes()))
+}
+
+fn generate_certificate(subject_alt_names: &[String]) -> Result<(String, String)> {
+    let mut params = CertificateParams::default();
+    params.subject_alt_names = if subject_alt_names.is_empty() {
+        vec![
+            SanType::DnsName("localhost".to_owned()),
+            SanType::DnsName("archer".to_owned()),
+        ]
+    } else {
+        subject_alt_names
+            .iter()
+            .map(|name| match name.parse::<IpAddr>() {
+                Ok(ip) => SanType::IpAddress(ip),
+                Err(_) => SanType::DnsName(name.clone()),
+            })
+            .collect()
+    };
+
+    let cert = rcgen::Certificate::from_params(params)?;
 
-    Ok((
-        Certificate(cert.serialize_der()?),
-        cert.serialize_pem()?,
-        PrivateKey(cert.serialize_private_key_der()),
-        cert.serialize_private_key_pem(),
-    ))
+    Ok((cert.serialize_pem()?, cert.serialize_private_key_pem()))
 }
 
-async fn handle_connection(conn: Connecting, database: Database) -> Result<()> {
+/// Maximum sizes of a single batch, before and after decompressing it.
+#[derive(Clone, Copy)]
+struct PayloadLimits {
+    request: usize,
+    decompressed: usize,
+}
+
+async fn handle_connection(
+    conn: Connecting,
+    database: Database,
+    auth_token: Option<&str>,
+    limits: PayloadLimits,
+) -> Result<()> {
     let connection = conn.await?;
 
     debug!(addr = %connection.remote_address(), "connection established");
 
+    let (version, compression, tenant, mut pending) =
+        match time::timeout(HANDSHAKE_TIMEOUT, handshake(&connection, auth_token)).await {
+            Ok(Ok(Some(accepted))) => accepted,
+            Ok(Ok(None)) => return Ok(()),
+            Ok(Err(e)) => {
+                connection.close(HANDSHAKE_FAILED.into(), b"handshake failed");
+                return Err(e);
+            }
+            Err(_) => {
+                warn!(addr = %connection.remote_address(), "client didn't send a handshake");
+                connection.close(HANDSHAKE_FAILED.into(), b"handshake required");
+                return Ok(());
+            }
+        };
+
+    let database = database.for_tenant(tenancy::resolve(tenant.as_deref()));
+
     loop {
-        let stream = match connection.accept_uni().await {
+        let stream = if let Some(recv) = pending.take() {
+            Ok((recv, None))
+        } else if version >= ACK_VERSION {
+            connection
+                .accept_bi()
+                .await
+                .map(|(send, recv)| (recv, Some(send)))
+        } else {
+            connection.accept_uni().await.map(|recv| (recv, None))
+        };
+        let (recv, send) = match stream {
             Err(ConnectionError::ApplicationClosed(_) | ConnectionError::TimedOut) => return Ok(()),
             Err(e) => return Err(e.into()),
             Ok(s) => s,
@@ -129,30 +306,230 @@ async fn handle_connection(conn: Connecting, database: Database) -> Result<()> {
 
         debug!(addr = %connection.remote_address(), "incoming request");
         let database = database.clone();
+        let peer = connection.remote_address();
 
-        tokio::spawn(async move {
-            if let Err(e) = handle_request(stream, database).await {
-                error!(error = ?e, "failed handling request");
+        tasks::spawn(async move {
+            let response =
+                match handle_request(recv, peer, database, version, compression, limits).await {
+                    Ok(response) => response,
+                    Err(e) => {
+                        error!(error = ?e, "failed handling request");
+                        BatchResponse::Failed {
+                            reason: format!("{e:#}"),
+                            retry: matches!(e.downcast_ref(), Some(StorageError::Busy(_))),
+                        }
+                    }
+                };
+
+            if let Some(send) = send {
+                if let Err(e) = respond(send, &response).await {
+                    warn!(error = ?e, "failed sending batch response");
+                }
             }
         });
     }
 }
 
-async fn handle_request(recv: RecvStream, database: Database) -> Result<()> {
+async fn respond(mut send: SendStream, response: &BatchResponse) -> Result<()> {
+    send.write_all(&rmp_serde::to_vec(response)?).await?;
+    send.finish().await?;
+
+    Ok(())
+}
+
+/// Receive the client's handshake and answer it with the negotiated settings. Returns the
+/// protocol version, compression and the client's tenant, or `None` if the client was rejected,
+/// in which case the connection is already closed.
+///
+/// Clients from before the handshake start sending spans right away. Their first request is
+/// returned as well, unless a token is required, which they have no way of sending.
+async fn handshake(
+    connection: &quinn::Connection,
+    auth_token: Option<&str>,
+) -> Result<Option<(u16, Compression, Option<String>, Option<RecvStream>)>> {
+    let (mut send, recv) = tokio::select! {
+        stream = connection.accept_bi() => stream?,
+        stream = connection.accept_uni() => {
+            let recv = stream?;
+            if auth_token.is_some() {
+                warn!(addr = %connection.remote_address(), "client sent data without a handshake");
+                connection.close(HANDSHAKE_FAILED.into(), b"handshake required");
+                return Ok(None);
+            }
+
+            debug!(addr = %connection.remote_address(), "client without handshake");
+            return Ok(Some((LEGACY_VERSION, Compression::Snappy, None, Some(recv))));
+        }
+    };
+
     let req = recv
-        .read_to_end(64 * 1024)
+        .read_to_end(MAX_HANDSHAKE_SIZE)
         .await
-        .context("failed reading request")?;
+        .context("failed reading handshake")?;
+    let handshake = rmp_serde::from_slice::<Handshake>(&req)?;
+
+    debug!(
+        addr = %connection.remote_address(),
+        service = %handshake.resource.service,
+        version = %handshake.resource.version,
+        "received handshake"
+    );
 
-    let raw = snap::raw::Decoder::new().decompress_vec(&req)?;
-    let span = rmp_serde::from_slice::<super::models::Span>(&raw)?;
-    let span = convert::span_from_quiver(span);
+    let response = negotiate(&handshake, auth_token);
 
-    tokio::spawn(async move {
-        if let Err(e) = database.save_spans(vec![span]).await {
-            error!(error = ?e, "failed to save spans to DB");
+    send.write_all(&rmp_serde::to_vec(&response)?).await?;
+    send.finish().await?;
+
+    match response {
+        HandshakeResponse::Accepted {
+            version,
+            compression,
+        } => Ok(Some((version, compression, handshake.tenant, None))),
+        HandshakeResponse::Rejected { reason } => {
+            warn!(addr = %connection.remote_address(), %reason, "rejected client");
+            connection.close(HANDSHAKE_FAILED.into(), reason.as_bytes());
+            Ok(None)
         }
-    });
+    }
+}
 
-    Ok(())
+/// Verify the client's token, if one is required, and pick the newest protocol version and the
+/// first known compression algorithm, that both sides support.
+fn negotiate(handshake: &Handshake, auth_token: Option<&str>) -> HandshakeResponse {
+    if let Some(expected) = auth_token {
+        if !handshake
+            .auth_token
+            .as_deref()
+            .is_some_and(|token| constant_time_eq(token, expected))
+        {
+            return HandshakeResponse::Rejected {
+                reason: "invalid or missing auth token".to_owned(),
+            };
+        }
+    }
+
+    let version = handshake.max_version.min(*SUPPORTED_VERSIONS.end());
+
+    if version < handshake.min_version || !SUPPORTED_VERSIONS.contains(&version) {
+        return HandshakeResponse::Rejected {
+            reason: format!(
+                "unsupported protocol version {}..={}, server supports {}..={}",
+                handshake.min_version,
+                handshake.max_version,
+                SUPPORTED_VERSIONS.start(),
+                SUPPORTED_VERSIONS.end()
+            ),
+        };
+    }
+
+    let Some(compression) = handshake
+        .compression
+        .iter()
+        .copied()
+        .find(|c| *c != Compression::Unknown)
+    else {
+        return HandshakeResponse::Rejected {
+            reason: "no supported compression algorithm".to_owned(),
+        };
+    };
+
+    HandshakeResponse::Accepted {
+        version,
+        compression,
+    }
+}
+
+/// Compare two strings without exiting early on the first difference, so the time taken doesn't
+/// reveal how much of a token was guessed correctly.
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+/// Decode and store a single batch of spans. Only once the spans are persisted, the batch is
+/// confirmed as stored.
+async fn handle_request(
+    recv: RecvStream,
+    peer: SocketAddr,
+    database: Database,
+    version: u16,
+    compression: Compression,
+    limits: PayloadLimits,
+) -> Result<BatchResponse> {
+    let req = match recv.read_to_end(limits.request).await {
+        Ok(req) => req,
+        Err(ReadToEndError::TooLong) => {
+            metrics::batch_rejected(PacketError::TooLarge);
+            bail!(
+                "request exceeds the maximum size of {} bytes",
+                limits.request
+            );
+        }
+        Err(e) => return Err(e).context("failed reading request"),
+    };
+
+    let raw = match compression {
+        Compression::None => req,
+        Compression::Snappy => decompress_snappy(&req, limits.decompressed)?,
+        Compression::Unknown => bail!("unknown compression"),
+    };
+    let spans = if version == LEGACY_VERSION {
+        rmp_serde::from_slice::<super::models::Span>(&raw).map(|span| vec![span])
+    } else {
+        rmp_serde::from_slice::<Vec<super::models::Span>>(&raw)
+    }
+    .inspect_err(|_| metrics::batch_rejected(PacketError::Malformed))?;
+    let count = spans.len();
+
+    metrics::spans_received(Receiver::Quiver, count);
+
+    let spans = spans
+        .into_iter()
+        .map(convert::span_from_quiver)
+        .collect::<Vec<_>>();
+    let origin = Origin {
+        receiver: Receiver::Quiver,
+        peer: Some(peer),
+        bytes: Some(raw.len()),
+    };
+
+    let spans = match ingest::prepare(database.tenant(), origin, count, spans) {
+        Ok(spans) => spans,
+        Err(e) => {
+            warn!(error = ?e, "dropping spans");
+            return Ok(BatchResponse::Failed {
+                reason: e.to_string(),
+                retry: false,
+            });
+        }
+    };
+
+    let dropped = u32::try_from(count - spans.len()).unwrap_or(u32::MAX);
+    database
+        .save_spans(spans)
+        .await
+        .context("failed to save spans to DB")?;
+
+    Ok(BatchResponse::Stored { dropped })
+}
+
+/// Decompress the snappy compressed request, but only if its decompressed size, which is stored in
+/// the header, is within the limit. Otherwise, a tiny request could make the server allocate huge
+/// amounts of memory.
+fn decompress_snappy(req: &[u8], limit: usize) -> Result<Vec<u8>> {
+    let len = snap::raw::decompress_len(req)
+        .inspect_err(|_| metrics::batch_rejected(PacketError::Malformed))?;
+
+    if len > limit {
+        metrics::batch_rejected(PacketError::DecompressedTooLarge);
+        bail!("decompressed request of {len} bytes exceeds the maximum size of {limit} bytes");
+    }
+
+    snap::raw::Decoder::new()
+        .decompress_vec(req)
+        .inspect_err(|_| metrics::batch_rejected(PacketError::Malformed))
+        .map_err(Into::into)
 }
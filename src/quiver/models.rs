@@ -1,6 +1,6 @@
 use std::num::{NonZeroU128, NonZeroU64};
 
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use time::{Duration, OffsetDateTime};
 
 #[derive(Debug, Deserialize)]
@@ -18,6 +18,15 @@ pub struct Span {
     pub tags: Vec<Tag>,
     pub logs: Vec<Log>,
     pub process: Process,
+    /// Only sent by clients since protocol version 2.
+    #[serde(default)]
+    pub status: Option<Status>,
+}
+
+#[derive(Debug, Deserialize)]
+pub enum Status {
+    Ok,
+    Error { message: String },
 }
 
 #[derive(Debug, Deserialize)]
@@ -93,3 +102,60 @@ pub struct Thread {
     pub id: u64,
     pub name: String,
 }
+
+/// First message of each connection, sent by the client over a bidirectional stream, before any
+/// spans are sent.
+#[derive(Debug, Deserialize)]
+pub struct Handshake {
+    /// Oldest protocol version, that the client is able to speak.
+    pub min_version: u16,
+    /// Newest protocol version, that the client is able to speak.
+    pub max_version: u16,
+    /// Supported compression algorithms for span batches, in order of preference.
+    pub compression: Vec<Compression>,
+    /// Information about the application on the client side.
+    pub resource: Resource,
+    /// Pre-shared token to authenticate the client, if the server requires one.
+    #[serde(default)]
+    pub auth_token: Option<String>,
+    /// Tenant that all spans of this connection belong to, if the server separates data by
+    /// tenant.
+    #[serde(default)]
+    pub tenant: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum Compression {
+    None,
+    Snappy,
+    /// Any algorithm unknown to this server, sent by newer clients.
+    #[serde(other)]
+    Unknown,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Resource {
+    pub service: String,
+    pub version: String,
+}
+
+/// Answer to the client's [`Handshake`], with the negotiated connection settings.
+#[derive(Debug, Serialize)]
+pub enum HandshakeResponse {
+    Accepted {
+        version: u16,
+        compression: Compression,
+    },
+    Rejected {
+        reason: String,
+    },
+}
+
+/// Answer to each batch of spans, sent since protocol version 3 once the batch was handled.
+#[derive(Debug, Serialize)]
+pub enum BatchResponse {
+    /// The spans were persisted, except for the `dropped` ones that exceeded the limits.
+    Stored { dropped: u32 },
+    /// None of the spans were persisted. If `retry` is set, the failure is only temporary.
+    Failed { reason: String, retry: bool },
+}
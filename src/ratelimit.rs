@@ -0,0 +1,169 @@
+//! Per-service limit for the amount of ingested spans, shared by all collectors. Each service has
+//! a budget of spans per second, and spans from a service that exhausted its budget are dropped,
+//! until it recovered again.
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use once_cell::sync::OnceCell;
+
+use crate::{
+    config,
+    metrics::{self, DropReason},
+    models::Span,
+};
+
+static LIMITER: OnceCell<Limiter> = OnceCell::new();
+
+/// Time between two sweeps for buckets that filled up again. A full bucket is no different from a
+/// new one, so removing it loses nothing, but keeps services that stopped sending from piling up.
+const SWEEP_INTERVAL: Duration = Duration::from_mins(1);
+
+/// Enable rate limiting with the given settings. Without calling this, all spans are accepted.
+pub fn init(config: Option<config::RateLimit>) -> Result<()> {
+    let Some(config) = config else {
+        return Ok(());
+    };
+
+    LIMITER
+        .set(Limiter {
+            config,
+            state: Mutex::new(State {
+                buckets: HashMap::new(),
+                last_sweep: Instant::now(),
+            }),
+        })
+        .map_err(|_| anyhow!("rate limiter can only be initialized once"))
+}
+
+/// Error for batches that were rejected, because all of their services exceeded their budget.
+#[derive(Debug, thiserror::Error)]
+#[error("rate limit exceeded for service `{0}`")]
+pub struct Exhausted(String);
+
+/// Check whether the spans fit into the budget of their services, and count them against it. Spans
+/// of services without any budget left are removed and counted as dropped, and the amount of them
+/// is returned. The batch is rejected as a whole if none of its services has any budget left.
+pub fn check(spans: &mut Vec<Span>) -> Result<usize, Exhausted> {
+    let Some(limiter) = LIMITER.get() else {
+        return Ok(0);
+    };
+
+    let result = limiter.acquire(spans);
+    match result {
+        Ok(0) => {}
+        Ok(dropped) => metrics::spans_dropped(DropReason::RateLimited, dropped),
+        Err(_) => metrics::spans_dropped(DropReason::RateLimited, spans.len()),
+    }
+
+    result
+}
+
+struct Limiter {
+    config: config::RateLimit,
+    state: Mutex<State>,
+}
+
+struct State {
+    buckets: HashMap<String, Bucket>,
+    last_sweep: Instant,
+}
+
+impl Limiter {
+    #[allow(clippy::cast_precision_loss)]
+    fn acquire(&self, spans: &mut Vec<Span>) -> Result<usize, Exhausted> {
+        let now = Instant::now();
+        let mut state = self
+            .state
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+
+        if now.duration_since(state.last_sweep) >= SWEEP_INTERVAL {
+            state
+                .buckets
+                .retain(|service, bucket| !bucket.is_full(self.rate(service), now));
+            state.last_sweep = now;
+        }
+
+        let mut counts = HashMap::<&str, usize>::new();
+        for span in spans.iter() {
+            *counts.entry(&span.process.service).or_default() += 1;
+        }
+
+        let mut exhausted = HashSet::new();
+
+        for service in counts.keys() {
+            let rate = self.rate(service);
+            let bucket = state
+                .buckets
+                .entry((*service).to_owned())
+                .or_insert_with(|| Bucket::new(rate, now));
+
+            if !bucket.refill(rate, now) {
+                exhausted.insert((*service).to_owned());
+            }
+        }
+
+        if exhausted.len() == counts.len() {
+            if let Some(service) = exhausted.iter().next() {
+                return Err(Exhausted(service.clone()));
+            }
+        }
+
+        for (service, count) in counts {
+            if let Some(bucket) = state.buckets.get_mut(service) {
+                if !exhausted.contains(service) {
+                    bucket.tokens -= count as f64;
+                }
+            }
+        }
+
+        let before = spans.len();
+        spans.retain(|span| !exhausted.contains(&span.process.service));
+
+        Ok(before - spans.len())
+    }
+
+    fn rate(&self, service: &str) -> f64 {
+        f64::from(
+            self.config
+                .services
+                .get(service)
+                .copied()
+                .unwrap_or(self.config.spans_per_second),
+        )
+    }
+}
+
+/// Token bucket of a single service. The tokens may become negative, when a batch is larger than
+/// the remaining budget, so large batches are accepted but delay the following ones.
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+}
+
+impl Bucket {
+    fn new(rate: f64, now: Instant) -> Self {
+        Self {
+            tokens: rate,
+            last: now,
+        }
+    }
+
+    /// Add the tokens that accumulated since the last refill, and report whether any budget is
+    /// available.
+    fn refill(&mut self, rate: f64, now: Instant) -> bool {
+        self.tokens = (self.tokens + now.duration_since(self.last).as_secs_f64() * rate).min(rate);
+        self.last = now;
+        self.tokens > 0.0
+    }
+
+    /// Whether the bucket would be completely refilled at the given time.
+    fn is_full(&self, rate: f64, now: Instant) -> bool {
+        self.tokens + now.duration_since(self.last).as_secs_f64() * rate >= rate
+    }
+}
@@ -1,142 +1,781 @@
-use std::{collections::HashMap, rc::Rc, sync::Arc};
+use std::{
+    borrow::Cow,
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
+    hash::Hasher,
+    num::NonZeroU64,
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::Arc,
+    time::Instant,
+};
 
-use anyhow::{anyhow, Context, Result};
-use once_cell::sync::OnceCell;
-use rusqlite::{named_params, params, types::Value, Connection, OpenFlags};
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use rusqlite::{
+    named_params, params, types::Value, Connection, InterruptHandle, OpenFlags, OptionalExtension,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use siphasher::sip128::{Hasher128, SipHasher13};
 use time::{Duration, OffsetDateTime};
-use tokio::sync::Mutex;
+use tokio::sync::{broadcast, Mutex};
+use tokio_shutdown::Shutdown;
 use tracing::instrument;
-use unidirs::{Directories, UnifiedDirs, Utf8Path, Utf8PathBuf};
 
-use crate::models::{Span, TagValue, TraceId};
+pub use self::{
+    cold::ColdStore,
+    error::StorageError,
+    live::{Received, StoredSpans, Subscription},
+};
+use self::{
+    cold::{ColdRow, ColdTrace},
+    index::{ServiceIndex, Services},
+};
+use crate::{
+    config,
+    metrics::{self, DropReason},
+    models::{LogRecord, Process, Span, SpanId, SpanKind, TagValue, TraceId},
+};
+
+mod cold;
+mod error;
+mod index;
+mod live;
 
 const BASIC_OPEN_FLAGS: OpenFlags = OpenFlags::SQLITE_OPEN_NO_MUTEX
     .union(OpenFlags::SQLITE_OPEN_PRIVATE_CACHE)
     .union(OpenFlags::SQLITE_OPEN_EXRESCODE);
 
+/// Tenant of all data that doesn't explicitly belong to one.
+pub const DEFAULT_TENANT: &str = "";
+
 #[derive(Clone)]
-pub struct Database(Arc<Mutex<Connection>>);
+pub struct Database {
+    conn: Arc<Mutex<Connection>>,
+    codec: Codec,
+    last_write: Arc<std::sync::Mutex<Instant>>,
+    tenant: Arc<str>,
+    /// Upper limit of stored spans, after which the oldest traces are removed.
+    max_spans: Option<u64>,
+    /// Services and operations, shared with the [`ReadOnlyDatabase`].
+    index: Arc<ServiceIndex>,
+    /// Broadcast of all stored spans, for live subscribers.
+    live: broadcast::Sender<Arc<StoredSpans>>,
+}
 
-pub async fn init() -> Result<Database> {
-    let conn = tokio::task::spawn_blocking(|| {
-        let mut conn = Connection::open_with_flags(
-            get_db_path()?,
+pub async fn init(config: &config::Storage) -> Result<Database> {
+    let path = db_path(config);
+    let settings = config.sqlite;
+    let (conn, index) = tokio::task::spawn_blocking(move || {
+        if let Some(dir) = path.as_deref().and_then(Path::parent) {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("failed creating data directory {}", dir.display()))?;
+        }
+
+        let mut conn = open(
+            path.as_deref(),
             BASIC_OPEN_FLAGS
                 .union(OpenFlags::SQLITE_OPEN_READ_WRITE)
                 .union(OpenFlags::SQLITE_OPEN_CREATE),
         )?;
 
         conn.trace(Some(|sql| tracing::trace!("{sql}")));
+        rusqlite::vtab::array::load_module(&conn)?;
         conn.execute_batch(include_str!("queries/00_pragmas.sql"))?;
-        conn.execute_batch(include_str!("queries/01_create.sql"))?;
+        conn.pragma_update(None, "journal_mode", settings.journal_mode.as_str())?;
+        conn.pragma_update(None, "synchronous", settings.synchronous.as_str())?;
+        apply_connection_settings(&conn, settings)?;
+        migrate(&mut conn)?;
+        let index = ServiceIndex::load(&conn)?;
 
-        anyhow::Ok(conn)
+        anyhow::Ok((conn, index))
     })
     .await??;
 
-    Ok(Database(Arc::new(Mutex::new(conn))))
+    Ok(Database {
+        conn: Arc::new(Mutex::new(conn)),
+        codec: match config.compression {
+            config::Compression::Snappy => Codec::Snappy,
+            config::Compression::Zstd => Codec::Zstd(config.zstd_level),
+        },
+        last_write: Arc::new(std::sync::Mutex::new(Instant::now())),
+        tenant: DEFAULT_TENANT.into(),
+        max_spans: match config.backend {
+            config::Backend::Sqlite => None,
+            config::Backend::Memory { max_spans } => Some(max_spans),
+        },
+        index: Arc::new(index),
+        live: live::channel(),
+    })
 }
 
-#[derive(Clone)]
-pub struct ReadOnlyDatabase(Arc<Mutex<Connection>>);
+/// Schema migrations, that are applied in order. The version of a database is the amount of
+/// migrations applied to it, which is tracked in the `user_version` pragma.
+///
+/// Migrations must never be changed once released. Instead, a new migration is appended.
+const MIGRATIONS: &[Migration] = &[
+    Migration::Sql(include_str!("queries/migrations/0001_create.sql")),
+    Migration::Sql(include_str!(
+        "queries/migrations/0002_operation_span_kind.sql"
+    )),
+    Migration::Sql(include_str!("queries/migrations/0003_tenants.sql")),
+    Migration::Sql(include_str!("queries/migrations/0004_last_seen.sql")),
+    Migration::Sql(include_str!("queries/migrations/0005_span_service.sql")),
+    Migration::Sql(include_str!("queries/migrations/0006_span_start.sql")),
+    Migration::Sql(include_str!("queries/migrations/0007_span_kind.sql")),
+    Migration::Sql(include_str!("queries/migrations/0008_tenant_keys.sql")),
+    Migration::Code(backfill_span_tags),
+    Migration::Code(backfill_span_durations),
+    Migration::Sql(include_str!("queries/migrations/0011_counters.sql")),
+];
 
-pub async fn init_readonly() -> Result<ReadOnlyDatabase> {
-    let conn = tokio::task::spawn_blocking(|| {
-        let mut conn = Connection::open_with_flags(
-            get_db_path()?,
-            BASIC_OPEN_FLAGS.union(OpenFlags::SQLITE_OPEN_READ_ONLY),
+enum Migration {
+    /// Schema change, or data change that can be expressed in SQL.
+    Sql(&'static str),
+    /// Data change, that needs to decode the stored spans.
+    Code(fn(&Connection) -> Result<()>),
+}
+
+/// Bring the database schema to the latest version, by applying all missing migrations.
+fn migrate(conn: &mut Connection) -> Result<()> {
+    let version = conn.pragma_query_value(None, "user_version", |row| row.get::<_, usize>(0))?;
+    ensure!(
+        version <= MIGRATIONS.len(),
+        "database schema version {version} is newer than the latest known version {}",
+        MIGRATIONS.len()
+    );
+
+    let tx = conn.transaction()?;
+
+    if version == 0 {
+        add_span_columns(&tx)?;
+    }
+
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        match migration {
+            Migration::Sql(sql) => tx.execute_batch(sql).map_err(Into::into),
+            Migration::Code(f) => f(&tx),
+        }
+        .with_context(|| format!("failed applying migration {}", i + 1))?;
+        tx.pragma_update(None, "user_version", i + 1)?;
+        tracing::info!(version = i + 1, "applied database migration");
+    }
+
+    tx.commit().map_err(Into::into)
+}
+
+/// Databases created before the schema was versioned may lack some of the span columns, which
+/// must be added before the first migration creates indexes on them.
+fn add_span_columns(conn: &Connection) -> Result<()> {
+    for (column, definition) in [
+        ("duration", "INTEGER NOT NULL DEFAULT 0"),
+        ("process", "BLOB"),
+    ] {
+        let (columns, found) = conn.query_row(
+            "SELECT count(*), count(*) FILTER (WHERE name = ?) FROM pragma_table_info('spans')",
+            [column],
+            |row| Ok((row.get::<_, u32>(0)?, row.get::<_, u32>(1)?)),
         )?;
 
-        conn.trace(Some(|sql| tracing::trace!("{sql}")));
-        rusqlite::vtab::array::load_module(&conn)?;
+        if columns > 0 && found == 0 {
+            conn.execute_batch(&format!(
+                "ALTER TABLE spans ADD COLUMN {column} {definition}"
+            ))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Databases created before the schema was versioned have no tags of their spans, which makes
+/// them invisible to tag searches. The kind was only derived from these tags as well.
+fn backfill_span_tags(conn: &Connection) -> Result<()> {
+    let tagged = conn
+        .prepare("SELECT DISTINCT tenant, trace_id, span_id FROM span_tags")?
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, Vec<u8>>(1)?,
+                row.get::<_, Vec<u8>>(2)?,
+            ))
+        })?
+        .collect::<Result<HashSet<_>, _>>()?;
+
+    let mut kinds = Vec::new();
+    let mut stmt = conn.prepare(
+        "SELECT spans.tenant, spans.data, processes.data FROM spans \
+         LEFT JOIN processes ON processes.hash = spans.process",
+    )?;
+    let mut rows = stmt.query([])?;
+
+    while let Some(row) = rows.next()? {
+        let tenant = row.get::<_, String>(0)?;
+        let span = decode_span(row.get(1)?, row.get(2)?).context("failed decoding span")?;
+        let key = (
+            tenant,
+            span.trace_id.to_bytes().to_vec(),
+            span.span_id.to_bytes().to_vec(),
+        );
+
+        if !tagged.contains(&key) {
+            save_span_tags(conn, &key.0, &span)?;
+            if let Some(kind) = span.kind {
+                kinds.push((key, kind));
+            }
+        }
+    }
+
+    let mut stmt = conn.prepare(
+        "UPDATE spans SET kind = ? WHERE tenant = ? AND trace_id = ? AND span_id = ? \
+         AND kind IS NULL",
+    )?;
+    for ((tenant, trace_id, span_id), kind) in kinds {
+        stmt.execute(params![kind.as_str(), tenant, trace_id, span_id])?;
+    }
+
+    Ok(())
+}
+
+/// The duration column of spans, stored before the schema was versioned, defaults to zero, which
+/// excludes them from searches by minimum duration.
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn backfill_span_durations(conn: &Connection) -> Result<()> {
+    let mut durations = Vec::new();
+    let mut stmt = conn.prepare("SELECT tenant, data FROM spans WHERE duration = 0")?;
+    let mut rows = stmt.query([])?;
+
+    while let Some(row) = rows.next()? {
+        let tenant = row.get::<_, String>(0)?;
+        let span = decode::<Span>(row.get(1)?).context("failed decoding span")?;
+
+        if span.duration.is_positive() {
+            durations.push((
+                tenant,
+                span.trace_id.to_bytes(),
+                span.span_id.to_bytes(),
+                span.duration.whole_microseconds() as u64,
+            ));
+        }
+    }
+
+    let mut stmt = conn.prepare(
+        "UPDATE spans SET duration = ? WHERE tenant = ? AND trace_id = ? AND span_id = ?",
+    )?;
+    for (tenant, trace_id, span_id, duration) in durations {
+        stmt.execute(params![duration, tenant, trace_id, span_id])?;
+    }
+
+    Ok(())
+}
+
+#[derive(Clone)]
+pub struct ReadOnlyDatabase {
+    conn: Arc<Mutex<Connection>>,
+    tenant: Arc<str>,
+    /// Location of the database file, or nothing if it's in memory.
+    path: Option<PathBuf>,
+    /// Archive of old traces, that are no longer in the database.
+    cold: Option<ColdStore>,
+    index: Arc<ServiceIndex>,
+}
 
-        anyhow::Ok(conn)
+/// Open a read-only connection to the database. It shares the service index with the given
+/// writable one, or loads its own if there is none, in which case the database must already exist
+/// and be fully migrated, as it can't be modified.
+pub async fn init_readonly(
+    config: &config::Storage,
+    database: Option<&Database>,
+) -> Result<ReadOnlyDatabase> {
+    let path = db_path(config);
+    ensure!(
+        database.is_some() || path.is_some(),
+        "the in-memory database can't be opened read-only, as it's always empty"
+    );
+
+    let settings = config.sqlite;
+    let shared = database.map(|database| Arc::clone(&database.index));
+    let (conn, index) = tokio::task::spawn_blocking({
+        let path = path.clone();
+        move || {
+            let mut conn = open(
+                path.as_deref(),
+                BASIC_OPEN_FLAGS.union(OpenFlags::SQLITE_OPEN_READ_ONLY),
+            )?;
+
+            conn.trace(Some(|sql| tracing::trace!("{sql}")));
+            apply_connection_settings(&conn, settings)?;
+            rusqlite::vtab::array::load_module(&conn)?;
+
+            let index = if let Some(index) = shared {
+                index
+            } else {
+                check_version(&conn)?;
+                Arc::new(ServiceIndex::load(&conn)?)
+            };
+
+            anyhow::Ok((conn, index))
+        }
     })
     .await??;
 
-    Ok(ReadOnlyDatabase(Arc::new(Mutex::new(conn))))
+    Ok(ReadOnlyDatabase {
+        conn: Arc::new(Mutex::new(conn)),
+        tenant: DEFAULT_TENANT.into(),
+        path,
+        cold: config
+            .cold_tier
+            .as_ref()
+            .map(|cold| ColdStore::open(cold, &config.data_dir))
+            .transpose()?,
+        index,
+    })
 }
 
-fn get_db_path() -> Result<&'static Utf8Path> {
-    static PATH: OnceCell<Utf8PathBuf> = OnceCell::new();
+/// Interval in which a standalone [`ReadOnlyDatabase`] reloads its service index.
+const INDEX_REFRESH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
 
-    let path = PATH.get_or_try_init(|| {
-        let dirs = UnifiedDirs::simple("rocks", "dnaka91", env!("CARGO_PKG_NAME"))
-            .default()
-            .context("failed finding project directories")?;
-        let data_dir = dirs.data_dir();
+/// Periodically reload the service index of a read-only database that doesn't share it with a
+/// writable one, so services and operations stored by another Archer instance show up.
+#[instrument(name = "index", skip_all)]
+pub async fn refresh_index(shutdown: Shutdown, db: ReadOnlyDatabase) -> Result<()> {
+    let mut interval = tokio::time::interval_at(
+        tokio::time::Instant::now() + INDEX_REFRESH_INTERVAL,
+        INDEX_REFRESH_INTERVAL,
+    );
 
-        std::fs::create_dir_all(data_dir)?;
+    loop {
+        tokio::select! {
+            () = shutdown.handle() => break,
+            _ = interval.tick() => {}
+        }
 
-        anyhow::Ok(data_dir.join("db.sqlite3"))
-    })?;
+        let index = Arc::clone(&db.index);
+        if let Err(e) = interact(&db.conn, move |conn| index.reload(conn)).await {
+            tracing::error!(error = ?e, "failed reloading the service index");
+        }
+    }
+
+    Ok(())
+}
+
+/// Ensure the database has the latest schema, without migrating it.
+fn check_version(conn: &Connection) -> Result<()> {
+    let version = conn.pragma_query_value(None, "user_version", |row| row.get::<_, usize>(0))?;
+    ensure!(
+        version == MIGRATIONS.len(),
+        "database schema version {version} doesn't match the expected version {}, start Archer \
+         once without `--read-only` to migrate it",
+        MIGRATIONS.len()
+    );
 
-    Ok(path)
+    Ok(())
 }
 
-async fn interact<F, T, E>(conn: &Arc<Mutex<Connection>>, f: F) -> Result<T>
+/// Run the integrity check of `SQLite` on the database, without modifying it. Returns the found
+/// problems, which are empty for a healthy database. An in-memory database is always healthy, as
+/// it only exists while Archer is running.
+pub async fn check_integrity(config: &config::Storage) -> Result<Vec<String>> {
+    let Some(path) = db_path(config) else {
+        return Ok(Vec::new());
+    };
+
+    let problems = tokio::task::spawn_blocking(move || {
+        let conn = open(
+            Some(&path),
+            BASIC_OPEN_FLAGS.union(OpenFlags::SQLITE_OPEN_READ_ONLY),
+        )
+        .context("failed opening database")?;
+
+        let problems = conn
+            .prepare("PRAGMA integrity_check")?
+            .query_map([], |row| row.get::<_, String>(0))?
+            .filter(|row| !matches!(row.as_deref(), Ok("ok")))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        anyhow::Ok(problems)
+    })
+    .await??;
+
+    Ok(problems)
+}
+
+/// Apply the settings that are local to a single connection, rather than stored in the database.
+fn apply_connection_settings(conn: &Connection, settings: config::Sqlite) -> Result<()> {
+    conn.pragma_update(None, "mmap_size", settings.mmap_size)?;
+    conn.pragma_update(None, "cache_size", settings.cache_size)?;
+    conn.busy_timeout(std::time::Duration::from_millis(settings.busy_timeout))?;
+
+    Ok(())
+}
+
+/// Location of the database file, or nothing for the in-memory database.
+fn db_path(config: &config::Storage) -> Option<PathBuf> {
+    match config.backend {
+        config::Backend::Sqlite => Some(config.data_dir.join("db.sqlite3")),
+        config::Backend::Memory { .. } => None,
+    }
+}
+
+fn open(path: Option<&Path>, flags: OpenFlags) -> Result<Connection> {
+    match path {
+        Some(path) => Connection::open_with_flags(path, flags),
+        // All connections to the same `memdb` database share it, and it only disappears once
+        // the last connection is closed.
+        None => Connection::open_with_flags(
+            "file:/archer?vfs=memdb",
+            flags.union(OpenFlags::SQLITE_OPEN_URI),
+        ),
+    }
+    .map_err(Into::into)
+}
+
+async fn interact<F, T, E>(conn: &Arc<Mutex<Connection>>, f: F) -> Result<T, StorageError>
 where
     F: FnOnce(&mut Connection) -> Result<T, E> + Send + 'static,
     T: Send + 'static,
     E: Into<anyhow::Error> + Send + Sync + 'static,
 {
     let mut conn = Arc::clone(conn).lock_owned().await;
+    let handle = conn.get_interrupt_handle();
 
-    tokio::task::spawn_blocking(move || f(&mut conn))
-        .await
-        .map_err(|e| anyhow!("{e}"))?
-        .map_err(Into::into)
+    // The connection is handed back, so it's only released after the interrupt is disarmed.
+    // Otherwise, a late interrupt could hit the query of the next caller.
+    let task = tokio::task::spawn_blocking(move || {
+        let result = f(&mut conn);
+        (conn, result)
+    });
+    // Declared after the task, so it's dropped first when the caller gives up, for example on a
+    // timeout, while the connection is still held by the task.
+    let mut interrupt = Interrupt(Some(handle));
+
+    let (conn, result) = task.await.map_err(|e| StorageError::Io(e.into()))?;
+    interrupt.0 = None;
+    drop(conn);
+
+    result.map_err(|e| Into::<anyhow::Error>::into(e).into())
+}
+
+/// Interrupts the running query of a connection when dropped, unless disarmed before, so the
+/// connection isn't kept busy by queries that nobody waits for anymore.
+struct Interrupt(Option<InterruptHandle>);
+
+impl Drop for Interrupt {
+    fn drop(&mut self) {
+        if let Some(handle) = self.0.take() {
+            handle.interrupt();
+        }
+    }
 }
 
 impl Database {
     #[inline]
-    async fn interact<F, T, E>(&self, f: F) -> Result<T>
+    async fn interact<F, T, E>(&self, f: F) -> Result<T, StorageError>
     where
         F: FnOnce(&mut Connection) -> Result<T, E> + Send + 'static,
         T: Send + 'static,
         E: Into<anyhow::Error> + Send + Sync + 'static,
     {
-        interact(&self.0, f).await
+        let result = interact(&self.conn, f).await;
+
+        if let Ok(mut last_write) = self.last_write.lock() {
+            *last_write = Instant::now();
+        }
+
+        result
+    }
+
+    /// Get a handle to the same database, that stores all data for the given tenant.
+    pub fn for_tenant(&self, tenant: &str) -> Self {
+        Self {
+            tenant: tenant.into(),
+            ..self.clone()
+        }
+    }
+
+    /// Tenant, whose data is stored by this handle.
+    pub fn tenant(&self) -> &str {
+        &self.tenant
+    }
+
+    /// Subscribe to all spans of the tenant, that are stored from now on.
+    pub fn subscribe(&self) -> Subscription {
+        Subscription {
+            receiver: self.live.subscribe(),
+            tenant: Arc::clone(&self.tenant),
+        }
+    }
+
+    /// Point in time of the last write to the database.
+    pub fn last_write(&self) -> Instant {
+        self.last_write
+            .lock()
+            .map_or_else(|e| *e.into_inner(), |last_write| *last_write)
+    }
+
+    /// Give unused pages back to the file system and update the query planner statistics. A
+    /// `full` run rebuilds the whole database file, which is slow and blocks all writes for its
+    /// duration. Returns the amount of bytes that the database shrunk.
+    #[instrument(skip(self))]
+    pub async fn maintain(&self, full: bool) -> Result<u64, StorageError> {
+        // Bypass `Self::interact`, as maintenance shouldn't count as write activity.
+        interact::<_, _, anyhow::Error>(&self.conn, move |conn| {
+            let size = |conn: &Connection| {
+                conn.query_row(
+                    "SELECT page_count * page_size FROM pragma_page_count(), pragma_page_size()",
+                    [],
+                    |row| row.get::<_, u64>(0),
+                )
+            };
+
+            let before = size(conn)?;
+            let auto_vacuum =
+                conn.pragma_query_value(None, "auto_vacuum", |row| row.get::<_, u8>(0))?;
+
+            // Databases without incremental vacuum must be rebuilt once to enable it.
+            if full || auto_vacuum != 2 {
+                conn.pragma_update(None, "auto_vacuum", "incremental")?;
+                conn.execute_batch("VACUUM")?;
+            } else {
+                conn.execute_batch("PRAGMA incremental_vacuum")?;
+            }
+
+            conn.execute_batch("PRAGMA optimize; PRAGMA wal_checkpoint(TRUNCATE);")?;
+
+            Ok(before.saturating_sub(size(conn)?))
+        })
+        .await
+    }
+
+    /// Move all traces, that started before the given time, out of the database and into the cold
+    /// tier. Traces are moved in batches, each of which ends up in a separate file. Returns the
+    /// amount of moved spans.
+    #[instrument(skip(self, cold))]
+    pub async fn roll_out(
+        &self,
+        cold: ColdStore,
+        before: OffsetDateTime,
+    ) -> Result<usize, StorageError> {
+        /// Maximum amount of traces, that are moved into a single file.
+        const BATCH_SIZE: usize = 10_000;
+
+        let codec = self.codec;
+
+        // Bypass `Self::interact`, as moving old data shouldn't count as write activity.
+        interact::<_, _, anyhow::Error>(&self.conn, move |conn| {
+            let mut moved = 0;
+
+            loop {
+                let tx = conn.transaction()?;
+                let mut traces =
+                    HashMap::<String, HashMap<TraceId, (String, OffsetDateTime)>>::new();
+
+                for entry in tx
+                    .prepare_cached(include_str!("queries/list_cold_traces.sql"))?
+                    .query_map(params![before, BATCH_SIZE], |row| {
+                        Ok((
+                            row.get::<_, String>(0)?,
+                            row.get::<_, [u8; 16]>(1)?,
+                            (row.get::<_, String>(2)?, row.get(3)?),
+                        ))
+                    })?
+                {
+                    let (tenant, trace_id, info) = entry?;
+                    traces
+                        .entry(tenant)
+                        .or_default()
+                        .insert(TraceId::try_from(trace_id)?, info);
+                }
+
+                if traces.is_empty() {
+                    return Ok(moved);
+                }
+
+                let mut rows = Vec::new();
+
+                for (tenant, traces) in traces {
+                    let trace_ids = traces.keys().copied().map(Value::from).collect::<Vec<_>>();
+                    let mut spans = tx
+                        .prepare_cached(include_str!("queries/list_cold_spans.sql"))?
+                        .query_map(params![tenant, Rc::new(trace_ids.clone())], |row| {
+                            Ok((row.get(0)?, row.get(1)?))
+                        })?
+                        .map(|entry| {
+                            let (data, process) = entry?;
+                            decode_span(data, process).context("failed decoding span")
+                        })
+                        .collect::<Result<Vec<_>>>()?;
+
+                    attach_logs(&tx, &tenant, spans.iter_mut())?;
+
+                    for span in spans {
+                        let Some((service, timestamp)) = traces.get(&span.trace_id) else {
+                            continue;
+                        };
+
+                        rows.push(ColdRow {
+                            tenant: tenant.clone(),
+                            trace_id: span.trace_id,
+                            service: service.clone(),
+                            timestamp: *timestamp,
+                            span: encode(&span, codec)?,
+                        });
+                    }
+
+                    delete_traces(&tx, &tenant, trace_ids)?;
+                }
+
+                let path = if rows.is_empty() {
+                    None
+                } else {
+                    Some(cold.write(&rows)?)
+                };
+
+                if let Err(e) = tx.commit() {
+                    if let Some(path) = path {
+                        std::fs::remove_file(path).ok();
+                    }
+                    return Err(e.into());
+                }
+
+                moved += rows.len();
+            }
+        })
+        .await
     }
 
     #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
-    pub async fn save_spans(&self, spans: Vec<Span>) -> Result<()> {
+    pub async fn save_spans(&self, spans: Vec<Span>) -> Result<(), StorageError> {
         let trace_info = TraceInfo::from_spans(&spans);
+        let services = Services::from_spans(&spans);
+        let subscribed = self.live.receiver_count() > 0;
+        let count = spans.len();
+        let start = Instant::now();
+        let codec = self.codec;
+        let tenant = Arc::clone(&self.tenant);
+        let max_spans = self.max_spans;
 
-        self.interact::<_, _, anyhow::Error>(move |conn| {
-            let conn = conn.transaction()?;
+        let result = self
+            .interact::<_, _, anyhow::Error>(move |conn| {
+                let conn = conn.transaction()?;
 
-            {
-                let mut stmt = conn.prepare_cached(include_str!("queries/save_service.sql"))?;
-                for span in &spans {
-                    stmt.execute([&span.process.service])?;
+                let spans = dedupe_spans(&conn, &tenant, codec, spans)?;
+                // Only keep a copy of the spans, if anyone is listening.
+                let live = subscribed.then(|| spans.clone());
+
+                {
+                    save_services(&conn, &tenant, &services)?;
+
+                    let mut stmt = conn.prepare_cached(include_str!("queries/save_trace.sql"))?;
+                    for (trace_id, info) in trace_info {
+                        stmt.execute(params![
+                            trace_id.to_bytes(),
+                            info.service,
+                            info.timestamp,
+                            info.min_duration.whole_microseconds() as u64,
+                            info.max_duration.whole_microseconds() as u64,
+                            &*tenant,
+                        ])?;
+                    }
+
+                    for span in &spans {
+                        save_span_tags(&conn, &tenant, span)?;
+                    }
+
+                    // Processes are stored separately, as they're mostly the same for all spans of
+                    // a service. Spans of a batch usually share the same process, so only the
+                    // last one is remembered to avoid encoding it again.
+                    let mut process_stmt =
+                        conn.prepare_cached(include_str!("queries/save_process.sql"))?;
+                    let mut last_process = None::<(Process, [u8; 16])>;
+
+                    let mut stmt = conn.prepare_cached(include_str!("queries/save_span.sql"))?;
+                    for mut span in spans {
+                        let process = std::mem::take(&mut span.process);
+                        let service = process.service.clone();
+                        let process_hash = match &last_process {
+                            Some((last, hash)) if *last == process => *hash,
+                            _ => {
+                                let data = encode(&process, codec)?;
+                                let hash = hash(&data);
+                                process_stmt.execute(params![hash, data])?;
+                                last_process = Some((process, hash));
+                                hash
+                            }
+                        };
+
+                        let params = params![
+                            span.trace_id.to_bytes(),
+                            span.span_id.to_bytes(),
+                            span.operation_name,
+                            span.duration.whole_microseconds() as u64,
+                            process_hash,
+                            encode(&span, codec)?,
+                            &*tenant,
+                            service,
+                            span.start,
+                            span.kind.map(SpanKind::as_str),
+                        ];
+                        stmt.execute(params)?;
+                    }
                 }
 
-                let mut stmt = conn.prepare_cached(include_str!("queries/save_operation.sql"))?;
-                for span in &spans {
-                    stmt.execute([&span.process.service, &span.operation_name])?;
+                if let Some(max_spans) = max_spans {
+                    evict_traces(&conn, max_spans)?;
                 }
 
-                let mut stmt = conn.prepare_cached(include_str!("queries/save_trace.sql"))?;
-                for (trace_id, info) in trace_info {
-                    stmt.execute(params![
-                        trace_id.to_bytes(),
-                        info.service,
-                        info.timestamp,
-                        info.min_duration.whole_microseconds() as u64,
-                        info.max_duration.whole_microseconds() as u64
-                    ])?;
+                conn.commit()?;
+
+                Ok((services, live))
+            })
+            .await;
+
+        metrics::storage_write(start);
+
+        match result {
+            Ok((services, live)) => {
+                self.index.record(&self.tenant, services);
+                if let Some(spans) = live {
+                    self.publish(spans);
                 }
+                Ok(())
+            }
+            Err(e) => {
+                metrics::spans_dropped(DropReason::Storage, count);
+                Err(e)
+            }
+        }
+    }
+
+    /// Notify all live subscribers about newly stored spans.
+    fn publish(&self, spans: Vec<Span>) {
+        let stored = StoredSpans {
+            tenant: Arc::clone(&self.tenant),
+            spans,
+        };
+
+        // Sending only fails if nobody is subscribed anymore.
+        self.live.send(Arc::new(stored)).ok();
+    }
+
+    /// Save log records, that were received independently of their spans. They're attached to
+    /// the span they refer to when loading traces.
+    pub async fn save_logs(&self, records: Vec<LogRecord>) -> Result<(), StorageError> {
+        let codec = self.codec;
+        let tenant = Arc::clone(&self.tenant);
+
+        self.interact::<_, _, anyhow::Error>(move |conn| {
+            let conn = conn.transaction()?;
 
-                let mut stmt = conn.prepare_cached(include_str!("queries/save_span.sql"))?;
-                for span in spans {
-                    let params = params![
-                        span.trace_id.to_bytes(),
-                        span.span_id.to_bytes(),
-                        span.operation_name,
-                        encode_span(&span)?,
-                    ];
-                    stmt.execute(params)?;
+            {
+                let mut stmt = conn.prepare_cached(include_str!("queries/save_log.sql"))?;
+                for record in records {
+                    stmt.execute(params![
+                        record.log.timestamp,
+                        record.trace_id.map(TraceId::to_bytes),
+                        record.span_id.map(SpanId::to_bytes),
+                        record.process.service,
+                        encode(&record, codec)?,
+                        &*tenant,
+                    ])?;
                 }
             }
 
@@ -148,122 +787,791 @@ impl Database {
 
 impl ReadOnlyDatabase {
     #[inline]
-    async fn interact<F, T, E>(&self, f: F) -> Result<T>
+    async fn interact<F, T, E>(&self, f: F) -> Result<T, StorageError>
     where
         F: FnOnce(&mut Connection) -> Result<T, E> + Send + 'static,
         T: Send + 'static,
         E: Into<anyhow::Error> + Send + Sync + 'static,
     {
-        interact(&self.0, f).await
+        interact(&self.conn, f).await
     }
 
-    #[instrument(skip_all)]
-    pub async fn list_services(&self) -> Result<Vec<String>> {
-        self.interact(|conn| {
-            conn.prepare(include_str!("queries/list_services.sql"))?
-                .query_map([], |row| row.get(0))?
-                .collect::<Result<Vec<_>, _>>()
-        })
-        .await
+    /// Get a handle to the same database, that only reads data of the given tenant.
+    pub fn for_tenant(&self, tenant: &str) -> Self {
+        Self {
+            conn: Arc::clone(&self.conn),
+            tenant: tenant.into(),
+            path: self.path.clone(),
+            cold: self.cold.clone(),
+            index: Arc::clone(&self.index),
+        }
     }
 
-    #[instrument(skip_all)]
-    pub async fn list_operations(&self, service: String) -> Result<Vec<String>> {
-        self.interact(|conn| {
-            conn.prepare(include_str!("queries/list_operations.sql"))?
-                .query_map([service], |row| row.get(0))?
-                .collect::<Result<Vec<_>, _>>()
-        })
-        .await
+    /// Tenant, whose data is read by this handle.
+    pub fn tenant(&self) -> &str {
+        &self.tenant
+    }
+
+    /// Load traces from the cold tier, if there is one. See [`ColdStore::scan`] for the filters.
+    async fn scan_cold(
+        &self,
+        window: Option<(OffsetDateTime, OffsetDateTime)>,
+        trace_ids: Option<HashSet<TraceId>>,
+    ) -> Result<HashMap<TraceId, ColdTrace>> {
+        let Some(cold) = self.cold.clone() else {
+            return Ok(HashMap::new());
+        };
+        let tenant = Arc::clone(&self.tenant);
+
+        tokio::task::spawn_blocking(move || cold.scan(&tenant, window, trace_ids.as_ref()))
+            .await
+            .map_err(|e| anyhow!("{e}"))?
+    }
+
+    /// List all services in alphabetical order, optionally limited to the ones that reported
+    /// spans since the given time.
+    pub fn list_services(&self, since: Option<OffsetDateTime>) -> Vec<String> {
+        self.index.services(&self.tenant, since)
+    }
+
+    /// List the names of all operations of a service, optionally limited to the ones that were
+    /// seen since the given time.
+    pub fn list_operations(&self, service: &str, since: Option<OffsetDateTime>) -> Vec<String> {
+        let mut operations = self
+            .index
+            .operations(&self.tenant, service, None, since)
+            .into_iter()
+            .map(|(operation, _)| operation)
+            .collect::<Vec<_>>();
+
+        operations.dedup();
+        operations
+    }
+
+    /// List all operations of a service together with their span kind, optionally limited to a
+    /// single kind and to the ones that were seen since the given time. Operations without a
+    /// known span kind have an empty string as kind.
+    pub fn find_operations(
+        &self,
+        service: &str,
+        span_kind: Option<&str>,
+        since: Option<OffsetDateTime>,
+    ) -> Vec<(String, String)> {
+        self.index
+            .operations(&self.tenant, service, span_kind, since)
     }
 
     #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
     #[instrument(skip_all)]
-    pub async fn list_spans(&self, params: ListSpansParams) -> Result<HashMap<TraceId, Vec<Span>>> {
-        self.interact::<_, _, anyhow::Error>(move |conn| {
-            let trace_ids = conn
-                .prepare(include_str!("queries/list_traces.sql"))?
-                .query_map(
-                    named_params! {
-                        ":service": params.service,
-                        ":t_min": params.start,
-                        ":t_max": params.end,
-                        ":d_min": params.duration_min.map(|d| d.whole_microseconds() as u64),
-                        ":d_max": params.duration_max.map(|d| d.whole_microseconds() as u64),
-                        ":limit": params.limit,
-                    },
-                    |row| row.get::<_, [u8; 16]>(0),
-                )?
-                .map(|raw| TraceId::try_from(raw?).map(Into::into))
-                .collect::<Result<Vec<Value>>>()
-                .context("failed listing trace IDs")?;
-
-            conn.prepare(include_str!("queries/list_spans.sql"))?
-                .query_map([Rc::new(trace_ids)], |row| row.get(0))?
-                .try_fold(HashMap::<TraceId, Vec<Span>>::new(), |mut map, entry| {
-                    let span = decode_span(entry?).context("failed decoding span")?;
-
-                    if span_contains_tag(&span, &params.tags) {
+    /// List the spans of all traces matching the search parameters, limited to the requested page.
+    /// Additionally, the total amount of matching traces is returned, regardless of the paging.
+    ///
+    /// Traces in the cold tier are always older than the ones in the database, so they follow
+    /// after the database results.
+    pub async fn list_spans(
+        &self,
+        params: ListSpansParams,
+    ) -> Result<(usize, HashMap<TraceId, Vec<Span>>), StorageError> {
+        let (tag_query, tag_count, tag_filters) = tag_filter(&params.tags);
+        let tenant = Arc::clone(&self.tenant);
+        let (offset, limit) = (params.offset, params.limit);
+
+        let mut cold = self
+            .scan_cold(Some((params.start, params.end)), None)
+            .await?
+            .into_iter()
+            .filter(|(_, trace)| matches_cold(&params, trace))
+            .collect::<Vec<_>>();
+        cold.sort_by_key(|(_, trace)| Reverse(trace.timestamp));
+
+        let (total, mut traces) = self
+            .interact::<_, _, anyhow::Error>(move |conn| {
+                let mut total = 0;
+                let trace_ids = conn
+                    .prepare(include_str!("queries/list_traces.sql"))?
+                    .query_map(
+                        named_params! {
+                            ":tenant": tenant,
+                            ":service": params.service,
+                            ":t_min": params.start,
+                            ":t_max": params.end,
+                            ":d_min": params.duration_min.map(|d| d.whole_microseconds() as u64),
+                            ":d_max": params.duration_max.map(|d| d.whole_microseconds() as u64),
+                            ":operation": params.operation,
+                            ":limit": params.limit,
+                            ":offset": params.offset,
+                            ":tag_count": tag_count,
+                            ":tag_query": tag_query,
+                            ":tag_filters": tag_filters,
+                            ":error": params.error,
+                            ":min_spans": params.min_span_count,
+                            ":span_kind": params.span_kind.map(SpanKind::as_str),
+                        },
+                        |row| Ok((row.get(0)?, row.get::<_, Option<[u8; 16]>>(1)?)),
+                    )?
+                    .filter_map(|row| match row {
+                        Ok((count, raw)) => {
+                            total = count;
+                            raw.map(|raw| TraceId::try_from(raw).map(Into::into))
+                        }
+                        Err(e) => Some(Err(e.into())),
+                    })
+                    .collect::<Result<Vec<Value>>>()
+                    .context("failed listing trace IDs")?;
+
+                let mut traces = conn
+                    .prepare(include_str!("queries/list_spans.sql"))?
+                    .query_map(params![tenant, Rc::new(trace_ids)], |row| {
+                        Ok((row.get(0)?, row.get(1)?))
+                    })?
+                    .try_fold(HashMap::<TraceId, Vec<Span>>::new(), |mut map, entry| {
+                        let (data, process) = entry?;
+                        let span = decode_span(data, process).context("failed decoding span")?;
                         map.entry(span.trace_id).or_default().push(span);
-                    }
+                        anyhow::Ok(map)
+                    })
+                    .context("failed listing spans")?;
 
-                    anyhow::Ok(map)
-                })
-                .context("failed listing spans")
-        })
-        .await
+                attach_logs(conn, &tenant, traces.values_mut().flatten())?;
+
+                Ok((total, traces))
+            })
+            .await?;
+
+        let cold_total = cold.len();
+        traces.extend(
+            cold.into_iter()
+                .skip(offset.saturating_sub(total))
+                .take(limit.saturating_sub(traces.len()))
+                .map(|(trace_id, trace)| (trace_id, trace.spans)),
+        );
+
+        Ok((total + cold_total, traces))
     }
 
+    /// List all spans of the given services, that started within the time range.
     #[instrument(skip_all)]
-    pub async fn find_trace(&self, trace_id: TraceId) -> Result<Vec<Span>> {
+    pub async fn list_service_spans(
+        &self,
+        services: Vec<String>,
+        start: OffsetDateTime,
+        end: OffsetDateTime,
+    ) -> Result<Vec<Span>, StorageError> {
+        let tenant = Arc::clone(&self.tenant);
+        let cold = self
+            .scan_cold(Some((start, end)), None)
+            .await?
+            .into_values()
+            .flat_map(|trace| trace.spans);
+
         let spans = self
+            .interact::<_, _, anyhow::Error>({
+                let services = services.clone();
+                move |conn| {
+                    let values = services
+                        .iter()
+                        .cloned()
+                        .map(Value::from)
+                        .collect::<Vec<_>>();
+                    let spans = conn
+                        .prepare(include_str!("queries/list_service_spans.sql"))?
+                        .query_map(
+                            named_params! {
+                                ":tenant": tenant,
+                                ":services": Rc::new(values),
+                                ":t_min": start,
+                                ":t_max": end,
+                            },
+                            |row| Ok((row.get(0)?, row.get(1)?)),
+                        )?
+                        .map(|entry| {
+                            let (data, process) = entry?;
+                            decode_span(data, process)
+                        })
+                        .collect::<Result<Vec<_>>>()
+                        .context("failed listing spans")?;
+
+                    Ok(spans)
+                }
+            })
+            .await?;
+
+        Ok(spans
+            .into_iter()
+            .chain(cold)
+            .filter(|span| {
+                services.contains(&span.process.service) && (start..=end).contains(&span.start)
+            })
+            .collect())
+    }
+
+    /// Get the start time and duration of all traces matching the search parameters, ignoring the
+    /// paging. This allows to give an overview of the results, without loading any spans.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    #[instrument(skip_all)]
+    pub async fn list_trace_durations(
+        &self,
+        params: ListSpansParams,
+    ) -> Result<Vec<(OffsetDateTime, u64)>, StorageError> {
+        let (tag_query, tag_count, tag_filters) = tag_filter(&params.tags);
+        let tenant = Arc::clone(&self.tenant);
+        let cold = self
+            .scan_cold(Some((params.start, params.end)), None)
+            .await?
+            .into_values()
+            .filter(|trace| matches_cold(&params, trace))
+            .map(|trace| {
+                let duration = trace.spans.iter().map(|span| span.duration).max();
+                (
+                    trace.timestamp,
+                    duration.map_or(0, |d| d.whole_microseconds() as u64),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let mut durations = self
             .interact(move |conn| {
-                conn.prepare(include_str!("queries/find_trace.sql"))?
-                    .query_map([trace_id.to_bytes()], |row| row.get::<_, Vec<u8>>(0))?
+                conn.prepare(include_str!("queries/list_trace_durations.sql"))?
+                    .query_map(
+                        named_params! {
+                            ":tenant": tenant,
+                            ":service": params.service,
+                            ":t_min": params.start,
+                            ":t_max": params.end,
+                            ":d_min": params.duration_min.map(|d| d.whole_microseconds() as u64),
+                            ":d_max": params.duration_max.map(|d| d.whole_microseconds() as u64),
+                            ":operation": params.operation,
+                            ":tag_count": tag_count,
+                            ":tag_query": tag_query,
+                            ":tag_filters": tag_filters,
+                            ":error": params.error,
+                            ":min_spans": params.min_span_count,
+                            ":span_kind": params.span_kind.map(SpanKind::as_str),
+                        },
+                        |row| Ok((row.get(0)?, row.get(1)?)),
+                    )?
                     .collect::<Result<Vec<_>, _>>()
             })
             .await?;
 
-        spans
-            .into_iter()
-            .map(decode_span)
-            .collect::<Result<Vec<_>>>()
+        durations.extend(cold);
+
+        Ok(durations)
     }
 
+    /// Get the start time and duration of all calls to a service, that started within the time
+    /// range. These are the spans of the given operation, or the whole traces of the service if
+    /// no operation is given.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    #[instrument(skip_all)]
+    pub async fn list_operation_durations(
+        &self,
+        service: String,
+        operation: Option<String>,
+        start: OffsetDateTime,
+        end: OffsetDateTime,
+    ) -> Result<Vec<(OffsetDateTime, u64)>, StorageError> {
+        let tenant = Arc::clone(&self.tenant);
+        let cold = self
+            .scan_cold(Some((start, end)), None)
+            .await?
+            .into_values()
+            .filter(|trace| trace.service == service)
+            .flat_map(|trace| match &operation {
+                Some(operation) => trace
+                    .spans
+                    .into_iter()
+                    .filter(|span| {
+                        span.operation_name == *operation && span.process.service == service
+                    })
+                    .map(|span| (span.start, span.duration))
+                    .collect::<Vec<_>>(),
+                None => trace
+                    .spans
+                    .iter()
+                    .map(|span| span.duration)
+                    .max()
+                    .map(|duration| (trace.timestamp, duration))
+                    .into_iter()
+                    .collect(),
+            })
+            .filter(|(timestamp, _)| (start..=end).contains(timestamp))
+            .map(|(timestamp, duration)| (timestamp, duration.whole_microseconds() as u64))
+            .collect::<Vec<_>>();
+
+        let mut durations = self
+            .interact(move |conn| {
+                let query = if operation.is_some() {
+                    include_str!("queries/list_operation_durations.sql")
+                } else {
+                    include_str!("queries/list_service_durations.sql")
+                };
+                let mut stmt = conn.prepare(query)?;
+                let mut params = named_params! {
+                    ":tenant": tenant,
+                    ":service": service,
+                    ":t_min": start,
+                    ":t_max": end,
+                }
+                .to_vec();
+                if let Some(operation) = &operation {
+                    params.push((":operation", operation));
+                }
+
+                let durations = stmt
+                    .query_map(&*params, |row| Ok((row.get(0)?, row.get(1)?)))?
+                    .collect::<Result<Vec<_>, _>>();
+                durations
+            })
+            .await?;
+
+        durations.extend(cold);
+
+        Ok(durations)
+    }
+
+    /// Load all spans of a trace. The cold tier is only searched, if the trace isn't found in the
+    /// database, as that requires reading all its files.
+    #[instrument(skip_all)]
+    pub async fn find_trace(&self, trace_id: TraceId) -> Result<Vec<Span>, StorageError> {
+        let tenant = Arc::clone(&self.tenant);
+
+        let spans = self
+            .interact::<_, _, anyhow::Error>(move |conn| {
+                let mut spans = conn
+                    .prepare(include_str!("queries/find_trace.sql"))?
+                    .query_map(params![tenant, trace_id.to_bytes()], |row| {
+                        Ok((row.get(0)?, row.get(1)?))
+                    })?
+                    .map(|entry| {
+                        let (data, process) = entry?;
+                        decode_span(data, process)
+                    })
+                    .collect::<Result<Vec<Span>>>()?;
+
+                attach_logs(conn, &tenant, spans.iter_mut())?;
+
+                Ok(spans)
+            })
+            .await?;
+
+        if !spans.is_empty() {
+            return Ok(spans);
+        }
+
+        self.scan_cold(None, Some(HashSet::from([trace_id])))
+            .await?
+            .remove(&trace_id)
+            .map(|trace| trace.spans)
+            .ok_or(StorageError::NotFound)
+    }
+
+    /// Load all spans of the given traces. Like [`Self::find_trace`], the cold tier is only
+    /// searched for traces that aren't in the database.
     #[instrument(skip_all)]
     pub async fn find_traces(
         &self,
         trace_ids: impl Iterator<Item = TraceId>,
-    ) -> Result<HashMap<TraceId, Vec<Span>>> {
-        let trace_ids = trace_ids.map(Into::into).collect::<Vec<Value>>();
+    ) -> Result<HashMap<TraceId, Vec<Span>>, StorageError> {
+        let mut trace_ids = trace_ids.collect::<HashSet<_>>();
+        let values = trace_ids
+            .iter()
+            .copied()
+            .map(Into::into)
+            .collect::<Vec<Value>>();
+        let tenant = Arc::clone(&self.tenant);
 
-        self.interact::<_, _, anyhow::Error>(move |conn| {
-            conn.prepare(include_str!("queries/find_traces.sql"))?
-                .query_map([Rc::new(trace_ids)], |row| row.get(0))?
-                .try_fold(HashMap::<TraceId, Vec<Span>>::new(), |mut map, entry| {
-                    let span = decode_span(entry?)?;
-                    map.entry(span.trace_id).or_default().push(span);
-                    Ok(map)
+        let mut traces = self
+            .interact::<_, _, anyhow::Error>(move |conn| {
+                let mut traces = conn
+                    .prepare(include_str!("queries/find_traces.sql"))?
+                    .query_map(params![tenant, Rc::new(values)], |row| {
+                        Ok((row.get(0)?, row.get(1)?))
+                    })?
+                    .try_fold(HashMap::<TraceId, Vec<Span>>::new(), |mut map, entry| {
+                        let (data, process) = entry?;
+                        let span = decode_span(data, process)?;
+                        map.entry(span.trace_id).or_default().push(span);
+                        anyhow::Ok(map)
+                    })?;
+
+                attach_logs(conn, &tenant, traces.values_mut().flatten())?;
+
+                Ok(traces)
+            })
+            .await?;
+
+        trace_ids.retain(|trace_id| !traces.contains_key(trace_id));
+
+        if !trace_ids.is_empty() {
+            traces.extend(
+                self.scan_cold(None, Some(trace_ids))
+                    .await?
+                    .into_iter()
+                    .map(|(trace_id, trace)| (trace_id, trace.spans)),
+            );
+        }
+
+        Ok(traces)
+    }
+
+    /// Gather statistics about the stored data, mostly useful for capacity planning.
+    #[instrument(skip_all)]
+    pub async fn stats(&self) -> Result<Stats, StorageError> {
+        let file_size = self.path.as_ref().map_or(0, |path| {
+            ["", "-wal"]
+                .into_iter()
+                .map(|suffix| {
+                    let mut path = path.clone().into_os_string();
+                    path.push(suffix);
+                    std::fs::metadata(path).map_or(0, |meta| meta.len())
                 })
+                .sum::<u64>()
+        });
+        let tenant = Arc::clone(&self.tenant);
+
+        self.interact::<_, _, anyhow::Error>(move |conn| {
+            let (span_count, trace_count, oldest_span, newest_span) = conn
+                .prepare(include_str!("queries/storage_stats.sql"))?
+                .query_row(named_params! { ":tenant": tenant }, |row| {
+                    Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+                })?;
+
+            let mut services = HashMap::<String, u64>::new();
+
+            for entry in conn
+                .prepare(include_str!("queries/count_service_spans.sql"))?
+                .query_map([&*tenant], |row| Ok((row.get(0)?, row.get(1)?)))?
+            {
+                let (process, count): (_, u64) = entry?;
+                let process = decode::<Process>(process)?;
+                *services.entry(process.service).or_default() += count;
+            }
+
+            // Spans from older versions carry the process themselves, and have to be decoded
+            // one by one.
+            for data in conn
+                .prepare("SELECT data FROM spans WHERE tenant = ? AND process IS NULL")?
+                .query_map([&*tenant], |row| row.get(0))?
+            {
+                let span = decode::<Span>(data?)?;
+                *services.entry(span.process.service).or_default() += 1;
+            }
+
+            let mut services = services.into_iter().collect::<Vec<_>>();
+            services.sort_unstable();
+
+            Ok(Stats {
+                span_count,
+                trace_count,
+                services,
+                oldest_span,
+                newest_span,
+                file_size,
+            })
         })
         .await
     }
 }
 
-fn encode_span(span: &Span) -> Result<Vec<u8>> {
-    let buf = rmp_serde::to_vec(span)?;
-    let buf = snap::raw::Encoder::new().compress_vec(&buf)?;
+/// Statistics about the stored data.
+pub struct Stats {
+    pub span_count: u64,
+    pub trace_count: u64,
+    /// Amount of spans per service, sorted by service name.
+    pub services: Vec<(String, u64)>,
+    /// Start of the oldest trace.
+    pub oldest_span: Option<OffsetDateTime>,
+    /// Start of the most recent trace.
+    pub newest_span: Option<OffsetDateTime>,
+    /// Combined size of the database and its write-ahead log, in bytes.
+    pub file_size: u64,
+}
+
+/// Compression algorithm for stored blobs.
+#[derive(Clone, Copy)]
+enum Codec {
+    Snappy,
+    /// Zstandard with the given compression level.
+    Zstd(i32),
+}
+
+impl Codec {
+    const SNAPPY: u8 = 1;
+    const ZSTD: u8 = 2;
+}
+
+/// Marks blobs, that carry the codec in a header. Older blobs are plain snappy data, which always
+/// starts with the (non-zero) uncompressed length, so they can't be confused with the header.
+const HEADER_MARKER: u8 = 0;
+
+fn encode<T: Serialize>(value: &T, codec: Codec) -> Result<Vec<u8>> {
+    let raw = rmp_serde::to_vec(value)?;
+    let mut buf = Vec::with_capacity(raw.len() / 2);
+
+    match codec {
+        Codec::Snappy => {
+            buf.extend([HEADER_MARKER, Codec::SNAPPY]);
+            buf.extend(snap::raw::Encoder::new().compress_vec(&raw)?);
+        }
+        Codec::Zstd(level) => {
+            buf.extend([HEADER_MARKER, Codec::ZSTD]);
+            zstd::stream::copy_encode(&raw[..], &mut buf, level)?;
+        }
+    }
 
     Ok(buf)
 }
 
-fn decode_span(span: Vec<u8>) -> Result<Span> {
-    let span = snap::raw::Decoder::new().decompress_vec(&span)?;
-    let span = rmp_serde::from_slice(&span)?;
+fn decode<T: DeserializeOwned>(value: Vec<u8>) -> Result<T> {
+    let decode = || {
+        let value = match value.as_slice() {
+            [HEADER_MARKER, Codec::SNAPPY, data @ ..] => {
+                snap::raw::Decoder::new().decompress_vec(data)?
+            }
+            [HEADER_MARKER, Codec::ZSTD, data @ ..] => zstd::stream::decode_all(data)?,
+            [HEADER_MARKER, codec, ..] => bail!("unknown codec {codec}"),
+            data => snap::raw::Decoder::new().decompress_vec(data)?,
+        };
+
+        anyhow::Ok(rmp_serde::from_slice(&value)?)
+    };
+
+    decode().map_err(|e| StorageError::Corrupt(e.into()).into())
+}
+
+/// Decode a span and attach its separately stored process. Spans saved by older versions still
+/// contain the process themselves, in which case there is no separate one.
+fn decode_span(data: Vec<u8>, process: Option<Vec<u8>>) -> Result<Span> {
+    // Spans stored before the kind had its own field, still carry it as tag.
+    let mut span = decode::<Span>(data)?.with_kind_from_tags();
+    if let Some(process) = process {
+        span.process = decode(process)?;
+    }
 
     Ok(span)
 }
 
+/// Record the services and operations of a batch, together with when they were last seen.
+fn save_services(conn: &Connection, tenant: &str, services: &Services) -> Result<()> {
+    let mut service_stmt = conn.prepare_cached(include_str!("queries/save_service.sql"))?;
+    let mut operation_stmt = conn.prepare_cached(include_str!("queries/save_operation.sql"))?;
+
+    for (name, service) in services.iter() {
+        service_stmt.execute(params![name, tenant, service.last_seen])?;
+
+        for ((operation, span_kind), last_seen) in &service.operations {
+            operation_stmt.execute(params![name, operation, span_kind, tenant, last_seen])?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Make the tags of a span, including the ones of its process, searchable. The kind stays
+/// searchable like any other tag.
+fn save_span_tags(conn: &Connection, tenant: &str, span: &Span) -> Result<()> {
+    let mut stmt = conn.prepare_cached(include_str!("queries/save_span_tag.sql"))?;
+    let kind = span.kind.map(SpanKind::tag);
+
+    for tag in span.tags.iter().chain(&span.process.tags).chain(&kind) {
+        stmt.execute(params![
+            tenant,
+            span.trace_id.to_bytes(),
+            span.span_id.to_bytes(),
+            format!("{}={}", tag.key, tag_value(&tag.value)),
+        ])?;
+    }
+
+    Ok(())
+}
+
+/// Resolve span IDs, that are already taken by another span of the same trace. Otherwise, the
+/// whole batch would be rejected. IDs usually only collide, if a client generates them badly, or
+/// they were missing and had to be generated.
+///
+/// Spans that are already stored with the exact same content are dropped, as they come from a
+/// retried batch. Any other collision, either within the batch or with a different stored span,
+/// gives the span a new random ID.
+fn dedupe_spans(
+    conn: &Connection,
+    tenant: &str,
+    codec: Codec,
+    spans: Vec<Span>,
+) -> Result<Vec<Span>> {
+    let mut stmt = conn.prepare_cached(include_str!("queries/find_span_data.sql"))?;
+    let mut seen = HashSet::with_capacity(spans.len());
+    let mut unique = Vec::with_capacity(spans.len());
+
+    for mut span in spans {
+        let original = span.span_id;
+        let mut collides = !seen.insert((span.trace_id, span.span_id));
+
+        if !collides {
+            let stored = stmt
+                .query_row(
+                    params![tenant, span.trace_id.to_bytes(), span.span_id.to_bytes()],
+                    |row| row.get::<_, Vec<u8>>(0),
+                )
+                .optional()?;
+
+            if let Some(stored) = stored {
+                // The process is stored separately, and not part of the span data.
+                let process = std::mem::take(&mut span.process);
+                let retried = stored == encode(&span, codec)?;
+                span.process = process;
+
+                if retried {
+                    continue;
+                }
+                collides = true;
+            }
+        }
+
+        while collides {
+            span.span_id = rand::random::<NonZeroU64>().into();
+            collides = !seen.insert((span.trace_id, span.span_id))
+                || stmt.exists(params![
+                    tenant,
+                    span.trace_id.to_bytes(),
+                    span.span_id.to_bytes()
+                ])?;
+        }
+
+        if span.span_id != original {
+            let (original, reassigned) = (original.get(), span.span_id.get());
+            tracing::warn!(
+                trace_id = %format_args!("{:032x}", span.trace_id.get()),
+                original = %format_args!("{original:016x}"),
+                reassigned = %format_args!("{reassigned:016x}"),
+                "span ID collision"
+            );
+            span.warnings.push(format!(
+                "span ID {original:016x} collides with another span of the trace, it was \
+                 reassigned to {reassigned:016x}"
+            ));
+        }
+
+        unique.push(span);
+    }
+
+    Ok(unique)
+}
+
+/// Remove the oldest traces, until at most `max_spans` spans are left.
+fn evict_traces(conn: &Connection, max_spans: u64) -> Result<()> {
+    loop {
+        let (spans, traces) = conn.query_row(
+            "SELECT (SELECT value FROM counters WHERE name = 'spans'), \
+             (SELECT value FROM counters WHERE name = 'traces')",
+            [],
+            |row| Ok((row.get::<_, u64>(0)?, row.get::<_, u64>(1)?)),
+        )?;
+
+        if spans <= max_spans || traces == 0 {
+            return Ok(());
+        }
+
+        // Estimate the amount of traces to remove from the average spans per trace.
+        let limit = ((spans - max_spans) * traces).div_ceil(spans);
+        let mut trace_ids = HashMap::<String, Vec<Value>>::new();
+        for entry in conn
+            .prepare_cached("SELECT tenant, trace_id FROM traces ORDER BY timestamp LIMIT ?")?
+            .query_map([limit], |row| Ok((row.get(0)?, row.get(1)?)))?
+        {
+            let (tenant, trace_id) = entry?;
+            trace_ids.entry(tenant).or_default().push(trace_id);
+        }
+
+        for (tenant, trace_ids) in trace_ids {
+            delete_traces(conn, &tenant, trace_ids)?;
+        }
+    }
+}
+
+/// Remove the traces of a tenant with all their spans, tags and logs, as well as the processes
+/// that no other span refers to anymore.
+fn delete_traces(conn: &Connection, tenant: &str, trace_ids: Vec<Value>) -> Result<()> {
+    let trace_ids = Rc::new(trace_ids);
+    let processes = conn
+        .prepare_cached(
+            "SELECT DISTINCT process FROM spans \
+             WHERE tenant = ? AND trace_id IN rarray(?) AND process IS NOT NULL",
+        )?
+        .query_map(params![tenant, Rc::clone(&trace_ids)], |row| {
+            row.get::<_, Value>(0)
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    for table in ["span_tags", "logs", "spans", "traces"] {
+        conn.prepare_cached(&format!(
+            "DELETE FROM {table} WHERE tenant = ? AND trace_id IN rarray(?)"
+        ))?
+        .execute(params![tenant, Rc::clone(&trace_ids)])?;
+    }
+
+    conn.prepare_cached(
+        "DELETE FROM processes WHERE hash IN rarray(?) \
+         AND NOT EXISTS (SELECT 1 FROM spans WHERE spans.process = processes.hash)",
+    )?
+    .execute([Rc::new(processes)])?;
+
+    Ok(())
+}
+
+/// Stable hash of encoded data, used as key for de-duplicated entries.
+fn hash(data: &[u8]) -> [u8; 16] {
+    let mut hasher = SipHasher13::new();
+    hasher.write(data);
+    hasher.finish128().as_bytes()
+}
+
+/// Attach all separately received logs to the spans they refer to, keeping the logs of each span
+/// in chronological order.
+fn attach_logs<'a>(
+    conn: &Connection,
+    tenant: &str,
+    spans: impl Iterator<Item = &'a mut Span>,
+) -> Result<()> {
+    let mut spans = spans
+        .map(|span| ((span.trace_id, span.span_id.get()), (span, false)))
+        .collect::<HashMap<_, _>>();
+    if spans.is_empty() {
+        return Ok(());
+    }
+
+    let trace_ids = spans
+        .keys()
+        .map(|(trace_id, _)| *trace_id)
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .map(Value::from)
+        .collect::<Vec<_>>();
+
+    let mut stmt = conn.prepare_cached(include_str!("queries/find_logs.sql"))?;
+    let records = stmt.query_map(params![tenant, Rc::new(trace_ids)], |row| row.get(0))?;
+
+    for entry in records {
+        let record = decode::<LogRecord>(entry?).context("failed decoding log")?;
+        let (Some(trace_id), Some(span_id)) = (record.trace_id, record.span_id) else {
+            continue;
+        };
+
+        if let Some((span, changed)) = spans.get_mut(&(trace_id, span_id.get())) {
+            span.logs.push(record.log);
+            *changed = true;
+        }
+    }
+
+    for (span, changed) in spans.into_values() {
+        if changed {
+            span.logs.sort_by_key(|log| log.timestamp);
+        }
+    }
+
+    Ok(())
+}
+
 struct TraceInfo {
     service: String,
     timestamp: OffsetDateTime,
@@ -301,43 +1609,167 @@ pub struct ListSpansParams {
     pub duration_min: Option<Duration>,
     pub duration_max: Option<Duration>,
     pub limit: usize,
-    pub tags: HashMap<String, String>,
+    pub offset: usize,
+    pub tags: TagFilters,
+    pub error: bool,
+    pub min_span_count: Option<u32>,
+    /// Kind that at least one span must have, which also has the operation and duration, if
+    /// these are given.
+    pub span_kind: Option<SpanKind>,
+}
+
+/// Filters on span tags, that must all match the same span.
+#[derive(Debug, Default, PartialEq)]
+pub struct TagFilters {
+    /// Tags that must have exactly the given value.
+    pub exact: HashMap<String, String>,
+    /// Tags that must have a numeric value, which compares to the given number.
+    pub numeric: Vec<(String, Comparison, f64)>,
 }
 
-fn span_contains_tag(span: &Span, filter: &HashMap<String, String>) -> bool {
-    if filter.is_empty() {
-        return true;
+impl TagFilters {
+    pub fn is_empty(&self) -> bool {
+        self.exact.is_empty() && self.numeric.is_empty()
     }
 
-    span.tags
-        .iter()
-        .chain(span.process.tags.iter())
-        .any(|tag| match filter.get(&tag.key) {
-            Some(value) => match &tag.value {
-                TagValue::F64(f) => {
-                    let mut buf = ryu::Buffer::new();
-                    value == buf.format(*f)
-                }
-                TagValue::I64(i) => {
-                    let mut buf = itoa::Buffer::new();
-                    value == buf.format(*i)
-                }
-                TagValue::U64(u) => {
-                    let mut buf = itoa::Buffer::new();
-                    value == buf.format(*u)
-                }
-                TagValue::I128(i) => {
-                    let mut buf = itoa::Buffer::new();
-                    value == buf.format(*i)
-                }
-                TagValue::U128(u) => {
-                    let mut buf = itoa::Buffer::new();
-                    value == buf.format(*u)
-                }
-                TagValue::Bool(b) => value == if *b { "true" } else { "false" },
-                TagValue::String(s) => value == s,
-                TagValue::Binary(b) => value == &hex::encode(b),
-            },
-            None => false,
+    fn len(&self) -> usize {
+        self.exact.len() + self.numeric.len()
+    }
+
+    /// Check whether the tags of a single span satisfy all filters.
+    fn matches(&self, tags: &[(&str, Cow<'_, str>)]) -> bool {
+        self.exact
+            .iter()
+            .all(|(key, value)| tags.iter().any(|(k, v)| k == key && v == value))
+            && self.numeric.iter().all(|(key, comparison, value)| {
+                tags.iter().any(|(k, v)| {
+                    k == key && v.parse::<f64>().is_ok_and(|v| comparison.eval(v, *value))
+                })
+            })
+    }
+}
+
+impl FromIterator<(String, String)> for TagFilters {
+    fn from_iter<T: IntoIterator<Item = (String, String)>>(iter: T) -> Self {
+        Self {
+            exact: iter.into_iter().collect(),
+            numeric: Vec::new(),
+        }
+    }
+}
+
+/// Numeric comparison of a tag value against a fixed number.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Comparison {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Comparison {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Lt => "<",
+            Self::Le => "<=",
+            Self::Gt => ">",
+            Self::Ge => ">=",
+        }
+    }
+
+    pub fn eval<T: PartialOrd>(self, lhs: T, rhs: T) -> bool {
+        match self {
+            Self::Lt => lhs < rhs,
+            Self::Le => lhs <= rhs,
+            Self::Gt => lhs > rhs,
+            Self::Ge => lhs >= rhs,
+        }
+    }
+}
+
+/// Check whether a trace from the cold tier matches the search parameters, the same way as the
+/// trace search queries do for the database.
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn matches_cold(params: &ListSpansParams, trace: &ColdTrace) -> bool {
+    fn tags(span: &Span) -> Vec<(&str, Cow<'_, str>)> {
+        span.tags
+            .iter()
+            .chain(&span.process.tags)
+            .map(|tag| (tag.key.as_str(), tag_value(&tag.value)))
+            .chain(
+                span.kind
+                    .map(|kind| (SpanKind::TAG_KEY, kind.as_str().into())),
+            )
+            .collect()
+    }
+
+    trace.service == params.service
+        && (params.start..=params.end).contains(&trace.timestamp)
+        && trace.spans.iter().any(|span| {
+            params.operation.as_ref().is_none_or(|operation| {
+                *operation == span.operation_name && span.process.service == params.service
+            }) && params.duration_min.is_none_or(|min| span.duration >= min)
+                && params.duration_max.is_none_or(|max| span.duration <= max)
+                && params.span_kind.is_none_or(|kind| {
+                    span.kind == Some(kind) && span.process.service == params.service
+                })
         })
+        && (params.tags.is_empty()
+            || trace
+                .spans
+                .iter()
+                .any(|span| params.tags.matches(&tags(span))))
+        && (!params.error
+            || trace
+                .spans
+                .iter()
+                .any(|span| tags(span).iter().any(|(k, v)| *k == "error" && v == "true")))
+        && params
+            .min_span_count
+            .is_none_or(|min| trace.spans.len() >= min as usize)
+}
+
+/// Convert tag filters into the parts that the trace search queries expect. That is, the full text
+/// search query for a quick pre-selection, the amount of filters and the filters themselves as JSON
+/// array. Exact matches are pre-selected by the whole `key=value` tag, numeric comparisons only by
+/// their key.
+fn tag_filter(tags: &TagFilters) -> (String, usize, String) {
+    let phrase = |text: &str| format!("\"{}\"", text.replace('"', "\"\""));
+
+    let tag_query = tags
+        .exact
+        .iter()
+        .map(|(key, value)| phrase(&format!("{key}={value}")))
+        .chain(tags.numeric.iter().map(|(key, _, _)| phrase(key)))
+        .collect::<Vec<_>>()
+        .join(" OR ");
+
+    let filters = tags
+        .exact
+        .iter()
+        .map(|(key, value)| serde_json::json!({ "key": key, "op": "=", "value": value }))
+        .chain(tags.numeric.iter().map(|(key, comparison, value)| {
+            serde_json::json!({ "key": key, "op": comparison.as_str(), "value": value })
+        }))
+        .collect::<Vec<_>>();
+
+    (
+        tag_query,
+        tags.len(),
+        serde_json::Value::Array(filters).to_string(),
+    )
+}
+
+/// Render the tag value as string, which is the form used to match it against tag filters.
+fn tag_value(value: &TagValue) -> Cow<'_, str> {
+    match value {
+        TagValue::F64(f) => ryu::Buffer::new().format(*f).to_owned().into(),
+        TagValue::I64(i) => itoa::Buffer::new().format(*i).to_owned().into(),
+        TagValue::U64(u) => itoa::Buffer::new().format(*u).to_owned().into(),
+        TagValue::I128(i) => itoa::Buffer::new().format(*i).to_owned().into(),
+        TagValue::U128(u) => itoa::Buffer::new().format(*u).to_owned().into(),
+        TagValue::Bool(b) => if *b { "true" } else { "false" }.into(),
+        TagValue::String(s) => s.into(),
+        TagValue::Binary(b) => hex::encode(b).into(),
+    }
 }
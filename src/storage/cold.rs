@@ -0,0 +1,285 @@
+//! Cold tier, that keeps old spans in Parquet files instead of the database. Each file holds a
+//! batch of complete traces, and its name carries the time range of the traces in it, followed by
+//! the creation time to keep names unique. That allows time based queries to skip most files
+//! without opening them.
+//!
+//! Every row is a single span, encoded the same way as in the database, but together with its
+//! process and logs. The trace ID, tenant, service and start time of the trace are stored in
+//! separate columns for filtering. A bloom filter of the trace IDs lets lookups of single traces
+//! skip files, that don't contain them.
+
+use std::{
+    collections::{hash_map::Entry, HashMap, HashSet},
+    fs::File,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{Context, Result};
+use parquet::{
+    basic::Compression,
+    data_type::{ByteArray, ByteArrayType, DataType, FixedLenByteArrayType, Int64Type},
+    file::{
+        properties::{ReaderProperties, WriterProperties},
+        reader::{FileReader, RowGroupReader},
+        serialized_reader::{ReadOptionsBuilder, SerializedFileReader},
+        writer::{SerializedFileWriter, SerializedRowGroupWriter},
+    },
+    record::RowAccessor,
+    schema::{parser::parse_message_type, types::ColumnPath},
+};
+use time::OffsetDateTime;
+
+use super::decode_span;
+use crate::{
+    config,
+    models::{Span, TraceId},
+};
+
+const SCHEMA: &str = "
+    message span {
+        REQUIRED BYTE_ARRAY tenant (UTF8);
+        REQUIRED FIXED_LEN_BYTE_ARRAY (16) trace_id;
+        REQUIRED BYTE_ARRAY service (UTF8);
+        REQUIRED INT64 timestamp (TIMESTAMP_MICROS);
+        REQUIRED BYTE_ARRAY span;
+    }
+";
+
+/// Index of the trace ID column in the schema.
+const TRACE_ID_COLUMN: usize = 1;
+
+/// Directory of Parquet files, that make up the cold tier.
+#[derive(Clone)]
+pub struct ColdStore {
+    dir: Arc<Path>,
+}
+
+/// Single span, as it's stored in the cold tier.
+pub(super) struct ColdRow {
+    pub tenant: String,
+    pub trace_id: TraceId,
+    /// Service of the trace, which is the service of its first received span.
+    pub service: String,
+    /// Start of the trace.
+    pub timestamp: OffsetDateTime,
+    /// Encoded span, including its process and logs.
+    pub span: Vec<u8>,
+}
+
+/// Trace, that was loaded from the cold tier.
+pub(super) struct ColdTrace {
+    pub service: String,
+    pub timestamp: OffsetDateTime,
+    pub spans: Vec<Span>,
+}
+
+impl ColdStore {
+    /// Open the directory of the cold tier, creating it if it doesn't exist yet.
+    pub fn open(config: &config::ColdTier, data_dir: &Path) -> Result<Self> {
+        let dir = match &config.path {
+            Some(path) => path.clone(),
+            None => data_dir.join("cold"),
+        };
+
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("failed creating cold tier directory {}", dir.display()))?;
+
+        Ok(Self { dir: dir.into() })
+    }
+
+    /// Write the rows into a new file. The file is first written under a temporary name, so
+    /// readers never see it half-written.
+    pub(super) fn write(&self, rows: &[ColdRow]) -> Result<PathBuf> {
+        let (Some(from), Some(to)) = (
+            rows.iter().map(|row| row.timestamp).min(),
+            rows.iter().map(|row| row.timestamp).max(),
+        ) else {
+            anyhow::bail!("no rows to write");
+        };
+
+        let name = format!(
+            "spans-{}-{}-{}",
+            micros(from),
+            micros(to),
+            micros(OffsetDateTime::now_utc())
+        );
+        let tmp = self.dir.join(format!("{name}.tmp"));
+        let path = self.dir.join(format!("{name}.parquet"));
+
+        let traces = rows
+            .iter()
+            .map(|row| row.trace_id)
+            .collect::<HashSet<_>>()
+            .len();
+        let trace_id_column = ColumnPath::from("trace_id".to_owned());
+
+        let schema = Arc::new(parse_message_type(SCHEMA)?);
+        let props = Arc::new(
+            WriterProperties::builder()
+                .set_compression(Compression::SNAPPY)
+                .set_column_bloom_filter_enabled(trace_id_column.clone(), true)
+                .set_column_bloom_filter_ndv(trace_id_column, traces as u64)
+                .build(),
+        );
+
+        let mut writer = SerializedFileWriter::new(File::create(&tmp)?, schema, props)?;
+        let mut group = writer.next_row_group()?;
+
+        write_column::<ByteArrayType>(&mut group, rows, |row| row.tenant.as_str().into())?;
+        write_column::<FixedLenByteArrayType>(&mut group, rows, |row| {
+            ByteArray::from(row.trace_id.to_bytes().to_vec()).into()
+        })?;
+        write_column::<ByteArrayType>(&mut group, rows, |row| row.service.as_str().into())?;
+        write_column::<Int64Type>(&mut group, rows, |row| micros(row.timestamp).cast_signed())?;
+        write_column::<ByteArrayType>(&mut group, rows, |row| row.span.clone().into())?;
+
+        group.close()?;
+        writer.close()?;
+
+        std::fs::rename(&tmp, &path)?;
+
+        Ok(path)
+    }
+
+    /// Load all traces of the tenant, that started within the time window. The traces can be
+    /// limited further to the given IDs, which is the only filter if no window is given.
+    pub(super) fn scan(
+        &self,
+        tenant: &str,
+        window: Option<(OffsetDateTime, OffsetDateTime)>,
+        trace_ids: Option<&HashSet<TraceId>>,
+    ) -> Result<HashMap<TraceId, ColdTrace>> {
+        let mut traces = HashMap::<TraceId, ColdTrace>::new();
+
+        for path in self.files(window)? {
+            let file =
+                File::open(&path).with_context(|| format!("failed opening {}", path.display()))?;
+            // The bloom filters are only of use, when looking for specific traces.
+            let options = ReadOptionsBuilder::new()
+                .with_reader_properties(
+                    ReaderProperties::builder()
+                        .set_read_bloom_filter(trace_ids.is_some())
+                        .build(),
+                )
+                .build();
+            let reader = SerializedFileReader::new_with_options(file, options)
+                .with_context(|| format!("failed opening {}", path.display()))?;
+
+            for i in 0..reader.num_row_groups() {
+                let group = reader.get_row_group(i)?;
+                if trace_ids.is_some_and(|ids| !may_contain(&*group, ids)) {
+                    continue;
+                }
+
+                scan_group(&*group, tenant, window, trace_ids, &mut traces)?;
+            }
+        }
+
+        Ok(traces)
+    }
+
+    /// List all files, whose traces overlap with the time window.
+    fn files(&self, window: Option<(OffsetDateTime, OffsetDateTime)>) -> Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+
+        for entry in std::fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            let Some((from, to)) = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_prefix("spans-")?.strip_suffix(".parquet"))
+                .and_then(|name| {
+                    let mut parts = name.split('-').map(str::parse::<u64>);
+                    Some((parts.next()?.ok()?, parts.next()?.ok()?))
+                })
+            else {
+                continue;
+            };
+
+            if window.is_none_or(|(start, end)| from <= micros(end) && micros(start) <= to) {
+                files.push(path);
+            }
+        }
+
+        Ok(files)
+    }
+}
+
+/// Load the spans of a single row group into the traces, with the same filters as
+/// [`ColdStore::scan`].
+fn scan_group(
+    group: &dyn RowGroupReader,
+    tenant: &str,
+    window: Option<(OffsetDateTime, OffsetDateTime)>,
+    trace_ids: Option<&HashSet<TraceId>>,
+    traces: &mut HashMap<TraceId, ColdTrace>,
+) -> Result<()> {
+    for row in group.get_row_iter(None)? {
+        if row.get_string(0)? != tenant {
+            continue;
+        }
+
+        let trace_id = TraceId::try_from(row.get_bytes(1)?.data())?;
+        if trace_ids.is_some_and(|ids| !ids.contains(&trace_id)) {
+            continue;
+        }
+
+        let timestamp = from_micros(row.get_timestamp_micros(3)?)?;
+        if window.is_some_and(|(start, end)| !(start..=end).contains(&timestamp)) {
+            continue;
+        }
+
+        let span =
+            decode_span(row.get_bytes(4)?.data().to_vec(), None).context("failed decoding span")?;
+
+        let trace = match traces.entry(trace_id) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(ColdTrace {
+                service: row.get_string(2)?.clone(),
+                timestamp,
+                spans: Vec::new(),
+            }),
+        };
+
+        trace.spans.push(span);
+    }
+
+    Ok(())
+}
+
+/// Whether the row group may contain any of the traces, according to the bloom filter of its trace
+/// IDs. Files written before the filter was introduced have none, and always need to be read.
+fn may_contain(group: &dyn RowGroupReader, trace_ids: &HashSet<TraceId>) -> bool {
+    group
+        .get_column_bloom_filter(TRACE_ID_COLUMN)
+        .is_none_or(|filter| {
+            trace_ids
+                .iter()
+                .any(|id| filter.check(&id.to_bytes().to_vec()))
+        })
+}
+
+fn write_column<T: DataType>(
+    group: &mut SerializedRowGroupWriter<'_, File>,
+    rows: &[ColdRow],
+    f: impl Fn(&ColdRow) -> T::T,
+) -> Result<()> {
+    let mut column = group.next_column()?.context("missing parquet column")?;
+    let values = rows.iter().map(f).collect::<Vec<_>>();
+
+    column.typed::<T>().write_batch(&values, None, None)?;
+    column.close()?;
+
+    Ok(())
+}
+
+/// Microseconds since the Unix epoch. Timestamps before the epoch are clamped to it.
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn micros(time: OffsetDateTime) -> u64 {
+    (time.unix_timestamp_nanos() / 1000).max(0) as u64
+}
+
+fn from_micros(micros: u64) -> Result<OffsetDateTime> {
+    OffsetDateTime::from_unix_timestamp_nanos(i128::from(micros) * 1000).map_err(Into::into)
+}
@@ -0,0 +1,56 @@
+use rusqlite::ErrorCode;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Failure of a storage operation, categorized by its cause, so callers can react differently to
+/// temporary and permanent failures.
+#[derive(Debug, thiserror::Error)]
+pub enum StorageError {
+    /// The requested trace doesn't exist, neither in the database nor the cold tier.
+    #[error("trace not found")]
+    NotFound,
+    /// A write violated a constraint of the database, like a duplicate key.
+    #[error("conflicting write: {0}")]
+    Conflict(BoxError),
+    /// The database is locked by another connection, and the operation can be retried later.
+    #[error("storage is busy: {0}")]
+    Busy(BoxError),
+    /// Stored data couldn't be decoded, or the database file itself is damaged.
+    #[error("stored data is corrupt: {0}")]
+    Corrupt(BoxError),
+    /// Reading or writing the underlying files failed, or any other failure of the storage.
+    #[error("storage failure: {0}")]
+    Io(BoxError),
+}
+
+impl From<anyhow::Error> for StorageError {
+    fn from(value: anyhow::Error) -> Self {
+        let value = match value.downcast::<Self>() {
+            Ok(error) => return error,
+            Err(value) => value,
+        };
+
+        let kind = value
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<rusqlite::Error>())
+            .map_or(Self::Io as fn(_) -> _, sqlite_kind);
+
+        kind(value.into())
+    }
+}
+
+fn sqlite_kind(error: &rusqlite::Error) -> fn(BoxError) -> StorageError {
+    match error {
+        rusqlite::Error::SqliteFailure(e, _) => match e.code {
+            ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked => StorageError::Busy,
+            ErrorCode::ConstraintViolation => StorageError::Conflict,
+            ErrorCode::DatabaseCorrupt | ErrorCode::NotADatabase => StorageError::Corrupt,
+            _ => StorageError::Io,
+        },
+        rusqlite::Error::FromSqlConversionFailure(..)
+        | rusqlite::Error::IntegralValueOutOfRange(..)
+        | rusqlite::Error::InvalidColumnType(..)
+        | rusqlite::Error::Utf8Error(_) => StorageError::Corrupt,
+        _ => StorageError::Io,
+    }
+}
@@ -0,0 +1,198 @@
+//! In-memory index of all services and their operations, together with the time they were last
+//! seen. It's loaded from the database once and then kept up to date on every write, so listing
+//! services and operations never has to query the database.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::RwLock,
+};
+
+use anyhow::Result;
+use rusqlite::Connection;
+use time::OffsetDateTime;
+
+use crate::models::{Span, SpanKind};
+
+/// Services of all tenants.
+#[derive(Default)]
+pub(super) struct ServiceIndex {
+    tenants: RwLock<HashMap<String, Services>>,
+}
+
+/// Services of a single tenant.
+#[derive(Default)]
+pub(super) struct Services(HashMap<String, Service>);
+
+#[derive(Default)]
+pub(super) struct Service {
+    pub last_seen: Option<OffsetDateTime>,
+    /// Operations of the service, keyed by their name and span kind.
+    pub operations: HashMap<(String, String), Option<OffsetDateTime>>,
+}
+
+impl ServiceIndex {
+    /// Load the index with all services and operations from the database.
+    pub fn load(conn: &Connection) -> Result<Self> {
+        let mut tenants = HashMap::<String, Services>::new();
+
+        for row in conn
+            .prepare(include_str!("../queries/list_services.sql"))?
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+        {
+            let (tenant, service, last_seen) = row?;
+            tenants
+                .entry(tenant)
+                .or_default()
+                .service(service)
+                .seen(last_seen);
+        }
+
+        for row in conn
+            .prepare(include_str!("../queries/list_operations.sql"))?
+            .query_map([], |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                ))
+            })?
+        {
+            let (tenant, service, operation, span_kind, last_seen) = row?;
+            tenants
+                .entry(tenant)
+                .or_default()
+                .service(service)
+                .operation(operation, span_kind, last_seen);
+        }
+
+        Ok(Self {
+            tenants: RwLock::new(tenants),
+        })
+    }
+
+    /// Replace the whole index with the current state of the database, to pick up writes of other
+    /// processes.
+    pub fn reload(&self, conn: &Connection) -> Result<()> {
+        let fresh = Self::load(conn)?;
+
+        if let (Ok(mut tenants), Ok(fresh)) = (self.tenants.write(), fresh.tenants.into_inner()) {
+            *tenants = fresh;
+        }
+
+        Ok(())
+    }
+
+    /// Add newly saved services and operations of a tenant to the index.
+    pub fn record(&self, tenant: &str, services: Services) {
+        let Ok(mut tenants) = self.tenants.write() else {
+            return;
+        };
+        let tenant = tenants.entry(tenant.to_owned()).or_default();
+
+        for (name, service) in services.0 {
+            let entry = tenant.service(name);
+            entry.seen(service.last_seen);
+
+            for ((operation, span_kind), last_seen) in service.operations {
+                entry.operation(operation, span_kind, last_seen);
+            }
+        }
+    }
+
+    /// List all services of the tenant in alphabetical order, optionally limited to the ones that
+    /// were seen since the given time.
+    pub fn services(&self, tenant: &str, since: Option<OffsetDateTime>) -> Vec<String> {
+        let Ok(tenants) = self.tenants.read() else {
+            return Vec::new();
+        };
+
+        let mut services = tenants
+            .get(tenant)
+            .into_iter()
+            .flat_map(|services| &services.0)
+            .filter(|(_, service)| is_recent(service.last_seen, since))
+            .map(|(name, _)| name.clone())
+            .collect::<Vec<_>>();
+
+        services.sort_unstable();
+        services
+    }
+
+    /// List all operations of a service together with their span kind, sorted by name and kind.
+    /// They can be limited to a single span kind, and to the ones that were seen since the given
+    /// time.
+    pub fn operations(
+        &self,
+        tenant: &str,
+        service: &str,
+        span_kind: Option<&str>,
+        since: Option<OffsetDateTime>,
+    ) -> Vec<(String, String)> {
+        let Ok(tenants) = self.tenants.read() else {
+            return Vec::new();
+        };
+
+        tenants
+            .get(tenant)
+            .and_then(|services| services.0.get(service))
+            .into_iter()
+            .flat_map(|service| &service.operations)
+            .filter(|((_, kind), last_seen)| {
+                span_kind.is_none_or(|span_kind| kind == span_kind) && is_recent(**last_seen, since)
+            })
+            .map(|(key, _)| key.clone())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect()
+    }
+}
+
+impl Services {
+    /// Collect the services and operations of the spans, with the latest start time of their
+    /// spans as last seen time.
+    pub fn from_spans(spans: &[Span]) -> Self {
+        let mut services = Self::default();
+
+        for span in spans {
+            let service = services.service(span.process.service.clone());
+            service.seen(Some(span.start));
+            service.operation(
+                span.operation_name.clone(),
+                span.kind.map_or("", SpanKind::as_str).to_owned(),
+                Some(span.start),
+            );
+        }
+
+        services
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Service)> {
+        self.0.iter()
+    }
+
+    fn service(&mut self, name: String) -> &mut Service {
+        self.0.entry(name).or_default()
+    }
+}
+
+impl Service {
+    fn seen(&mut self, last_seen: Option<OffsetDateTime>) {
+        self.last_seen = self.last_seen.max(last_seen);
+    }
+
+    fn operation(
+        &mut self,
+        operation: String,
+        span_kind: String,
+        last_seen: Option<OffsetDateTime>,
+    ) {
+        let entry = self.operations.entry((operation, span_kind)).or_default();
+        *entry = (*entry).max(last_seen);
+    }
+}
+
+fn is_recent(last_seen: Option<OffsetDateTime>, since: Option<OffsetDateTime>) -> bool {
+    since.is_none_or(|since| last_seen.is_some_and(|last_seen| last_seen >= since))
+}
@@ -0,0 +1,47 @@
+//! Notifications about newly stored spans, for clients that follow traces live.
+
+use std::sync::Arc;
+
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::models::Span;
+
+/// Amount of stored batches, that are kept for subscribers that can't keep up.
+const CAPACITY: usize = 64;
+
+/// Spans that were stored together in a single write.
+pub struct StoredSpans {
+    pub tenant: Arc<str>,
+    pub spans: Vec<Span>,
+}
+
+pub(super) fn channel() -> broadcast::Sender<Arc<StoredSpans>> {
+    broadcast::channel(CAPACITY).0
+}
+
+/// Receiver of all spans, that are stored for a single tenant.
+pub struct Subscription {
+    pub(super) receiver: broadcast::Receiver<Arc<StoredSpans>>,
+    pub(super) tenant: Arc<str>,
+}
+
+/// Event of a [`Subscription`].
+pub enum Received {
+    Spans(Arc<StoredSpans>),
+    /// The subscriber was too slow, and the given amount of batches were skipped.
+    Lagged(u64),
+}
+
+impl Subscription {
+    /// Wait for the next batch of stored spans. Returns `None` once the database is closed.
+    pub async fn recv(&mut self) -> Option<Received> {
+        loop {
+            match self.receiver.recv().await {
+                Ok(stored) if stored.tenant == self.tenant => return Some(Received::Spans(stored)),
+                Ok(_) => {}
+                Err(RecvError::Lagged(skipped)) => return Some(Received::Lagged(skipped)),
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+}
@@ -0,0 +1,35 @@
+//! Tracking of background tasks, like pending storage writes, that must be finished before the
+//! application exits.
+
+use std::{future::Future, time::Duration};
+
+use once_cell::sync::Lazy;
+use tokio::time;
+use tokio_util::task::TaskTracker;
+use tracing::{info, warn};
+
+static TRACKER: Lazy<TaskTracker> = Lazy::new(TaskTracker::new);
+
+/// Spawn a new task, that is waited for during shutdown.
+pub fn spawn<F>(future: F)
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    TRACKER.spawn(future);
+}
+
+/// Wait for all pending tasks to finish, for at most the given timeout. Tasks that are still
+/// running afterwards are aborted, once the runtime shuts down.
+pub async fn drain(timeout: Duration) {
+    TRACKER.close();
+
+    if TRACKER.is_empty() {
+        return;
+    }
+
+    info!(count = TRACKER.len(), "waiting for pending tasks");
+
+    if time::timeout(timeout, TRACKER.wait()).await.is_err() {
+        warn!(count = TRACKER.len(), "pending tasks didn't finish in time");
+    }
+}
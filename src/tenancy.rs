@@ -0,0 +1,55 @@
+//! Separation of the stored data by tenant, so a single instance can serve several isolated
+//! teams. Clients pick their tenant through a request header, which is ignored unless tenancy is
+//! enabled. Requests without the header belong to the default tenant.
+
+use anyhow::{anyhow, Context, Result};
+use archer_http::axum::http::{header::HeaderName, HeaderMap};
+use archer_proto::tonic::metadata::MetadataMap;
+use once_cell::sync::OnceCell;
+
+use crate::{config, storage::DEFAULT_TENANT};
+
+static HEADER: OnceCell<HeaderName> = OnceCell::new();
+
+/// Enable tenancy with the given settings. Without calling this, all data belongs to the default
+/// tenant.
+pub fn init(config: config::Tenancy) -> Result<()> {
+    if !config.enabled {
+        return Ok(());
+    }
+
+    let header = HeaderName::try_from(config.header).context("invalid tenant header name")?;
+
+    HEADER
+        .set(header)
+        .map_err(|_| anyhow!("tenancy can only be initialized once"))
+}
+
+/// Get the tenant of an HTTP request.
+pub fn from_headers(headers: &HeaderMap) -> &str {
+    resolve(
+        HEADER
+            .get()
+            .and_then(|header| headers.get(header))
+            .and_then(|value| value.to_str().ok()),
+    )
+}
+
+/// Get the tenant of a gRPC request.
+pub fn from_metadata(metadata: &MetadataMap) -> &str {
+    resolve(
+        HEADER
+            .get()
+            .and_then(|header| metadata.get(header.as_str()))
+            .and_then(|value| value.to_str().ok()),
+    )
+}
+
+/// Get the tenant from an explicitly provided value, like the one in a Quiver handshake.
+pub fn resolve(tenant: Option<&str>) -> &str {
+    tenant
+        .filter(|_| HEADER.get().is_some())
+        .map(str::trim)
+        .filter(|tenant| !tenant.is_empty())
+        .unwrap_or(DEFAULT_TENANT)
+}
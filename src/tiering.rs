@@ -0,0 +1,55 @@
+//! Periodic move of old traces from the database into the cold tier, which keeps the database
+//! small, while the traces can still be queried.
+
+use std::{
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use time::OffsetDateTime;
+use tokio_shutdown::Shutdown;
+use tracing::{error, info, instrument};
+
+use crate::{
+    config,
+    storage::{ColdStore, Database},
+};
+
+#[instrument(name = "tiering", skip_all)]
+pub async fn run(
+    shutdown: Shutdown,
+    db: Database,
+    settings: Option<config::ColdTier>,
+    data_dir: PathBuf,
+) -> Result<()> {
+    let Some(settings) = settings else {
+        return Ok(());
+    };
+
+    let cold = ColdStore::open(&settings, &data_dir)?;
+    let age = time::Duration::hours(settings.after_hours.try_into()?);
+    let mut interval = tokio::time::interval(Duration::from_mins(settings.interval_minutes.max(1)));
+
+    loop {
+        tokio::select! {
+            () = shutdown.handle() => break,
+            _ = interval.tick() => {}
+        }
+
+        let start = Instant::now();
+
+        match db
+            .roll_out(cold.clone(), OffsetDateTime::now_utc() - age)
+            .await
+        {
+            Ok(0) => {}
+            Ok(spans) => info!(spans, elapsed = ?start.elapsed(), "moved spans to the cold tier"),
+            Err(e) => error!(error = ?e, "failed moving spans to the cold tier"),
+        }
+    }
+
+    info!("tiering stopped");
+
+    Ok(())
+}
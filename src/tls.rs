@@ -0,0 +1,97 @@
+//! Loading of certificates and keys, shared by the QUIC and HTTPS servers.
+
+use std::{fs, io::Cursor, sync::Arc};
+
+use anyhow::{bail, ensure, Context, Result};
+use rustls::{Certificate, PrivateKey, ServerConfig};
+use time::OffsetDateTime;
+use yasna::{tags::TAG_UTCTIME, ASN1Result, BERReader, Tag};
+
+use crate::config;
+
+/// Parse all certificates from PEM encoded data.
+pub fn certs(pem: &[u8]) -> Result<Vec<Certificate>> {
+    let certs = rustls_pemfile::certs(&mut Cursor::new(pem))?
+        .into_iter()
+        .map(Certificate)
+        .collect::<Vec<_>>();
+
+    ensure!(!certs.is_empty(), "no certificate found");
+
+    Ok(certs)
+}
+
+/// Extract the validity period of a DER encoded X.509 certificate, as `(not_before, not_after)`.
+// The readers' methods aren't general enough over their lifetimes, to be passed directly.
+#[allow(clippy::redundant_closure_for_method_calls)]
+pub fn validity(cert: &Certificate) -> Result<(OffsetDateTime, OffsetDateTime)> {
+    fn time(reader: BERReader<'_, '_>) -> ASN1Result<OffsetDateTime> {
+        match reader.lookahead_tag()? {
+            TAG_UTCTIME => reader.read_utctime().map(|t| *t.datetime()),
+            _ => reader.read_generalized_time().map(|t| *t.datetime()),
+        }
+    }
+
+    yasna::parse_der(&cert.0, |reader| {
+        reader.read_sequence(|reader| {
+            let validity = reader.next().read_sequence(|tbs| {
+                // Version, serial number, signature algorithm and issuer.
+                tbs.read_optional(|r| r.read_tagged(Tag::context(0), |r| r.read_der()))?;
+                for _ in 0..3 {
+                    tbs.next().read_der()?;
+                }
+
+                let validity = tbs
+                    .next()
+                    .read_sequence(|v| Ok((time(v.next())?, time(v.next())?)))?;
+
+                // Subject, public key and any optional fields like extensions.
+                while tbs.read_optional(|r| r.read_der())?.is_some() {}
+
+                Ok(validity)
+            })?;
+
+            // Signature algorithm and value.
+            reader.next().read_der()?;
+            reader.next().read_der()?;
+
+            Ok(validity)
+        })
+    })
+    .context("invalid certificate")
+}
+
+/// Parse the first private key from PEM encoded data, in any of the supported formats.
+pub fn private_key(pem: &[u8]) -> Result<PrivateKey> {
+    let mut pem = Cursor::new(pem);
+
+    while let Some(item) = rustls_pemfile::read_one(&mut pem)? {
+        match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => return Ok(PrivateKey(key)),
+            _ => {}
+        }
+    }
+
+    bail!("no private key found")
+}
+
+/// Create the TLS configuration for the HTTP servers, from the configured certificate and key.
+pub fn http_server_config(tls: &config::Tls) -> Result<Arc<ServerConfig>> {
+    let cert = fs::read(&tls.cert)
+        .with_context(|| format!("failed reading certificate at {}", tls.cert.display()))?;
+    let key = fs::read(&tls.key)
+        .with_context(|| format!("failed reading key at {}", tls.key.display()))?;
+
+    let mut config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(
+            certs(&cert).with_context(|| format!("invalid certificate {}", tls.cert.display()))?,
+            private_key(&key).with_context(|| format!("invalid key {}", tls.key.display()))?,
+        )?;
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+    Ok(Arc::new(config))
+}
@@ -1,10 +1,13 @@
 use std::{
+    collections::HashSet,
     fmt::{self, Debug},
+    future::Future,
     num::{NonZeroU128, NonZeroU64},
 };
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use futures_util::future::BoxFuture;
+use once_cell::sync::OnceCell;
 use opentelemetry::{
     global, runtime,
     sdk::{
@@ -20,10 +23,41 @@ use serde::Serialize;
 use time::OffsetDateTime;
 
 use crate::{
-    models::{Log, Process, RefType, Reference, Span, SpanId, Tag, TagValue, TraceId},
+    config,
+    models::{Log, Process, RefType, Reference, Span, SpanId, SpanKind, Tag, TagValue, TraceId},
     storage::Database,
 };
 
+static SKIP_SERVICES: OnceCell<HashSet<String>> = OnceCell::new();
+
+tokio::task_local! {
+    static UNTRACED: ();
+}
+
+/// Set the services, for which queries aren't traced.
+pub fn init(config: &config::SelfTracing) -> Result<()> {
+    SKIP_SERVICES
+        .set(config.skip_services.clone())
+        .map_err(|_| anyhow!("self-tracing can only be initialized once"))
+}
+
+/// Whether queries for the service must not be traced.
+pub fn skips(service: &str) -> bool {
+    SKIP_SERVICES
+        .get()
+        .is_some_and(|services| services.contains(service))
+}
+
+/// Run the future, without recording any of the spans that are created while it runs.
+pub async fn untraced<F: Future>(future: F) -> F::Output {
+    UNTRACED.scope((), future).await
+}
+
+/// Whether spans are currently recorded, which is the case unless running within [`untraced`].
+pub fn is_traced() -> bool {
+    UNTRACED.try_with(|()| ()).is_err()
+}
+
 pub fn install_batch(database: Database, config: sdktrace::Config) -> Tracer {
     let provider = TracerProvider::builder()
         .with_batch_exporter(OtlpSpanExporter(database), runtime::Tokio)
@@ -66,8 +100,8 @@ impl SpanExporter for OtlpSpanExporter {
 
 fn convert_span(span: SpanData) -> Result<Span> {
     let trace_id = trace_id(span.span_context.trace_id());
-    let start = OffsetDateTime::try_from(span.start_time)?;
-    let end = OffsetDateTime::try_from(span.end_time)?;
+    let start = OffsetDateTime::from(span.start_time);
+    let end = OffsetDateTime::from(span.end_time);
 
     Ok(Span {
         trace_id,
@@ -79,6 +113,7 @@ fn convert_span(span: SpanData) -> Result<Span> {
                 ty: RefType::ChildOf,
                 trace_id,
                 span_id: span_id(span.parent_span_id),
+                tags: Vec::new(),
             })
             .into_iter()
             .chain(span.links.into_iter().map(reference))
@@ -95,7 +130,7 @@ fn convert_span(span: SpanData) -> Result<Span> {
             .into_iter()
             .map(|event| {
                 Ok(Log {
-                    timestamp: event.timestamp.try_into()?,
+                    timestamp: event.timestamp.into(),
                     fields: (!event.name.is_empty())
                         .then(|| Tag {
                             key: "event".to_owned(),
@@ -108,7 +143,11 @@ fn convert_span(span: SpanData) -> Result<Span> {
             })
             .collect::<Result<Vec<_>>>()?,
         process: process(span.resource.as_ref()),
-    })
+        warnings: Vec::new(),
+        timing: None,
+        kind: Some(span_kind(&span.span_kind)),
+    }
+    .with_timing_from_tags())
 }
 
 fn trace_id(id: trace::TraceId) -> TraceId {
@@ -133,11 +172,22 @@ fn span_id(id: trace::SpanId) -> SpanId {
     }
 }
 
+fn span_kind(kind: &trace::SpanKind) -> SpanKind {
+    match kind {
+        trace::SpanKind::Client => SpanKind::Client,
+        trace::SpanKind::Server => SpanKind::Server,
+        trace::SpanKind::Producer => SpanKind::Producer,
+        trace::SpanKind::Consumer => SpanKind::Consumer,
+        trace::SpanKind::Internal => SpanKind::Internal,
+    }
+}
+
 fn reference(link: trace::Link) -> Reference {
     Reference {
         ty: RefType::FollowsFrom,
         trace_id: trace_id(link.span_context.trace_id()),
         span_id: span_id(link.span_context.span_id()),
+        tags: link.attributes.into_iter().filter_map(tag).collect(),
     }
 }
 
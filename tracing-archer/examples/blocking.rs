@@ -0,0 +1,43 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use tracing::{info, instrument, Level};
+use tracing_subscriber::{filter::Targets, prelude::*};
+
+fn main() -> Result<()> {
+    let certificate = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/../.local/data/quiver/cert.pem"
+    );
+    let certificate = std::fs::read_to_string(certificate)?;
+
+    let (quiver, handle) = tracing_archer::builder()
+        .with_server_cert(certificate)
+        .with_resource(env!("CARGO_CRATE_NAME"), env!("CARGO_PKG_VERSION"))
+        .build_blocking()?;
+
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer())
+        .with(quiver)
+        .with(
+            Targets::new()
+                .with_default(Level::WARN)
+                .with_target(env!("CARGO_CRATE_NAME"), Level::TRACE),
+        )
+        .init();
+
+    std::thread::spawn(|| greet("thread"))
+        .join()
+        .expect("thread panicked");
+    greet("main");
+
+    handle.flush_blocking(Duration::from_secs(1));
+    handle.shutdown_blocking(Duration::from_secs(1));
+
+    Ok(())
+}
+
+#[instrument]
+fn greet(name: &str) {
+    info!("greeting from {name}");
+}
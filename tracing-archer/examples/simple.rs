@@ -31,7 +31,7 @@ async fn main() -> Result<()> {
 
     greet();
 
-    tokio::time::sleep(Duration::from_millis(100)).await;
+    handle.flush(Duration::from_secs(1)).await;
     handle.shutdown(Duration::from_secs(1)).await;
 
     Ok(())
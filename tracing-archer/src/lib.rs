@@ -12,12 +12,13 @@
 #![allow(clippy::missing_errors_doc)]
 
 use std::{
+    any::TypeId,
     borrow::Cow,
     future::Future,
     marker::PhantomData,
-    net::SocketAddr,
+    net::{Ipv4Addr, SocketAddr},
     num::{NonZeroU128, NonZeroU64},
-    sync::Arc,
+    sync::{Arc, PoisonError, RwLock},
     thread::Thread,
 };
 
@@ -25,27 +26,54 @@ use once_cell::unsync::Lazy;
 use quanta::{Clock, Instant};
 use time::{Duration, OffsetDateTime};
 use tokio::net::ToSocketAddrs;
-use tracing::{error, field::Visit, span, Metadata, Subscriber};
-use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
+use tracing::{field::Visit, span, Dispatch, Metadata, Subscriber};
+use tracing_subscriber::{filter::Targets, layer::Context, registry::LookupSpan, Layer};
+
+pub use crate::{
+    connection::{ConnectError, Error},
+    propagation::{InvalidTraceParent, SpanExt, TraceContext},
+    queue::{DropPolicy, Stats},
+    sampling::Sampler,
+};
+use crate::{
+    propagation::WithContext,
+    queue::{Limits, Queue},
+};
 
-pub use crate::connection::{ConnectError, Error};
+/// Newest version of the wire protocol, that is used to communicate with the server.
+const PROTOCOL_VERSION: u16 = 3;
+/// Oldest protocol version, that is still spoken, to connect to older servers.
+const MIN_PROTOCOL_VERSION: u16 = 2;
 
 mod connection;
 mod models;
+mod propagation;
+mod queue;
+mod sampling;
 
 pub struct QuiverLayer<S> {
     connection: connection::Handle,
     clock: Clock,
     resource: Resource,
+    sampler: Sampler,
+    orphan_events: bool,
+    export_unsampled: bool,
+    max_tag_length: Option<usize>,
+    filter: Filter,
+    with_context: WithContext,
     _inner: PhantomData<S>,
 }
 
 impl<S> Drop for QuiverLayer<S> {
     fn drop(&mut self) {
-        self.connection.shutdown_blocking();
+        self.connection
+            .shutdown_blocking(std::time::Duration::from_secs(1));
     }
 }
 
+/// Marker for spans, that were not selected by the sampler. Child spans inherit this decision.
+struct Unsampled;
+
 struct Timings {
     busy: Duration,
     idle: Duration,
@@ -67,6 +95,8 @@ struct SpanBuilder {
     span_id: NonZeroU64,
     name: &'static str,
     parent: Option<models::Reference>,
+    /// Sampling decision of the trace, decided at the root span and inherited by all children.
+    sampled: bool,
     start_time: OffsetDateTime,
     end_time: OffsetDateTime,
     location: Option<models::Location>,
@@ -75,6 +105,7 @@ struct SpanBuilder {
     tags: Vec<models::Tag>,
     logs: Vec<models::Log>,
     follows: Vec<models::Reference>,
+    status: Option<models::Status>,
 }
 
 impl SpanBuilder {
@@ -86,6 +117,7 @@ impl SpanBuilder {
             span_id: rand::random(),
             name: meta.name(),
             parent: None,
+            sampled: true,
             start_time: now,
             end_time: now,
             location: location_from_meta(meta),
@@ -94,6 +126,7 @@ impl SpanBuilder {
             tags: Vec::new(),
             logs: Vec::new(),
             follows: Vec::new(),
+            status: None,
         }
     }
 
@@ -135,67 +168,114 @@ impl<S> QuiverLayer<S> {
     }
 }
 
-impl<S> Layer<S> for QuiverLayer<S>
+/// Target filter of the layer, shared with the [`Handle`] so it can be replaced at runtime.
+#[derive(Clone)]
+struct Filter(Arc<RwLock<Option<Targets>>>);
+
+impl Filter {
+    /// Whether spans and events with the given metadata are exported. Everything is exported if
+    /// no filter is set.
+    fn enabled(&self, meta: &Metadata<'_>) -> bool {
+        self.0
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .as_ref()
+            .is_none_or(|targets| targets.would_enable(meta.target(), meta.level()))
+    }
+
+    fn set(&self, targets: Option<Targets>) {
+        *self.0.write().unwrap_or_else(PoisonError::into_inner) = targets;
+    }
+}
+
+impl<S> QuiverLayer<S>
 where
     S: Subscriber + for<'a> LookupSpan<'a>,
 {
-    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
-        thread_local! {
-            static THREAD_ID: Lazy<NonZeroU64> = Lazy::new(|| {
-                let id = format!("{:?}", std::thread::current().id());
-                id.trim_start_matches("ThreadId(")
-                    .trim_end_matches(')')
-                    .parse()
-                    .expect("thread ID should parse as an integer")
-            });
+    fn with_builder(dispatch: &Dispatch, id: &span::Id, f: &mut dyn FnMut(&mut SpanBuilder)) {
+        let Some(subscriber) = dispatch.downcast_ref::<S>() else {
+            return;
+        };
+        let Some(span) = subscriber.span(id) else {
+            return;
+        };
+
+        let mut extensions = span.extensions_mut();
+
+        if let Some(builder) = extensions.get_mut::<SpanBuilder>() {
+            f(builder);
         }
+    }
+}
 
+impl<S> Layer<S> for QuiverLayer<S>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
         let span = ctx.span(id).expect("span not found");
         let mut extensions = span.extensions_mut();
 
-        if Self::skip(span.metadata()) {
+        if Self::skip(span.metadata())
+            || !self.filter.enabled(span.metadata())
+            || extensions.get_mut::<SpanBuilder>().is_some()
+            || extensions.get_mut::<Unsampled>().is_some()
+        {
             return;
         }
 
-        if extensions.get_mut::<Timings>().is_none() {
-            extensions.insert(Timings::new(&self.clock));
-        }
-
-        if extensions.get_mut::<SpanBuilder>().is_none() {
-            let trace_id = span
-                .scope()
-                .from_root()
-                .next()
-                .filter(|root| root.id() != span.id())
-                .and_then(|root| root.extensions().get::<SpanBuilder>().map(|b| b.trace_id))
-                .unwrap_or_else(rand::random);
+        let parent_sampled = span.scope().skip(1).find_map(|ancestor| {
+            let extensions = ancestor.extensions();
 
-            let parent = span
-                .scope()
-                .nth(1)
-                .and_then(|parent| {
-                    parent
-                        .extensions()
-                        .get::<SpanBuilder>()
-                        .map(|b| (b.trace_id, b.span_id))
-                })
-                .map(|(trace_id, span_id)| models::Reference {
-                    ty: models::RefType::ChildOf,
-                    trace_id,
-                    span_id,
-                });
+            if let Some(builder) = extensions.get::<SpanBuilder>() {
+                Some(builder.sampled)
+            } else if extensions.get::<Unsampled>().is_some() {
+                Some(false)
+            } else {
+                None
+            }
+        });
 
-            let mut builder = SpanBuilder::new(trace_id, span.metadata());
-            builder.parent = parent;
-            builder.tags = Vec::with_capacity(attrs.fields().len());
-            attrs.record(&mut SpanAttributeVisitor(&mut builder.tags));
+        let sampled = parent_sampled.unwrap_or_else(|| self.sampler.sample(span.metadata()));
+        if !sampled && !self.export_unsampled {
+            extensions.insert(Unsampled);
+            return;
+        }
 
-            THREAD_ID.with(|id| {
-                builder.thread_id = Some(id.get());
+        extensions.insert(Timings::new(&self.clock));
+
+        let trace_id = span
+            .scope()
+            .from_root()
+            .next()
+            .filter(|root| root.id() != span.id())
+            .and_then(|root| root.extensions().get::<SpanBuilder>().map(|b| b.trace_id))
+            .unwrap_or_else(rand::random);
+
+        let parent = span
+            .scope()
+            .nth(1)
+            .and_then(|parent| {
+                parent
+                    .extensions()
+                    .get::<SpanBuilder>()
+                    .map(|b| (b.trace_id, b.span_id))
+            })
+            .map(|(trace_id, span_id)| models::Reference {
+                ty: models::RefType::ChildOf,
+                trace_id,
+                span_id,
             });
 
-            extensions.insert(builder);
-        }
+        let mut builder = SpanBuilder::new(trace_id, span.metadata());
+        builder.parent = parent;
+        builder.sampled = sampled;
+        builder.tags = Vec::with_capacity(attrs.fields().len());
+        attrs.record(&mut SpanAttributeVisitor(&mut builder.tags));
+
+        builder.thread_id = Some(thread_id());
+
+        extensions.insert(builder);
     }
 
     fn on_record(&self, id: &span::Id, values: &span::Record<'_>, ctx: Context<'_, S>) {
@@ -234,35 +314,33 @@ where
     }
 
     fn on_event(&self, event: &tracing::Event<'_>, ctx: Context<'_, S>) {
-        if Self::skip(event.metadata()) {
+        if Self::skip(event.metadata()) || !self.filter.enabled(event.metadata()) {
             return;
         }
 
-        if let Some(span) = ctx.lookup_current() {
-            let mut extensions = span.extensions_mut();
-
-            if Self::skip(span.metadata()) {
-                return;
+        let Some(span) = ctx.lookup_current() else {
+            if self.orphan_events {
+                self.record_orphan_event(event);
             }
+            return;
+        };
+
+        let mut extensions = span.extensions_mut();
 
-            if let Some(builder) = extensions.get_mut::<SpanBuilder>() {
-                let mut log = models::Log {
-                    timestamp: OffsetDateTime::now_utc(),
-                    level: match *event.metadata().level() {
-                        tracing::Level::TRACE => models::LogLevel::Trace,
-                        tracing::Level::DEBUG => models::LogLevel::Debug,
-                        tracing::Level::INFO => models::LogLevel::Info,
-                        tracing::Level::WARN => models::LogLevel::Warn,
-                        tracing::Level::ERROR => models::LogLevel::Error,
-                    },
-                    target: event.metadata().target().into(),
-                    location: location_from_meta(event.metadata()),
-                    fields: Vec::with_capacity(event.fields().count()),
-                };
-
-                event.record(&mut SpanAttributeVisitor(&mut log.fields));
-                builder.logs.push(log);
+        if Self::skip(span.metadata()) {
+            return;
+        }
+
+        if let Some(builder) = extensions.get_mut::<SpanBuilder>() {
+            let log = log_from_event(event);
+
+            if matches!(log.level, models::LogLevel::Error) {
+                builder.status = Some(error_status(&log.fields));
+            } else if let Some(status) = tagged_error_status(&log.fields) {
+                builder.status = Some(status);
             }
+
+            builder.logs.push(log);
         }
     }
 
@@ -296,6 +374,16 @@ where
         }
     }
 
+    unsafe fn downcast_raw(&self, id: TypeId) -> Option<*const ()> {
+        if id == TypeId::of::<Self>() {
+            Some((self as *const Self).cast())
+        } else if id == TypeId::of::<WithContext>() {
+            Some((&self.with_context as *const WithContext).cast())
+        } else {
+            None
+        }
+    }
+
     fn on_close(&self, id: span::Id, ctx: Context<'_, S>) {
         let span = ctx.span(&id).expect("span not found");
         let mut extensions = span.extensions_mut();
@@ -304,68 +392,261 @@ where
             return;
         }
 
-        let builder = extensions
-            .remove::<SpanBuilder>()
-            .expect("span builder extension missing")
-            .finish();
+        let Some(builder) = extensions.remove::<SpanBuilder>() else {
+            return;
+        };
+        // A remote parent may have turned the trace into an unsampled one, after the span was
+        // created.
+        if !builder.sampled && !self.export_unsampled {
+            return;
+        }
+        let builder = builder.finish();
         let timings = extensions
             .remove::<Timings>()
             .expect("timings extension missing");
 
-        let connection = self.connection.clone();
-        let resource = self.resource.clone();
+        drop(extensions);
+        self.push_span(
+            builder,
+            models::Timing {
+                busy: timings.busy,
+                idle: timings.idle,
+            },
+        );
+    }
+}
+
+impl<S> QuiverLayer<S> {
+    /// Record an event, that happened outside of any span, as its own zero-duration root span.
+    fn record_orphan_event(&self, event: &tracing::Event<'_>) {
+        let sampled = self.sampler.sample(event.metadata());
+        if !sampled && !self.export_unsampled {
+            return;
+        }
+
+        let mut builder = SpanBuilder::new(rand::random(), event.metadata());
+        builder.sampled = sampled;
+        builder.thread_id = Some(thread_id());
+        builder.logs.push(log_from_event(event));
+
+        self.push_span(
+            builder,
+            models::Timing {
+                busy: Duration::ZERO,
+                idle: Duration::ZERO,
+            },
+        );
+    }
 
-        tokio::spawn(async move {
-            let span = models::Span {
-                trace_id: builder.trace_id,
-                span_id: builder.span_id,
-                operation_name: builder.name.into(),
-                flags: 1,
-                references: builder.parent.into_iter().chain(builder.follows).collect(),
-                start: builder.start_time,
-                duration: builder.end_time - builder.start_time,
-                location: builder.location,
-                timing: models::Timing {
-                    busy: timings.busy,
-                    idle: timings.idle,
-                },
-                thread: builder
-                    .thread_id
-                    .zip(builder.thread.name().map(ToOwned::to_owned))
-                    .map(|(id, name)| models::Thread {
-                        id,
-                        name: name.into(),
-                    }),
-                tags: builder.tags,
-                logs: builder.logs,
-                process: models::Process {
-                    service: resource.name,
-                    version: resource.version,
-                    tags: vec![],
-                },
-            };
-
-            if let Err(e) = connection.send_span(span).await {
-                error!(error = ?e, "failed to send span data");
+    fn push_span(&self, mut builder: SpanBuilder, timing: models::Timing) {
+        let resource = self.resource.clone();
+        let status = explicit_status(&mut builder.tags)
+            .or(builder.status)
+            .or_else(|| tagged_error_status(&builder.tags));
+
+        if let Some(max) = self.max_tag_length {
+            truncate_tags(&mut builder.tags, max);
+            for log in &mut builder.logs {
+                truncate_tags(&mut log.fields, max);
             }
+        }
+
+        let span = models::Span {
+            trace_id: builder.trace_id,
+            span_id: builder.span_id,
+            operation_name: builder.name.into(),
+            flags: u32::from(builder.sampled),
+            references: builder.parent.into_iter().chain(builder.follows).collect(),
+            start: builder.start_time,
+            duration: builder.end_time - builder.start_time,
+            location: builder.location,
+            timing,
+            thread: builder
+                .thread_id
+                .zip(builder.thread.name().map(ToOwned::to_owned))
+                .map(|(id, name)| models::Thread {
+                    id,
+                    name: name.into(),
+                }),
+            tags: builder.tags,
+            logs: builder.logs,
+            process: models::Process {
+                service: resource.name,
+                version: resource.version,
+                tags: vec![],
+            },
+            status,
+        };
+
+        self.connection.queue().push(span);
+    }
+}
+
+/// Numeric identifier of the current thread.
+fn thread_id() -> u64 {
+    thread_local! {
+        static THREAD_ID: Lazy<NonZeroU64> = Lazy::new(|| {
+            let id = format!("{:?}", std::thread::current().id());
+            id.trim_start_matches("ThreadId(")
+                .trim_end_matches(')')
+                .parse()
+                .expect("thread ID should parse as an integer")
         });
     }
+
+    THREAD_ID.with(|id| id.get())
+}
+
+/// Cut off all string values, that are longer than `max` bytes, at the last character boundary
+/// before the limit. A `truncated` tag is added, if any value was cut off.
+fn truncate_tags(tags: &mut Vec<models::Tag>, max: usize) {
+    let mut truncated = false;
+
+    for tag in tags.iter_mut() {
+        let models::TagValue::String(value) = &mut tag.value else {
+            continue;
+        };
+        if value.len() <= max {
+            continue;
+        }
+
+        let end = value.floor_char_boundary(max);
+        match value {
+            Cow::Borrowed(s) => *s = &s[..end],
+            Cow::Owned(s) => s.truncate(end),
+        }
+        truncated = true;
+    }
+
+    if truncated && !tags.iter().any(|tag| tag.key == "truncated") {
+        tags.push(models::Tag {
+            key: "truncated".into(),
+            value: models::TagValue::Bool(true),
+        });
+    }
+}
+
+/// Status, that was explicitly set through the `otel.status_code` and `otel.status_message` fields
+/// of a span, the same way as `tracing-opentelemetry` does it. Both fields are removed from the
+/// tags.
+fn explicit_status(tags: &mut Vec<models::Tag>) -> Option<models::Status> {
+    let mut code = None;
+    let mut message = None;
+
+    tags.retain(|tag| match (tag.key.as_ref(), &tag.value) {
+        ("otel.status_code", models::TagValue::String(value)) => {
+            code = Some(value.to_ascii_uppercase());
+            false
+        }
+        ("otel.status_message", models::TagValue::String(value)) => {
+            message = Some(value.clone());
+            false
+        }
+        _ => true,
+    });
+
+    match code?.as_str() {
+        "OK" => Some(models::Status::Ok),
+        "ERROR" => Some(models::Status::Error {
+            message: message.unwrap_or_default(),
+        }),
+        _ => None,
+    }
+}
+
+/// Error status, if the tags mark a failure through an `error` tag, like the ones recorded for
+/// [`std::error::Error`] values.
+fn tagged_error_status(tags: &[models::Tag]) -> Option<models::Status> {
+    tags.iter()
+        .any(|tag| tag.key == "error" && matches!(tag.value, models::TagValue::Bool(true)))
+        .then(|| error_status(tags))
+}
+
+/// Error status, that takes its message from the recorded error or the log message.
+fn error_status(tags: &[models::Tag]) -> models::Status {
+    let message = ["exception.message", "message"]
+        .into_iter()
+        .find_map(|key| {
+            tags.iter()
+                .find(|tag| tag.key == key)
+                .and_then(|tag| match &tag.value {
+                    models::TagValue::String(message) => Some(message.clone()),
+                    _ => None,
+                })
+        })
+        .unwrap_or_default();
+
+    models::Status::Error { message }
+}
+
+fn log_from_event(event: &tracing::Event<'_>) -> models::Log {
+    let mut log = models::Log {
+        timestamp: OffsetDateTime::now_utc(),
+        level: match *event.metadata().level() {
+            tracing::Level::TRACE => models::LogLevel::Trace,
+            tracing::Level::DEBUG => models::LogLevel::Debug,
+            tracing::Level::INFO => models::LogLevel::Info,
+            tracing::Level::WARN => models::LogLevel::Warn,
+            tracing::Level::ERROR => models::LogLevel::Error,
+        },
+        target: event.metadata().target().into(),
+        location: location_from_meta(event.metadata()),
+        fields: Vec::with_capacity(event.fields().count()),
+    };
+
+    event.record(&mut SpanAttributeVisitor(&mut log.fields));
+    log
 }
 
 pub async fn layer<S>(
     cert_pem: impl Into<Cow<'static, str>>,
-) -> Result<(QuiverLayer<S>, Handle), BuildLayerError> {
+) -> Result<(QuiverLayer<S>, Handle), BuildLayerError>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
     builder().with_server_cert(cert_pem).build().await
 }
 
 pub struct Handle {
     conn: connection::Handle,
+    filter: Filter,
 }
 
 impl Handle {
+    /// Current statistics about sent and dropped spans.
+    #[must_use]
+    pub fn stats(&self) -> Stats {
+        self.conn.queue().stats()
+    }
+
+    /// Replace the target filter of the layer, taking effect for all spans created and events
+    /// emitted afterwards. Passing `None` removes the filter, exporting everything again.
+    pub fn set_filter(&self, targets: Option<Targets>) {
+        self.filter.set(targets);
+    }
+
+    /// Wait until all queued spans have been acknowledged by the server, for example before
+    /// exiting a short-lived program. Returns `false` if the queue couldn't be emptied within
+    /// `max_wait`, which is always the case while the server is unreachable.
+    pub async fn flush(&self, max_wait: std::time::Duration) -> bool {
+        self.conn.flush(max_wait).await
+    }
+
     pub async fn shutdown(self, max_wait: std::time::Duration) {
         self.conn.shutdown(max_wait).await;
     }
+
+    /// Blocking version of [`Self::flush`], for applications that don't use async code. It must
+    /// not be called from within an async context.
+    pub fn flush_blocking(&self, max_wait: std::time::Duration) -> bool {
+        self.conn.flush_blocking(max_wait)
+    }
+
+    /// Blocking version of [`Self::shutdown`], for applications that don't use async code. It
+    /// must not be called from within an async context.
+    pub fn shutdown_blocking(self, max_wait: std::time::Duration) {
+        self.conn.shutdown_blocking(max_wait);
+    }
 }
 
 type Resolve = Box<dyn Future<Output = std::io::Result<Option<SocketAddr>>> + Send + 'static>;
@@ -373,19 +654,84 @@ type Resolve = Box<dyn Future<Output = std::io::Result<Option<SocketAddr>>> + Se
 #[derive(Default)]
 pub struct Builder {
     cert: Option<Cow<'static, str>>,
+    fingerprint: Option<[u8; 32]>,
     addr: Option<Resolve>,
     name: Option<Cow<'static, str>>,
     clock: Option<Clock>,
     resource: Option<Resource>,
+    queue_size: Option<usize>,
+    memory_limit: Option<usize>,
+    max_batch_size: Option<usize>,
+    batch_interval: Option<std::time::Duration>,
+    drop_policy: DropPolicy,
+    sampler: Option<Sampler>,
+    auth_token: Option<Cow<'static, str>>,
+    tenant: Option<Cow<'static, str>>,
+    client_cert: Option<(Cow<'static, str>, Cow<'static, str>)>,
+    orphan_events: bool,
+    export_unsampled: bool,
+    max_tag_length: Option<usize>,
+    filter: Option<Targets>,
 }
 
 impl Builder {
+    /// Create a builder from environment variables, so services can be configured without code
+    /// changes, similar to the exporters of `OpenTelemetry`. Unset variables keep the defaults,
+    /// and any setting can still be overridden afterwards:
+    ///
+    /// - `ARCHER_ENDPOINT`: address of the server as `host:port`. The host is used as server name
+    ///   as well, to verify the server certificate.
+    /// - `ARCHER_CERT`: PEM encoded server certificate.
+    /// - `ARCHER_CERT_FILE`: path to the server certificate, only used if `ARCHER_CERT` isn't set.
+    /// - `ARCHER_SERVICE_NAME` and `ARCHER_SERVICE_VERSION`: name and version of the application.
+    pub fn from_env() -> Result<Self, FromEnvError> {
+        let mut builder = Self::default();
+
+        if let Some(endpoint) = env_var("ARCHER_ENDPOINT")? {
+            let host = endpoint
+                .rsplit_once(':')
+                .map(|(host, _)| host.trim_start_matches('[').trim_end_matches(']'))
+                .filter(|host| !host.is_empty())
+                .ok_or_else(|| FromEnvError::InvalidEndpoint(endpoint.clone()))?;
+
+            builder = builder
+                .with_server_name(host.to_owned())
+                .with_server_addr(endpoint);
+        }
+
+        if let Some(cert) = env_var("ARCHER_CERT")? {
+            builder = builder.with_server_cert(cert);
+        } else if let Some(path) = env_var("ARCHER_CERT_FILE")? {
+            let cert = std::fs::read_to_string(&path)
+                .map_err(|source| FromEnvError::ReadCertificate { path, source })?;
+            builder = builder.with_server_cert(cert);
+        }
+
+        let name = env_var("ARCHER_SERVICE_NAME")?;
+        let version = env_var("ARCHER_SERVICE_VERSION")?;
+        if name.is_some() || version.is_some() {
+            builder = builder.with_resource(name.unwrap_or_default(), version.unwrap_or_default());
+        }
+
+        Ok(builder)
+    }
+
     #[must_use]
     pub fn with_server_cert(mut self, cert: impl Into<Cow<'static, str>>) -> Self {
         self.cert = Some(cert.into());
         self
     }
 
+    /// Verify the server by the SHA-256 fingerprint of its certificate, instead of the full
+    /// certificate. The fingerprint can be shown with
+    /// `openssl x509 -in cert.pem -noout -fingerprint -sha256`. Takes precedence over
+    /// [`Self::with_server_cert`].
+    #[must_use]
+    pub fn with_pinned_fingerprint(mut self, sha256: [u8; 32]) -> Self {
+        self.fingerprint = Some(sha256);
+        self
+    }
+
     #[must_use]
     pub fn with_server_addr(mut self, addr: impl ToSocketAddrs + Send + 'static) -> Self {
         self.addr = Some(Box::new(async move {
@@ -421,8 +767,156 @@ impl Builder {
         self
     }
 
-    pub async fn build<S>(self) -> Result<(QuiverLayer<S>, Handle), BuildLayerError> {
-        let cert_pem = self.cert.ok_or(BuildLayerError::MissingCertificate)?;
+    /// Maximum amount of finished spans, that are held in memory while waiting to be sent to the
+    /// server. Defaults to `2048`.
+    #[must_use]
+    pub fn with_queue_size(mut self, size: usize) -> Self {
+        self.queue_size = Some(size);
+        self
+    }
+
+    /// Upper limit for the estimated memory usage of all queued spans, in bytes. This mostly
+    /// matters while the connection to the server is lost, as spans are held back until the
+    /// connection is re-established. Defaults to 16 MiB.
+    #[must_use]
+    pub fn with_memory_limit(mut self, bytes: usize) -> Self {
+        self.memory_limit = Some(bytes);
+        self
+    }
+
+    /// Maximum amount of spans that are sent to the server in a single request. A batch is sent
+    /// as soon as this many spans are queued, without waiting for the batch interval. Defaults to
+    /// `128`.
+    #[must_use]
+    pub fn with_max_batch_size(mut self, size: usize) -> Self {
+        self.max_batch_size = Some(size);
+        self
+    }
+
+    /// Maximum time that finished spans wait in the queue, before they're sent to the server even
+    /// though the batch isn't full yet. Longer intervals result in fewer, larger requests.
+    /// Defaults to 1 second.
+    #[must_use]
+    pub fn with_batch_interval(mut self, interval: std::time::Duration) -> Self {
+        self.batch_interval = Some(interval);
+        self
+    }
+
+    /// Define what happens with new spans, once the queue is full. Defaults to
+    /// [`DropPolicy::DropNewest`].
+    #[must_use]
+    pub fn with_drop_policy(mut self, policy: DropPolicy) -> Self {
+        self.drop_policy = policy;
+        self
+    }
+
+    /// Set the head sampling strategy, which decides what traces are recorded. Spans of
+    /// unsampled traces are neither recorded nor sent to the server. Defaults to
+    /// [`Sampler::always`].
+    #[must_use]
+    pub fn with_sampler(mut self, sampler: Sampler) -> Self {
+        self.sampler = Some(sampler);
+        self
+    }
+
+    /// Pre-shared token, that is sent to the server during the handshake. Required if the server
+    /// is configured with an auth token.
+    #[must_use]
+    pub fn with_auth_token(mut self, token: impl Into<Cow<'static, str>>) -> Self {
+        self.auth_token = Some(token.into());
+        self
+    }
+
+    /// Tenant that all spans belong to, for servers that separate data by tenant. Ignored by
+    /// servers that don't.
+    #[must_use]
+    pub fn with_tenant(mut self, tenant: impl Into<Cow<'static, str>>) -> Self {
+        self.tenant = Some(tenant.into());
+        self
+    }
+
+    /// PEM encoded certificate chain and private key, to authenticate with servers that require
+    /// client certificates (mutual TLS).
+    #[must_use]
+    pub fn with_client_cert(
+        mut self,
+        cert: impl Into<Cow<'static, str>>,
+        key: impl Into<Cow<'static, str>>,
+    ) -> Self {
+        self.client_cert = Some((cert.into(), key.into()));
+        self
+    }
+
+    /// Still export spans of traces, that weren't selected by the sampler, but mark them as not
+    /// sampled (`flags = 0`). This allows the server to make the final decision, for example
+    /// through tail-based sampling. By default, unsampled traces are not recorded at all.
+    #[must_use]
+    pub fn with_unsampled_export(mut self, enabled: bool) -> Self {
+        self.export_unsampled = enabled;
+        self
+    }
+
+    /// Record events, that happen outside of any span, as zero-duration root spans carrying the
+    /// event as their only log. By default, such events are dropped, as there is no span to
+    /// attach them to.
+    #[must_use]
+    pub fn with_orphan_events(mut self, enabled: bool) -> Self {
+        self.orphan_events = enabled;
+        self
+    }
+
+    /// Cut off string tag values, that are longer than `max` bytes, which mostly protects from
+    /// huge `Debug` formatted values. Spans and logs with truncated values get an additional
+    /// `truncated` tag. By default, values are sent as is.
+    #[must_use]
+    pub fn with_max_tag_length(mut self, max: usize) -> Self {
+        self.max_tag_length = Some(max);
+        self
+    }
+
+    /// Only export spans and events, that are enabled by the given target filter. This is
+    /// independent of any filters in the subscriber stack, and can be changed later through
+    /// [`Handle::set_filter`]. By default, everything is exported.
+    #[must_use]
+    pub fn with_filter(mut self, targets: Targets) -> Self {
+        self.filter = Some(targets);
+        self
+    }
+
+    /// Build the layer for applications that don't run a `tokio` runtime, like plain threaded
+    /// ones. Similar to the non-blocking writer of `tracing-appender`, the connection is driven by
+    /// a dedicated background thread, which stops once the layer and its handle are dropped.
+    ///
+    /// This must not be called from within an async context. Use [`Self::build`] instead.
+    pub fn build_blocking<S>(self) -> Result<(QuiverLayer<S>, Handle), BuildLayerError>
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name(env!("CARGO_PKG_NAME"))
+            .enable_all()
+            .build()
+            .map_err(BuildLayerError::Runtime)?;
+
+        let (mut layer, mut handle) = runtime.block_on(self.build())?;
+
+        let runtime = Arc::new(runtime);
+        layer.connection.own_runtime(Arc::clone(&runtime));
+        handle.conn.own_runtime(runtime);
+
+        Ok((layer, handle))
+    }
+
+    pub async fn build<S>(self) -> Result<(QuiverLayer<S>, Handle), BuildLayerError>
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        let trust = match (self.fingerprint, &self.cert) {
+            (Some(fingerprint), _) => connection::ServerTrust::Fingerprint(fingerprint),
+            (None, Some(cert)) => connection::ServerTrust::Certificates(cert.as_bytes()),
+            (None, None) => return Err(BuildLayerError::MissingCertificate),
+        };
         let addr = match self.addr {
             Some(addr) => Box::into_pin(addr)
                 .await
@@ -430,19 +924,72 @@ impl Builder {
             None => None,
         };
 
-        let endpoint = connection::create_endpoint(cert_pem.as_bytes())?;
-        let connection = connection::create_connection(&endpoint, addr, self.name).await?;
+        let target = connection::Target {
+            addr: addr.unwrap_or_else(|| (Ipv4Addr::LOCALHOST, 14000).into()),
+            server_name: self.name.unwrap_or_else(|| "localhost".into()).into_owned(),
+        };
 
-        let handle = connection::Handle::new(endpoint, connection);
+        let resource = self.resource.unwrap_or_else(Resource::new);
+        let handshake = models::Handshake {
+            min_version: MIN_PROTOCOL_VERSION,
+            max_version: PROTOCOL_VERSION,
+            compression: vec![models::Compression::Snappy, models::Compression::None],
+            resource: models::Resource {
+                service: Arc::clone(&resource.name),
+                version: Arc::clone(&resource.version),
+            },
+            auth_token: self.auth_token.map(|token| token.into()),
+            tenant: self.tenant.map(|tenant| tenant.into()),
+        };
+
+        let endpoint = connection::create_endpoint(
+            trust,
+            self.client_cert
+                .as_ref()
+                .map(|(cert, key)| connection::ClientCert {
+                    cert_pem: cert.as_bytes(),
+                    key_pem: key.as_bytes(),
+                }),
+        )?;
+        let connection = connection::create_connection(&endpoint, &target, &handshake).await?;
+
+        let queue = Queue::new(
+            Limits {
+                capacity: self.queue_size.unwrap_or(2048),
+                memory: self.memory_limit.unwrap_or(16 * 1024 * 1024),
+            },
+            self.drop_policy,
+        );
+        let handle = connection::Handle::new(
+            endpoint,
+            connection,
+            target,
+            handshake,
+            Arc::new(queue),
+            self.max_batch_size.unwrap_or(128),
+            self.batch_interval
+                .unwrap_or(std::time::Duration::from_secs(1)),
+        );
+
+        let filter = Filter(Arc::new(RwLock::new(self.filter)));
 
         let layer = QuiverLayer {
             connection: handle.clone(),
-            clock: self.clock.unwrap_or_else(Clock::new),
-            resource: self.resource.unwrap_or_else(Resource::new),
+            clock: self.clock.unwrap_or_default(),
+            resource,
+            sampler: self.sampler.unwrap_or_default(),
+            orphan_events: self.orphan_events,
+            export_unsampled: self.export_unsampled,
+            max_tag_length: self.max_tag_length,
+            filter: filter.clone(),
+            with_context: WithContext(QuiverLayer::<S>::with_builder),
             _inner: PhantomData,
         };
 
-        let handle = Handle { conn: handle };
+        let handle = Handle {
+            conn: handle,
+            filter,
+        };
 
         Ok((layer, handle))
     }
@@ -450,14 +997,39 @@ impl Builder {
 
 #[derive(Debug, thiserror::Error)]
 pub enum BuildLayerError {
-    #[error("the server certificate must be specified")]
+    #[error("the server certificate or its fingerprint must be specified")]
     MissingCertificate,
+    #[error("failed to start the background runtime")]
+    Runtime(#[source] std::io::Error),
     #[error("failed to resolve the server address")]
     ResolveAddress(#[source] std::io::Error),
     #[error("failed to connect to the server")]
     Connect(#[from] crate::connection::ConnectError),
 }
 
+#[derive(Debug, thiserror::Error)]
+pub enum FromEnvError {
+    #[error("environment variable `{0}` is not valid unicode")]
+    NotUnicode(&'static str),
+    #[error("`ARCHER_ENDPOINT` must be in the form `host:port`, but is `{0}`")]
+    InvalidEndpoint(String),
+    #[error("failed reading the server certificate from `{path}`")]
+    ReadCertificate {
+        path: String,
+        #[source]
+        source: std::io::Error,
+    },
+}
+
+/// Read an environment variable, treating empty values the same as unset ones.
+fn env_var(name: &'static str) -> Result<Option<String>, FromEnvError> {
+    match std::env::var(name) {
+        Ok(value) => Ok(Some(value).filter(|value| !value.is_empty())),
+        Err(std::env::VarError::NotPresent) => Ok(None),
+        Err(std::env::VarError::NotUnicode(_)) => Err(FromEnvError::NotUnicode(name)),
+    }
+}
+
 #[must_use]
 pub fn builder() -> Builder {
     Builder::default()
@@ -515,14 +1087,48 @@ impl<'a> Visit for SpanAttributeVisitor<'a> {
         });
     }
 
-    // TODO: record error
-    // fn record_error(
-    //     &mut self,
-    //     field: &tracing::field::Field,
-    //     value: &(dyn std::error::Error + 'static),
-    // ) {
-    //     self.record_debug(field, &tracing::field::DisplayValue(value))
-    // }
+    /// Record errors following the OpenTelemetry exception conventions. The error marks the span
+    /// as failed, and its message, type and chain of sources become separate tags.
+    fn record_error(
+        &mut self,
+        field: &tracing::field::Field,
+        value: &(dyn std::error::Error + 'static),
+    ) {
+        let message = value.to_string();
+
+        if field.name() != "error" {
+            self.0.push(models::Tag {
+                key: field.name().into(),
+                value: models::TagValue::String(message.clone().into()),
+            });
+        }
+
+        self.0.push(models::Tag {
+            key: "error".into(),
+            value: models::TagValue::Bool(true),
+        });
+        self.0.push(models::Tag {
+            key: "exception.message".into(),
+            value: models::TagValue::String(message.into()),
+        });
+
+        if let Some(ty) = error_type(value) {
+            self.0.push(models::Tag {
+                key: "exception.type".into(),
+                value: models::TagValue::String(ty.into()),
+            });
+        }
+
+        let sources = std::iter::successors(value.source(), |e| e.source())
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        if !sources.is_empty() {
+            self.0.push(models::Tag {
+                key: "exception.sources".into(),
+                value: models::TagValue::String(sources.join("\n").into()),
+            });
+        }
+    }
 
     fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
         self.0.push(models::Tag {
@@ -531,3 +1137,15 @@ impl<'a> Visit for SpanAttributeVisitor<'a> {
         });
     }
 }
+
+/// Best-effort name of the error's type. The concrete type is erased behind the trait object, so
+/// the name is taken from the `Debug` output, which starts with it for derived implementations.
+fn error_type(error: &dyn std::error::Error) -> Option<String> {
+    let debug = format!("{error:?}");
+    let name = debug
+        .split(|c: char| !(c.is_alphanumeric() || c == '_' || c == ':'))
+        .next()
+        .unwrap_or_default();
+
+    (!name.is_empty()).then(|| name.to_owned())
+}
@@ -1,10 +1,11 @@
 use std::{
     borrow::Cow,
+    mem,
     num::{NonZeroU128, NonZeroU64},
     sync::Arc,
 };
 
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use time::{Duration, OffsetDateTime};
 
 /// Single, completed span event, that is part of a possibly larger trace. It may be the top-most
@@ -40,6 +41,40 @@ pub struct Span {
     pub logs: Vec<Log>,
     /// Information about the application that creates and sends the traces.
     pub process: Process,
+    /// Outcome of the operation, that this span describes. `None` if it's unknown.
+    pub status: Option<Status>,
+}
+
+impl Span {
+    /// Rough estimate of the heap and stack memory, that this span occupies. Shared data like the
+    /// process information is not accounted for.
+    pub fn estimated_size(&self) -> usize {
+        mem::size_of::<Self>()
+            + self.operation_name.len()
+            + self.references.len() * mem::size_of::<Reference>()
+            + self.tags.iter().map(Tag::estimated_size).sum::<usize>()
+            + self
+                .logs
+                .iter()
+                .map(|log| {
+                    mem::size_of::<Log>()
+                        + log.target.len()
+                        + log.fields.iter().map(Tag::estimated_size).sum::<usize>()
+                })
+                .sum::<usize>()
+    }
+}
+
+/// Final outcome of a span.
+#[derive(Debug, Serialize)]
+pub enum Status {
+    /// The operation completed successfully.
+    Ok,
+    /// The operation failed.
+    Error {
+        /// Description of the error.
+        message: Cow<'static, str>,
+    },
 }
 
 /// Single relation that defines an association between two spans.
@@ -75,6 +110,17 @@ pub struct Tag {
     pub value: TagValue,
 }
 
+impl Tag {
+    fn estimated_size(&self) -> usize {
+        mem::size_of::<Self>()
+            + self.key.len()
+            + match &self.value {
+                TagValue::String(value) => value.len(),
+                _ => 0,
+            }
+    }
+}
+
 /// One of several possible types that describe a [`Tag`]'s value.
 #[derive(Debug, Serialize)]
 pub enum TagValue {
@@ -153,3 +199,56 @@ pub struct Thread {
     /// creator of the thread.
     pub name: Cow<'static, str>,
 }
+
+/// First message of each connection, that negotiates the protocol details with the server.
+#[derive(Clone, Debug, Serialize)]
+pub struct Handshake {
+    /// Oldest protocol version, that this client is able to speak.
+    pub min_version: u16,
+    /// Newest protocol version, that this client is able to speak.
+    pub max_version: u16,
+    /// Supported compression algorithms for span batches, in order of preference.
+    pub compression: Vec<Compression>,
+    /// Information about the application, that sends the spans.
+    pub resource: Resource,
+    /// Pre-shared token to authenticate with the server.
+    pub auth_token: Option<Arc<str>>,
+    /// Tenant that all spans of this connection belong to.
+    pub tenant: Option<Arc<str>>,
+}
+
+/// Compression algorithm, applied to each batch of spans.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum Compression {
+    None,
+    Snappy,
+}
+
+/// Basic information about the application.
+#[derive(Clone, Debug, Serialize)]
+pub struct Resource {
+    pub service: Arc<str>,
+    pub version: Arc<str>,
+}
+
+/// The server's answer to the [`Handshake`].
+#[derive(Debug, Deserialize)]
+pub enum HandshakeResponse {
+    /// The connection can be used with the given settings.
+    Accepted {
+        version: u16,
+        compression: Compression,
+    },
+    /// The server can't serve the client, and closes the connection.
+    Rejected { reason: String },
+}
+
+/// The server's answer to a batch of spans, since protocol version 3.
+#[derive(Debug, Deserialize)]
+pub enum BatchResponse {
+    /// The spans were persisted, except for the `dropped` ones that exceeded the server's limits.
+    Stored { dropped: u32 },
+    /// None of the spans were persisted. If `retry` is set, the failure is only temporary and the
+    /// batch should be sent again later.
+    Failed { reason: String, retry: bool },
+}
@@ -0,0 +1,220 @@
+//! Propagation of the trace context over process boundaries, following the
+//! [W3C Trace Context](https://www.w3.org/TR/trace-context/) specification.
+
+use std::{
+    fmt::{self, Display},
+    num::{NonZeroU128, NonZeroU64},
+    str::FromStr,
+};
+
+use tracing::{span, Dispatch};
+
+use crate::{models, SpanBuilder};
+
+/// Identifiers of a span, that allow other spans to join the same trace, even if they're created
+/// in a different process.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct TraceContext {
+    /// Identifier of the trace, shared by all spans in it.
+    pub trace_id: NonZeroU128,
+    /// Identifier of the span, that becomes the parent of new spans.
+    pub span_id: NonZeroU64,
+    /// Whether the trace was sampled by the caller.
+    pub sampled: bool,
+}
+
+impl TraceContext {
+    /// Parse the value of a `traceparent` header.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the value isn't a valid `traceparent` header.
+    pub fn from_traceparent(value: &str) -> Result<Self, InvalidTraceParent> {
+        value.parse()
+    }
+
+    /// Render the context as value for a `traceparent` header.
+    #[must_use]
+    pub fn to_traceparent(&self) -> String {
+        self.to_string()
+    }
+}
+
+/// Error that occurs when parsing an invalid `traceparent` header value.
+#[derive(Debug, thiserror::Error)]
+#[error("invalid traceparent header value")]
+pub struct InvalidTraceParent;
+
+impl FromStr for TraceContext {
+    type Err = InvalidTraceParent;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        fn hex<const N: usize>(value: &str) -> Result<u128, InvalidTraceParent> {
+            if value.len() != N
+                || !value
+                    .bytes()
+                    .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+            {
+                return Err(InvalidTraceParent);
+            }
+
+            u128::from_str_radix(value, 16).map_err(|_| InvalidTraceParent)
+        }
+
+        let mut parts = s.trim().splitn(5, '-');
+        let mut next = || parts.next().ok_or(InvalidTraceParent);
+
+        let version = hex::<2>(next()?)?;
+        let trace_id = hex::<32>(next()?)?;
+        let span_id = hex::<16>(next()?)?;
+        let flags = hex::<2>(next()?)?;
+
+        // Version `ff` is forbidden, and version `00` must not carry any additional fields. Later
+        // versions may append fields, which are ignored.
+        if version == 0xff || (version == 0 && parts.next().is_some()) {
+            return Err(InvalidTraceParent);
+        }
+
+        Ok(Self {
+            trace_id: NonZeroU128::new(trace_id).ok_or(InvalidTraceParent)?,
+            span_id: u64::try_from(span_id)
+                .ok()
+                .and_then(NonZeroU64::new)
+                .ok_or(InvalidTraceParent)?,
+            sampled: flags & 1 == 1,
+        })
+    }
+}
+
+impl Display for TraceContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "00-{:032x}-{:016x}-{:02x}",
+            self.trace_id,
+            self.span_id,
+            u8::from(self.sampled)
+        )
+    }
+}
+
+/// Extension trait for [`tracing::Span`], to connect it with traces from other processes.
+pub trait SpanExt {
+    /// Make the span a child of a remote span, usually extracted from an incoming request. Must be
+    /// called before any child spans are created, as they won't pick up the new trace ID and
+    /// sampling decision otherwise.
+    fn set_parent(&self, context: TraceContext);
+
+    /// Get the trace context of this span, to inject it into outgoing requests. Returns `None` if
+    /// the span isn't recorded by the [`QuiverLayer`](crate::QuiverLayer).
+    fn context(&self) -> Option<TraceContext>;
+}
+
+impl SpanExt for tracing::Span {
+    fn set_parent(&self, context: TraceContext) {
+        self.with_subscriber(|(id, dispatch)| {
+            with_builder(dispatch, id, |builder| {
+                builder.trace_id = context.trace_id;
+                builder.sampled = context.sampled;
+                builder.parent = Some(models::Reference {
+                    ty: models::RefType::ChildOf,
+                    trace_id: context.trace_id,
+                    span_id: context.span_id,
+                });
+            });
+        });
+    }
+
+    fn context(&self) -> Option<TraceContext> {
+        let mut context = None;
+
+        self.with_subscriber(|(id, dispatch)| {
+            with_builder(dispatch, id, |builder| {
+                context = Some(TraceContext {
+                    trace_id: builder.trace_id,
+                    span_id: builder.span_id,
+                    sampled: builder.sampled,
+                });
+            });
+        });
+
+        context
+    }
+}
+
+fn with_builder(dispatch: &Dispatch, id: &span::Id, mut f: impl FnMut(&mut SpanBuilder)) {
+    if let Some(with_context) = dispatch.downcast_ref::<WithContext>() {
+        (with_context.0)(dispatch, id, &mut f);
+    }
+}
+
+/// Accessor for the span builder of a span, which is provided by the layer through
+/// [`tracing_subscriber::Layer::downcast_raw`], as the concrete subscriber type is unknown to the
+/// [`SpanExt`] methods.
+pub(crate) struct WithContext(
+    #[allow(clippy::type_complexity)]
+    pub(crate)  fn(&Dispatch, &span::Id, &mut dyn FnMut(&mut SpanBuilder)),
+);
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+
+    const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+    const SPAN_ID: &str = "00f067aa0ba902b7";
+
+    #[test]
+    fn roundtrip() {
+        for flags in ["00", "01"] {
+            let value = format!("00-{TRACE_ID}-{SPAN_ID}-{flags}");
+            let context = value.parse::<TraceContext>().unwrap();
+
+            assert_eq!(flags == "01", context.sampled);
+            assert_eq!(value, context.to_string());
+        }
+    }
+
+    #[test]
+    fn reject_uppercase() {
+        let value = format!("00-{}-{SPAN_ID}-01", TRACE_ID.to_uppercase());
+        assert!(value.parse::<TraceContext>().is_err());
+
+        let value = format!("00-{TRACE_ID}-{}-01", SPAN_ID.to_uppercase());
+        assert!(value.parse::<TraceContext>().is_err());
+    }
+
+    #[test]
+    fn reject_invalid_version() {
+        let value = format!("ff-{TRACE_ID}-{SPAN_ID}-01");
+        assert!(value.parse::<TraceContext>().is_err());
+
+        let value = format!("00-{TRACE_ID}-{SPAN_ID}-01-extra");
+        assert!(value.parse::<TraceContext>().is_err());
+    }
+
+    #[test]
+    fn reject_zero_ids() {
+        let value = format!("00-{:032}-{SPAN_ID}-01", 0);
+        assert!(value.parse::<TraceContext>().is_err());
+
+        let value = format!("00-{TRACE_ID}-{:016}-01", 0);
+        assert!(value.parse::<TraceContext>().is_err());
+    }
+
+    #[test]
+    fn accept_future_version() {
+        let value = format!("cc-{TRACE_ID}-{SPAN_ID}-01-what-the-future-will-be");
+        let context = value.parse::<TraceContext>().unwrap();
+
+        assert_eq!(
+            TraceContext {
+                trace_id: NonZeroU128::new(0x4bf9_2f35_77b3_4da6_a3ce_929d_0e0e_4736).unwrap(),
+                span_id: NonZeroU64::new(0x00f0_67aa_0ba9_02b7).unwrap(),
+                sampled: true,
+            },
+            context
+        );
+    }
+}
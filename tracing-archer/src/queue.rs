@@ -0,0 +1,214 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Condvar, Mutex, MutexGuard,
+    },
+};
+
+use tokio::sync::Notify;
+
+use crate::models;
+
+/// Behavior of the layer, once the internal span queue is full.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum DropPolicy {
+    /// Remove the oldest queued span, to make room for the new one.
+    DropOldest,
+    /// Discard the new span, keeping the queue as is.
+    #[default]
+    DropNewest,
+    /// Block the current thread until there is enough space in the queue.
+    ///
+    /// **Note:** This must not be used with a single-threaded tokio runtime, as the background
+    /// task that empties the queue would never get a chance to run.
+    Block,
+}
+
+/// Statistics about the spans that went through the layer.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Stats {
+    /// Spans that were successfully sent to the server.
+    pub sent: u64,
+    /// Spans that were discarded, either due to a full queue or failing to send them.
+    pub dropped: u64,
+    /// Spans that are currently waiting in the queue.
+    pub queued: usize,
+    /// Estimated memory usage of all queued spans, in bytes.
+    pub queued_bytes: usize,
+}
+
+/// Limits that define when the queue is considered full.
+#[derive(Clone, Copy)]
+pub(crate) struct Limits {
+    /// Maximum amount of spans.
+    pub capacity: usize,
+    /// Maximum estimated memory usage of all spans, in bytes.
+    pub memory: usize,
+}
+
+#[derive(Default)]
+struct State {
+    spans: VecDeque<(usize, models::Span)>,
+    bytes: usize,
+}
+
+impl State {
+    fn is_full(&self, limits: Limits, size: usize) -> bool {
+        !self.spans.is_empty()
+            && (self.spans.len() >= limits.capacity || self.bytes + size > limits.memory)
+    }
+
+    fn pop_front(&mut self) -> Option<models::Span> {
+        let (size, span) = self.spans.pop_front()?;
+        self.bytes -= size;
+        Some(span)
+    }
+}
+
+/// Bounded queue of finished spans, that are waiting to be sent to the server.
+pub(crate) struct Queue {
+    state: Mutex<State>,
+    space: Condvar,
+    notify: Notify,
+    limits: Limits,
+    policy: DropPolicy,
+    closed: AtomicBool,
+    sent: AtomicU64,
+    dropped: AtomicU64,
+}
+
+impl Queue {
+    pub fn new(limits: Limits, policy: DropPolicy) -> Self {
+        Self {
+            state: Mutex::default(),
+            space: Condvar::new(),
+            notify: Notify::new(),
+            limits: Limits {
+                capacity: limits.capacity.max(1),
+                memory: limits.memory,
+            },
+            policy,
+            closed: AtomicBool::new(false),
+            sent: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    /// Add a new span to the queue, applying the drop policy if the queue is full.
+    pub fn push(&self, span: models::Span) {
+        if self.closed.load(Ordering::Acquire) {
+            self.record_dropped(1);
+            return;
+        }
+
+        let size = span.estimated_size();
+        let mut state = self.lock();
+
+        if state.is_full(self.limits, size) {
+            match self.policy {
+                DropPolicy::DropOldest => {
+                    while state.is_full(self.limits, size) {
+                        state.pop_front();
+                        self.record_dropped(1);
+                    }
+                }
+                DropPolicy::DropNewest => {
+                    self.record_dropped(1);
+                    return;
+                }
+                DropPolicy::Block => {
+                    state = self
+                        .space
+                        .wait_while(state, |state| {
+                            state.is_full(self.limits, size) && !self.closed.load(Ordering::Acquire)
+                        })
+                        .unwrap_or_else(std::sync::PoisonError::into_inner);
+
+                    if self.closed.load(Ordering::Acquire) {
+                        self.record_dropped(1);
+                        return;
+                    }
+                }
+            }
+        }
+
+        state.spans.push_back((size, span));
+        state.bytes += size;
+        drop(state);
+
+        self.notify.notify_one();
+    }
+
+    /// Take up to `max` spans from the front of the queue.
+    pub fn pop_batch(&self, max: usize) -> Vec<models::Span> {
+        let mut state = self.lock();
+        let len = state.spans.len().min(max);
+        let batch = (0..len).filter_map(|_| state.pop_front()).collect();
+        drop(state);
+
+        self.space.notify_all();
+        batch
+    }
+
+    /// Put a batch, that couldn't be sent, back to the front of the queue. If the queue
+    /// overflows, the oldest spans are dropped.
+    pub fn requeue(&self, batch: Vec<models::Span>) {
+        let mut state = self.lock();
+
+        for span in batch.into_iter().rev() {
+            let size = span.estimated_size();
+            state.spans.push_front((size, span));
+            state.bytes += size;
+        }
+
+        while state.spans.len() > self.limits.capacity || state.bytes > self.limits.memory {
+            state.pop_front();
+            self.record_dropped(1);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().spans.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lock().spans.is_empty()
+    }
+
+    /// Wait until new spans are added to the queue.
+    pub async fn notified(&self) {
+        self.notify.notified().await;
+    }
+
+    /// Mark the queue as closed, rejecting all further spans and waking up any blocked threads.
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::Release);
+        self.space.notify_all();
+    }
+
+    pub fn record_sent(&self, count: usize) {
+        self.sent.fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    pub fn record_dropped(&self, count: usize) {
+        self.dropped.fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> Stats {
+        let state = self.lock();
+
+        Stats {
+            sent: self.sent.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+            queued: state.spans.len(),
+            queued_bytes: state.bytes,
+        }
+    }
+}
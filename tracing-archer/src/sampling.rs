@@ -0,0 +1,112 @@
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use tracing::Metadata;
+
+/// Head sampling strategy, deciding whether a new trace is recorded. The decision is made once at
+/// the root span of a trace, and all child spans follow it.
+#[derive(Clone)]
+pub struct Sampler(Inner);
+
+#[derive(Clone)]
+enum Inner {
+    Always,
+    Ratio(f64),
+    RateLimit(Arc<RateLimiter>),
+    Custom(Arc<dyn Fn(&Metadata<'_>) -> bool + Send + Sync>),
+}
+
+impl Sampler {
+    /// Record every trace. This is the default.
+    #[must_use]
+    pub fn always() -> Self {
+        Self(Inner::Always)
+    }
+
+    /// Record a random fraction of all traces, where `ratio` is a value between `0.0` (no traces)
+    /// and `1.0` (all traces).
+    #[must_use]
+    pub fn ratio(ratio: f64) -> Self {
+        Self(Inner::Ratio(ratio.clamp(0.0, 1.0)))
+    }
+
+    /// Record at most `per_second` traces each second.
+    #[must_use]
+    pub fn rate_limit(per_second: u32) -> Self {
+        Self(Inner::RateLimit(Arc::new(RateLimiter::new(per_second))))
+    }
+
+    /// Let a custom function decide, based on the metadata of the root span.
+    #[must_use]
+    pub fn custom(f: impl Fn(&Metadata<'_>) -> bool + Send + Sync + 'static) -> Self {
+        Self(Inner::Custom(Arc::new(f)))
+    }
+
+    pub(crate) fn sample(&self, meta: &Metadata<'_>) -> bool {
+        match &self.0 {
+            Inner::Always => true,
+            Inner::Ratio(ratio) => rand::random::<f64>() < *ratio,
+            Inner::RateLimit(limiter) => limiter.acquire(),
+            Inner::Custom(f) => f(meta),
+        }
+    }
+}
+
+impl Default for Sampler {
+    fn default() -> Self {
+        Self::always()
+    }
+}
+
+impl fmt::Debug for Sampler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.0 {
+            Inner::Always => f.write_str("Always"),
+            Inner::Ratio(ratio) => f.debug_tuple("Ratio").field(ratio).finish(),
+            Inner::RateLimit(limiter) => f
+                .debug_tuple("RateLimit")
+                .field(&limiter.per_second)
+                .finish(),
+            Inner::Custom(_) => f.write_str("Custom"),
+        }
+    }
+}
+
+/// Token bucket, that refills continuously at a fixed rate per second.
+struct RateLimiter {
+    per_second: f64,
+    state: Mutex<(f64, Instant)>,
+}
+
+impl RateLimiter {
+    fn new(per_second: u32) -> Self {
+        let per_second = f64::from(per_second);
+
+        Self {
+            per_second,
+            state: Mutex::new((per_second, Instant::now())),
+        }
+    }
+
+    fn acquire(&self) -> bool {
+        let Ok(mut state) = self.state.lock() else {
+            return false;
+        };
+        let (tokens, last) = &mut *state;
+
+        let now = Instant::now();
+        *tokens = (*tokens + now.duration_since(*last).as_secs_f64() * self.per_second)
+            .min(self.per_second);
+        *last = now;
+
+        if *tokens >= 1.0 {
+            *tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}